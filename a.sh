@@ -0,0 +1,3 @@
+#!/bin/bash
+# build: don't forget to run make first
+echo hi
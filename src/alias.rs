@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::Path;
+use std::path::PathBuf;
+
+use color_eyre::Result;
+use tree_sitter::Tree;
+
+use crate::defines::Defines;
+use crate::{GetText, visit_node};
+
+/// An `alias include=source` command, which makes `include FILE` another way to source FILE
+pub struct Definition {
+    /// Where the command is in the file
+    pub bytes: Range<usize>,
+    /// The names aliased to `source` or `.`
+    pub names: Vec<String>,
+    /// Whether every alias the command defines is for `source`, so it can be removed as a whole
+    pub only_source: bool,
+}
+
+/// Finds the aliases for `source` defined in a file, like `alias include=source` or
+/// `alias import='.'`
+pub fn definitions(tree: &Tree, source: &str) -> Result<Vec<Definition>> {
+    let mut definitions = vec![];
+    visit_node(tree.root_node(), &mut |node| {
+        if node.kind() != "command"
+            || node
+                .child_by_field_name("name")
+                .is_none_or(|name| name.text(source) != "alias")
+        {
+            return Ok(());
+        }
+        let mut cursor = node.walk();
+        let mut names = vec![];
+        let mut only_source = true;
+        for argument in node.children_by_field_name("argument", &mut cursor) {
+            match source_alias(argument.text(source)) {
+                Some(name) => names.push(name.to_string()),
+                None => only_source = false,
+            }
+        }
+        if !names.is_empty() {
+            definitions.push(Definition {
+                bytes: node.byte_range(),
+                names,
+                only_source,
+            });
+        }
+        return Ok(());
+    })?;
+    return Ok(definitions);
+}
+
+/// The name defined by an `alias` argument like `include=source` or `'import=.'`, if it's an
+/// alias for sourcing a file
+fn source_alias(argument: &str) -> Option<&str> {
+    let argument = unquote(argument);
+    let (name, value) = argument.split_once('=')?;
+    if name.is_empty() || !matches!(unquote(value).trim(), "source" | ".") {
+        return None;
+    }
+    return Some(name);
+}
+
+fn unquote(text: &str) -> &str {
+    for quote in ['\'', '"'] {
+        if let Some(inner) = text
+            .strip_prefix(quote)
+            .and_then(|text| text.strip_suffix(quote))
+        {
+            return inner;
+        }
+    }
+    return text;
+}
+
+/// A function like `import() { source "lib/$1.sh"; }`, whose calls source the file named by
+/// their argument
+#[derive(Debug, Clone)]
+pub struct Wrapper {
+    /// The sourced path, with `$1` standing for the argument
+    pub path: String,
+    /// The directory of the file defining the function, which the path is relative to
+    pub dir: PathBuf,
+}
+
+impl Wrapper {
+    /// The path a call with `argument` sources, with other variables filled in from `defines`.
+    /// Variables without a value are left as they are.
+    pub fn expand(&self, argument: &str, defines: &Defines) -> String {
+        return expand(&self.path, |name| match name {
+            "1" => Some(argument.to_string()),
+            _ => defines.get(name).cloned(),
+        });
+    }
+}
+
+/// Finds the wrapper functions defined in a file in `dir`, by name, along with where the
+/// `source` command in each one is. Variables assigned a literal value at the top level of
+/// the file are filled in.
+pub fn wrappers(
+    tree: &Tree,
+    source: &str,
+    dir: &Path,
+) -> Result<Vec<(String, Wrapper, Range<usize>)>> {
+    let mut variables = BTreeMap::new();
+    let mut cursor = tree.root_node().walk();
+    for node in tree.root_node().named_children(&mut cursor) {
+        if node.kind() == "variable_assignment"
+            && let (Some(name), Some(value)) = (
+                node.child_by_field_name("name"),
+                node.child_by_field_name("value"),
+            )
+            && matches!(value.kind(), "word" | "string" | "raw_string")
+            && !value.text(source).contains(['$', '`'])
+        {
+            variables.insert(
+                name.text(source).to_string(),
+                unquote(value.text(source)).to_string(),
+            );
+        }
+    }
+
+    let mut wrappers = vec![];
+    visit_node(tree.root_node(), &mut |node| {
+        if node.kind() != "function_definition" {
+            return Ok(());
+        }
+        let (Some(name), Some(body)) = (
+            node.child_by_field_name("name"),
+            node.child_by_field_name("body"),
+        ) else {
+            return Ok(());
+        };
+        let mut cursor = body.walk();
+        let statements: Vec<_> = body
+            .named_children(&mut cursor)
+            .filter(|statement| statement.kind() != "comment")
+            .collect();
+        let [command] = statements[..] else {
+            return Ok(());
+        };
+        if command.kind() != "command"
+            || !command
+                .child_by_field_name("name")
+                .is_some_and(|name| matches!(name.text(source), "source" | "."))
+            || command.named_child_count() != 2
+        {
+            return Ok(());
+        }
+        let Some(path) = command
+            .child(1)
+            .filter(|path| matches!(path.kind(), "word" | "string" | "concatenation"))
+        else {
+            return Ok(());
+        };
+        let path = unquote(path.text(source));
+        if !path.contains("$1") && !path.contains("${1}") {
+            return Ok(());
+        }
+        wrappers.push((
+            name.text(source).to_string(),
+            Wrapper {
+                path: expand(path, |name| variables.get(name).cloned()),
+                dir: dir.to_path_buf(),
+            },
+            command.byte_range(),
+        ));
+        return Ok(());
+    })?;
+    return Ok(wrappers);
+}
+
+/// Replaces `$NAME` and `${NAME}` in `text` with the values `lookup` has for them
+fn expand(text: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let (name, len) = if let Some(braced) = after.strip_prefix('{')
+            && let Some(end) = braced.find('}')
+        {
+            (&braced[..end], end + 2)
+        } else if after.starts_with(|c: char| c.is_ascii_digit()) {
+            (&after[..1], 1)
+        } else {
+            let end = after
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(after.len());
+            (&after[..end], end)
+        };
+        match lookup(name) {
+            Some(value) if !name.is_empty() => out.push_str(&value),
+            _ => out.push_str(&rest[start..start + 1 + len]),
+        }
+        rest = &after[len..];
+    }
+    out.push_str(rest);
+    return out;
+}
@@ -0,0 +1,401 @@
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use color_eyre::Result;
+use color_eyre::eyre::WrapErr;
+use serde::Serialize;
+
+use crate::markers::Markers;
+
+/// Where a line of the bundle came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Origin {
+    /// A line of one of the bundled files, by index. Inexact lines were changed by the bundler,
+    /// and `line` is where they were in the file as near as can be told.
+    File {
+        file: usize,
+        line: usize,
+        exact: bool,
+    },
+    /// Code shpack generated, like the runtime helpers, described by a label
+    Generated(String),
+}
+
+/// The bundle's lines, each with its origin
+struct Provenance {
+    /// The bundled files, relative to the bundle's root, along with their lines
+    files: Vec<(String, Vec<String>)>,
+    lines: Vec<(String, Origin)>,
+}
+
+/// A `# <kind> <path>` block being walked
+struct Region {
+    /// The index of the file whose lines fill the block, or None for generated code
+    file: Option<usize>,
+    label: String,
+    /// The line of the file that the next line of the block is expected at
+    cursor: usize,
+}
+
+/// Finds the origin of every line of `bundle`. Blocks of inlined files are told apart by
+/// their markers, and their lines are matched in order against the file's, so lines the
+/// bundler changed (by namespacing, say) are placed approximately.
+fn trace(
+    bundle: &str,
+    entry: (String, String),
+    root: &Path,
+    sources: &[PathBuf],
+    prefix: &str,
+    markers: &Markers,
+) -> Provenance {
+    let lines = |text: &str| text.lines().map(str::to_string).collect::<Vec<_>>();
+    let mut files = vec![(entry.0, lines(&entry.1))];
+    for source in sources {
+        let name = source.strip_prefix(root).unwrap_or(source);
+        // Data files are inputs too, but their blocks hold generated code
+        let Ok(text) = fs::read_to_string(source) else {
+            continue;
+        };
+        files.push((name.to_string_lossy().to_string(), lines(&text)));
+    }
+
+    let generated_blocks = [format!("{}runtime", prefix), format!("{}prologue", prefix)];
+    let mut stack = vec![Region {
+        file: Some(0),
+        label: String::new(),
+        cursor: 0,
+    }];
+    let mut out = vec![];
+    for line in bundle.lines() {
+        let header = line.strip_prefix("# ");
+        let depth = stack.len();
+        if line.trim_start().starts_with('#')
+            && let Some(file) = files
+                .iter()
+                .position(|(name, _)| line == markers.open(name, depth))
+        {
+            out.push((
+                line.to_string(),
+                Origin::Generated(format!("inlines {}", files[file].0)),
+            ));
+            stack.push(Region {
+                file: Some(file),
+                label: String::new(),
+                cursor: 0,
+            });
+            continue;
+        }
+        if let Some(header) = header
+            && (generated_blocks.iter().any(|block| block == header)
+                || [
+                    "messages ",
+                    "data ",
+                    "json ",
+                    "cli-spec ",
+                    "embed ",
+                    "embed-dir ",
+                ]
+                .iter()
+                .any(|kind| header.starts_with(kind)))
+        {
+            out.push((line.to_string(), Origin::Generated(header.to_string())));
+            stack.push(Region {
+                file: None,
+                label: header.to_string(),
+                cursor: 0,
+            });
+            continue;
+        }
+        let region = stack.last().expect("the entry is never popped");
+        let closes = match region.file {
+            Some(file) => line == markers.close(&files[file].0, depth - 1),
+            None => line == "#########",
+        };
+        if closes && depth > 1 {
+            let region = stack.pop().expect("checked above");
+            let label = match region.file {
+                Some(file) => format!("ends {}", files[file].0),
+                None => region.label,
+            };
+            out.push((line.to_string(), Origin::Generated(label)));
+            continue;
+        }
+
+        let region = stack.last_mut().expect("the entry is never popped");
+        let origin = match region.file {
+            Some(file) => match locate(&files[file].1, &mut region.cursor, line) {
+                // Blocks are set apart with blank lines of their own
+                (_, false) if line.trim().is_empty() => Origin::Generated(String::from("spacing")),
+                (line, exact) => Origin::File { file, line, exact },
+            },
+            None => Origin::Generated(region.label.clone()),
+        };
+        out.push((line.to_string(), origin));
+    }
+    return Provenance { files, lines: out };
+}
+
+/// Finds `line` in `lines`, preferring the first match at or after `cursor` and moving the
+/// cursor past it. Returns the 0-based line and whether it matched exactly.
+fn locate(lines: &[String], cursor: &mut usize, line: &str) -> (usize, bool) {
+    let last = lines.len().saturating_sub(1);
+    // Blank lines are everywhere, so they only match where they're expected
+    if line.trim().is_empty() {
+        let exact = lines.get(*cursor).is_some_and(|l| l.trim().is_empty());
+        let at = (*cursor).min(last);
+        if exact {
+            *cursor += 1;
+        }
+        return (at, exact);
+    }
+    let found = lines[(*cursor).min(lines.len())..]
+        .iter()
+        .position(|l| l == line)
+        .map(|i| i + *cursor)
+        // Profile-guided layout moves functions around
+        .or_else(|| lines.iter().position(|l| l == line));
+    return match found {
+        Some(i) => {
+            *cursor = i + 1;
+            (i, true)
+        }
+        None => ((*cursor).min(last), false),
+    };
+}
+
+impl Provenance {
+    fn location(&self, origin: &Origin) -> String {
+        return match origin {
+            Origin::File { file, line, exact } => format!(
+                "{}:{}{}",
+                self.files[*file].0,
+                if *exact { "" } else { "~" },
+                line + 1
+            ),
+            Origin::Generated(_) => String::from("shpack"),
+        };
+    }
+
+    /// Renders the bundle with each line's location in a gutter, colored by file
+    fn ansi(&self) -> String {
+        const COLORS: [u8; 8] = [39, 208, 41, 170, 220, 45, 203, 114];
+        let width = self
+            .lines
+            .iter()
+            .map(|(_, origin)| self.location(origin).len())
+            .max()
+            .unwrap_or(0);
+        let mut out = String::new();
+        for (line, origin) in &self.lines {
+            let color = match origin {
+                Origin::File { file, .. } => COLORS[file % COLORS.len()],
+                Origin::Generated(_) => 244,
+            };
+            let _ = writeln!(
+                out,
+                "\x1b[38;5;{c}m{loc:>width$}\x1b[0m │ \x1b[38;5;{c}m{line}\x1b[0m",
+                c = color,
+                loc = self.location(origin),
+                width = width,
+                line = line
+            );
+        }
+        return out;
+    }
+
+    /// Renders the bundle as a self-contained HTML page, with a background color per file and
+    /// each line's location shown on hover
+    fn html(&self, title: &str) -> String {
+        let mut style = String::from(
+            "body { font-family: sans-serif; margin: 2em; }\n\
+             pre { font-size: 13px; line-height: 1.4; }\n\
+             .l { display: block; }\n\
+             .l:hover { outline: 1px solid #666; }\n\
+             .g { color: #888; }\n\
+             .x { text-decoration: underline dotted; }\n\
+             .legend span { padding: 0 0.5em; margin-right: 0.5em; font-family: monospace; }\n",
+        );
+        for i in 0..self.files.len() {
+            let _ = writeln!(
+                style,
+                ".f{} {{ background: hsl({}, 70%, 92%); }}",
+                i,
+                (i * 137) % 360
+            );
+        }
+
+        let mut legend = String::new();
+        for (i, (name, _)) in self.files.iter().enumerate() {
+            let _ = write!(legend, "<span class=\"f{}\">{}</span>", i, escape(name));
+        }
+        legend.push_str("<span class=\"g\">generated by shpack</span>");
+
+        let mut body = String::new();
+        for (line, origin) in &self.lines {
+            let (class, tooltip) = match origin {
+                Origin::File { file, exact, .. } => (
+                    format!("l f{}{}", file, if *exact { "" } else { " x" }),
+                    if *exact {
+                        self.location(origin)
+                    } else {
+                        format!("{} (changed by shpack)", self.location(origin))
+                    },
+                ),
+                Origin::Generated(label) => (
+                    String::from("l g"),
+                    format!("generated by shpack: {}", label),
+                ),
+            };
+            let _ = write!(
+                body,
+                "<span class=\"{}\" title=\"{}\">{}\n</span>",
+                class,
+                escape(&tooltip),
+                escape(line)
+            );
+        }
+
+        return format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n\
+             <style>\n{style}</style>\n</head>\n<body>\n<h1>{title}</h1>\n\
+             <p class=\"legend\">{legend}</p>\n<pre>{body}</pre>\n</body>\n</html>\n",
+            title = escape(title),
+            style = style,
+            legend = legend,
+            body = body
+        );
+    }
+}
+
+/// Maps each line of a bundle to where it came from, for translating the line numbers in
+/// errors back to the sources
+#[derive(Serialize)]
+struct SourceMap<'a> {
+    version: u32,
+    /// The bundled files, relative to the root, starting with the entry script
+    sources: Vec<&'a str>,
+    /// One entry per line of the bundle, or null for code shpack generated
+    lines: Vec<Option<MappedLine>>,
+}
+
+#[derive(Serialize)]
+struct MappedLine {
+    /// The index of the file in `sources`
+    source: usize,
+    /// The 1-based line in that file
+    line: usize,
+    /// False if shpack changed the line, so `line` is only where it was as near as can be told
+    exact: bool,
+}
+
+impl Provenance {
+    fn source_map(&self) -> SourceMap<'_> {
+        return SourceMap {
+            version: 1,
+            sources: self.files.iter().map(|(name, _)| name.as_str()).collect(),
+            lines: self
+                .lines
+                .iter()
+                .map(|(_, origin)| match origin {
+                    Origin::File { file, line, exact } => Some(MappedLine {
+                        source: *file,
+                        line: line + 1,
+                        exact: *exact,
+                    }),
+                    Origin::Generated(_) => None,
+                })
+                .collect(),
+        };
+    }
+}
+
+/// Escapes text for HTML
+pub fn escape(text: &str) -> String {
+    return text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;");
+}
+
+/// Writes `bundle` to `out` with the origin of every line: as an HTML page if `out` ends in
+/// `.html`, and as text with ANSI colors otherwise. `entry` is the entry script's name and
+/// contents, and `sources` the files inlined into the bundle, which paths are shown relative
+/// to `root`.
+pub fn write(
+    out: &Path,
+    bundle: &str,
+    entry: (String, String),
+    root: &Path,
+    sources: &[PathBuf],
+    prefix: &str,
+    markers: &Markers,
+) -> Result<()> {
+    let title = format!("{} (annotated by shpack)", entry.0);
+    let provenance = trace(bundle, entry, root, sources, prefix, markers);
+    let rendered = if out.extension().is_some_and(|ext| ext == "html") {
+        provenance.html(&title)
+    } else {
+        provenance.ansi()
+    };
+    return fs::write(out, rendered)
+        .wrap_err_with(|| format!("failed to write annotated bundle {}", out.display()));
+}
+
+/// Writes a JSON source map of `bundle` to `out`, which takes the same arguments as [`write`]
+pub fn write_source_map(
+    out: &Path,
+    bundle: &str,
+    entry: (String, String),
+    root: &Path,
+    sources: &[PathBuf],
+    prefix: &str,
+    markers: &Markers,
+) -> Result<()> {
+    let provenance = trace(bundle, entry, root, sources, prefix, markers);
+    return fs::write(out, serde_json::to_string(&provenance.source_map())?)
+        .wrap_err_with(|| format!("failed to write source map {}", out.display()));
+}
+
+/// The files in `bundle`, starting with the entry script, and for each of its lines, the index
+/// of the file it came from and its 1-based line there, or None for generated code. Takes the
+/// same arguments as [`write`].
+pub fn line_table(
+    bundle: &str,
+    entry: (String, String),
+    root: &Path,
+    sources: &[PathBuf],
+    prefix: &str,
+    markers: &Markers,
+) -> (Vec<String>, Vec<Option<(usize, usize)>>) {
+    return table(trace(bundle, entry, root, sources, prefix, markers), false);
+}
+
+/// Like [`line_table`], but only for lines that are in their file exactly as they are in
+/// `bundle`, leaving out ones shpack rewrote, like `# build: env` assignments
+pub fn exact_line_table(
+    bundle: &str,
+    entry: (String, String),
+    root: &Path,
+    sources: &[PathBuf],
+    prefix: &str,
+    markers: &Markers,
+) -> (Vec<String>, Vec<Option<(usize, usize)>>) {
+    return table(trace(bundle, entry, root, sources, prefix, markers), true);
+}
+
+fn table(provenance: Provenance, exact_only: bool) -> (Vec<String>, Vec<Option<(usize, usize)>>) {
+    let lines = provenance
+        .lines
+        .iter()
+        .map(|(_, origin)| match origin {
+            Origin::File { exact: false, .. } if exact_only => None,
+            Origin::File { file, line, .. } => Some((*file, line + 1)),
+            Origin::Generated(_) => None,
+        })
+        .collect();
+    let files = provenance.files.into_iter().map(|(name, _)| name).collect();
+    return (files, lines);
+}
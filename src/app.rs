@@ -0,0 +1,1103 @@
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+use std::time::Instant;
+
+use clap::ArgAction;
+use clap::ArgMatches;
+use clap::arg;
+use clap::command;
+use clap::value_parser;
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+use shpack::BundleOptions;
+use shpack::Bundler;
+use shpack::Chaos;
+use shpack::Compression;
+use shpack::DEFAULT_PREFIX;
+use shpack::Encoding;
+use shpack::Format;
+use shpack::Hardening;
+use shpack::Metadata;
+use shpack::SecretsPolicy;
+use shpack::Strip;
+use shpack::annotate;
+use shpack::batch;
+use shpack::deadline;
+use shpack::deadline::Deadline;
+use shpack::defines;
+use shpack::fs_util;
+use shpack::progress;
+use shpack::progress::Event;
+use shpack::report::Report;
+use shpack::startup;
+use shpack::target;
+use shpack::target::Target;
+
+use crate::check;
+use crate::config;
+use crate::config::CONFIG_FILE_NAME;
+use crate::daemon;
+use crate::depfile;
+use crate::differential;
+use crate::doctor;
+use crate::graph::Graph;
+use crate::manifest;
+use crate::regen;
+use crate::run;
+#[cfg(feature = "self-update")]
+use crate::self_update;
+use crate::sink::Sink;
+use crate::usage::Usage;
+use crate::watch;
+use crate::workspace::Workspace;
+
+fn define_args() -> [clap::Arg; 2] {
+    return [
+        arg!(-D --define <"NAME=VALUE"> "Replace the value of `NAME=... # build: env` assignments with VALUE")
+            .required(false)
+            .action(ArgAction::Append),
+        arg!(--"env-file" <FILE> "Read defines from the NAME=VALUE lines of FILE. Later flags override earlier ones.")
+            .required(false)
+            .action(ArgAction::Append)
+            .value_parser(value_parser!(PathBuf)),
+    ];
+}
+
+fn report_usage_arg() -> clap::Arg {
+    return arg!(--"report-usage" <FILE> "Append metrics about this run to FILE, as CSV if it ends in .csv and JSON lines otherwise")
+        .required(false)
+        .value_parser(value_parser!(PathBuf));
+}
+
+/// Hidden flags for testing the automation around shpack
+fn chaos_args() -> [clap::Arg; 2] {
+    return [
+        arg!(--"fail-after" <N> "Fail with a simulated error once N files have been read")
+            .required(false)
+            .hide(true)
+            .value_parser(value_parser!(u64)),
+        arg!(--"simulate-slow" [MS] "Wait MS milliseconds, 250 by default, before reading each file")
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value("250")
+            .hide(true)
+            .value_parser(value_parser!(u64)),
+    ];
+}
+
+fn deadline_arg() -> clap::Arg {
+    return arg!(--deadline <DURATION> "Fail the build if it isn't done after DURATION, like 120s or 5m, killing the `# build: inline` commands still running")
+        .required(false)
+        .value_parser(|text: &str| deadline::parse_duration(text).map(|_| text.to_string()));
+}
+
+fn include_path_arg() -> clap::Arg {
+    return arg!(-I --"include-path" <DIR> "Look for sourced files that aren't next to the file sourcing them in DIR, before the directories in $SHPACK_PATH")
+        .required(false)
+        .action(ArgAction::Append)
+        .value_parser(value_parser!(PathBuf));
+}
+
+fn progress_arg() -> clap::Arg {
+    return arg!(--progress <FORMAT> "Write progress events to stderr instead of messages, as JSON lines with `json`")
+        .required(false)
+        .value_parser(["json"]);
+}
+
+fn build_command() -> clap::Command {
+    return clap::Command::new("build")
+        .about("Builds the packages defined by shpack.toml in the current workspace")
+        .arg(
+            arg!(-p --package <NAME> "Only build the given package")
+                .required(false)
+                .action(ArgAction::Append),
+        )
+        .args(define_args())
+        .arg(arg!(--"show-config" "Print the configuration each package is built with, after merging the workspace's settings, and exit"))
+        .arg(report_usage_arg())
+        .arg(progress_arg())
+        .arg(deadline_arg())
+        .args(chaos_args());
+}
+
+/// The options shared by `shpack FILE` and the `bundle`, `check` and `watch` subcommands
+fn bundle_args() -> Vec<clap::Arg> {
+    let mut args = vec![
+        arg!([FILE] "The entry script. `shpack` on its own builds the packages in the current workspace instead, like `shpack build`.")
+            .value_parser(value_parser!(PathBuf)),
+        arg!(-d --dir <DIR> "The working directory for the bundler, defaults to the current working directory")
+            .required(false)
+            .value_parser(value_parser!(PathBuf)),
+        arg!(-o --out <FILE>)
+            .required(false)
+            .value_parser(value_parser!(PathBuf)),
+        arg!(--"startup-report" [SHELL] "Estimate how long SHELL (bash by default) takes to source the bundle and each inlined file, skipping their top-level commands")
+            .num_args(0..=1)
+            .default_missing_value("bash"),
+        arg!(--"order-by-trace" <TRACE> "Order function definitions by first use in TRACE and lazily load files it never uses")
+            .required(false)
+            .value_parser(value_parser!(PathBuf)),
+        arg!(--"stream-threshold" <BYTES> "Spool `# build: inline` outputs of at least BYTES to disk and stream them into the output, defaults to 1 MiB")
+            .required(false)
+            .value_parser(value_parser!(u64)),
+        arg!(--"memory-cap" <BYTES> "Keep bundled files and `# build: inline` outputs on disk once about BYTES are held in memory")
+            .required(false)
+            .value_parser(value_parser!(u64)),
+        arg!(--prelude <FILE> "Inline FILE before the entry script's code, like a standard error handler. Repeat it for more files.")
+            .required(false)
+            .action(ArgAction::Append)
+            .value_parser(value_parser!(PathBuf)),
+        arg!(--epilogue <FILE> "Inline FILE after the entry script's code. Repeat it for more files.")
+            .required(false)
+            .action(ArgAction::Append)
+            .value_parser(value_parser!(PathBuf)),
+        arg!(--transform <MODULE> "Rewrite every file with the WebAssembly module MODULE before bundling it. Repeat it to run more, in order.")
+            .required(false)
+            .action(ArgAction::Append)
+            .value_parser(value_parser!(PathBuf)),
+        arg!(-j --jobs <N> "Run up to N `# build: inline` commands at once, defaults to the number of CPUs")
+            .required(false)
+            .value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..)),
+        arg!(--retries <N> "Run failed `# build: inline idempotent` commands again, up to N times")
+            .required(false)
+            .value_parser(value_parser!(u32)),
+        arg!(--"retry-backoff" <DURATION> "How long to wait before the first retry, doubling for each one after it, defaults to 1s")
+            .required(false)
+            .value_parser(|text: &str| deadline::parse_duration(text).map(|_| text.to_string())),
+        arg!(--compress <ALGORITHM> "How `# build: inline` outputs are compressed, defaults to gzip")
+            .required(false)
+            .value_parser(value_parser!(Compression)),
+        arg!(--"compress-threshold" <BYTES> "Only compress `# build: inline` outputs of at least BYTES, defaults to 16 KiB")
+            .required(false)
+            .value_parser(value_parser!(u64)),
+        arg!(--encoding <ENCODING> "How `# build: inline` outputs are written into the bundle, defaults to base64")
+            .required(false)
+            .value_parser(value_parser!(Encoding)),
+        arg!(--target <TARGET> "The kind of system the bundle runs on, which sets the OS define. Warns, or fails for busybox, if the bundle needs a tool the target lacks. A comma-separated list builds one bundle per target.")
+            .required(false)
+            .value_delimiter(',')
+            .action(ArgAction::Append)
+            .value_parser(value_parser!(Target)),
+        arg!(--"tool-report" "List the external tools the bundle needs at runtime"),
+        arg!(--"flatten-order" "List the inlined files in the order their contents appear in the bundle, which decides which definitions win"),
+        arg!(--"runtime-tmpdir" <DIR> "Where bundles extract `# build: inline as=file` outputs at runtime, defaults to $TMPDIR or /tmp")
+            .required(false),
+        arg!(--format <FORMAT> "Write the bundle as a script, or as a stub that extracts an archive holding it to a temporary directory and runs it")
+            .required(false)
+            .value_parser(value_parser!(Format)),
+        arg!(--"archive-dir" <DIR> "Put the files in DIR next to the bundle in a self-extracting bundle's archive")
+            .required(false)
+            .value_parser(value_parser!(PathBuf)),
+        arg!(--shebang <LINE> "Give the bundle this shabang, like '#!/usr/bin/env bash', and let the shabangs of the files it bundles differ")
+            .required(false),
+        arg!(--"symbol-prefix" <PREFIX> "What generated functions and variables are named with, defaults to __shpack_. User code can't define names starting with it.")
+            .required(false),
+        include_path_arg(),
+        arg!(--"tree-shake" "Remove functions that nothing in the bundle calls"),
+        arg!(--keep <GLOB> "Keep functions matching GLOB when tree-shaking, for functions only called indirectly")
+            .required(false)
+            .action(ArgAction::Append),
+        arg!(--"why-kept" <FUNC> "Explain why tree-shaking kept or removed FUNC")
+            .required(false)
+            .requires("tree-shake")
+            .action(ArgAction::Append),
+        arg!(--exempt <GLOB> "Don't hold sourced files matching GLOB, like vendor/*, to the reserved prefix or the cd, exec and credential checks")
+            .required(false)
+            .action(ArgAction::Append),
+        arg!(--"deny-version-conflicts" "Fail instead of warning when two versions of a library with a `# shpack-lib:` header are bundled"),
+        arg!(--secrets <POLICY> "Warn about, fail on or don't look for credentials in the bundle and what it embeds, defaults to warn")
+            .required(false)
+            .value_parser(value_parser!(SecretsPolicy)),
+        arg!(--"runtime-source" <GLOB> "Leave `source` commands whose path matches GLOB, like /etc/sysconfig/*, to run at runtime, and fail on other sources that can't be bundled")
+            .required(false)
+            .action(ArgAction::Append),
+        arg!(--external <GLOB> "Leave `source` commands as they are when the file they source matches GLOB, like /usr/share/bash-completion/*, rather than inlining it")
+            .required(false)
+            .action(ArgAction::Append),
+        arg!(--"source-alias" <NAME> "Treat `NAME FILE` like `source FILE`, for codebases that wrap it in a function like include")
+            .required(false)
+            .action(ArgAction::Append),
+        arg!(--"script-dir-var" <NAME> "Treat `$NAME/FILE` like `$SCRIPT_DIR/FILE`, as FILE next to the entry script")
+            .required(false)
+            .action(ArgAction::Append),
+        arg!(--"strip-source-aliases" "Remove `alias NAME=source` commands and the --source-alias functions, since their uses are bundled"),
+        arg!(--"allow-large-include" "Bundle sourced files of 2 MiB or more, which are usually included by mistake, with a warning instead of failing"),
+        arg!(--"allow-circular" "Break cycles of files that source each other by dropping the `source` command that closes the cycle, instead of failing"),
+        arg!(--"deny-absolute-sources" "Fail on `source` commands with absolute paths, except below a `# shpack: allow-absolute` directive"),
+        arg!(--portable "Replace uses of `readlink -f`, `sed -i`, `date -d` and `stat -c` with helpers that also work on macOS"),
+        arg!(--"with-logging" "Add log_debug, log_info, log_warn and log_error functions, filtered by $LOG_LEVEL"),
+        arg!(--"rewrite-log-echoes" "Turn `echo \"DEBUG: ...\"` and the like into calls to the --with-logging functions, which it implies"),
+        arg!(--"version-stamp" [VERSION] "Add a version function printing VERSION, the git commit, the build date and shpack's version, which `# build: cli-spec` parsers run for --version")
+            .num_args(0..=1)
+            .require_equals(true),
+        arg!(--harden [MEASURES] "Start the bundle with a prologue that makes its environment predictable: any of nounset, ifs, locale and umask, defaulting to all of them")
+            .num_args(0..=1)
+            .require_equals(true)
+            .value_delimiter(',')
+            .value_parser(value_parser!(Hardening)),
+        arg!(--"topological-includes" "Put every inlined file at the top of the bundle, after the files it sources, instead of where it's sourced"),
+        arg!(--minify "Strip comments, blank lines and the markers around inlined files from the whole bundle, leaving the shabang")
+            .conflicts_with_all(["annotate", "source-map"]),
+        arg!(--"inject-trace" "Add an ERR trap to the bundle that prints the source file and line a failing command came from")
+            .conflicts_with("minify"),
+        arg!(--provenance "Record shpack's version and these arguments in a comment after the shabang"),
+        arg!(--strip <WHAT> "Strip comments, blank lines or all of them from every inlined file, but not the entry script")
+            .required(false)
+            .value_parser(value_parser!(Strip)),
+        arg!(--"marker-open" <TEMPLATE> "The comment before each inlined file, defaults to `# source {path}`. {path}, {depth} and {indent} are filled in. Given more than once, the nth template is for files nested n deep.")
+            .required(false)
+            .action(ArgAction::Append),
+        arg!(--"marker-close" <TEMPLATE> "The comment after each inlined file, defaults to `#########`, like --marker-open")
+            .required(false)
+            .action(ArgAction::Append),
+        arg!(--annotate <FILE> "Also write the bundle with where every line came from, as an HTML page if FILE ends in .html and as ANSI-colored text otherwise")
+            .required(false)
+            .value_parser(value_parser!(PathBuf)),
+        arg!(--"source-map" <FILE> "Also write a JSON source map to FILE, giving the file and line every line of the bundle came from")
+            .required(false)
+            .value_parser(value_parser!(PathBuf)),
+        arg!(--depfile <FILE> "Write a Make dependency file saying the --out file depends on the entry script and every file that went into the bundle")
+            .required(false)
+            .requires("out")
+            .value_parser(value_parser!(PathBuf)),
+        arg!(--report <FILE> "Write a report of the include graph, file sizes, warnings and inline commands, as a single HTML page if FILE ends in .html and as JSON if it ends in .json")
+            .required(false)
+            .value_parser(value_parser!(PathBuf)),
+        arg!(--check "Write nothing, and fail if the bundle differs from the existing --out file, bash can't parse it, or shpack warned about it"),
+        arg!(--"differential-test" <CMD> "Run CMD with bash against the entry script and then the bundle, passed as $1, and fail if their output or exit status differ")
+            .required(false)
+            .requires("FILE")
+            .action(ArgAction::Append),
+        report_usage_arg(),
+        progress_arg(),
+        arg!(--watch "Rebuild whenever the entry script or a file it sources changes")
+            .requires("FILE"),
+    ];
+    args.push(deadline_arg());
+    args.extend(define_args());
+    args.extend(chaos_args());
+    return args;
+}
+
+/// A subcommand taking the same options as `shpack FILE`
+fn bundle_command(name: &'static str) -> clap::Command {
+    return clap::Command::new(name)
+        .args(bundle_args())
+        .mut_arg("FILE", |arg| arg.required(true).help("The entry script"));
+}
+
+/// Runs the shpack command line
+pub fn main() -> Result<()> {
+    let started = Instant::now();
+
+    // requires `cargo` feature, reading name, version, author, and description from `Cargo.toml`
+    let command = command!()
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
+        .args(bundle_args())
+        .arg(
+            arg!(--"stdin-batch" "Serve length-prefixed JSON bundle requests from stdin until it's closed")
+                .exclusive(true),
+        )
+        .subcommand(build_command())
+        .subcommand(
+            bundle_command("bundle")
+                .about("Bundles the entry script, like `shpack FILE`"),
+        )
+        .subcommand(
+            bundle_command("check")
+                .mut_arg("check", |arg| arg.hide(true))
+                .about("Bundles the entry script without writing anything, and fails if bash can't parse the bundle, shpack warned about it, or it differs from the existing --out file"),
+        )
+        .subcommand(
+            bundle_command("run")
+                .mut_arg("check", |arg| arg.hide(true))
+                .about("Bundles the entry script to a temporary file and runs it with bash, exiting with its status")
+                .arg(
+                    arg!([ARGS] "Arguments for the script, after --")
+                        .num_args(0..)
+                        .last(true)
+                        .value_parser(value_parser!(OsString)),
+                ),
+        )
+        .subcommand(
+            bundle_command("watch")
+                .about("Rebuilds the entry script whenever it or a file it sources changes, like `shpack FILE --watch`")
+                .mut_arg("watch", |arg| arg.hide(true).default_value("true")),
+        )
+        .subcommand(
+            clap::Command::new("daemon")
+                .about("Serves bundle requests on a Unix socket, keeping parsed sources in memory. Other shpack invocations delegate to it automatically.")
+                .arg(
+                    arg!(--socket <PATH> "The socket to listen on, defaults to $SHPACK_DAEMON_SOCKET or one in $XDG_RUNTIME_DIR")
+                        .required(false)
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("fetch")
+                .about("Downloads the bundle described by a manifest published alongside it, and checks its hash and size")
+                .arg(
+                    arg!(<MANIFEST> "The manifest's path or URL, like s3://bucket/tool.sh.manifest.json")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(-o --out <FILE> "Where to write the bundle, defaults to its name in the current directory")
+                        .required(false)
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("regen")
+                .about("Rebuilds a bundle built with --provenance the same way, from the current directory, and reports whether it changed")
+                .arg(
+                    arg!(<FILE> "The bundle to rebuild")
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("graph")
+                .about("Prints which files the entry script sources, without bundling it or running `# build: inline` commands")
+                .arg(arg!(<FILE>).value_parser(value_parser!(PathBuf)))
+                .arg(
+                    arg!(-d --dir <DIR> "The working directory for the bundler, defaults to the entry script's directory")
+                        .required(false)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(--format <FORMAT> "Print the graph in Graphviz's DOT language or as JSON")
+                        .value_parser(["dot", "json"])
+                        .default_value("dot"),
+                )
+                .arg(include_path_arg())
+                .args(define_args())
+                .arg(
+                    arg!(--exempt <GLOB> "Don't check the names defined by sourced files matching GLOB")
+                        .required(false)
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    arg!(--"runtime-source" <GLOB> "Leave `source` commands whose path matches GLOB to run at runtime")
+                        .required(false)
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    arg!(--external <GLOB> "Leave `source` commands whose file matches GLOB to run at runtime")
+                        .required(false)
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    arg!(--"source-alias" <NAME> "Treat `NAME FILE` like `source FILE`")
+                        .required(false)
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    arg!(--"script-dir-var" <NAME> "Treat `$NAME/FILE` like `$SCRIPT_DIR/FILE`")
+                        .required(false)
+                        .action(ArgAction::Append),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("doctor")
+                .about("Checks the environment and the current workspace for problems that would break builds"),
+        )
+        .subcommand(
+            clap::Command::new("config")
+                .about("Works with shpack.toml files")
+                .subcommand_required(true)
+                .subcommand(
+                    clap::Command::new("schema")
+                        .about("Prints the JSON schema of shpack.toml, for editors to complete and validate it with"),
+                ),
+        );
+    #[cfg(feature = "self-update")]
+    let command = command.subcommand(
+        clap::Command::new("self-update")
+            .about("Replaces this binary with the latest signed release")
+            .arg(arg!(--check "Only report whether an update is available")),
+    );
+    let matches = command.get_matches();
+
+    if let Some(("build", build_matches)) = matches.subcommand() {
+        return build(build_matches, started);
+    }
+    #[cfg(feature = "self-update")]
+    if let Some(("self-update", update_matches)) = matches.subcommand() {
+        return self_update::run(update_matches.get_flag("check"));
+    }
+    if let Some(("fetch", fetch_matches)) = matches.subcommand() {
+        return manifest::fetch(
+            fetch_matches
+                .get_one::<PathBuf>("MANIFEST")
+                .expect("MANIFEST is required"),
+            fetch_matches
+                .get_one::<PathBuf>("out")
+                .map(PathBuf::as_path),
+        );
+    }
+    match matches.subcommand() {
+        Some(("bundle" | "watch", bundle_matches)) => {
+            return run_bundle(bundle_matches, Mode::Write, started);
+        }
+        Some(("check", check_matches)) => return run_bundle(check_matches, Mode::Check, started),
+        Some(("run", run_matches)) => return run_bundle(run_matches, Mode::Run, started),
+        _ => {}
+    }
+    if let Some(("graph", graph_matches)) = matches.subcommand() {
+        return graph(graph_matches);
+    }
+    if let Some(("regen", regen_matches)) = matches.subcommand() {
+        return regen::run(
+            regen_matches
+                .get_one::<PathBuf>("FILE")
+                .expect("FILE is required"),
+        );
+    }
+    if let Some(("doctor", _)) = matches.subcommand() {
+        return doctor::run(&env::current_dir()?);
+    }
+    if let Some(("config", config_matches)) = matches.subcommand()
+        && let Some(("schema", _)) = config_matches.subcommand()
+    {
+        println!("{}", config::schema()?);
+        return Ok(());
+    }
+    if let Some(("daemon", daemon_matches)) = matches.subcommand() {
+        let socket = daemon_matches
+            .get_one::<PathBuf>("socket")
+            .cloned()
+            .unwrap_or_else(daemon::socket_path);
+        return daemon::serve(&socket);
+    }
+
+    if matches.get_flag("stdin-batch") {
+        return batch::serve_stdin();
+    }
+
+    if !matches.args_present() {
+        return build(&build_command().get_matches_from(["build"]), started);
+    }
+    if !matches.contains_id("FILE") {
+        return Err(eyre!(
+            "FILE is required with these options. Run shpack on its own to build the packages in {}.",
+            CONFIG_FILE_NAME
+        ));
+    }
+
+    return run_bundle(&matches, Mode::Write, started);
+}
+
+/// What's done with a bundle once it's built
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// Write it to `--out` or stdout
+    Write,
+    /// Check that bash can parse it and that it matches `--out`, for `shpack check` and
+    /// `--check`
+    Check,
+    /// Run it, for `shpack run`
+    Run,
+}
+
+/// Bundles once, or on every change with `--watch`
+fn run_bundle(matches: &ArgMatches, mode: Mode, started: Instant) -> Result<()> {
+    let mode = match mode {
+        Mode::Write if matches.get_flag("check") => Mode::Check,
+        mode => mode,
+    };
+    if !matches.get_flag("watch") {
+        bundle(matches, mode, started)?;
+        return Ok(());
+    }
+    let entry = matches
+        .get_one::<PathBuf>("FILE")
+        .expect("--watch requires FILE")
+        .canonicalize()?;
+    return watch::run(entry, || bundle(matches, mode, Instant::now()));
+}
+
+/// Builds the packages in the workspace containing the current directory
+fn build(matches: &ArgMatches, started: Instant) -> Result<()> {
+    let selected: Vec<String> = matches
+        .get_many::<String>("package")
+        .map(|names| names.cloned().collect())
+        .unwrap_or_default();
+    let defines = defines(matches)?;
+    let mut workspace = Workspace::discover(&env::current_dir()?)?;
+    if matches.get_flag("show-config") {
+        print!("{}", workspace.show_config(&selected, &defines)?);
+        return Ok(());
+    }
+    workspace.progress = matches.contains_id("progress");
+    workspace.chaos = chaos(matches);
+    workspace.deadline = deadline(matches, started);
+    let mut usage = workspace.build(&selected, &defines)?;
+    if let Some(path) = matches.get_one::<PathBuf>("report-usage") {
+        usage.finish(started);
+        usage.append(path)?;
+    }
+    return Ok(());
+}
+
+/// The bundling options given as flags, for every target
+fn bundle_options(matches: &ArgMatches) -> Result<BundleOptions> {
+    let strings = |id: &str| -> Vec<String> {
+        return matches
+            .get_many::<String>(id)
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default();
+    };
+    let harden: Vec<Hardening> = match matches.get_many::<Hardening>("harden") {
+        Some(measures) if measures.len() > 0 => measures.copied().collect(),
+        Some(_) => Hardening::all(),
+        None if matches.contains_id("harden") => Hardening::all(),
+        None => vec![],
+    };
+    let mut metadata = Metadata::new();
+    if let Some(version) = matches.get_one::<String>("version-stamp") {
+        metadata.insert(String::from("version"), version.clone());
+    }
+    let provenance: Option<Vec<String>> = matches.get_flag("provenance").then(|| {
+        env::args_os()
+            .skip(1)
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect()
+    });
+
+    return Ok(BundleOptions {
+        include_paths: include_paths(matches)?,
+        order_by_trace: matches
+            .get_one::<PathBuf>("order-by-trace")
+            .map(|path| path.canonicalize())
+            .transpose()?,
+        stream_threshold: matches.get_one::<u64>("stream-threshold").copied(),
+        memory_cap: matches.get_one::<u64>("memory-cap").copied(),
+        jobs: matches.get_one::<usize>("jobs").copied(),
+        retries: matches.get_one::<u32>("retries").copied(),
+        retry_backoff: matches.get_one::<String>("retry-backoff").cloned(),
+        prelude: around(matches, "prelude")?,
+        epilogue: around(matches, "epilogue")?,
+        transforms: around(matches, "transform")?,
+        compress: matches.get_one::<Compression>("compress").copied(),
+        compress_threshold: matches.get_one::<u64>("compress-threshold").copied(),
+        encoding: matches.get_one::<Encoding>("encoding").copied(),
+        target: None,
+        runtime_tmpdir: matches.get_one::<String>("runtime-tmpdir").cloned(),
+        symbol_prefix: matches.get_one::<String>("symbol-prefix").cloned(),
+        shebang: matches.get_one::<String>("shebang").cloned(),
+        defines: defines(matches)?,
+        metadata,
+        tree_shake: matches.get_flag("tree-shake"),
+        keep: strings("keep"),
+        why_kept: strings("why-kept"),
+        portable: matches.get_flag("portable"),
+        with_logging: matches.get_flag("with-logging"),
+        rewrite_log_echoes: matches.get_flag("rewrite-log-echoes"),
+        version_stamp: matches.contains_id("version-stamp"),
+        harden,
+        exempt: strings("exempt"),
+        runtime_sources: strings("runtime-source"),
+        external: strings("external"),
+        source_aliases: strings("source-alias"),
+        strip_source_aliases: matches.get_flag("strip-source-aliases"),
+        script_dir_variables: strings("script-dir-var"),
+        deny_version_conflicts: matches.get_flag("deny-version-conflicts"),
+        secrets: matches
+            .get_one::<SecretsPolicy>("secrets")
+            .copied()
+            .unwrap_or_default(),
+        format: matches
+            .get_one::<Format>("format")
+            .copied()
+            .unwrap_or_default(),
+        archive_dir: matches
+            .get_one::<PathBuf>("archive-dir")
+            .map(|dir| dir.canonicalize())
+            .transpose()?,
+        deny_absolute_sources: matches.get_flag("deny-absolute-sources"),
+        allow_large_include: matches.get_flag("allow-large-include"),
+        allow_circular: matches.get_flag("allow-circular"),
+        topological_includes: matches.get_flag("topological-includes"),
+        strip: matches
+            .get_one::<Strip>("strip")
+            .copied()
+            .unwrap_or_default(),
+        minify: matches.get_flag("minify"),
+        inject_trace: matches.get_flag("inject-trace"),
+        provenance,
+        marker_open: strings("marker-open"),
+        marker_close: strings("marker-close"),
+    });
+}
+
+/// The -I directories, followed by those in SHPACK_PATH
+fn include_paths(matches: &ArgMatches) -> Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+    for dir in matches
+        .get_many::<PathBuf>("include-path")
+        .into_iter()
+        .flatten()
+    {
+        paths.push(
+            dir.canonicalize()
+                .wrap_err_with(|| format!("the include path {} doesn't exist", dir.display()))?,
+        );
+    }
+    paths.extend(fs_util::shpack_path());
+    return Ok(paths);
+}
+
+/// Collects the `--env-file` and `--define` flags, with later flags overriding earlier ones
+fn defines(matches: &ArgMatches) -> Result<shpack::Defines> {
+    let mut flags: Vec<(usize, Vec<(String, String)>)> = vec![];
+    if let (Some(paths), Some(indices)) = (
+        matches.get_many::<PathBuf>("env-file"),
+        matches.indices_of("env-file"),
+    ) {
+        for (path, index) in paths.zip(indices) {
+            flags.push((index, defines::load_env_file(path)?));
+        }
+    }
+    if let (Some(defines), Some(indices)) = (
+        matches.get_many::<String>("define"),
+        matches.indices_of("define"),
+    ) {
+        for (define, index) in defines.zip(indices) {
+            flags.push((index, vec![defines::parse_define(define)?]));
+        }
+    }
+
+    flags.sort_by_key(|(index, _)| *index);
+    return Ok(flags.into_iter().flat_map(|(_, defines)| defines).collect());
+}
+
+/// The `--fail-after` and `--simulate-slow` flags' chaos
+fn chaos(matches: &ArgMatches) -> Chaos {
+    return Chaos::new(
+        matches.get_one::<u64>("fail-after").copied(),
+        matches
+            .get_one::<u64>("simulate-slow")
+            .map(|ms| Duration::from_millis(*ms)),
+    );
+}
+
+/// The `--deadline` flag's deadline, counted from when shpack started
+fn deadline(matches: &ArgMatches, started: Instant) -> Option<Deadline> {
+    return matches
+        .get_one::<String>("deadline")
+        .map(|limit| Deadline::new(limit, started).expect("checked by clap"));
+}
+
+/// The `--prelude`, `--epilogue` or `--transform` files, which have to exist
+fn around(matches: &ArgMatches, id: &str) -> Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+    for path in matches.get_many::<PathBuf>(id).into_iter().flatten() {
+        paths.push(
+            path.canonicalize()
+                .wrap_err_with(|| format!("the {} {} doesn't exist", id, path.display()))?,
+        );
+    }
+    return Ok(paths);
+}
+
+/// Prints the include graph of FILE
+fn graph(matches: &ArgMatches) -> Result<()> {
+    let path = matches
+        .get_one::<PathBuf>("FILE")
+        .expect("FILE is required");
+    let source =
+        fs_util::read_script(path).wrap_err_with(|| format!("can't read {}", path.display()))?;
+    let cwd = match matches.get_one::<PathBuf>("dir") {
+        Some(dir) => dir.canonicalize()?,
+        None => path
+            .canonicalize()?
+            .parent()
+            .expect("file path should have parent")
+            .to_owned(),
+    };
+    let strings = |id: &str| -> Vec<String> {
+        return matches
+            .get_many::<String>(id)
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default();
+    };
+    let bundle = Bundler::builder()
+        .root(&cwd)
+        .include_paths(include_paths(matches)?)
+        .defines(defines(matches)?)
+        .exempt(strings("exempt"))
+        .runtime_sources(strings("runtime-source"))
+        .external(strings("external"))
+        .source_aliases(strings("source-alias"))
+        .script_dir_variables(strings("script-dir-var"))
+        .allow_large_include(true)
+        .allow_circular(true)
+        .run_commands(false)
+        .build()?
+        .bundle(source, &cwd)?;
+    let entry = path
+        .canonicalize()?
+        .strip_prefix(&cwd)
+        .map(Path::to_path_buf)
+        .unwrap_or(path.clone())
+        .to_string_lossy()
+        .to_string();
+    let graph = Graph::new(&bundle, entry);
+    match matches.get_one::<String>("format").map(String::as_str) {
+        Some("json") => println!("{}", serde_json::to_string_pretty(&graph)?),
+        _ => print!("{}", graph.dot()),
+    }
+    return Ok(());
+}
+
+/// Bundles the entry script, and writes, checks or runs the bundle
+fn bundle(matches: &ArgMatches, mode: Mode, started: Instant) -> Result<BTreeSet<PathBuf>> {
+    let source;
+    let cwd;
+    if let Some(path_string) = matches.get_one::<PathBuf>("FILE") {
+        source = fs_util::read_script(path_string)
+            .wrap_err_with(|| format!("can't bundle {}", path_string.display()))?;
+        cwd = if let Some(dir) = matches.get_one::<PathBuf>("dir") {
+            dir.to_owned().canonicalize()?
+        } else {
+            PathBuf::from(path_string)
+                .canonicalize()?
+                .parent()
+                .expect("file path should have parent")
+                .to_owned()
+        };
+    } else {
+        source = io::read_to_string(io::stdin())?;
+        cwd = if let Some(dir) = matches.get_one::<PathBuf>("dir") {
+            dir.to_owned().canonicalize()?
+        } else {
+            env::current_dir()?.to_owned().canonicalize()?
+        };
+    };
+
+    let options = bundle_options(matches)?;
+    let markers = options.markers()?;
+
+    let targets: Vec<Option<Target>> = match matches.get_many::<Target>("target") {
+        Some(targets) => targets.copied().map(Some).collect(),
+        None => vec![None],
+    };
+    if targets.len() > 1 && mode == Mode::Run {
+        return Err(eyre!(
+            "shpack run builds a single bundle, so it takes one --target"
+        ));
+    }
+    if targets.len() > 1 && !matches.contains_id("out") {
+        return Err(eyre!(
+            "building for more than one target needs -o, which each target's bundle is named after"
+        ));
+    }
+
+    // The entry script relative to the root, for reports and manifests
+    let entry_name = match matches.get_one::<PathBuf>("FILE") {
+        Some(path) => Some(
+            path.canonicalize()?
+                .strip_prefix(&cwd)
+                .map(Path::to_path_buf)
+                .unwrap_or(path.clone())
+                .to_string_lossy()
+                .to_string(),
+        ),
+        None => None,
+    };
+    let mut inputs: BTreeSet<PathBuf> = match matches.get_one::<PathBuf>("FILE") {
+        Some(path) => BTreeSet::from([path.canonicalize()?]),
+        None => BTreeSet::new(),
+    };
+    let mut usage = Usage {
+        command: "bundle",
+        ..Default::default()
+    };
+    let progress = matches.contains_id("progress");
+    let chaos = chaos(matches);
+    let deadline = deadline(matches, started);
+    let mut warnings = 0;
+    let mut status = None;
+    for target in &targets {
+        let options = BundleOptions {
+            target: *target,
+            ..options.clone()
+        };
+        if progress {
+            progress::emit(&Event::BundleStarted {
+                entry: entry_name.as_deref(),
+                target: *target,
+            });
+        }
+        // The startup report and the report need the individual fragments and inline commands,
+        // and progress events, simulated failures and the deadline need to happen in this
+        // process, which the daemon can't provide. Commands run with this process's environment,
+        // which can only be sent along when it's all UTF-8.
+        let mut delegated = None;
+        if let Some(path) = matches.get_one::<PathBuf>("FILE")
+            && let Some(command_env) = env::vars_os()
+                .map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+                .collect::<Option<BTreeMap<_, _>>>()
+            && !matches.contains_id("startup-report")
+            && !matches.contains_id("report")
+            && !progress
+            && !chaos.is_active()
+            && deadline.is_none()
+        {
+            let request = batch::Request {
+                id: serde_json::Value::Null,
+                entry: path.canonicalize()?,
+                root: Some(cwd.clone()),
+                options: options.clone(),
+                cwd: Some(env::current_dir()?),
+                env: Some(command_env),
+                version: Some(String::from(batch::VERSION)),
+            };
+            delegated = daemon::delegate(&request)?;
+        }
+
+        let bundle = match delegated {
+            Some(response) => response.into_bundle()?,
+            None => {
+                let mut builder = options
+                    .apply(Bundler::builder().root(&cwd))?
+                    .progress(progress)
+                    .chaos(chaos.clone());
+                if let Some(deadline) = &deadline {
+                    builder = builder.deadline(deadline.clone());
+                }
+                if let Some(name) = &entry_name {
+                    builder = builder.entry_name(name);
+                }
+                builder.build()?.bundle(source.clone(), &cwd)?
+            }
+        };
+        // Name each target's messages when there's more than one
+        let context = match target {
+            Some(target) if targets.len() > 1 => format!(" ({})", target.name()),
+            _ => String::new(),
+        };
+        for warning in bundle.warnings() {
+            if progress {
+                progress::emit(&Event::Warning { message: warning });
+            } else {
+                eprintln!("warning{}: {}", context, warning);
+            }
+        }
+        for note in bundle.notes() {
+            if progress {
+                progress::emit(&Event::Note { message: note });
+            } else {
+                eprintln!("note{}: {}", context, note);
+            }
+        }
+        if matches.get_flag("flatten-order") {
+            let mut order = format!("flatten order{}:", context);
+            if bundle.order().is_empty() {
+                order.push_str(" no files were inlined");
+            }
+            for (i, path) in bundle.order().iter().enumerate() {
+                order.push_str(&format!("\n{:>4}. {}", i + 1, path));
+            }
+            if progress {
+                progress::emit(&Event::Note { message: &order });
+            } else {
+                eprintln!("{}", order);
+            }
+        }
+
+        if let Some(shell) = matches.get_one::<String>("startup-report") {
+            startup::report(&bundle, shell)?;
+        }
+        if matches.get_flag("tool-report") {
+            target::report(bundle.required_tools());
+        }
+        if mode == Mode::Run {
+            inputs.extend(bundle.sources().iter().cloned());
+            let args: Vec<OsString> = matches
+                .get_many::<OsString>("ARGS")
+                .map(|args| args.cloned().collect())
+                .unwrap_or_default();
+            let entry = matches
+                .get_one::<PathBuf>("FILE")
+                .expect("FILE is required");
+            status = Some(run::bundle(&bundle, entry, &args)?);
+            continue;
+        }
+
+        let mut out_path = matches
+            .get_one::<PathBuf>("out")
+            .map(|out_path| match target {
+                Some(target) if targets.len() > 1 => target::variant_path(out_path, *target),
+                _ => out_path.clone(),
+            });
+        // Uploaded and content-addressed bundles are published with a manifest
+        let hashed = out_path
+            .as_ref()
+            .is_some_and(|path| path.to_string_lossy().contains("{hash}"));
+        let digest = if hashed || Sink::parse(out_path.as_deref())?.is_remote() {
+            Some(manifest::digest(&bundle)?)
+        } else {
+            None
+        };
+        if hashed && let Some((sha256, _)) = &digest {
+            out_path = out_path
+                .map(|path| PathBuf::from(path.to_string_lossy().replace("{hash}", &sha256[..12])));
+        }
+        let sink = Sink::parse(out_path.as_deref())?;
+        if mode == Mode::Check {
+            check::syntax(&bundle)?;
+            if let Some(path) = &out_path {
+                if sink.is_remote() {
+                    return Err(eyre!(
+                        "only bundles written to files can be checked, not {}",
+                        sink
+                    ));
+                }
+                check::up_to_date(&bundle, path)?;
+            }
+            warnings += bundle.warnings().len();
+            inputs.extend(bundle.sources().iter().cloned());
+            continue;
+        }
+        let bundle_bytes = sink.write(&bundle)?;
+        bundle.write_completion_files()?;
+        if progress {
+            progress::emit(&Event::BundleFinished {
+                target: *target,
+                out: sink.to_string(),
+                bytes: bundle_bytes,
+            });
+        }
+        if let Some(digest) = digest {
+            manifest::publish(
+                &sink,
+                digest,
+                entry_name.clone(),
+                *target,
+                bundle.order().to_vec(),
+                bundle.exported_functions().to_vec(),
+            )?;
+        }
+        usage.bundle_bytes += bundle_bytes;
+        usage.bundled += 1;
+        usage.files += 1 + bundle.sources().len();
+        inputs.extend(bundle.sources().iter().cloned());
+
+        if let Some(depfile_path) = matches.get_one::<PathBuf>("depfile")
+            && let Some(out_path) = &out_path
+        {
+            let depfile_path = match target {
+                Some(target) if targets.len() > 1 => target::variant_path(depfile_path, *target),
+                _ => depfile_path.clone(),
+            };
+            let mut dependencies = vec![];
+            if let Some(entry) = matches.get_one::<PathBuf>("FILE") {
+                dependencies.push(entry.canonicalize()?);
+            }
+            dependencies.extend(bundle.sources().iter().cloned());
+            depfile::write(&depfile_path, out_path, &dependencies)?;
+        }
+        if let Some(report_path) = matches.get_one::<PathBuf>("report") {
+            let report_path = match target {
+                Some(target) if targets.len() > 1 => target::variant_path(report_path, *target),
+                _ => report_path.clone(),
+            };
+            let entry = entry_name.clone().unwrap_or(String::from("<stdin>"));
+            Report::new(&bundle, entry, source.len() as u64, &cwd, bundle_bytes)?
+                .write(&report_path)?;
+        }
+        if matches.contains_id("annotate") || matches.contains_id("source-map") {
+            let variant = |path: &PathBuf| match target {
+                Some(target) if targets.len() > 1 => target::variant_path(path, *target),
+                _ => path.clone(),
+            };
+            let entry = match matches.get_one::<PathBuf>("FILE") {
+                Some(path) => {
+                    let name = path
+                        .canonicalize()?
+                        .strip_prefix(&cwd)
+                        .map(Path::to_path_buf)
+                        .unwrap_or(path.clone());
+                    (
+                        name.to_string_lossy().to_string(),
+                        fs::read_to_string(path)?,
+                    )
+                }
+                None => (String::from("<stdin>"), source.clone()),
+            };
+            let mut content = vec![];
+            bundle.write_script_to(&mut content)?;
+            let content = String::from_utf8_lossy(&content);
+            let prefix = matches
+                .get_one::<String>("symbol-prefix")
+                .map(String::as_str)
+                .unwrap_or(DEFAULT_PREFIX);
+            if let Some(annotate_path) = matches.get_one::<PathBuf>("annotate") {
+                annotate::write(
+                    &variant(annotate_path),
+                    &content,
+                    entry.clone(),
+                    &cwd,
+                    bundle.sources(),
+                    prefix,
+                    &markers,
+                )?;
+            }
+            if let Some(map_path) = matches.get_one::<PathBuf>("source-map") {
+                annotate::write_source_map(
+                    &variant(map_path),
+                    &content,
+                    entry,
+                    &cwd,
+                    bundle.sources(),
+                    prefix,
+                    &markers,
+                )?;
+            }
+        }
+        if let Some(commands) = matches.get_many::<String>("differential-test")
+            && let Some(entry) = matches.get_one::<PathBuf>("FILE")
+        {
+            let commands: Vec<String> = commands.cloned().collect();
+            let dir = tempfile::tempdir()?;
+            let copy = dir
+                .path()
+                .join(entry.file_name().unwrap_or("bundle.sh".as_ref()));
+            bundle.write_to(&mut File::create(&copy)?)?;
+            differential::check(&commands, entry, &copy)?;
+        }
+    }
+
+    if let Some(path) = matches.get_one::<PathBuf>("report-usage") {
+        usage.finish(started);
+        usage.append(path)?;
+    }
+    if let Some(status) = status
+        && !status.success()
+    {
+        // A watched script is run again after the next change, so shpack keeps going
+        if matches.get_flag("watch") {
+            return Err(eyre!("the bundle exited with {}", status));
+        }
+        process::exit(run::exit_code(status));
+    }
+    if warnings > 0 {
+        return Err(eyre!(
+            "the bundle parses, but shpack gave {} warning{}",
+            warnings,
+            if warnings == 1 { "" } else { "s" }
+        ));
+    }
+
+    return Ok(inputs);
+}
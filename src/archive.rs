@@ -0,0 +1,61 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use color_eyre::Result;
+use color_eyre::eyre::WrapErr;
+use tar::Builder;
+use tar::HeaderMode;
+use tempfile::NamedTempFile;
+
+/// The files and directories under `dir`, relative to it and sorted, so the same tree always
+/// packs into the same archive
+pub fn entries(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut entries = vec![];
+    walk(dir, Path::new(""), &mut entries)?;
+    return Ok(entries);
+}
+
+fn walk(root: &Path, relative: &Path, entries: &mut Vec<PathBuf>) -> Result<()> {
+    let dir = root.join(relative);
+    let mut children = fs::read_dir(&dir)
+        .wrap_err_with(|| format!("failed to read {}", dir.display()))?
+        .map(|entry| entry.map(|entry| relative.join(entry.file_name())))
+        .collect::<Result<Vec<_>, _>>()?;
+    children.sort();
+    for child in children {
+        let is_dir = root.join(&child).is_dir();
+        entries.push(child.clone());
+        if is_dir {
+            walk(root, &child, entries)?;
+        }
+    }
+    return Ok(());
+}
+
+/// Packs `entries` of `dir` into an uncompressed tar file. Owners and timestamps are left
+/// out, so the archive only changes when the files do.
+pub fn pack(dir: &Path, entries: &[PathBuf]) -> Result<NamedTempFile> {
+    let spool = NamedTempFile::new()?;
+    let mut builder = Builder::new(spool.reopen()?);
+    builder.mode(HeaderMode::Deterministic);
+    append(&mut builder, dir, entries)?;
+    builder.into_inner()?.sync_all()?;
+    return Ok(spool);
+}
+
+/// Adds `entries` of `dir` to `builder`, under their paths relative to `dir`
+pub fn append<W: Write>(builder: &mut Builder<W>, dir: &Path, entries: &[PathBuf]) -> Result<()> {
+    for entry in entries {
+        let path = dir.join(entry);
+        if path.is_dir() {
+            builder.append_dir(entry, &path)?;
+        } else {
+            builder
+                .append_path_with_name(&path, entry)
+                .wrap_err_with(|| format!("failed to archive {}", path.display()))?;
+        }
+    }
+    return Ok(());
+}
@@ -0,0 +1,254 @@
+use std::collections::BTreeMap;
+use std::io::BufRead;
+use std::io::Read;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::cli::CompletionFile;
+use crate::library::Library;
+use crate::options::BundleOptions;
+use crate::self_extract::SelfExtract;
+use crate::source_cache::SourceCache;
+use crate::{Bundle, Bundler};
+
+/// The version of shpack answering requests, which clients can check responses against
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// A request to bundle one entry script
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Request {
+    /// Echoed back in the response so clients can match them up
+    #[serde(default)]
+    pub id: serde_json::Value,
+    pub entry: PathBuf,
+    /// The directory bundled paths are relative to, defaults to the entry's directory
+    pub root: Option<PathBuf>,
+    #[serde(default)]
+    pub options: BundleOptions,
+    /// The working directory for `# build: inline` commands, defaults to the server's
+    pub cwd: Option<PathBuf>,
+    /// The environment of `# build: inline` commands, defaults to the server's
+    pub env: Option<BTreeMap<String, String>>,
+    /// The version of shpack the request is meant for. A server of another version answers
+    /// with an error rather than building it.
+    pub version: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Response {
+    pub id: serde_json::Value,
+    /// The version of shpack that answered
+    #[serde(default)]
+    pub version: String,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bundle: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<PathBuf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+    #[serde(default)]
+    pub ran_commands: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub order: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub required_tools: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub libraries: Vec<Library>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exported_functions: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub self_extract: Option<SelfExtract>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub completion_files: Vec<CompletionFile>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Request {
+    pub fn bundle(&self, source_cache: Option<&Arc<SourceCache>>) -> Result<Bundle> {
+        let entry = self
+            .entry
+            .canonicalize()
+            .wrap_err_with(|| format!("entry not found: {}", self.entry.display()))?;
+        let cwd = entry.parent().expect("entry path should have parent");
+        let root = match &self.root {
+            Some(root) => root.canonicalize()?,
+            None => cwd.to_owned(),
+        };
+
+        let mut builder = self.options.apply(Bundler::builder().root(root))?;
+        if let Some(cache) = source_cache {
+            builder = builder.source_cache(cache.clone());
+        }
+        if let Some(dir) = &self.cwd {
+            builder = builder.command_dir(dir);
+        }
+        if let Some(env) = &self.env {
+            builder = builder.command_env(env.clone());
+        }
+        return builder.build()?.bundle_file(&entry);
+    }
+}
+
+/// Handles one framed request, always producing a response
+pub fn respond(message: &[u8], source_cache: Option<&Arc<SourceCache>>) -> Response {
+    let request: Request = match serde_json::from_slice(message) {
+        Ok(request) => request,
+        Err(e) => return Response::error(serde_json::Value::Null, eyre!("invalid request: {}", e)),
+    };
+    if let Some(version) = &request.version
+        && version != VERSION
+    {
+        return Response::error(
+            request.id,
+            eyre!(
+                "the request is for shpack {}, but this is {}",
+                version,
+                VERSION
+            ),
+        );
+    }
+
+    let bundle = match request.bundle(source_cache) {
+        Ok(bundle) => bundle,
+        Err(e) => return Response::error(request.id, e),
+    };
+    let sources = bundle.sources.clone();
+    let warnings = bundle.warnings.clone();
+    let notes = bundle.notes.clone();
+    let ran_commands = bundle.ran_commands;
+    let order = bundle.order.clone();
+    let required_tools = bundle.required_tools.clone();
+    let libraries = bundle.libraries.clone();
+    let exported_functions = bundle.exported_functions.clone();
+    let self_extract = bundle.self_extract.clone();
+    let completion_files = bundle.completion_files.clone();
+    return match bundle.into_string() {
+        Ok(content) => Response {
+            id: request.id,
+            version: String::from(VERSION),
+            ok: true,
+            bundle: Some(content),
+            sources,
+            warnings,
+            notes,
+            ran_commands,
+            order,
+            required_tools,
+            libraries,
+            exported_functions,
+            self_extract,
+            completion_files,
+            error: None,
+        },
+        Err(e) => Response::error(request.id, e),
+    };
+}
+
+impl Response {
+    fn error(id: serde_json::Value, error: color_eyre::Report) -> Self {
+        Response {
+            id,
+            version: String::from(VERSION),
+            ok: false,
+            bundle: None,
+            sources: vec![],
+            warnings: vec![],
+            notes: vec![],
+            ran_commands: false,
+            order: vec![],
+            required_tools: BTreeMap::new(),
+            libraries: vec![],
+            exported_functions: vec![],
+            self_extract: None,
+            completion_files: vec![],
+            error: Some(format!("{:#}", error)),
+        }
+    }
+
+    /// Turns a response back into the bundle, or the error, that produced it
+    pub fn into_bundle(self) -> Result<Bundle> {
+        if !self.ok {
+            return Err(eyre!(self.error.unwrap_or_default()));
+        }
+        return Ok(Bundle {
+            content: self.bundle.unwrap_or_default(),
+            payloads: vec![],
+            sources: self.sources,
+            fragments: vec![],
+            warnings: self.warnings,
+            notes: self.notes,
+            ran_commands: self.ran_commands,
+            required_tools: self.required_tools,
+            includes: vec![],
+            order: self.order,
+            commands: vec![],
+            libraries: self.libraries,
+            exported_functions: self.exported_functions,
+            self_extract: self.self_extract,
+            completion_files: self.completion_files,
+            listings: vec![],
+        });
+    }
+}
+
+/// Serves requests from stdin until it's closed, writing a response to stdout for each one.
+///
+/// Every message in either direction is framed as its length in bytes, written in decimal on a
+/// line of its own, followed by that many bytes of JSON.
+pub fn serve_stdin() -> Result<()> {
+    let mut input = std::io::stdin().lock();
+    let mut output = std::io::stdout().lock();
+    let source_cache = Arc::new(SourceCache::default());
+    while let Some(message) = read_message(&mut input)? {
+        let response = serde_json::to_vec(&respond(&message, Some(&source_cache)))?;
+        write_message(&mut output, &response)?;
+    }
+    return Ok(());
+}
+
+/// The largest message `read_message` accepts, which is checked before the message is read
+/// so a bad length header can't make it allocate more
+pub const MAX_MESSAGE_LEN: usize = 1 << 30;
+
+/// Reads one framed message, or None at the end of the input
+pub fn read_message(input: &mut impl BufRead) -> Result<Option<Vec<u8>>> {
+    let mut header = String::new();
+    // A length never needs more digits than this, so a header without a newline can't grow
+    // without bound either
+    if input.by_ref().take(32).read_line(&mut header)? == 0 {
+        return Ok(None);
+    }
+    let len: usize = header
+        .trim()
+        .parse()
+        .wrap_err_with(|| format!("invalid message length: \"{}\"", header.trim()))?;
+    if len > MAX_MESSAGE_LEN {
+        return Err(eyre!(
+            "message of {} bytes is larger than the limit of {} bytes",
+            len,
+            MAX_MESSAGE_LEN
+        ));
+    }
+
+    let mut message = vec![0; len];
+    input.read_exact(&mut message)?;
+    return Ok(Some(message));
+}
+
+pub fn write_message(output: &mut impl Write, message: &[u8]) -> Result<()> {
+    writeln!(output, "{}", message.len())?;
+    output.write_all(message)?;
+    output.flush()?;
+    return Ok(());
+}
@@ -0,0 +1,421 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use color_eyre::Result;
+use color_eyre::eyre::WrapErr;
+
+use crate::chaos::Chaos;
+use crate::deadline::Deadline;
+use crate::jobs::Jobs;
+use crate::source_cache::SourceCache;
+use crate::trace::Trace;
+use crate::{
+    Bundler, Compression, Defines, Encoding, Format, Hardening, Markers, Metadata, SecretsPolicy,
+    Strip, Target,
+};
+
+/// Which shabang a bundle gets
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ShebangPolicy {
+    /// The entry script's, which the shabangs of the files it bundles have to match
+    #[default]
+    Entry,
+    /// This line, like `#!/usr/bin/env bash`, whatever the bundled files start with
+    Replace(String),
+}
+
+/// Configures a [`Bundler`]. Every option has the default the command line uses when its flag
+/// isn't given.
+pub struct BundlerBuilder {
+    root: Option<PathBuf>,
+    trace: Option<PathBuf>,
+    bundler: Bundler,
+}
+
+impl BundlerBuilder {
+    pub(crate) fn new() -> Self {
+        return BundlerBuilder {
+            root: None,
+            trace: None,
+            bundler: Bundler::unrooted(),
+        };
+    }
+
+    /// The directory sourced files must be in, which paths in the bundle are relative to.
+    /// Defaults to the current directory.
+    pub fn root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.root = Some(root.into());
+        return self;
+    }
+
+    /// Directories searched, in order, for sourced files that don't exist relative to the
+    /// sourcing file
+    pub fn include_paths(mut self, paths: Vec<PathBuf>) -> Self {
+        self.bundler.include_paths = paths;
+        return self;
+    }
+
+    /// Orders function definitions by their first use in a recorded trace, and lazily loads
+    /// the files it never uses
+    pub fn order_by_trace(mut self, trace: impl Into<PathBuf>) -> Self {
+        self.trace = Some(trace.into());
+        return self;
+    }
+
+    /// Spools `# build: inline` outputs of at least this many bytes to disk
+    pub fn stream_threshold(mut self, bytes: u64) -> Self {
+        self.bundler.stream_threshold = bytes;
+        return self;
+    }
+
+    /// Keeps bundled files and `# build: inline` outputs on disk once about this many bytes
+    /// are held in memory
+    pub fn memory_cap(mut self, bytes: u64) -> Self {
+        self.bundler.memory_cap = Some(bytes);
+        return self;
+    }
+
+    /// How many `# build: inline` commands run at once. Defaults to the number of CPUs, and 1
+    /// runs them one after another.
+    pub fn jobs(mut self, limit: usize) -> Self {
+        self.bundler.jobs = Jobs::new(limit);
+        return self;
+    }
+
+    /// How many times a failed `# build: inline idempotent` command is run again. Defaults to
+    /// 0, which never runs commands again.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.bundler.retries = retries;
+        return self;
+    }
+
+    /// How long to wait before the first retry, which doubles for each one after it.
+    /// Defaults to a second.
+    pub fn retry_backoff(mut self, backoff: Duration) -> Self {
+        self.bundler.retry_backoff = backoff;
+        return self;
+    }
+
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.bundler.compression = compression;
+        return self;
+    }
+
+    /// Only compresses `# build: inline` outputs of at least this many bytes
+    pub fn compress_threshold(mut self, bytes: u64) -> Self {
+        self.bundler.compress_threshold = bytes;
+        return self;
+    }
+
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.bundler.encoding = encoding;
+        return self;
+    }
+
+    /// The kind of system the bundle runs on, which sets the `OS` define
+    pub fn target(mut self, target: Target) -> Self {
+        self.bundler.target = Some(target);
+        return self;
+    }
+
+    /// Where the bundle extracts `# build: inline as=file` outputs at runtime
+    pub fn runtime_tmpdir(mut self, dir: impl Into<String>) -> Self {
+        self.bundler.runtime.tmpdir = Some(dir.into());
+        return self;
+    }
+
+    /// The shabang the bundle gets instead of the entry script's, which lets the shabangs of
+    /// the files it bundles differ. Short for [`ShebangPolicy::Replace`].
+    pub fn shabang(self, shabang: impl Into<String>) -> Self {
+        return self.shebang_policy(ShebangPolicy::Replace(shabang.into()));
+    }
+
+    /// Which shabang the bundle gets, and whether the files it bundles have to agree with it
+    pub fn shebang_policy(mut self, policy: ShebangPolicy) -> Self {
+        self.bundler.shabang_override = match policy {
+            ShebangPolicy::Entry => None,
+            ShebangPolicy::Replace(shabang) => Some(shabang),
+        };
+        return self;
+    }
+
+    /// What generated functions and variables are named with
+    pub fn symbol_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.bundler.runtime.prefix = prefix.into();
+        return self;
+    }
+
+    /// Values that replace those of `# build: env` assignments
+    pub fn defines(mut self, defines: Defines) -> Self {
+        self.bundler.defines = defines;
+        return self;
+    }
+
+    /// What `{{name}}`, `{{version}}` and `{{description}}` are replaced with
+    pub fn metadata(mut self, metadata: Metadata) -> Self {
+        self.bundler.metadata = metadata;
+        return self;
+    }
+
+    /// Removes functions nothing calls
+    pub fn tree_shake(mut self, tree_shake: bool) -> Self {
+        self.bundler.tree_shake = tree_shake;
+        return self;
+    }
+
+    /// Globs of functions tree-shaking keeps, for functions only called indirectly
+    pub fn keep(mut self, globs: Vec<String>) -> Self {
+        self.bundler.keep = globs;
+        return self;
+    }
+
+    /// Functions whose tree-shaking decision is explained in the notes
+    pub fn why_kept(mut self, functions: Vec<String>) -> Self {
+        self.bundler.why_kept = functions;
+        return self;
+    }
+
+    /// Replaces GNU-only tool options with helpers that also work on BSD systems
+    pub fn portable(mut self, portable: bool) -> Self {
+        self.bundler.portable = portable;
+        return self;
+    }
+
+    /// Adds the logging library to the bundle
+    pub fn with_logging(mut self, with_logging: bool) -> Self {
+        self.bundler.with_logging = with_logging;
+        return self;
+    }
+
+    /// Turns `echo "DEBUG: ..."` and the like into calls to the logging library
+    pub fn rewrite_log_echoes(mut self, rewrite: bool) -> Self {
+        self.bundler.rewrite_log_echoes = rewrite;
+        return self;
+    }
+
+    /// Adds a helper printing the version, commit and build date
+    pub fn version_stamp(mut self, version_stamp: bool) -> Self {
+        self.bundler.version_stamp = version_stamp;
+        return self;
+    }
+
+    /// Measures applied before anything else in the bundle runs
+    pub fn harden(mut self, measures: Vec<Hardening>) -> Self {
+        self.bundler.harden = measures;
+        return self;
+    }
+
+    /// Globs of sourced files, relative to the root, that may use the reserved prefix and
+    /// aren't checked for top-level `cd` and `exec` or credentials
+    pub fn exempt(mut self, globs: Vec<String>) -> Self {
+        self.bundler.exempt = globs;
+        return self;
+    }
+
+    /// Globs of paths, as they're written in `source` commands, that are left to be sourced at
+    /// runtime
+    pub fn runtime_sources(mut self, globs: Vec<String>) -> Self {
+        self.bundler.runtime_sources = globs;
+        return self;
+    }
+
+    /// Globs of sourced files, relative to the root or absolute, whose `source` commands are
+    /// left as they are rather than inlined
+    pub fn external(mut self, globs: Vec<String>) -> Self {
+        self.bundler.external = globs;
+        return self;
+    }
+
+    /// Commands besides `source` and `.` that source a file
+    pub fn source_aliases(mut self, names: Vec<String>) -> Self {
+        self.bundler.source_aliases = names;
+        return self;
+    }
+
+    /// Variables besides `SCRIPT_DIR` that hold the entry script's directory, so sourcing
+    /// `$NAME/lib.sh` bundles `lib.sh` from there
+    pub fn script_dir_variables(mut self, names: Vec<String>) -> Self {
+        self.bundler.script_dir_variables = names;
+        return self;
+    }
+
+    /// Removes the definitions of aliases for `source`
+    pub fn strip_source_aliases(mut self, strip: bool) -> Self {
+        self.bundler.strip_source_aliases = strip;
+        return self;
+    }
+
+    /// Fails when two versions of the same library are bundled, rather than warning
+    pub fn deny_version_conflicts(mut self, deny: bool) -> Self {
+        self.bundler.deny_version_conflicts = deny;
+        return self;
+    }
+
+    /// What happens when the bundle looks like it contains a credential, like an API token
+    /// a `# build: inline` command printed
+    pub fn secrets(mut self, policy: SecretsPolicy) -> Self {
+        self.bundler.secrets = policy;
+        return self;
+    }
+
+    /// Whether the bundle is written as a script or a self-extracting archive
+    pub fn format(mut self, format: Format) -> Self {
+        self.bundler.format = format;
+        return self;
+    }
+
+    /// A directory whose files a self-extracting bundle's archive holds too, which the
+    /// bundle finds next to itself
+    pub fn archive_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.bundler.archive_dir = Some(dir.into());
+        return self;
+    }
+
+    /// Fails on sources with absolute paths that aren't explicitly allowed
+    pub fn deny_absolute_sources(mut self, deny: bool) -> Self {
+        self.bundler.deny_absolute_sources = deny;
+        return self;
+    }
+
+    /// Bundles sourced files of 2 MiB or more with a warning, rather than failing
+    pub fn allow_large_include(mut self, allow: bool) -> Self {
+        self.bundler.allow_large_include = allow;
+        return self;
+    }
+
+    /// Breaks cycles of files that source each other, rather than failing, by skipping the
+    /// `source` command that closes the cycle
+    pub fn allow_circular(mut self, allow: bool) -> Self {
+        self.bundler.allow_circular = allow;
+        return self;
+    }
+
+    /// Puts every inlined file at the top of the bundle, after the files it sources, rather
+    /// than where it's sourced
+    pub fn topological_includes(mut self, topological: bool) -> Self {
+        self.bundler.topological_includes = topological;
+        return self;
+    }
+
+    /// Files inlined before the entry script's code, which have to be in the root or an
+    /// include path
+    pub fn prelude(mut self, paths: Vec<PathBuf>) -> Self {
+        self.bundler.prelude = paths;
+        return self;
+    }
+
+    /// Files inlined after the entry script's code
+    pub fn epilogue(mut self, paths: Vec<PathBuf>) -> Self {
+        self.bundler.epilogue = paths;
+        return self;
+    }
+
+    /// WebAssembly modules that rewrite each file, in order, before it's bundled
+    pub fn transforms(mut self, paths: Vec<PathBuf>) -> Self {
+        self.bundler.transforms = paths;
+        return self;
+    }
+
+    /// What's stripped from every inlined file, unless its `# shpack: inline` directive says
+    /// otherwise
+    pub fn strip(mut self, strip: Strip) -> Self {
+        self.bundler.include_options.strip = strip;
+        return self;
+    }
+
+    /// The comments around each inlined file
+    pub fn markers(mut self, markers: Markers) -> Self {
+        self.bundler.markers = markers;
+        return self;
+    }
+
+    /// Runs `# build: inline` commands, which is the default. When false, they're left in the
+    /// bundle to run at runtime.
+    pub fn run_commands(mut self, run: bool) -> Self {
+        self.bundler.run_commands = run;
+        return self;
+    }
+
+    /// Strips comments, blank lines and the markers around inlined files from the whole
+    /// bundle, leaving the shabang
+    pub fn minify(mut self, minify: bool) -> Self {
+        self.bundler.minify = minify;
+        return self;
+    }
+
+    /// Adds an `ERR` trap that prints the source file and line a failing command came from
+    pub fn inject_trace(mut self, inject: bool) -> Self {
+        self.bundler.inject_trace = inject;
+        return self;
+    }
+
+    /// What the entry script is called in the `ERR` trap's messages. [`Bundler::bundle_file`]
+    /// names it after its path.
+    pub fn entry_name(mut self, name: impl Into<String>) -> Self {
+        self.bundler.entry_name = name.into();
+        return self;
+    }
+
+    /// Records the arguments shpack was run with in a comment after the shabang, so the
+    /// bundle can be rebuilt the same way
+    pub fn provenance(mut self, args: Vec<String>) -> Self {
+        self.bundler.provenance = Some(args);
+        return self;
+    }
+
+    /// Parsed sources kept between bundles, so files that haven't changed aren't parsed again
+    pub fn source_cache(mut self, cache: Arc<SourceCache>) -> Self {
+        self.bundler.source_cache = Some(cache);
+        return self;
+    }
+
+    /// The working directory of `# build: inline` commands. Defaults to the current directory.
+    pub fn command_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.bundler.command_dir = Some(dir.into());
+        return self;
+    }
+
+    /// The environment of `# build: inline` commands, in place of this process's
+    pub fn command_env(mut self, env: BTreeMap<String, String>) -> Self {
+        self.bundler.command_env = Some(env);
+        return self;
+    }
+
+    /// Writes progress events to stderr as files are inlined
+    pub fn progress(mut self, progress: bool) -> Self {
+        self.bundler.progress = progress;
+        return self;
+    }
+
+    #[doc(hidden)]
+    pub fn chaos(mut self, chaos: Chaos) -> Self {
+        self.bundler.chaos = chaos;
+        return self;
+    }
+
+    /// When the build fails if it isn't done, killing the `# build: inline` commands that are
+    /// still running
+    pub fn deadline(mut self, deadline: Deadline) -> Self {
+        self.bundler.deadline = Some(deadline);
+        return self;
+    }
+
+    /// Creates the bundler, loading the trace given to [`BundlerBuilder::order_by_trace`]
+    pub fn build(self) -> Result<Bundler> {
+        let mut bundler = self.bundler;
+        let root = match self.root {
+            Some(root) => root,
+            None => env::current_dir()?,
+        };
+        bundler.path_relative_to = root
+            .canonicalize()
+            .wrap_err_with(|| format!("the root {} doesn't exist", root.display()))?;
+        if let Some(trace) = self.trace {
+            bundler.trace = Some(Trace::load(&trace)?);
+        }
+        return Ok(bundler);
+    }
+}
@@ -0,0 +1,111 @@
+use std::fs;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::path::PathBuf;
+
+use color_eyre::Result;
+use sha2::{Digest, Sha256};
+use shpack::Listing;
+use shpack::fs_util::{lock_exclusive, write_atomic};
+
+/// An on-disk record of each package's inputs as of its last build, used to skip rebuilding
+/// packages whose sources haven't changed and whose globs still match the same files.
+///
+/// Several shpack processes may share a cache, so records are replaced atomically and each
+/// package's build should happen while holding its [`Cache::lock`].
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new(dir: PathBuf) -> Self {
+        Cache { dir }
+    }
+
+    pub fn dir(&self) -> &Path {
+        return &self.dir;
+    }
+
+    fn fingerprint_path(&self, package: &str) -> PathBuf {
+        return self.dir.join("fingerprints").join(package);
+    }
+
+    /// Waits for exclusive access to the package's record and output, which lasts until the
+    /// returned file is dropped
+    pub fn lock(&self, package: &str) -> Result<File> {
+        return lock_exclusive(
+            &self.dir.join("locks").join(package),
+            &format!("package `{}`", package),
+        );
+    }
+
+    /// Returns true if `out` exists and the package was last built with the same `key` from
+    /// inputs that haven't changed since
+    pub fn is_fresh(&self, package: &str, key: &str, out: &Path) -> Result<bool> {
+        if !out.exists() {
+            return Ok(false);
+        }
+
+        let recorded = match fs::read_to_string(self.fingerprint_path(package)) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut lines = recorded.lines();
+        if lines.next() != Some(key) {
+            return Ok(false);
+        }
+        for line in lines {
+            if let Some(listing) = line.strip_prefix("listing ") {
+                match serde_json::from_str::<Listing>(listing) {
+                    Ok(listing) if listing.is_current() => continue,
+                    _ => return Ok(false),
+                }
+            }
+            let Some((hash, path)) = line.split_once(' ') else {
+                return Ok(false);
+            };
+            match hash_file(Path::new(path)) {
+                Ok(current) if current == hash => {}
+                _ => return Ok(false),
+            }
+        }
+
+        return Ok(true);
+    }
+
+    /// Records the inputs of a successful build, and the directory listings that found some
+    /// of them
+    pub fn record(
+        &self,
+        package: &str,
+        key: &str,
+        inputs: &[PathBuf],
+        listings: &[Listing],
+    ) -> Result<()> {
+        let mut text = format!("{}\n", key);
+        for input in inputs {
+            text.push_str(&format!("{} {}\n", hash_file(input)?, input.display()));
+        }
+        for listing in listings {
+            text.push_str(&format!("listing {}\n", serde_json::to_string(listing)?));
+        }
+
+        return write_atomic(&self.fingerprint_path(package), text.as_bytes());
+    }
+
+    /// Drops the package's record so its next build always runs
+    pub fn forget(&self, package: &str) -> Result<()> {
+        return match fs::remove_file(self.fingerprint_path(package)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        };
+    }
+}
+
+fn hash_file(path: &Path) -> Result<String> {
+    let bytes = fs::read(path)?;
+    return Ok(format!("{:x}", Sha256::digest(&bytes)));
+}
@@ -0,0 +1,42 @@
+use color_eyre::Result;
+use tree_sitter::{Node, Tree};
+
+use crate::GetText;
+use crate::directive;
+
+/// Finds the `cd`, `pushd` and `popd` commands that run in the shell sourcing a file, rather
+/// than in a function or a subshell, so they change the working directory for the code
+/// bundled after it. Returns the 0-based line and text of each, leaving out those below a
+/// `# shpack: allow-cd` directive.
+pub fn top_level(tree: &Tree, source: &str) -> Result<Vec<(usize, String)>> {
+    let mut found = vec![];
+    visit(tree.root_node(), source, &mut found)?;
+    return Ok(found);
+}
+
+fn visit(node: Node, source: &str, found: &mut Vec<(usize, String)>) -> Result<()> {
+    match node.kind() {
+        // Their commands don't run when the file is sourced, or don't run in its shell
+        "function_definition"
+        | "subshell"
+        | "command_substitution"
+        | "process_substitution"
+        | "pipeline" => return Ok(()),
+        "command" => {
+            if node
+                .child_by_field_name("name")
+                .is_some_and(|name| matches!(name.text(source), "cd" | "pushd" | "popd"))
+                && !directive::is_above(node, source, "allow-cd")?
+            {
+                found.push((node.start_position().row, node.text(source).to_string()));
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        visit(child, source, found)?;
+    }
+    return Ok(());
+}
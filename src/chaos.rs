@@ -0,0 +1,47 @@
+use std::thread;
+use std::time::Duration;
+
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+
+/// Deliberate failures and delays, for testing the automation that runs shpack, like CI
+/// wrappers with retries and alerts
+#[derive(Debug, Clone, Default)]
+pub struct Chaos {
+    /// Fail once this many files have been read
+    pub fail_after: Option<u64>,
+    /// How long to wait before reading each file
+    pub delay: Option<Duration>,
+    files: u64,
+}
+
+impl Chaos {
+    /// Fails the build once `fail_after` files have been read, and waits `delay` before
+    /// reading each one
+    pub fn new(fail_after: Option<u64>, delay: Option<Duration>) -> Self {
+        return Chaos {
+            fail_after,
+            delay,
+            files: 0,
+        };
+    }
+
+    pub fn is_active(&self) -> bool {
+        return self.fail_after.is_some() || self.delay.is_some();
+    }
+
+    /// Called before each file is read
+    pub fn file(&mut self) -> Result<()> {
+        if let Some(delay) = self.delay {
+            thread::sleep(delay);
+        }
+        if self.fail_after.is_some_and(|n| self.files >= n) {
+            return Err(eyre!(
+                "simulated failure after reading {} file(s) (--fail-after)",
+                self.files
+            ));
+        }
+        self.files += 1;
+        return Ok(());
+    }
+}
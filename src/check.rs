@@ -0,0 +1,93 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+use shpack::Bundle;
+
+/// Checks that bash can parse the bundle, for `shpack check`
+pub fn syntax(bundle: &Bundle) -> Result<()> {
+    let mut file = tempfile::NamedTempFile::new()?;
+    bundle.write_script_to(&mut file)?;
+    let output = Command::new("bash")
+        .arg("-n")
+        .arg(file.path())
+        .output()
+        .wrap_err("failed to run bash")?;
+    if !output.status.success() {
+        let path = file.path().to_string_lossy();
+        return Err(eyre!(
+            "bash can't parse the bundle:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+                .replace(path.as_ref(), "bundle")
+                .trim_end()
+        ));
+    }
+    return Ok(());
+}
+
+/// Checks that `path` holds exactly this bundle, describing the first difference if it doesn't
+pub fn up_to_date(bundle: &Bundle, path: &Path) -> Result<()> {
+    let mut content = vec![];
+    bundle.write_to(&mut content)?;
+    let existing = match fs::read(path) {
+        Ok(existing) => existing,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(eyre!(
+                "{} doesn't exist, so it's out of date",
+                path.display()
+            ));
+        }
+        Err(e) => {
+            return Err(e).wrap_err_with(|| format!("can't read {}", path.display()));
+        }
+    };
+    if existing == content {
+        return Ok(());
+    }
+
+    let existing = String::from_utf8_lossy(&existing);
+    let content = String::from_utf8_lossy(&content);
+    return Err(eyre!(
+        "{} is out of date:\n{}",
+        path.display(),
+        summary(&existing, &content)
+    ));
+}
+
+/// The first line that changed, and how long the file would be
+fn summary(before: &str, after: &str) -> String {
+    let show = |line: Option<&str>| match line {
+        Some(line) => line.to_string(),
+        None => String::from("(end of file)"),
+    };
+    let mut before_lines = before.lines();
+    let mut after_lines = after.lines();
+    let mut first = None;
+    for line in 1.. {
+        match (before_lines.next(), after_lines.next()) {
+            (Some(b), Some(a)) if a == b => continue,
+            (None, None) => break,
+            (b, a) => {
+                first = Some(format!(
+                    "  first difference on line {}:\n  - {}\n  + {}",
+                    line,
+                    show(b),
+                    show(a)
+                ));
+                break;
+            }
+        }
+    }
+    let first = first.unwrap_or(String::from(
+        "  the files differ only in their line endings",
+    ));
+    return format!(
+        "{}\n  the new bundle has {} lines, the existing one {}",
+        first,
+        after.lines().count(),
+        before.lines().count()
+    );
+}
@@ -0,0 +1,532 @@
+use std::path::Path;
+use std::path::PathBuf;
+
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::data;
+use crate::shell;
+
+/// A standalone completion file asked for with `completion=`, which is only written along
+/// with the bundle
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CompletionFile {
+    pub path: PathBuf,
+    pub content: String,
+}
+
+/// The command line of a bundled tool, read from a TOML or JSON file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CliSpec {
+    /// The name the tool is run as
+    pub name: String,
+    /// Shown in `--help`
+    pub description: Option<String>,
+    #[serde(default)]
+    pub flags: Vec<Flag>,
+    #[serde(default)]
+    pub subcommands: Vec<Subcommand>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Subcommand {
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub flags: Vec<Flag>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Flag {
+    /// The name of the long form, without the dashes
+    pub long: String,
+    /// A single letter for the short form
+    pub short: Option<String>,
+    /// What the flag's value is called, like `FILE`, if it takes one. Values named like files
+    /// or directories complete as such.
+    pub value: Option<String>,
+    /// The only values the flag accepts, which are completed
+    #[serde(default)]
+    pub values: Vec<String>,
+    pub description: Option<String>,
+    /// What the flag's variable is set to when it isn't given, for flags that take a value
+    pub default: Option<String>,
+    /// The variable the parser sets, which defaults to the long form with dashes replaced by
+    /// underscores
+    pub variable: Option<String>,
+}
+
+impl Flag {
+    /// The flag's forms, like `--out -o`
+    fn forms(&self) -> Vec<String> {
+        let mut forms = vec![format!("--{}", self.long)];
+        forms.extend(self.short.iter().map(|short| format!("-{}", short)));
+        return forms;
+    }
+
+    fn takes_value(&self) -> bool {
+        return self.value.is_some() || !self.values.is_empty();
+    }
+
+    /// The variable the generated parser sets
+    fn variable(&self) -> String {
+        return match &self.variable {
+            Some(variable) => variable.clone(),
+            None => self.long.replace(['-', '.'], "_"),
+        };
+    }
+
+    /// How the flag is shown in `--help`, like `-o, --out FILE`
+    fn usage(&self) -> String {
+        let mut usage = match &self.short {
+            Some(short) => format!("-{}, --{}", short, self.long),
+            None => format!("    --{}", self.long),
+        };
+        if let Some(value) = &self.value {
+            usage.push_str(&format!(" {}", value));
+        } else if !self.values.is_empty() {
+            usage.push_str(&format!(" <{}>", self.values.join("|")));
+        }
+        return usage;
+    }
+
+    fn help(&self) -> String {
+        let mut help = self.description.clone().unwrap_or_default();
+        if let Some(default) = &self.default {
+            if !help.is_empty() {
+                help.push(' ');
+            }
+            help.push_str(&format!("(default: {})", default));
+        }
+        return help;
+    }
+
+    /// The `compgen` options that complete the flag's value
+    fn value_completion(&self) -> Option<String> {
+        if !self.values.is_empty() {
+            return Some(format!("-W {}", shell::quote(&self.values.join(" "))));
+        }
+        let value = self.value.as_deref()?.to_ascii_uppercase();
+        return if value.contains("DIR") {
+            Some(String::from("-d"))
+        } else if value.contains("FILE") || value.contains("PATH") {
+            Some(String::from("-f"))
+        } else {
+            None
+        };
+    }
+}
+
+/// Whether `name` is safe to use as a flag, subcommand or value in generated code, which
+/// `compgen -W` would otherwise expand
+fn is_word(name: &str, extra: &str) -> bool {
+    return !name.is_empty()
+        && !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c) || extra.contains(c));
+}
+
+impl CliSpec {
+    pub fn load(path: &Path) -> Result<CliSpec> {
+        let spec: CliSpec = serde_json::from_value(data::load(path)?)
+            .wrap_err_with(|| format!("invalid CLI spec {}", path.display()))?;
+        spec.validate()
+            .wrap_err_with(|| format!("invalid CLI spec {}", path.display()))?;
+        return Ok(spec);
+    }
+
+    fn validate(&self) -> Result<()> {
+        if !is_word(&self.name, "") {
+            return Err(eyre!("`{}` isn't a valid command name", self.name));
+        }
+        let mut flag_lists = vec![&self.flags];
+        for subcommand in &self.subcommands {
+            if !is_word(&subcommand.name, "") {
+                return Err(eyre!("`{}` isn't a valid subcommand name", subcommand.name));
+            }
+            flag_lists.push(&subcommand.flags);
+            // Global flags are accepted after a subcommand too, so they can't share forms
+            let mut forms = vec![String::from("--help"), String::from("-h")];
+            for flag in self.flags.iter().chain(&subcommand.flags) {
+                if let Some(form) = flag.forms().into_iter().find(|form| forms.contains(form)) {
+                    return Err(eyre!("{} is defined twice for `{}`", form, subcommand.name));
+                }
+                forms.extend(flag.forms());
+            }
+        }
+        let mut forms = vec![String::from("--help"), String::from("-h")];
+        for flag in &self.flags {
+            if let Some(form) = flag.forms().into_iter().find(|form| forms.contains(form)) {
+                return Err(eyre!("{} is defined twice", form));
+            }
+            forms.extend(flag.forms());
+        }
+        for flag in flag_lists.into_iter().flatten() {
+            if !is_word(&flag.long, "") {
+                return Err(eyre!("`{}` isn't a valid flag name", flag.long));
+            }
+            if let Some(short) = &flag.short
+                && (short.len() != 1 || !short.chars().all(|c| c.is_ascii_alphanumeric()))
+            {
+                return Err(eyre!(
+                    "the short form of --{} must be a single letter or digit, found `{}`",
+                    flag.long,
+                    short
+                ));
+            }
+            if let Some(value) = flag.values.iter().find(|value| !is_word(value, ":/@+=,")) {
+                return Err(eyre!("`{}` isn't a valid value for --{}", value, flag.long));
+            }
+            let variable = flag.variable();
+            if !shell::is_identifier(&variable) || variable == "subcommand" {
+                return Err(eyre!(
+                    "--{} would set `{}`, which isn't a usable variable name, so it needs a \
+                     `variable`",
+                    flag.long,
+                    variable
+                ));
+            }
+            if let Some(default) = &flag.default {
+                if !flag.takes_value() {
+                    return Err(eyre!(
+                        "--{} doesn't take a value, so it can't have a default",
+                        flag.long
+                    ));
+                }
+                if !flag.values.is_empty() && !flag.values.contains(default) {
+                    return Err(eyre!(
+                        "the default of --{} must be one of its values, found `{}`",
+                        flag.long,
+                        default
+                    ));
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    /// The name of the generated completion function
+    fn completion_function(&self, prefix: &str) -> String {
+        return format!("{}complete_{}", prefix, self.name.replace(['-', '.'], "_"));
+    }
+
+    /// A bash completion function for the tool, registered with `complete`. Running it in a
+    /// script is harmless, and sourcing it in an interactive shell enables completion.
+    pub fn completion(&self, prefix: &str) -> String {
+        let p = prefix;
+        let mut code = format!(
+            "{}() {{\n  \
+             local {p}cur=${{COMP_WORDS[COMP_CWORD]}} {p}prev=${{COMP_WORDS[COMP_CWORD-1]}}\n",
+            self.completion_function(prefix),
+            p = p
+        );
+        let top_level = |code: &mut String, indent: &str| {
+            let mut words: Vec<String> = self.subcommands.iter().map(|s| s.name.clone()).collect();
+            words.extend(self.flags.iter().flat_map(Flag::forms));
+            words.push(String::from("--help"));
+            complete_flags(code, &self.flags, &words, indent, p);
+        };
+        if self.subcommands.is_empty() {
+            top_level(&mut code, "  ");
+        } else {
+            let names: Vec<&str> = self.subcommands.iter().map(|s| s.name.as_str()).collect();
+            code.push_str(&format!(
+                "  local {p}sub= {p}i\n  \
+                 for (({p}i = 1; {p}i < COMP_CWORD; {p}i++)); do\n    \
+                 case ${{COMP_WORDS[{p}i]}} in\n      \
+                 {names}) {p}sub=${{COMP_WORDS[{p}i]}}; break ;;\n    \
+                 esac\n  \
+                 done\n  \
+                 case ${p}sub in\n",
+                p = p,
+                names = names.join("|")
+            ));
+            for subcommand in &self.subcommands {
+                code.push_str(&format!("    {})\n", subcommand.name));
+                let mut words: Vec<String> =
+                    subcommand.flags.iter().flat_map(Flag::forms).collect();
+                words.push(String::from("--help"));
+                complete_flags(&mut code, &subcommand.flags, &words, "      ", p);
+                code.push_str("      ;;\n");
+            }
+            code.push_str("    *)\n");
+            top_level(&mut code, "      ");
+            code.push_str("      ;;\n  esac\n");
+        }
+        code.push_str(&format!(
+            "}}\ncomplete -F {} {}\n",
+            self.completion_function(prefix),
+            self.name
+        ));
+        return code;
+    }
+
+    /// Whether any flag already uses `--version` or `-V`
+    fn has_version_flag(&self) -> bool {
+        return self
+            .flags
+            .iter()
+            .chain(self.subcommands.iter().flat_map(|s| &s.flags))
+            .flat_map(Flag::forms)
+            .any(|form| form == "--version" || form == "-V");
+    }
+
+    /// The lines `--help` prints, for the subcommand if there is one
+    fn help(&self, subcommand: Option<&Subcommand>, version: bool) -> Vec<String> {
+        let mut lines = vec![match subcommand {
+            Some(subcommand) => format!("Usage: {} {} [OPTIONS]", self.name, subcommand.name),
+            None if !self.subcommands.is_empty() => {
+                format!("Usage: {} [OPTIONS] <COMMAND>", self.name)
+            }
+            None => format!("Usage: {} [OPTIONS]", self.name),
+        }];
+        let description = match subcommand {
+            Some(subcommand) => &subcommand.description,
+            None => &self.description,
+        };
+        if let Some(description) = description {
+            lines.push(String::new());
+            lines.extend(description.lines().map(str::to_string));
+        }
+
+        let mut sections: Vec<(&str, Vec<(String, String)>)> = vec![];
+        if subcommand.is_none() && !self.subcommands.is_empty() {
+            let commands = self
+                .subcommands
+                .iter()
+                .map(|s| (s.name.clone(), s.description.clone().unwrap_or_default()))
+                .collect();
+            sections.push(("Commands:", commands));
+        }
+        let mut options: Vec<(String, String)> = subcommand
+            .into_iter()
+            .flat_map(|s| &s.flags)
+            .chain(&self.flags)
+            .map(|flag| (flag.usage(), flag.help()))
+            .collect();
+        if version {
+            options.push((String::from("-V, --version"), String::from("Print version")));
+        }
+        options.push((String::from("-h, --help"), String::from("Print help")));
+        sections.push(("Options:", options));
+
+        for (title, entries) in sections {
+            let width = entries
+                .iter()
+                .map(|(left, _)| left.len())
+                .max()
+                .unwrap_or(0);
+            lines.push(String::new());
+            lines.push(title.to_string());
+            for (left, help) in entries {
+                let line = format!("  {:width$}  {}", left, help, width = width);
+                lines.push(line.trim_end().to_string());
+            }
+        }
+        return lines;
+    }
+
+    /// Code that parses the script's arguments, setting a variable for each flag and leaving
+    /// the other arguments in `$@`. With subcommands, the chosen one is put in `$subcommand`.
+    /// With `version`, `--version` runs the `version` helper unless the spec defines it itself.
+    pub fn parser(&self, prefix: &str, version: bool) -> String {
+        let p = prefix;
+        let version = version && !self.has_version_flag();
+        let name = shell::quote(&self.name);
+        let help_lines = |subcommand| {
+            let lines: Vec<String> = self
+                .help(subcommand, version)
+                .iter()
+                .map(|line| shell::quote(line))
+                .collect();
+            format!("printf '%s\\n' {}", lines.join(" "))
+        };
+
+        let mut code = format!(
+            "{p}usage_error() {{\n  \
+             printf '%s: %s\\n' {name} \"$1\" >&2\n  \
+             printf \"Try '%s --help' for more information.\\n\" {name} >&2\n  \
+             exit 2\n\
+             }}\n\
+             {p}help() {{\n  \
+             case $1 in\n",
+            p = p,
+            name = name
+        );
+        for subcommand in &self.subcommands {
+            code.push_str(&format!(
+                "    {}) {} ;;\n",
+                subcommand.name,
+                help_lines(Some(subcommand))
+            ));
+        }
+        code.push_str(&format!("    *) {} ;;\n  esac\n}}\n", help_lines(None)));
+
+        let all_flags = self
+            .flags
+            .iter()
+            .chain(self.subcommands.iter().flat_map(|s| &s.flags));
+        let mut variables = vec![];
+        for flag in all_flags {
+            let variable = flag.variable();
+            if !variables.contains(&variable) {
+                code.push_str(&format!(
+                    "{}={}\n",
+                    variable,
+                    flag.default
+                        .as_deref()
+                        .map(shell::quote)
+                        .unwrap_or_default()
+                ));
+                variables.push(variable);
+            }
+        }
+        if !self.subcommands.is_empty() {
+            code.push_str("subcommand=\n");
+        }
+
+        code.push_str(&format!(
+            "{p}args=()\n\
+             while [ $# -gt 0 ]; do\n  \
+             {p}flag=$1 {p}value= {p}inline=\n  \
+             shift\n  \
+             case ${p}flag in\n    \
+             --) {p}args+=(\"$@\"); break ;;\n    \
+             --*=*) {p}value=${{{p}flag#*=}}; {p}flag=${{{p}flag%%=*}}; {p}inline=long ;;\n    \
+             -[!-]?*) {p}value=${{{p}flag:2}}; {p}flag=${{{p}flag:0:2}}; {p}inline=short ;;\n  \
+             esac\n",
+            p = p
+        ));
+        // With subcommands, flags are matched along with the subcommand they belong to
+        let scoped = !self.subcommands.is_empty();
+        let scope = |subcommand: &str| match scoped {
+            true => format!("{}:", subcommand),
+            false => String::new(),
+        };
+        code.push_str(&format!("  case {}${}flag in\n", scope("$subcommand"), p));
+        let mut flags: Vec<(&str, &Flag)> = self.flags.iter().map(|flag| ("*", flag)).collect();
+        for subcommand in &self.subcommands {
+            flags.extend(
+                subcommand
+                    .flags
+                    .iter()
+                    .map(|flag| (subcommand.name.as_str(), flag)),
+            );
+        }
+        for (subcommand, flag) in flags {
+            let patterns: Vec<String> = flag
+                .forms()
+                .iter()
+                .map(|form| format!("{}{}", scope(subcommand), form))
+                .collect();
+            code.push_str(&format!("    {})\n", patterns.join("|")));
+            if flag.takes_value() {
+                code.push_str(&format!(
+                    "      if [ -z \"${p}inline\" ]; then\n        \
+                     [ $# -gt 0 ] || {p}usage_error \"${p}flag needs a value\"\n        \
+                     {p}value=$1\n        \
+                     shift\n      \
+                     fi\n",
+                    p = p
+                ));
+                if !flag.values.is_empty() {
+                    let values: Vec<String> = flag.values.iter().map(|v| shell::quote(v)).collect();
+                    code.push_str(&format!(
+                        "      case ${p}value in\n        \
+                         {values}) ;;\n        \
+                         *) {p}usage_error \"invalid value '${p}value' for ${p}flag, expected one of: {list}\" ;;\n      \
+                         esac\n",
+                        p = p,
+                        values = values.join("|"),
+                        list = flag.values.join(", ")
+                    ));
+                }
+                code.push_str(&format!("      {}=${}value ;;\n", flag.variable(), p));
+            } else {
+                code.push_str(&format!(
+                    "      case ${p}inline in\n        \
+                     long) {p}usage_error \"${p}flag doesn't take a value\" ;;\n        \
+                     short) set -- \"-${p}value\" \"$@\" ;;\n      \
+                     esac\n      \
+                     {variable}=1 ;;\n",
+                    p = p,
+                    variable = flag.variable()
+                ));
+            }
+        }
+        if version {
+            code.push_str(&format!(
+                "    {any}--version|{any}-V) {p}version; exit 0 ;;\n",
+                p = p,
+                any = scope("*")
+            ));
+        }
+        code.push_str(&format!(
+            "    {any}--help|{any}-h) {p}help \"{sub}\"; exit 0 ;;\n    \
+             {any}-?*) {p}usage_error \"unknown option ${p}flag\" ;;\n",
+            p = p,
+            any = scope("*"),
+            sub = if scoped { "$subcommand" } else { "" }
+        ));
+        if !scoped {
+            code.push_str(&format!("    *) {p}args+=(\"${p}flag\") ;;\n", p = p));
+        } else {
+            let names: Vec<&str> = self.subcommands.iter().map(|s| s.name.as_str()).collect();
+            code.push_str(&format!(
+                "    :*)\n      \
+                 case ${p}flag in\n        \
+                 {names}) subcommand=${p}flag ;;\n        \
+                 *) {p}usage_error \"unknown command ${p}flag\" ;;\n      \
+                 esac ;;\n    \
+                 *) {p}args+=(\"${p}flag\") ;;\n",
+                p = p,
+                names = names.join("|")
+            ));
+        }
+        code.push_str(&format!(
+            "  esac\n\
+             done\n\
+             set -- ${{{p}args[@]+\"${{{p}args[@]}}\"}}\n\
+             unset {p}args {p}flag {p}value {p}inline\n",
+            p = p
+        ));
+        return code;
+    }
+}
+
+/// Completes the value of whichever of `flags` comes before the cursor, or otherwise `words`
+fn complete_flags(code: &mut String, flags: &[Flag], words: &[String], indent: &str, p: &str) {
+    let with_values: Vec<&Flag> = flags.iter().filter(|flag| flag.takes_value()).collect();
+    if !with_values.is_empty() {
+        code.push_str(&format!("{}case ${}prev in\n", indent, p));
+        for flag in with_values {
+            let reply = match flag.value_completion() {
+                Some(options) if options == "-f" || options == "-d" => format!(
+                    "compopt -o filenames 2>/dev/null; COMPREPLY=($(compgen {} -- \"${}cur\"))",
+                    options, p
+                ),
+                Some(options) => format!("COMPREPLY=($(compgen {} -- \"${}cur\"))", options, p),
+                None => String::from("COMPREPLY=()"),
+            };
+            code.push_str(&format!(
+                "{}  {}) {}; return ;;\n",
+                indent,
+                flag.forms().join("|"),
+                reply
+            ));
+        }
+        code.push_str(&format!("{}esac\n", indent));
+    }
+    code.push_str(&format!(
+        "{}COMPREPLY=($(compgen -W {} -- \"${}cur\"))\n",
+        indent,
+        shell::quote(&words.join(" ")),
+        p
+    ));
+}
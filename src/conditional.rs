@@ -0,0 +1,141 @@
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+
+use crate::defines::Defines;
+use crate::directive::Directive;
+
+/// Where a `# shpack: if` section is
+struct Section {
+    /// Whether the code around the section is kept
+    outer: bool,
+    /// Whether a branch of the section has been kept already
+    taken: bool,
+    /// Whether the current branch is kept
+    active: bool,
+    /// Which `# shpack:` directive opened the current branch, and on what line
+    opened: (&'static str, usize),
+}
+
+/// Keeps the branches of `# shpack: if`/`elif`/`else`/`endif` sections whose conditions hold
+/// for `defines`, and removes the others along with the directives themselves. Directives must
+/// be on lines of their own. Returns None if `source` has no sections.
+///
+/// A condition is a list of tests that must all hold: `NAME` is set to something other than
+/// the empty string, `!NAME` isn't, `NAME=a,b` is one of the listed values and `NAME!=a,b`
+/// is none of them.
+pub fn select(source: &str, defines: &Defines) -> Result<Option<String>> {
+    if !source.contains("# shpack: if") {
+        return Ok(None);
+    }
+
+    let mut out = String::with_capacity(source.len());
+    let mut sections: Vec<Section> = vec![];
+    for (i, line) in source.split_inclusive('\n').enumerate() {
+        let row = i + 1;
+        let active = sections.last().is_none_or(|section| section.active);
+        let directive = match Directive::parse(line.trim())? {
+            Some(directive)
+                if ["if", "elif", "else", "endif"].contains(&directive.name.as_str()) =>
+            {
+                directive
+            }
+            _ => {
+                if active {
+                    out.push_str(line);
+                }
+                continue;
+            }
+        };
+
+        match directive.name.as_str() {
+            "if" => {
+                let holds = active && holds(&directive, defines, row)?;
+                sections.push(Section {
+                    outer: active,
+                    taken: holds,
+                    active: holds,
+                    opened: ("if", row),
+                });
+            }
+            "elif" | "else" => {
+                let kind = if directive.name == "else" {
+                    "else"
+                } else {
+                    "elif"
+                };
+                let section = sections.last_mut().ok_or(eyre!(
+                    "line {}: `# shpack: {}` without a `# shpack: if`",
+                    row,
+                    directive.name
+                ))?;
+                if section.opened.0 == "else" {
+                    return Err(eyre!(
+                        "line {}: `# shpack: {}` after the `# shpack: else` on line {}",
+                        row,
+                        directive.name,
+                        section.opened.1
+                    ));
+                }
+                let holds = if kind == "else" {
+                    if !directive.args.is_empty() || !directive.options.is_empty() {
+                        return Err(eyre!("line {}: `# shpack: else` takes no condition", row));
+                    }
+                    section.outer && !section.taken
+                } else {
+                    section.outer && !section.taken && holds(&directive, defines, row)?
+                };
+                section.taken |= holds;
+                section.active = holds;
+                section.opened = (kind, row);
+            }
+            _ => {
+                sections.pop().ok_or(eyre!(
+                    "line {}: `# shpack: endif` without a `# shpack: if`",
+                    row
+                ))?;
+            }
+        }
+    }
+
+    if let Some(section) = sections.last() {
+        return Err(eyre!(
+            "line {}: `# shpack: {}` is never closed with `# shpack: endif`",
+            section.opened.1,
+            section.opened.0
+        ));
+    }
+    return Ok(Some(out));
+}
+
+/// Whether every test in an `if` or `elif` directive holds
+fn holds(directive: &Directive, defines: &Defines, row: usize) -> Result<bool> {
+    if directive.args.is_empty() && directive.options.is_empty() {
+        return Err(eyre!(
+            "line {}: `# shpack: {}` is missing its condition",
+            row,
+            directive.name
+        ));
+    }
+
+    let is_set = |name: &str| defines.get(name).is_some_and(|value| !value.is_empty());
+    for arg in &directive.args {
+        let holds = match arg.strip_prefix('!') {
+            Some(name) => !is_set(name),
+            None => is_set(arg),
+        };
+        if !holds {
+            return Ok(false);
+        }
+    }
+    for (key, values) in &directive.options {
+        let (name, negated) = match key.strip_suffix('!') {
+            Some(name) => (name, true),
+            None => (key.as_str(), false),
+        };
+        let value = defines.get(name).map(String::as_str).unwrap_or_default();
+        if values.split(',').any(|v| v == value) == negated {
+            return Ok(false);
+        }
+    }
+    return Ok(true);
+}
@@ -0,0 +1,268 @@
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use color_eyre::Result;
+use color_eyre::eyre::WrapErr;
+use color_eyre::eyre::eyre;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use shpack::Compression;
+use shpack::Encoding;
+use shpack::Format;
+use shpack::Hardening;
+use shpack::SecretsPolicy;
+use shpack::Strip;
+use shpack::defines::Defines;
+use shpack::target::Target;
+
+pub const CONFIG_FILE_NAME: &str = "shpack.toml";
+
+/// The contents of a `shpack.toml` file
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    pub workspace: Option<WorkspaceConfig>,
+    pub package: Option<PackageConfig>,
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct WorkspaceConfig {
+    /// Package directories, relative to the workspace root
+    #[serde(default)]
+    pub members: Vec<PathBuf>,
+    /// Include paths shared by every package in the workspace
+    #[serde(default)]
+    pub include_paths: Vec<PathBuf>,
+    /// Build cache shared by every package in the workspace
+    pub cache_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PackageConfig {
+    pub name: String,
+    pub version: Option<String>,
+    pub description: Option<String>,
+    /// The entry script, relative to the package directory. Leave it out to generate one that
+    /// sources `includes` and then runs `main`.
+    pub entry: Option<PathBuf>,
+    /// Files a generated entry script sources, in order, found like the paths of its `source`
+    /// commands
+    #[serde(default)]
+    pub includes: Vec<PathBuf>,
+    /// The command a generated entry script ends with, like `backup_main "$@"`
+    pub main: Option<String>,
+    /// Where the bundle is written, relative to the package directory
+    pub out: PathBuf,
+    /// Directories searched for sourced files that aren't found next to the sourcing file
+    #[serde(default)]
+    pub include_paths: Vec<PathBuf>,
+    /// `# build: inline` outputs at least this many bytes long are spooled to disk and streamed
+    /// into the bundle rather than held in memory
+    pub stream_threshold: Option<u64>,
+    /// Once about this many bytes are held in memory, bundled files and `# build: inline`
+    /// outputs are kept on disk instead
+    pub memory_cap: Option<u64>,
+    /// How many `# build: inline` commands run at once, defaults to the number of CPUs
+    pub jobs: Option<usize>,
+    /// How many times a failed `# build: inline idempotent` command is run again
+    pub retries: Option<u32>,
+    /// How long to wait before the first retry, like `2s`, which doubles for each one after
+    /// it
+    pub retry_backoff: Option<String>,
+    /// Files inlined before the entry script's code, relative to the package directory
+    #[serde(default)]
+    pub prelude: Vec<PathBuf>,
+    /// Files inlined after the entry script's code, relative to the package directory
+    #[serde(default)]
+    pub epilogue: Vec<PathBuf>,
+    /// WebAssembly modules that rewrite each file, in order, before it's bundled, relative to
+    /// the package directory. Needs the `wasm-transforms` feature.
+    #[serde(default)]
+    pub transforms: Vec<PathBuf>,
+    /// How `# build: inline` outputs of at least `compress-threshold` bytes are compressed
+    pub compress: Option<Compression>,
+    /// `# build: inline` outputs at least this many bytes long are compressed before they're
+    /// encoded, if that makes them smaller, defaults to 16 KiB
+    pub compress_threshold: Option<u64>,
+    /// How `# build: inline` outputs are written into the bundle
+    pub encoding: Option<Encoding>,
+    /// The kind of system the bundle runs on
+    pub target: Option<Target>,
+    /// Where the bundle extracts `# build: inline as=file` outputs at runtime
+    pub runtime_tmpdir: Option<String>,
+    /// What generated functions and variables are named with
+    pub symbol_prefix: Option<String>,
+    /// The bundle's shabang, which lets the shabangs of the files it bundles differ
+    pub shebang: Option<String>,
+    /// Read before `defines`, relative to the package directory
+    pub env_file: Option<PathBuf>,
+    /// Values that replace those of `# build: env` assignments
+    #[serde(default)]
+    pub defines: Defines,
+    /// Remove functions that nothing in the bundle calls
+    #[serde(default)]
+    pub tree_shake: bool,
+    /// Globs of functions to keep when tree-shaking
+    #[serde(default)]
+    pub keep: Vec<String>,
+    /// Replace GNU-only tool options with helpers that also work on macOS
+    #[serde(default)]
+    pub portable: bool,
+    /// Add the logging library, filtered by `LOG_LEVEL` at runtime
+    #[serde(default)]
+    pub with_logging: bool,
+    /// Turn `echo "DEBUG: ..."` and the like into calls to the logging library
+    #[serde(default)]
+    pub rewrite_log_echoes: bool,
+    /// Add a version helper printing `version`, the git commit and the build date
+    #[serde(default)]
+    pub version_stamp: bool,
+    /// The hardening measures applied before the bundle's own code
+    pub harden: Option<HardenConfig>,
+    /// Globs of sourced files, relative to the workspace root, that may use the reserved
+    /// prefix and aren't checked for top-level `cd` and `exec` or credentials, like
+    /// third-party libraries
+    #[serde(default)]
+    pub exempt: Vec<String>,
+    /// Globs of paths in `source` commands, like `/etc/sysconfig/*`, that are sourced at runtime
+    /// rather than bundled. When there are any, every other source must be bundled.
+    #[serde(default)]
+    pub runtime_sources: Vec<String>,
+    /// Globs of sourced files, relative to the workspace root or absolute, like
+    /// `/usr/share/bash-completion/*`, whose `source` commands are left as they are
+    #[serde(default)]
+    pub external: Vec<String>,
+    /// Commands that source a file like `source` does, like an `include` function
+    #[serde(default)]
+    pub source_aliases: Vec<String>,
+    /// Remove the definitions of aliases for `source`, since their uses are bundled
+    #[serde(default)]
+    pub strip_source_aliases: bool,
+    /// Variables besides `SCRIPT_DIR` that hold the entry script's directory, like `DIR` in
+    /// `source "$DIR/lib.sh"`
+    #[serde(default)]
+    pub script_dir_variables: Vec<String>,
+    /// Fail rather than warn when two versions of the same library are bundled
+    #[serde(default)]
+    pub deny_version_conflicts: bool,
+    /// Whether to warn about, fail on or not look for credentials in the bundle, like tokens
+    /// frozen into it by `# build: inline` commands
+    #[serde(default)]
+    pub secrets: SecretsPolicy,
+    /// Whether the bundle is written as a script or as a self-extracting archive that runs
+    /// it
+    #[serde(default)]
+    pub format: Format,
+    /// A directory whose files a self-extracting bundle's archive holds next to the bundle,
+    /// relative to the package directory
+    pub archive_dir: Option<PathBuf>,
+    /// Fail on `source` commands with absolute paths, unless they're explicitly allowed
+    #[serde(default)]
+    pub deny_absolute_sources: bool,
+    /// Bundle sourced files of 2 MiB or more with a warning, rather than failing
+    #[serde(default)]
+    pub allow_large_include: bool,
+    /// Break cycles of files that source each other by skipping the `source` command that
+    /// closes the cycle, rather than failing
+    #[serde(default)]
+    pub allow_circular: bool,
+    /// Put every inlined file at the top of the bundle, after the files it sources, rather
+    /// than where it's sourced
+    #[serde(default)]
+    pub topological_includes: bool,
+    /// What's stripped from every inlined file: `comments`, `blank` lines or `all` of them
+    #[serde(default)]
+    pub strip: Strip,
+    /// Strip comments, blank lines and the markers around inlined files from the whole bundle
+    #[serde(default)]
+    pub minify: bool,
+    /// Add an `ERR` trap that prints the source file and line a failing command came from
+    #[serde(default)]
+    pub inject_trace: bool,
+    /// Record the shpack version and the command that rebuilds the package in a comment after
+    /// the shabang
+    #[serde(default)]
+    pub provenance: bool,
+    /// The comments before each inlined file, one for each nesting depth, with `{path}`,
+    /// `{depth}` and `{indent}` filled in
+    #[serde(default)]
+    pub marker_open: Vec<String>,
+    /// The comments after each inlined file, like `marker-open`
+    #[serde(default)]
+    pub marker_close: Vec<String>,
+}
+
+/// `true` for every hardening measure, or a list of them
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum HardenConfig {
+    All(bool),
+    Some(Vec<Hardening>),
+}
+
+impl HardenConfig {
+    pub fn measures(&self) -> Vec<Hardening> {
+        return match self {
+            HardenConfig::All(true) => Hardening::all(),
+            HardenConfig::All(false) => vec![],
+            HardenConfig::Some(measures) => measures.clone(),
+        };
+    }
+}
+
+/// The JSON schema of a `shpack.toml` file, generated from the structs that parse it
+pub fn schema() -> Result<String> {
+    return Ok(serde_json::to_string_pretty(&schemars::schema_for!(
+        Config
+    ))?);
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config> {
+        let text = fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read {}", path.display()))?;
+        return toml::from_str(&text)
+            .map_err(|e| match suggestion(e.message()) {
+                Some(suggestion) => eyre!("{}did you mean `{}`?", e, suggestion),
+                None => eyre!(e),
+            })
+            .wrap_err_with(|| format!("invalid config: {}", path.display()));
+    }
+}
+
+/// The closest expected name to an unknown key or value, from an error like "unknown field
+/// `tree_shak`, expected one of `tree-shake`, ...", if any is close enough to be a typo
+fn suggestion(message: &str) -> Option<&str> {
+    let rest = message
+        .strip_prefix("unknown field `")
+        .or_else(|| message.strip_prefix("unknown variant `"))?;
+    let (unknown, expected) = rest.split_once('`')?;
+    let unknown = unknown.to_lowercase().replace('_', "-");
+    return expected
+        .split('`')
+        .skip(1)
+        .step_by(2)
+        .map(|candidate| (distance(&unknown, candidate), candidate))
+        .filter(|(distance, candidate)| *distance <= 2.max(candidate.len() / 3))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate);
+}
+
+/// The number of single-character insertions, deletions and substitutions that turn `a` into
+/// `b`
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    return previous[b.len()];
+}
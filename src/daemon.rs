@@ -0,0 +1,171 @@
+use std::env;
+use std::fs;
+use std::fs::DirBuilder;
+use std::fs::Permissions;
+use std::io::BufReader;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixListener;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+use rustix::process::getuid;
+use shpack::SourceCache;
+use shpack::batch;
+use shpack::batch::{Request, Response, read_message, respond, write_message};
+
+/// The socket the daemon listens on, `$SHPACK_DAEMON_SOCKET` if it's set. Without
+/// `$XDG_RUNTIME_DIR` it goes in a directory of its own under the temporary directory, since
+/// that one is shared with every other user.
+pub fn socket_path() -> PathBuf {
+    if let Some(path) = env::var_os("SHPACK_DAEMON_SOCKET") {
+        return PathBuf::from(path);
+    }
+    if let Some(dir) = env::var_os("XDG_RUNTIME_DIR") {
+        return PathBuf::from(dir).join(format!(
+            "shpack-{}.sock",
+            env::var("USER").unwrap_or_default()
+        ));
+    }
+    return env::temp_dir()
+        .join(format!("shpack-{}", getuid().as_raw()))
+        .join("daemon.sock");
+}
+
+/// Fails unless only the current user (or root) can create or replace files in `dir`, so
+/// another user can't put a socket of their own where ours is expected
+fn check_dir(dir: &Path) -> Result<()> {
+    let metadata =
+        fs::symlink_metadata(dir).wrap_err_with(|| format!("failed to read {}", dir.display()))?;
+    let owner = metadata.uid();
+    let writable_by_others = metadata.mode() & 0o022 != 0;
+    let sticky = metadata.mode() & 0o1000 != 0;
+    if !metadata.is_dir() || (owner != getuid().as_raw() && owner != 0) {
+        return Err(eyre!(
+            "{} isn't a directory owned by you, so it isn't safe for the daemon's socket",
+            dir.display()
+        ));
+    }
+    if writable_by_others && !sticky {
+        return Err(eyre!(
+            "{} can be written by other users, so it isn't safe for the daemon's socket",
+            dir.display()
+        ));
+    }
+    return Ok(());
+}
+
+fn parent(socket: &Path) -> &Path {
+    return match socket.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+}
+
+/// Whether `socket` is a socket the current user made and only they can connect to, which
+/// is what `serve` leaves behind
+fn is_trusted(socket: &Path) -> bool {
+    if check_dir(parent(socket)).is_err() {
+        return false;
+    }
+    return match fs::symlink_metadata(socket) {
+        Ok(metadata) => {
+            metadata.file_type().is_socket()
+                && metadata.uid() == getuid().as_raw()
+                && metadata.mode() & 0o077 == 0
+        }
+        Err(_) => false,
+    };
+}
+
+/// Serves bundle requests on a Unix socket until killed, keeping parsed sources in memory
+/// across requests. Connections speak the same protocol as `--stdin-batch`.
+pub fn serve(socket: &Path) -> Result<()> {
+    let dir = parent(socket);
+    if !dir.exists() {
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .wrap_err_with(|| format!("failed to create {}", dir.display()))?;
+    }
+    check_dir(dir)?;
+    if socket.exists() {
+        if UnixStream::connect(socket).is_ok() {
+            return Err(eyre!(
+                "a daemon is already listening on {}",
+                socket.display()
+            ));
+        }
+        // Left behind by a daemon that didn't shut down cleanly
+        fs::remove_file(socket)?;
+    }
+
+    let listener = UnixListener::bind(socket)
+        .wrap_err_with(|| format!("failed to listen on {}", socket.display()))?;
+    // Whoever can connect can have files bundled, and read them back, as this user
+    fs::set_permissions(socket, Permissions::from_mode(0o600))?;
+    eprintln!("Listening on {}", socket.display());
+
+    let source_cache = Arc::new(SourceCache::default());
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let source_cache = source_cache.clone();
+        thread::spawn(move || {
+            if let Err(e) = handle(stream, &source_cache) {
+                eprintln!("connection failed: {:#}", e);
+            }
+        });
+    }
+
+    return Ok(());
+}
+
+fn handle(stream: UnixStream, source_cache: &Arc<SourceCache>) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    while let Some(message) = read_message(&mut reader)? {
+        let response = serde_json::to_vec(&respond(&message, Some(source_cache)))?;
+        write_message(&mut writer, &response)?;
+    }
+    return Ok(());
+}
+
+/// Hands a request to the running daemon, returning None if there isn't one, its socket
+/// wasn't made by this user, it's another version of shpack, or `$SHPACK_NO_DAEMON` is set
+pub fn delegate(request: &Request) -> Result<Option<Response>> {
+    if env::var_os("SHPACK_NO_DAEMON").is_some() {
+        return Ok(None);
+    }
+    let socket = socket_path();
+    if !is_trusted(&socket) {
+        return Ok(None);
+    }
+    let stream = match UnixStream::connect(&socket) {
+        Ok(stream) => stream,
+        Err(_) => return Ok(None),
+    };
+
+    let mut writer = stream.try_clone()?;
+    write_message(&mut writer, &serde_json::to_vec(request)?)?;
+    let message = read_message(&mut BufReader::new(stream))?
+        .ok_or(eyre!("the daemon closed the connection without responding"))?;
+    // A daemon left running across an upgrade refuses the request, or can't parse it
+    let response = match serde_json::from_slice::<Response>(&message) {
+        Ok(response) if response.version == batch::VERSION => response,
+        _ => {
+            eprintln!(
+                "note: the daemon is running another version of shpack, so this is bundled in-process. Restart it to use it again."
+            );
+            return Ok(None);
+        }
+    };
+    return Ok(Some(response));
+}
@@ -0,0 +1,178 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+use serde_json::Value;
+
+use crate::shell;
+
+/// Reads a TOML or JSON file, chosen by its extension
+pub fn load(path: &Path) -> Result<Value> {
+    let text =
+        fs::read_to_string(path).wrap_err_with(|| format!("failed to read {}", path.display()))?;
+    let value = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => toml_to_json(toml::from_str(&text)?),
+        Some("json") => serde_json::from_str(&text)?,
+        _ => {
+            return Err(eyre!(
+                "expected a .toml or .json file, found {}",
+                path.display()
+            ));
+        }
+    };
+    return Ok(value);
+}
+
+fn toml_to_json(value: toml::Value) -> Value {
+    return match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(i) => Value::from(i),
+        toml::Value::Float(f) => Value::from(f),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(d) => Value::String(d.to_string()),
+        toml::Value::Array(a) => Value::Array(a.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(t) => {
+            Value::Object(t.into_iter().map(|(k, v)| (k, toml_to_json(v))).collect())
+        }
+    };
+}
+
+/// Generates a readonly variable for every value in `data`, named by joining `prefix` and the
+/// keys leading to the value with `_`. Arrays of plain values become indexed arrays, and other
+/// arrays are flattened by index.
+pub fn variables(data: &Value, prefix: &str) -> Result<String> {
+    let mut out = String::new();
+    let mut names = HashSet::new();
+    flatten(data, prefix.to_string(), &mut names, &mut out)?;
+    return Ok(out);
+}
+
+fn flatten(
+    value: &Value,
+    name: String,
+    names: &mut HashSet<String>,
+    out: &mut String,
+) -> Result<()> {
+    let child = |key: &str| {
+        let key = key.replace(|c: char| !c.is_ascii_alphanumeric() && c != '_', "_");
+        if name.is_empty() || name.ends_with('_') {
+            return format!("{}{}", name, key);
+        }
+        return format!("{}_{}", name, key);
+    };
+
+    let assignment = match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                flatten(value, child(key), names, out)?;
+            }
+            return Ok(());
+        }
+        Value::Array(items) if items.iter().all(|item| scalar(item).is_some()) => {
+            let items: Vec<String> = items
+                .iter()
+                .filter_map(scalar)
+                .map(|item| shell::quote(&item))
+                .collect();
+            format!("readonly -a {}=({})\n", name, items.join(" "))
+        }
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                flatten(item, child(&i.to_string()), names, out)?;
+            }
+            return Ok(());
+        }
+        _ => format!(
+            "readonly {}={}\n",
+            name,
+            shell::quote(&scalar(value).unwrap_or_default())
+        ),
+    };
+
+    if !shell::is_identifier(&name) {
+        return Err(eyre!("`{}` isn't a valid variable name", name));
+    }
+    if !names.insert(name.clone()) {
+        return Err(eyre!("more than one value would be named `{}`", name));
+    }
+    out.push_str(&assignment);
+    return Ok(());
+}
+
+/// The text of a value that isn't an object or array
+fn scalar(value: &Value) -> Option<String> {
+    return match value {
+        Value::Null => Some(String::new()),
+        Value::Bool(b) => Some(b.to_string()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::String(s) => Some(s.clone()),
+        Value::Array(_) | Value::Object(_) => None,
+    };
+}
+
+/// Generates an associative array `name` mapping the dotted path of every plain value in
+/// `data` (like `servers.0.host`) to the value, along with `name_get PATH` and
+/// `name_keys [PATH]` accessors. The child keys of each object and array are kept in a second
+/// array named with `prefix`.
+pub fn accessors(data: &Value, name: &str, prefix: &str) -> Result<String> {
+    if !shell::is_identifier(name) {
+        return Err(eyre!("`{}` isn't a valid variable name", name));
+    }
+
+    let mut values = vec![];
+    let mut children = vec![];
+    index(data, ".", &mut values, &mut children);
+
+    let entries = |pairs: &[(String, String)]| -> String {
+        return pairs
+            .iter()
+            .map(|(path, value)| format!("  [{}]={}\n", shell::quote(path), shell::quote(value)))
+            .collect();
+    };
+    let keys = format!("{}keys_{}", prefix, name);
+    return Ok(format!(
+        "declare -gA {name}=(\n{values})\n\
+         declare -gA {keys}=(\n{children})\n\
+         {name}_get() {{ [[ ${{{name}[$1]+set}} ]] && printf '%s\\n' \"${{{name}[$1]}}\"; }}\n\
+         {name}_keys() {{ [[ ${{{keys}[${{1:-.}}]+set}} ]] && printf '%s\\n' \"${{{keys}[${{1:-.}}]}}\"; }}\n",
+        name = name,
+        keys = keys,
+        values = entries(&values),
+        children = entries(&children),
+    ));
+}
+
+/// Lists the dotted path and text of every plain value under `value` in `values`, and the
+/// child keys of every object and array, one per line, in `children`. The root's path is `.`.
+pub fn index(
+    value: &Value,
+    path: &str,
+    values: &mut Vec<(String, String)>,
+    children: &mut Vec<(String, String)>,
+) {
+    let child = |key: &str| {
+        if path == "." {
+            return key.to_string();
+        }
+        return format!("{}.{}", path, key);
+    };
+
+    let keys: Vec<String> = match value {
+        Value::Object(map) => map.keys().cloned().collect(),
+        Value::Array(items) => (0..items.len()).map(|i| i.to_string()).collect(),
+        _ => {
+            values.push((path.to_string(), scalar(value).unwrap_or_default()));
+            return;
+        }
+    };
+    children.push((path.to_string(), keys.join("\n")));
+    for key in keys {
+        let item = match value {
+            Value::Object(map) => &map[&key],
+            _ => &value[key.parse::<usize>().expect("array keys are indices")],
+        };
+        index(item, &child(&key), values, children);
+    }
+}
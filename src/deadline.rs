@@ -0,0 +1,71 @@
+use std::time::Duration;
+use std::time::Instant;
+
+use color_eyre::Result;
+use color_eyre::eyre::{Report, eyre};
+
+/// A time the build has to be done by, so a hung `# build: inline` command or a stuck step
+/// fails the build instead of stalling CI
+#[derive(Debug, Clone)]
+pub struct Deadline {
+    /// The limit as it was given, like `120s`
+    limit: String,
+    at: Instant,
+}
+
+impl Deadline {
+    /// A deadline `limit`, like `120s`, after `started`
+    pub fn new(limit: &str, started: Instant) -> Result<Self, String> {
+        return Ok(Deadline {
+            limit: limit.to_string(),
+            at: started + parse_duration(limit)?,
+        });
+    }
+
+    pub fn at(&self) -> Instant {
+        return self.at;
+    }
+
+    pub fn has_passed(&self) -> bool {
+        return Instant::now() >= self.at;
+    }
+
+    /// The error the build fails with when the deadline passes while it's `doing` something,
+    /// like `bundling lib/a.sh`
+    pub fn error(&self, doing: &str) -> Report {
+        return eyre!(
+            "the build passed its {} deadline while {}",
+            self.limit,
+            doing
+        );
+    }
+}
+
+/// Parses a duration given in a request or `shpack.toml`, like `retry-backoff = "2s"`
+pub fn parse_option(name: &str, text: &str) -> Result<Duration> {
+    return parse_duration(text).map_err(|e| eyre!("invalid `{}`: {}", name, e));
+}
+
+/// Parses durations like `500ms`, `90s`, `2m` or `1h`. A number on its own is in seconds.
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("expected a duration like 120s or 2m, found `{}`", text))?;
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => {
+            return Err(format!(
+                "unknown unit `{}` in `{}`, use ms, s, m or h",
+                unit, text
+            ));
+        }
+    };
+    return Ok(Duration::from_secs_f64(seconds));
+}
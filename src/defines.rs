@@ -0,0 +1,49 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+
+use crate::shell;
+
+/// Build-time values, set with `--define NAME=value` or loaded from env files, that
+/// `# build: env` assignments are replaced with
+pub type Defines = BTreeMap<String, String>;
+
+/// Parses a `NAME=value` define
+pub fn parse_define(define: &str) -> Result<(String, String)> {
+    let (name, value) = define
+        .split_once('=')
+        .ok_or(eyre!("expected NAME=value, found `{}`", define))?;
+    if !shell::is_identifier(name) {
+        return Err(eyre!("invalid define name: {}", name));
+    }
+    return Ok((name.to_string(), value.to_string()));
+}
+
+/// Reads the `NAME=value` lines of a .env file. Blank lines and `#` comments are skipped,
+/// lines may start with `export`, and values may be wrapped in single or double quotes.
+pub fn load_env_file(path: &Path) -> Result<Vec<(String, String)>> {
+    let text = fs::read_to_string(path)
+        .wrap_err_with(|| format!("failed to read env file {}", path.display()))?;
+
+    let mut defines = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+        let (name, value) =
+            parse_define(line).wrap_err_with(|| format!("{}:{}", path.display(), i + 1))?;
+
+        let value = value.trim();
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|q| value.strip_prefix(*q).and_then(|v| v.strip_suffix(*q)))
+            .unwrap_or(value);
+        defines.push((name, value.to_string()));
+    }
+    return Ok(defines);
+}
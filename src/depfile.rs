@@ -0,0 +1,36 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use color_eyre::Result;
+use shpack::fs_util;
+
+/// Writes a Make-style dependency file saying that `target` depends on each of `inputs`, for
+/// Make's `include` and Ninja's `depfile`. Inputs in the current directory are written
+/// relative to it.
+pub fn write(path: &Path, target: &Path, inputs: &[PathBuf]) -> Result<()> {
+    let cwd = env::current_dir()?.canonicalize()?;
+    let mut out = format!("{}:", escape(target));
+    for input in inputs {
+        let input = input.strip_prefix(&cwd).unwrap_or(input);
+        out.push_str(" \\\n  ");
+        out.push_str(&escape(input));
+    }
+    out.push('\n');
+    return fs_util::write_atomic(path, out.as_bytes());
+}
+
+/// Escapes the characters Make treats specially in a file name
+fn escape(path: &Path) -> String {
+    let mut escaped = String::new();
+    for c in path.to_string_lossy().chars() {
+        match c {
+            ' ' | '#' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '$' => escaped.push_str("$$"),
+            _ => escaped.push(c),
+        }
+    }
+    return escaped;
+}
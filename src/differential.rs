@@ -0,0 +1,113 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+use std::process::Stdio;
+
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+
+/// What running a test command produced
+#[derive(Debug, PartialEq, Eq)]
+struct Outcome {
+    stdout: String,
+    stderr: String,
+    /// None if the command was killed by a signal
+    status: Option<i32>,
+}
+
+/// Runs `command` with bash, with `script` as `$1`. Mentions of the script's path in its
+/// output are replaced with `$1`, since the original and the bundle live in different places.
+fn run(command: &str, script: &Path) -> Result<Outcome> {
+    let output = Command::new("bash")
+        .arg("-c")
+        .arg(command)
+        .arg("shpack-test")
+        .arg(script)
+        .stdin(Stdio::null())
+        .output()
+        .wrap_err_with(|| format!("failed to run test command `{}`", command))?;
+    let script = script.to_string_lossy();
+    let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).replace(script.as_ref(), "$1");
+    return Ok(Outcome {
+        stdout: text(&output.stdout),
+        stderr: text(&output.stderr),
+        status: output.status.code(),
+    });
+}
+
+/// Describes the first line where `before` and `after` differ
+fn first_difference(before: &str, after: &str) -> String {
+    let mut before_lines = before.lines();
+    let mut after_lines = after.lines();
+    for line in 1.. {
+        match (before_lines.next(), after_lines.next()) {
+            (Some(b), Some(a)) if a == b => continue,
+            (None, None) => return String::from("differs only in its line endings"),
+            (b, a) => {
+                let show = |line: Option<&str>| match line {
+                    Some(line) => format!("{:?}", line),
+                    None => String::from("nothing"),
+                };
+                return format!(
+                    "line {}: {} before bundling, {} after",
+                    line,
+                    show(b),
+                    show(a)
+                );
+            }
+        }
+    }
+    unreachable!("the outputs differ");
+}
+
+/// Runs every test command against the original entry script, with real sourcing, and
+/// against the bundle, and fails if any of them behaves differently. `bundle` is a copy of
+/// the bundle with the same file name as `original`, so `$0` is the same for both.
+pub fn check(commands: &[String], original: &Path, bundle: &Path) -> Result<()> {
+    fs::set_permissions(bundle, fs::Permissions::from_mode(0o755))?;
+    let mut failed = 0;
+    for command in commands {
+        let before = run(command, original)?;
+        let after = run(command, bundle)?;
+        if before == after {
+            eprintln!("same: {}", command);
+            continue;
+        }
+
+        failed += 1;
+        eprintln!("different: {}", command);
+        if before.status != after.status {
+            let show = |status: Option<i32>| match status {
+                Some(code) => code.to_string(),
+                None => String::from("killed by a signal"),
+            };
+            eprintln!(
+                "  exit status: {} before bundling, {} after",
+                show(before.status),
+                show(after.status)
+            );
+        }
+        if before.stdout != after.stdout {
+            eprintln!(
+                "  stdout {}",
+                first_difference(&before.stdout, &after.stdout)
+            );
+        }
+        if before.stderr != after.stderr {
+            eprintln!(
+                "  stderr {}",
+                first_difference(&before.stderr, &after.stderr)
+            );
+        }
+    }
+
+    if failed > 0 {
+        return Err(eyre!(
+            "bundling changed the behavior of {} of {} test command(s)",
+            failed,
+            commands.len()
+        ));
+    }
+    return Ok(());
+}
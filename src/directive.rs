@@ -0,0 +1,144 @@
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+use tree_sitter::Node;
+
+use crate::GetText;
+
+/// A `# shpack: <name> [args...]` comment controlling how the bundler treats the code around it
+#[derive(Debug, Clone)]
+pub struct Directive {
+    pub name: String,
+    /// Bare words following the name
+    pub args: Vec<String>,
+    /// `key=value` pairs following the name
+    pub options: Vec<(String, String)>,
+}
+
+/// The names of `# shpack:` directives. Other comments that start with `# shpack:` are just
+/// comments.
+const SHPACK_NAMES: &[&str] = &[
+    "allow-absolute",
+    "allow-cd",
+    "allow-exec",
+    "allow-secret",
+    "elif",
+    "else",
+    "endif",
+    "if",
+    "ignore",
+    "include",
+    "inline",
+    "isolate",
+    "keep",
+    "once",
+    "with",
+];
+
+/// The names of `# build:` directives, like [`SHPACK_NAMES`]
+const BUILD_NAMES: &[&str] = &[
+    "cli-spec",
+    "data",
+    "embed",
+    "embed-dir",
+    "env",
+    "inline",
+    "json",
+    "messages",
+];
+
+impl Directive {
+    /// Parses a comment, returning None if it isn't a `# shpack:` directive
+    pub fn parse(comment: &str) -> Result<Option<Directive>> {
+        return Directive::parse_prefixed(comment, "# shpack:", SHPACK_NAMES);
+    }
+
+    /// Parses a comment, returning None if it isn't a `# build:` directive
+    pub fn parse_build(comment: &str) -> Result<Option<Directive>> {
+        return Directive::parse_prefixed(comment, "# build:", BUILD_NAMES);
+    }
+
+    /// Parses a comment that starts with `prefix` and one of `names`. Comments like
+    /// `# build: don't forget to run make first` aren't directives, so they're left alone.
+    fn parse_prefixed(comment: &str, prefix: &str, names: &[&str]) -> Result<Option<Directive>> {
+        let Some(rest) = comment.strip_prefix(prefix) else {
+            return Ok(None);
+        };
+        let Some(name) = rest.split_whitespace().next() else {
+            return Ok(None);
+        };
+        if !names.contains(&name) {
+            return Ok(None);
+        }
+
+        let mut args = vec![];
+        let mut options = vec![];
+        for word in split_words(rest)?.into_iter().skip(1) {
+            match word.split_once('=') {
+                Some((key, value)) => options.push((key.to_string(), value.to_string())),
+                None => args.push(word),
+            }
+        }
+
+        return Ok(Some(Directive {
+            name: name.to_string(),
+            args,
+            options,
+        }));
+    }
+}
+
+/// Whether a `# shpack: <name>` directive is on the line directly above `node`, like
+/// `# shpack: allow-exec` above an `exec`
+pub fn is_above(node: Node, source: &str, name: &str) -> Result<bool> {
+    let Some(comment) = node.prev_sibling() else {
+        return Ok(false);
+    };
+    if comment.kind() != "comment" || comment.end_position().row + 1 != node.start_position().row {
+        return Ok(false);
+    }
+    return Ok(
+        Directive::parse(comment.text(source))?.is_some_and(|directive| directive.name == name)
+    );
+}
+
+/// Splits directive arguments on whitespace, honoring single and double quotes
+pub fn split_words(text: &str) -> Result<Vec<String>> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut quote: Option<char> = None;
+
+    for c in text.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.get_or_insert_default().push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_default();
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_default().push(c),
+        }
+    }
+
+    if quote.is_some() {
+        return Err(eyre!(
+            "unterminated quote in directive: \"{}\"",
+            text.trim()
+        ));
+    }
+    words.extend(word);
+    return Ok(words);
+}
+
+/// Parses a boolean directive or config value
+pub fn parse_bool(key: &str, value: &str) -> Result<bool> {
+    return match value {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => Err(eyre!(
+            "expected a boolean for `{}`, found \"{}\"",
+            key,
+            value
+        )),
+    };
+}
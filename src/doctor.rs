@@ -0,0 +1,147 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+
+use crate::config::CONFIG_FILE_NAME;
+use crate::workspace::Workspace;
+
+/// A problem found by `shpack doctor`, along with how to fix it
+struct Finding {
+    problem: String,
+    fix: String,
+}
+
+/// Checks the environment and the workspace containing `start` for problems that would break
+/// builds, printing what was checked to stderr. Fails if anything needs fixing.
+pub fn run(start: &Path) -> Result<()> {
+    let mut findings = vec![];
+
+    check_bash(&mut findings);
+
+    let configs_exist = start
+        .ancestors()
+        .any(|dir| dir.join(CONFIG_FILE_NAME).is_file());
+    if !configs_exist {
+        eprintln!(
+            "note: no {} found, only single-file bundling is available here",
+            CONFIG_FILE_NAME
+        );
+    } else {
+        match Workspace::discover(start) {
+            Ok(workspace) => {
+                eprintln!("ok: workspace at {}", workspace.root.display());
+                check_cache(&workspace, &mut findings);
+                check_packages(&workspace, &mut findings);
+            }
+            Err(e) => findings.push(Finding {
+                problem: format!("{:#}", e),
+                fix: format!("correct the {} named above", CONFIG_FILE_NAME),
+            }),
+        }
+    }
+
+    for finding in &findings {
+        eprintln!("error: {}", finding.problem);
+        eprintln!("  fix: {}", finding.fix);
+    }
+    if !findings.is_empty() {
+        return Err(eyre!("found {} problem(s)", findings.len()));
+    }
+    eprintln!("No problems found");
+    return Ok(());
+}
+
+/// `# build: inline` commands are run with `bash -c`
+fn check_bash(findings: &mut Vec<Finding>) {
+    let output = Command::new("bash")
+        .arg("-c")
+        .arg("echo \"$BASH_VERSION\"")
+        .output();
+    match output {
+        Ok(output) if output.status.success() => {
+            eprintln!(
+                "ok: bash {} for `# build: inline` commands",
+                String::from_utf8_lossy(&output.stdout).trim()
+            );
+        }
+        Ok(output) => findings.push(Finding {
+            problem: format!("bash exited with {} when asked its version", output.status),
+            fix: "check that the `bash` on your PATH is a working bash".to_string(),
+        }),
+        Err(e) => findings.push(Finding {
+            problem: format!("couldn't run bash: {}", e),
+            fix: "install bash, or add it to your PATH, to use `# build: inline` commands"
+                .to_string(),
+        }),
+    }
+}
+
+fn check_cache(workspace: &Workspace, findings: &mut Vec<Finding>) {
+    let dir = workspace.cache.dir();
+
+    // The cache directory is created on the first build, so check whatever will contain it
+    let existing = dir
+        .ancestors()
+        .find(|dir| dir.exists())
+        .expect("the root directory should exist");
+    match tempfile::tempfile_in(existing) {
+        Ok(_) => eprintln!("ok: cache directory {} is writable", dir.display()),
+        Err(e) => findings.push(Finding {
+            problem: format!("can't write to {}: {}", existing.display(), e),
+            fix: format!(
+                "fix the permissions of {}, or set a different `cache-dir` in the workspace's {}",
+                existing.display(),
+                CONFIG_FILE_NAME
+            ),
+        }),
+    }
+}
+
+fn check_packages(workspace: &Workspace, findings: &mut Vec<Finding>) {
+    for package in &workspace.packages {
+        let mut ok = true;
+        if package.main.is_none() && !package.entry.is_file() {
+            ok = false;
+            findings.push(Finding {
+                problem: format!(
+                    "the entry script of package `{}` doesn't exist: {}",
+                    package.name,
+                    package.entry.display()
+                ),
+                fix: "create it, or point `entry` at the right file".to_string(),
+            });
+        }
+        for include in &package.includes {
+            if package.find_include(include).is_none() {
+                ok = false;
+                findings.push(Finding {
+                    problem: format!(
+                        "package `{}` includes a file that isn't in it or its include paths: {}",
+                        package.name,
+                        include.display()
+                    ),
+                    fix: "create it, or fix its path in `includes`".to_string(),
+                });
+            }
+        }
+        for dir in &package.options.include_paths {
+            if fs::read_dir(dir).is_err() {
+                ok = false;
+                findings.push(Finding {
+                    problem: format!(
+                        "include path of package `{}` isn't a readable directory: {}",
+                        package.name,
+                        dir.display()
+                    ),
+                    fix: "create it, or remove it from `include-paths`".to_string(),
+                });
+            }
+        }
+        if ok {
+            eprintln!("ok: package `{}`", package.name);
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use crate::shell;
+
+/// How many lines [`handler`] takes up
+pub const LINES: usize = 3;
+
+/// An `ERR` trap, with functions inheriting it, that prints the file and line a failing command
+/// came from. `files` and `lines` are the bundle's line table, from
+/// [`crate::annotate::line_table`], and the handler goes after the first `offset` lines.
+pub fn handler(
+    files: &[String],
+    lines: &[Option<(usize, usize)>],
+    offset: usize,
+    prefix: &str,
+) -> String {
+    let files: Vec<String> = files.iter().map(|file| shell::quote(file)).collect();
+    let mut table = vec![];
+    for (i, origin) in lines.iter().enumerate() {
+        if let Some((file, line)) = origin {
+            // Lines after the handler move down by its length
+            let lineno = if i < offset { i + 1 } else { i + 1 + LINES };
+            table.push(format!("[{}]={}:{}", lineno, file, line));
+        }
+    }
+    return format!(
+        "{p}files=({files})\n\
+         {p}lines=({table})\n\
+         set -o errtrace; trap '{p}at=${{{p}lines[$LINENO]-}}; [ -z \"${p}at\" ] || echo \"error: command failed at ${{{p}files[${{{p}at%%:*}}]}}:${{{p}at#*:}} (line $LINENO of the bundle)\" >&2' ERR\n",
+        p = prefix,
+        files = files.join(" "),
+        table = table.join(" ")
+    );
+}
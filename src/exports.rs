@@ -0,0 +1,76 @@
+use std::collections::BTreeSet;
+
+use color_eyre::Result;
+use tree_sitter::Node;
+
+use crate::{GetText, parse_file};
+
+/// The functions a script exports to child processes with `export -f` or `declare -fx`, and
+/// the functions it defines
+#[derive(Default)]
+pub struct Exports {
+    pub exported: BTreeSet<String>,
+    pub defined: BTreeSet<String>,
+}
+
+impl Exports {
+    pub fn find(source: &str) -> Result<Self> {
+        let tree = parse_file(source)?;
+        let mut exports = Exports::default();
+        exports.visit(tree.root_node(), source);
+        return Ok(exports);
+    }
+
+    /// The exported functions that aren't defined
+    pub fn missing(&self) -> impl Iterator<Item = &String> {
+        return self.exported.difference(&self.defined);
+    }
+
+    fn visit(&mut self, node: Node, source: &str) {
+        match node.kind() {
+            "function_definition" => {
+                if let Some(name) = node.child_by_field_name("name") {
+                    self.defined.insert(name.text(source).to_string());
+                }
+            }
+            "declaration_command" => {
+                self.exported
+                    .extend(exported_names(node, source).into_iter().map(str::to_string));
+            }
+            _ => {}
+        }
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            self.visit(child, source);
+        }
+    }
+}
+
+/// The function names a declaration like `export -f name` or `declare -fx name` exports, if
+/// it exports functions
+pub fn exported_names<'a>(declaration: Node, source: &'a str) -> Vec<&'a str> {
+    let Some(keyword) = declaration.child(0) else {
+        return vec![];
+    };
+    let mut cursor = declaration.walk();
+    let arguments: Vec<Node> = declaration.named_children(&mut cursor).collect();
+    let flags: String = arguments
+        .iter()
+        .map(|argument| argument.text(source))
+        .filter(|text| text.starts_with('-') && !text.starts_with("--"))
+        .collect();
+    let exports_functions = match keyword.text(source) {
+        "export" => flags.contains('f') && !flags.contains('n'),
+        "declare" | "typeset" => flags.contains('f') && flags.contains('x'),
+        _ => false,
+    };
+    if !exports_functions {
+        return vec![];
+    }
+    return arguments
+        .iter()
+        .map(|argument| argument.text(source))
+        .filter(|text| !text.starts_with('-'))
+        .map(|text| text.trim_matches(['"', '\'']))
+        .collect();
+}
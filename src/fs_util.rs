@@ -0,0 +1,140 @@
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::fs::Permissions;
+use std::fs::TryLockError;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::path::PathBuf;
+
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+
+use crate::shake;
+
+/// Replaces the file at `path` in one step, so concurrent readers and writers never see a
+/// partially written file. Creates the parent directories if needed.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    return write_atomic_with(path, |file| {
+        file.write_all(contents)?;
+        return Ok(());
+    });
+}
+
+/// Like [`write_atomic`], but the contents are written by `write`
+pub fn write_atomic_with(path: &Path, write: impl FnOnce(&mut File) -> Result<()>) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(dir)
+        .wrap_err_with(|| format!("failed to create directory {}", dir.display()))?;
+
+    // Match what a plain write would have produced: keep the mode of the file being replaced,
+    // or let the umask decide for new files
+    let mut file = tempfile::Builder::new()
+        .permissions(Permissions::from_mode(0o666))
+        .tempfile_in(dir)?;
+    if let Ok(metadata) = fs::metadata(path) {
+        file.as_file().set_permissions(metadata.permissions())?;
+    }
+    write(file.as_file_mut())?;
+    file.as_file().sync_all()?;
+    file.persist(path)
+        .wrap_err_with(|| format!("failed to write {}", path.display()))?;
+    return Ok(());
+}
+
+/// Takes an advisory exclusive lock on `path`, creating it if needed and waiting for other
+/// processes to release it. The lock is held until the returned file is dropped.
+pub fn lock_exclusive(path: &Path, what: &str) -> Result<File> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let file = File::create(path)
+        .wrap_err_with(|| format!("failed to open lock file {}", path.display()))?;
+
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            eprintln!("    Blocking waiting for file lock on {}", what);
+            file.lock()?;
+        }
+        Err(TryLockError::Error(e)) => return Err(e.into()),
+    }
+    return Ok(file);
+}
+
+/// Reads a script, failing with an explanation if it looks like a binary file rather than text
+pub fn read_script(path: &Path) -> Result<String> {
+    let bytes = fs::read(path)?;
+    if let Some(offset) = bytes.iter().position(|byte| *byte == 0) {
+        return Err(eyre!(
+            "it looks like a binary file, not a shell script: there's a NUL byte at offset {}",
+            offset
+        ));
+    }
+    return String::from_utf8(bytes).map_err(|error| {
+        eyre!(
+            "it isn't UTF-8 text, so it's probably not a shell script: the byte at offset {} is invalid",
+            error.utf8_error().valid_up_to()
+        )
+    });
+}
+
+/// Whether a path has `*` or `?` in it, which the shell would expand
+pub fn is_glob(path: &str) -> bool {
+    return path.contains(['*', '?']);
+}
+
+/// The files matching `pattern`, relative to `dir`, sorted like the shell sorts them. As in the
+/// shell, wildcards don't match names starting with a dot unless the pattern does.
+pub fn glob(dir: &Path, pattern: &str) -> Result<Vec<PathBuf>> {
+    let mut candidates = vec![if pattern.starts_with('/') {
+        PathBuf::from("/")
+    } else {
+        dir.to_path_buf()
+    }];
+    for component in pattern.split('/').filter(|component| !component.is_empty()) {
+        if !is_glob(component) {
+            candidates = candidates
+                .into_iter()
+                .map(|candidate| candidate.join(component))
+                .collect();
+            continue;
+        }
+        let mut matches = vec![];
+        for candidate in candidates.iter().filter(|candidate| candidate.is_dir()) {
+            for entry in fs::read_dir(candidate)
+                .wrap_err_with(|| format!("failed to list {}", candidate.display()))?
+            {
+                let name = entry?.file_name().to_string_lossy().to_string();
+                if (!name.starts_with('.') || component.starts_with('.'))
+                    && shake::glob_match(component, &name)
+                {
+                    matches.push(candidate.join(name));
+                }
+            }
+        }
+        candidates = matches;
+    }
+    let mut files: Vec<PathBuf> = candidates
+        .into_iter()
+        .filter(|candidate| candidate.is_file())
+        .collect();
+    files.sort();
+    return Ok(files);
+}
+
+/// The directories in `SHPACK_PATH`, separated by colons like `PATH`, which are searched for
+/// sourced files after any others. Ones that don't exist are skipped.
+pub fn shpack_path() -> Vec<PathBuf> {
+    let Some(value) = env::var_os("SHPACK_PATH") else {
+        return vec![];
+    };
+    return env::split_paths(&value)
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .filter_map(|dir| dir.canonicalize().ok())
+        .collect();
+}
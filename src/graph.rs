@@ -0,0 +1,60 @@
+use std::fmt::Write;
+
+use serde::Serialize;
+use shpack::Bundle;
+
+/// Which file sources which, for `shpack graph`
+#[derive(Debug, Serialize)]
+pub struct Graph {
+    /// The entry script, relative to the root
+    pub entry: String,
+    /// Every file in the graph, starting with the entry script, in the order they were first
+    /// sourced
+    pub files: Vec<String>,
+    pub edges: Vec<Edge>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+}
+
+impl Graph {
+    pub fn new(bundle: &Bundle, entry: String) -> Self {
+        let mut files = vec![entry.clone()];
+        let mut edges = vec![];
+        for (from, to) in bundle.includes() {
+            if !files.contains(to) {
+                files.push(to.clone());
+            }
+            edges.push(Edge {
+                from: from.clone().unwrap_or_else(|| entry.clone()),
+                to: to.clone(),
+            });
+        }
+        return Graph {
+            entry,
+            files,
+            edges,
+        };
+    }
+
+    /// The graph in Graphviz's DOT language, for `dot -Tsvg`
+    pub fn dot(&self) -> String {
+        let mut out = format!("digraph {} {{\n", quote(&self.entry));
+        for file in &self.files {
+            let _ = writeln!(out, "    {};", quote(file));
+        }
+        for edge in &self.edges {
+            let _ = writeln!(out, "    {} -> {};", quote(&edge.from), quote(&edge.to));
+        }
+        out.push_str("}\n");
+        return out;
+    }
+}
+
+/// Quotes a DOT identifier
+fn quote(name: &str) -> String {
+    return format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""));
+}
@@ -0,0 +1,57 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+/// A way the hardening prologue makes a bundle start from a predictable state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum, schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum Hardening {
+    /// Fail on references to unset variables (`set -u`)
+    Nounset,
+    /// Reset `IFS` to space, tab and newline
+    Ifs,
+    /// Sort and format numbers the same everywhere (`LC_COLLATE=C`, `LC_NUMERIC=C`), while
+    /// keeping the user's language for messages
+    Locale,
+    /// Create files readable by everyone but writable only by their owner (`umask 022`)
+    Umask,
+}
+
+impl Hardening {
+    /// Every hardening measure, which `--harden` without a list applies
+    pub fn all() -> Vec<Hardening> {
+        return vec![
+            Hardening::Nounset,
+            Hardening::Ifs,
+            Hardening::Locale,
+            Hardening::Umask,
+        ];
+    }
+
+    fn code(self) -> &'static str {
+        return match self {
+            Hardening::Nounset => "set -u\n",
+            Hardening::Ifs => "IFS=$' \\t\\n'\n",
+            // LC_ALL overrides every category, so it's moved to LANG, which the categories
+            // that aren't set fall back to
+            Hardening::Locale => {
+                "if [ -n \"${LC_ALL:-}\" ]; then LANG=$LC_ALL; unset LC_ALL; fi\n\
+                 export LANG LC_COLLATE=C LC_NUMERIC=C\n"
+            }
+            Hardening::Umask => "umask 022\n",
+        };
+    }
+}
+
+/// The block that applies `measures` before anything else in the bundle runs, or nothing if
+/// there are none
+pub fn prologue(measures: &[Hardening], prefix: &str) -> String {
+    if measures.is_empty() {
+        return String::new();
+    }
+    let mut measures = measures.to_vec();
+    measures.sort();
+    measures.dedup();
+    let code: String = measures.iter().map(|measure| measure.code()).collect();
+    return format!("# {}prologue\n\n{}\n#########\n\n", prefix, code);
+}
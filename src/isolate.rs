@@ -0,0 +1,55 @@
+use color_eyre::Result;
+use tree_sitter::Node;
+
+use crate::{GetText, parse_file};
+
+/// Runs an inlined file's code in a subshell, so its changes to the environment, like `cd`,
+/// `set -e` or variables, don't reach the code after it
+pub fn wrap(body: &str) -> String {
+    return format!("(\n{})\n", body);
+}
+
+/// The functions a file defines and the variables it exports outside functions, none of
+/// which outlive the subshell an isolated file runs in
+pub fn lost_definitions(source: &str) -> Result<Vec<String>> {
+    let tree = parse_file(source)?;
+    let mut names = vec![];
+    visit(tree.root_node(), source, &mut names);
+    names.dedup();
+    return Ok(names);
+}
+
+fn visit(node: Node, source: &str, names: &mut Vec<String>) {
+    match node.kind() {
+        "function_definition" => {
+            if let Some(name) = node.child_by_field_name("name") {
+                names.push(format!("{}()", name.text(source)));
+            }
+            return;
+        }
+        "subshell" | "command_substitution" | "process_substitution" | "pipeline" => return,
+        "declaration_command"
+            if node
+                .child(0)
+                .is_some_and(|keyword| keyword.text(source) == "export") =>
+        {
+            let mut cursor = node.walk();
+            for child in node.named_children(&mut cursor) {
+                let name = match child.kind() {
+                    "variable_assignment" => child.child_by_field_name("name"),
+                    "variable_name" => Some(child),
+                    _ => None,
+                };
+                if let Some(name) = name {
+                    names.push(name.text(source).to_string());
+                }
+            }
+            return;
+        }
+        _ => {}
+    }
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        visit(child, source, names);
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::mem;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Output, Stdio};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+use serde::Serialize;
+
+/// Runs `# build: inline` commands in the background, at most `limit` at a time. Commands
+/// start in the order they were found, so with a limit of 1 they run one after another like
+/// they would in the script.
+pub struct Jobs {
+    limit: usize,
+    state: Arc<(Mutex<State>, Condvar)>,
+    handles: Vec<JoinHandle<(Result<Output>, Vec<Attempt>)>>,
+    /// The commands named with `id=`, by name
+    names: HashMap<String, usize>,
+    /// When commands that are still running are killed, and ones that haven't started yet
+    /// aren't started
+    pub deadline: Option<Instant>,
+}
+
+#[derive(Default)]
+struct State {
+    /// How many commands have started, which is the index of the next one to start
+    started: usize,
+    running: usize,
+    /// Whether each command succeeded, once it's finished
+    finished: Vec<Option<bool>>,
+}
+
+/// How a failed command is run again, for `# build: inline idempotent` commands
+pub struct Retry {
+    /// How many times it's run again at most
+    pub retries: u32,
+    /// How long to wait before the first retry, which doubles before each one after it
+    pub backoff: Duration,
+    /// The file the command's stdout is appended to, which is emptied before each retry
+    pub stdout: File,
+}
+
+/// A failed run of a command that was then run again
+#[derive(Debug, Clone, Serialize)]
+pub struct Attempt {
+    /// How it failed, like `exit status: 1`
+    pub result: String,
+    pub seconds: f64,
+    /// The last line it wrote to stderr
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub stderr: String,
+}
+
+impl Attempt {
+    fn new(output: &Result<Output>, took: Duration) -> Self {
+        let (result, stderr) = match output {
+            Ok(output) => (
+                output.status.to_string(),
+                String::from_utf8_lossy(&output.stderr)
+                    .lines()
+                    .last()
+                    .unwrap_or_default()
+                    .to_string(),
+            ),
+            Err(e) => (e.to_string(), String::new()),
+        };
+        return Attempt {
+            result,
+            seconds: took.as_secs_f64(),
+            stderr,
+        };
+    }
+}
+
+impl fmt::Display for Attempt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} after {:.1}s", self.result, self.seconds)?;
+        if !self.stderr.is_empty() {
+            write!(f, " ({})", self.stderr)?;
+        }
+        return Ok(());
+    }
+}
+
+/// How long the first retry waits by default
+pub const DEFAULT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// One job per CPU
+pub fn default_limit() -> usize {
+    return thread::available_parallelism().map_or(1, |n| n.get());
+}
+
+impl Jobs {
+    pub fn new(limit: usize) -> Self {
+        return Jobs {
+            limit: limit.max(1),
+            state: Arc::new((Mutex::new(State::default()), Condvar::new())),
+            handles: vec![],
+            names: HashMap::new(),
+            deadline: None,
+        };
+    }
+
+    /// Starts running `command` once a slot is free and the commands named in `after` have
+    /// finished. It isn't run at all if one of them failed. With `retry`, it's run again when
+    /// it fails.
+    pub fn start(
+        &mut self,
+        mut command: Command,
+        id: Option<&str>,
+        after: &[String],
+        retry: Option<Retry>,
+    ) -> Result<()> {
+        let index = self.handles.len();
+        let mut dependencies = vec![];
+        for name in after {
+            let Some(dependency) = self.names.get(name) else {
+                return Err(eyre!(
+                    "`after={}` doesn't name an earlier `# build: inline` command, give one `id={}`",
+                    name,
+                    name
+                ));
+            };
+            dependencies.push(*dependency);
+        }
+        if let Some(id) = id
+            && self.names.insert(id.to_string(), index).is_some()
+        {
+            return Err(eyre!("two `# build: inline` commands have `id={}`", id));
+        }
+
+        let (lock, changed) = &*self.state;
+        lock.lock()
+            .expect("job state lock poisoned")
+            .finished
+            .push(None);
+        let limit = self.limit;
+        let deadline = self.deadline;
+        let state = self.state.clone();
+        self.handles.push(thread::spawn(move || {
+            let (lock, changed) = &*state;
+            let mut state = changed
+                .wait_while(lock.lock().expect("job state lock poisoned"), |state| {
+                    return state.started != index
+                        || state.running >= limit
+                        || dependencies.iter().any(|&i| state.finished[i].is_none());
+                })
+                .expect("job state lock poisoned");
+            state.started += 1;
+            let blocked = dependencies
+                .iter()
+                .any(|&i| state.finished[i] == Some(false));
+            if !blocked {
+                state.running += 1;
+            }
+            drop(state);
+            changed.notify_all();
+
+            let mut attempts = vec![];
+            let output = loop {
+                let started = Instant::now();
+                let output = if blocked {
+                    Err(eyre!("not run because a command it runs after failed"))
+                } else if let Some(deadline) = deadline {
+                    run_until(&mut command, deadline)
+                } else {
+                    command.output().map_err(|e| eyre!(e))
+                };
+                let Some(retry) = &retry else {
+                    break output;
+                };
+                if blocked
+                    || output.as_ref().is_ok_and(|o| o.status.success())
+                    || attempts.len() as u32 >= retry.retries
+                {
+                    break output;
+                }
+                let backoff = retry.backoff.saturating_mul(1 << attempts.len().min(16));
+                if deadline.is_some_and(|deadline| Instant::now() + backoff >= deadline) {
+                    break output;
+                }
+                attempts.push(Attempt::new(&output, started.elapsed()));
+                thread::sleep(backoff);
+                if let Err(e) = retry.stdout.set_len(0) {
+                    break Err(eyre!(e));
+                }
+            };
+
+            let mut state = lock.lock().expect("job state lock poisoned");
+            if !blocked {
+                state.running -= 1;
+            }
+            state.finished[index] = Some(output.as_ref().is_ok_and(|o| o.status.success()));
+            drop(state);
+            changed.notify_all();
+            return (output, attempts);
+        }));
+        changed.notify_all();
+        return Ok(());
+    }
+
+    /// Waits for every command started so far, returning their outputs in the order they
+    /// were started, along with the failed attempts before them
+    pub fn finish(&mut self) -> Vec<(Result<Output>, Vec<Attempt>)> {
+        let mut jobs = mem::replace(self, Jobs::new(self.limit));
+        self.deadline = jobs.deadline.take();
+        return jobs
+            .handles
+            .into_iter()
+            .map(|handle| handle.join().expect("a job panicked"))
+            .collect();
+    }
+}
+
+/// Runs `command` like [`Command::output`], but kills it and everything it started if it's
+/// still running at `deadline`. It runs in a process group of its own for that, so it doesn't
+/// get the terminal's Ctrl-C.
+fn run_until(command: &mut Command, deadline: Instant) -> Result<Output> {
+    if Instant::now() >= deadline {
+        return Err(eyre!("not run because the deadline passed"));
+    }
+    let mut child = command
+        .stdin(Stdio::null())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()?;
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let reader = thread::spawn(move || {
+        let mut bytes = vec![];
+        stderr.read_to_end(&mut bytes).map(|_| bytes)
+    });
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            // A negative ID names the group, which the command leads
+            let killed = Command::new("kill")
+                .args(["-KILL", "--", &format!("-{}", child.id())])
+                .status();
+            if !killed.is_ok_and(|status| status.success()) {
+                child.kill()?;
+            }
+            child.wait()?;
+            return Err(eyre!("killed when the deadline passed"));
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    let stderr = reader.join().expect("the stderr reader panicked")?;
+    return Ok(Output {
+        status,
+        stdout: vec![],
+        stderr,
+    });
+}
@@ -0,0 +1,2670 @@
+//! A bundler for bash scripts, which replaces `source` commands with the files they source
+//! and runs `# build: inline` commands ahead of time.
+//!
+//! [`Bundler::builder`] configures a bundle, and [`Bundler::bundle_file`] builds it:
+//!
+//! ```no_run
+//! let bundle = shpack::Bundler::builder()
+//!     .root("scripts")
+//!     .tree_shake(true)
+//!     .build()?
+//!     .bundle_file("scripts/main.sh".as_ref())?;
+//! bundle.write_to(&mut std::io::stdout())?;
+//! # Ok::<(), color_eyre::Report>(())
+//! ```
+#![allow(clippy::needless_return)]
+
+mod alias;
+pub mod annotate;
+mod archive;
+pub mod batch;
+mod builder;
+mod cd;
+mod chaos;
+mod cli;
+mod conditional;
+mod data;
+pub mod deadline;
+pub mod defines;
+mod directive;
+mod error_trap;
+mod exports;
+mod harden;
+mod isolate;
+mod jobs;
+mod library;
+mod listing;
+mod logging;
+mod markers;
+mod messages;
+mod options;
+mod payload;
+mod portable;
+pub mod progress;
+pub mod provenance;
+mod pure;
+pub mod report;
+mod runtime;
+mod script_dir;
+mod secrets;
+mod self_extract;
+mod shake;
+mod shell;
+mod source_cache;
+mod stamp;
+pub mod startup;
+pub mod target;
+mod template;
+mod trace;
+mod transform;
+mod unreachable;
+mod wasm;
+
+// Helpers the `shpack` binary shares with the library, which aren't part of its API
+#[doc(hidden)]
+pub mod fs_util;
+#[doc(hidden)]
+pub use crate::chaos::Chaos;
+#[doc(hidden)]
+pub use crate::stamp::{commit, utc};
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashSet;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::iter;
+use std::mem;
+use std::ops::Range;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+use tempfile::NamedTempFile;
+use tempfile::TempPath;
+use tree_sitter::Node;
+use tree_sitter::Parser;
+use tree_sitter::Tree;
+use tree_sitter_bash::LANGUAGE as bash_language;
+
+use crate::alias::Wrapper;
+use crate::cli::{CliSpec, CompletionFile};
+use crate::deadline::Deadline;
+use crate::directive::Directive;
+use crate::directive::parse_bool;
+use crate::exports::Exports;
+use crate::jobs::{Attempt, Jobs, Retry};
+use crate::library::Library;
+use crate::messages::Catalog;
+use crate::progress::Event;
+use crate::runtime::Runtime;
+use crate::script_dir::Anchor;
+use crate::self_extract::SelfExtract;
+use crate::stamp::Stamp;
+use crate::trace::Trace;
+use crate::transform::{IncludeOptions, lazy_loader, minify, once_guard, transform_edits};
+
+pub use crate::builder::{BundlerBuilder, ShebangPolicy};
+pub use crate::defines::Defines;
+pub use crate::harden::Hardening;
+pub use crate::listing::Listing;
+pub use crate::markers::Markers;
+pub use crate::options::BundleOptions;
+pub use crate::payload::{Compression, Encoding};
+pub use crate::runtime::DEFAULT_PREFIX;
+pub use crate::secrets::SecretsPolicy;
+pub use crate::self_extract::Format;
+pub use crate::source_cache::SourceCache;
+pub use crate::target::Target;
+pub use crate::template::Metadata;
+pub use crate::transform::Strip;
+
+trait GetText {
+    fn text<'a>(&self, source: &'a str) -> &'a str;
+}
+
+impl<'tree> GetText for Node<'tree> {
+    fn text<'a>(&self, source: &'a str) -> &'a str {
+        return &source[self.start_byte()..self.end_byte()];
+    }
+}
+
+fn parse_file(source: &str) -> Result<Tree> {
+    let mut parser = Parser::new();
+    parser.set_language(&bash_language.into())?;
+
+    let tree = parser
+        .parse(source, None)
+        .ok_or(eyre!("couldn't parse file"))?;
+
+    return Ok(tree);
+}
+
+/// Recursively visits every node in the tree rooted at `node` and calls `f` for each node.
+fn visit_node<F>(node: tree_sitter::Node, f: &mut F) -> Result<()>
+where
+    F: FnMut(tree_sitter::Node) -> Result<()>,
+{
+    f(node)?;
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        visit_node(child, f)?;
+    }
+    return Ok(());
+}
+
+/// Bundles an entry script with the files it sources. Each bundler builds one bundle, and is
+/// configured with [`Bundler::builder`].
+pub struct Bundler {
+    path_relative_to: PathBuf,
+    /// Directories searched, in order, for sourced files that don't exist relative to the
+    /// sourcing file
+    include_paths: Vec<PathBuf>,
+    shabang: Option<String>,
+    /// The shabang the bundle gets instead of the entry script's, which lets the files' own
+    /// shabangs differ
+    shabang_override: Option<String>,
+    /// Options applied to every included file unless overridden by a directive
+    include_options: IncludeOptions,
+    /// A recorded run of the bundle used to optimize its layout
+    trace: Option<Trace>,
+    /// Parsed files shared with other bundles built by the same process
+    source_cache: Option<Arc<SourceCache>>,
+    /// The working directory for `# build: inline` commands, defaults to the current directory
+    command_dir: Option<PathBuf>,
+    /// The environment of `# build: inline` commands, defaults to this process's
+    command_env: Option<BTreeMap<String, String>>,
+    visiting: Vec<PathBuf>,
+    visited: HashSet<PathBuf>,
+    /// Files whose `# shpack: once` pragma asks for them to be included only once
+    once: HashSet<PathBuf>,
+    fragments: Vec<Fragment>,
+    warnings: Vec<String>,
+    notes: Vec<String>,
+    ran_commands: bool,
+    /// Command outputs at least this many bytes long are spooled to disk instead of memory
+    stream_threshold: u64,
+    payloads: Vec<(TempPath, Encoding)>,
+    /// Once about this many bytes of sources, fragments and command outputs are held in memory,
+    /// fragments and command outputs are written to disk instead
+    memory_cap: Option<u64>,
+    /// The approximate number of bytes held in memory, as counted by [`Bundler::hold`]
+    held: u64,
+    over_cap: bool,
+    /// How command outputs of at least `compress_threshold` bytes are compressed, unless their
+    /// directive says otherwise
+    compression: Compression,
+    compress_threshold: u64,
+    /// How command outputs are written into the bundle, unless their directive says otherwise
+    encoding: Encoding,
+    /// The kind of system the bundle has to run on, which limits the tools it can rely on
+    target: Option<Target>,
+    /// Helpers the generated code calls, defined once at the top of the bundle
+    runtime: Runtime,
+    /// Values that replace those of `# build: env` assignments
+    defines: Defines,
+    /// What `{{name}}`, `{{version}}` and `{{description}}` are replaced with
+    metadata: Metadata,
+    /// Files read by `# build:` directives, which are inputs to the bundle like sourced files
+    data_files: BTreeSet<PathBuf>,
+    /// The directories globs searched, whose contents are inputs too
+    listings: Vec<Listing>,
+    /// Remove functions nothing calls, except those matching the `keep` globs
+    tree_shake: bool,
+    keep: Vec<String>,
+    /// Functions whose tree-shaking decision is explained in the notes
+    why_kept: Vec<String>,
+    /// Replace GNU-only tool options with helpers that also work on BSD systems
+    portable: bool,
+    /// Add the logging library to the runtime
+    with_logging: bool,
+    /// Turn `echo "DEBUG: ..."` and the like into calls to the logging library
+    rewrite_log_echoes: bool,
+    /// Add a helper printing the version, commit and build date
+    version_stamp: bool,
+    /// Measures applied before anything else in the bundle runs
+    harden: Vec<Hardening>,
+    /// Which file sourced which, relative to the root, with None for the entry script
+    includes: Vec<(Option<String>, String)>,
+    /// The inlined files, relative to the root, in the order their contents appear in the
+    /// bundle. Files inlined more than once appear each time.
+    order: Vec<String>,
+    commands: Vec<InlineCommand>,
+    /// The libraries whose `# shpack-lib:` headers were read, in the order they were sourced
+    libraries: Vec<Library>,
+    /// Fail when two versions of the same library are bundled, rather than warning
+    deny_version_conflicts: bool,
+    /// What happens when the bundle, or something embedded in it, looks like it contains a
+    /// credential
+    secrets: SecretsPolicy,
+    /// What looked like credentials so far, described
+    secret_findings: Vec<String>,
+    /// Whether the bundle is written as a script or a self-extracting archive
+    format: Format,
+    /// A directory whose files a self-extracting bundle's archive holds too
+    archive_dir: Option<PathBuf>,
+    /// The code that extracts each `# build: embed` file, along with the placeholder that
+    /// stands in for it
+    embedded: Vec<(String, String)>,
+    /// How many files have had `# shpack: with` variables set around them, so each one saves
+    /// its callers' values in variables of its own
+    scoped_variables: usize,
+    /// The standalone completion files `# build: cli-spec` directives asked for
+    completion_files: Vec<CompletionFile>,
+    /// Fail on sources with absolute paths that aren't explicitly allowed
+    deny_absolute_sources: bool,
+    /// Bundle sourced files of at least [`LARGE_INCLUDE`] bytes with a warning, rather than
+    /// failing
+    allow_large_include: bool,
+    /// Skip `source` commands that would inline a file into itself, rather than failing
+    allow_circular: bool,
+    /// Globs of files, relative to the root, that may use the reserved prefix
+    exempt: Vec<String>,
+    /// Globs of paths, as they're written in `source` commands, that are left to be sourced at
+    /// runtime. When there are any, every other source must be bundled.
+    runtime_sources: Vec<String>,
+    /// Globs of files, relative to the root or absolute, whose `source` commands are left as
+    /// they are rather than inlined
+    external: Vec<String>,
+    /// Commands besides `source` and `.` that source a file, from --source-alias and the
+    /// `alias include=source` commands found so far
+    source_aliases: Vec<String>,
+    /// Remove the definitions of the aliases, since their uses are bundled
+    strip_source_aliases: bool,
+    /// Functions like `import() { source "lib/$1.sh"; }` found so far, by name
+    wrappers: BTreeMap<String, Wrapper>,
+    /// Variables besides `SCRIPT_DIR` that hold the entry script's directory, so sourcing
+    /// `$NAME/lib.sh` bundles `lib.sh` from there
+    script_dir_variables: Vec<String>,
+    /// The directory of the entry script, which `$(dirname "$0")` stands for
+    entry_dir: PathBuf,
+    /// The comments around each inlined file
+    markers: Markers,
+    /// Put every inlined file at the top of the bundle, after the files it sources, rather
+    /// than where it's sourced
+    topological_includes: bool,
+    /// The inlined files moved to the top of the bundle, in order
+    hoisted: Vec<String>,
+    /// Files inlined before the entry script's code, like a standard error handler
+    prelude: Vec<PathBuf>,
+    /// Files inlined after the entry script's code
+    epilogue: Vec<PathBuf>,
+    /// WebAssembly modules that rewrite each file, in order, before it's bundled
+    transforms: Vec<PathBuf>,
+    /// The compiled `transforms`
+    wasm: Vec<wasm::Transform>,
+    /// Run `# build: inline` commands. When false, they're left to run at runtime.
+    run_commands: bool,
+    /// Runs `# build: inline` commands in the background
+    jobs: Jobs,
+    /// The commands started so far, whose outputs go in place of their placeholders once the
+    /// entry script has been visited
+    pending: Vec<PendingCommand>,
+    /// Strip comments, blank lines and markers from the whole bundle
+    minify: bool,
+    /// Add an `ERR` trap that prints where in the sources a failing command came from
+    inject_trace: bool,
+    /// The entry script's name in the `ERR` trap's messages
+    entry_name: String,
+    /// The arguments shpack was run with, recorded in a comment after the shabang
+    provenance: Option<Vec<String>>,
+    /// Emit progress events as files are inlined
+    progress: bool,
+    /// Simulated failures and delays
+    chaos: Chaos,
+    /// When the build fails if it isn't done
+    deadline: Option<Deadline>,
+    /// How many times a failed `# build: inline idempotent` command is run again
+    retries: u32,
+    /// How long the first retry waits, which doubles for each one after it
+    retry_backoff: Duration,
+}
+
+/// Sourced files at least this large are probably a mistake, like a generated file or a build
+/// artifact, so they're only bundled with `--allow-large-include`
+const LARGE_INCLUDE: u64 = 2 * 1024 * 1024;
+
+/// The result of bundling an entry script
+pub struct Bundle {
+    /// The bundled script, with markers where spooled payloads go. Use [`Bundle::write_to`] to
+    /// get the finished script.
+    content: String,
+    /// Large command outputs kept on disk until the bundle is written
+    payloads: Vec<(TempPath, Encoding)>,
+    /// Every file that was inlined into the bundle, not including the entry script
+    sources: Vec<PathBuf>,
+    /// The bundled contents of each inlined file, in the order they were finished
+    fragments: Vec<Fragment>,
+    /// Problems that didn't stop the bundle from being built
+    warnings: Vec<String>,
+    /// Decisions the bundler made that are worth knowing about, like which functions
+    /// tree-shaking kept for indirect uses
+    notes: Vec<String>,
+    /// Whether any `# build: inline` command was executed to produce the bundle
+    ran_commands: bool,
+    /// External tools the bundle relies on at runtime, and why
+    required_tools: BTreeMap<String, String>,
+    /// Which file sourced which, relative to the root, with None for the entry script
+    includes: Vec<(Option<String>, String)>,
+    /// The inlined files, relative to the root, in the order their contents appear in the
+    /// bundle. Files inlined more than once appear each time.
+    order: Vec<String>,
+    /// The `# build: inline` commands that were run
+    commands: Vec<InlineCommand>,
+    /// The libraries that describe themselves with `# shpack-lib:` headers
+    libraries: Vec<Library>,
+    /// The functions the bundle exports to child processes with `export -f`
+    exported_functions: Vec<String>,
+    /// How the script is wrapped in a self-extracting archive, if it is
+    self_extract: Option<SelfExtract>,
+    /// The standalone completion files `# build: cli-spec` directives asked for
+    completion_files: Vec<CompletionFile>,
+    /// The directories globs searched, whose contents are inputs to the bundle too
+    listings: Vec<Listing>,
+}
+
+/// A `# build: inline` command that was run to produce the bundle
+#[derive(Debug, Clone, serde::Serialize)]
+struct InlineCommand {
+    /// The file it's in, relative to the root, or None for the entry script
+    file: Option<String>,
+    line: usize,
+    command: String,
+    output_bytes: u64,
+    encoding: Encoding,
+    /// The compression that was applied, which is none if it wouldn't have helped
+    compression: Compression,
+    as_file: bool,
+    /// What the command wrote to stderr, whatever was done with it
+    #[serde(skip_serializing_if = "String::is_empty")]
+    stderr: String,
+    /// The runs that failed before the one that succeeded
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    attempts: Vec<Attempt>,
+}
+
+/// A `# build: inline` command that was started, and how its output is embedded
+struct PendingCommand {
+    /// The file it's in, relative to the root, or None for the entry script
+    file: Option<String>,
+    line: usize,
+    command: String,
+    /// Where its stdout is written
+    spool: NamedTempFile,
+    compression: Compression,
+    encoding: Encoding,
+    as_file: bool,
+    stderr_policy: StderrPolicy,
+    /// Whether a `# shpack: allow-secret` directive is above it, so its output isn't scanned
+    allow_secrets: bool,
+}
+
+impl PendingCommand {
+    /// Where the command is, like `line 3 of lib/a.sh`
+    fn location(&self) -> String {
+        return match &self.file {
+            Some(file) => format!("line {} of {}", self.line, file),
+            None => format!("line {} of the entry script", self.line),
+        };
+    }
+
+    /// What stands in for the command substitution until the command has finished. Bundled
+    /// files can't use the reserved prefix, so it can't clash with their code.
+    fn output_placeholder(prefix: &str, index: usize) -> String {
+        return format!("{}output_{}_", prefix, index);
+    }
+
+    /// What stands in for the directive's comment, which is removed or replaced with the
+    /// command's stderr
+    fn stderr_placeholder(prefix: &str, index: usize) -> String {
+        return format!("# {}stderr_{}_", prefix, index);
+    }
+}
+
+/// What's done with a `# build: inline` command's stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StderrPolicy {
+    /// Print it along with shpack's own messages
+    Show,
+    /// Fail the build if there is any
+    Fail,
+    Silence,
+    /// Put it in comments next to the substitution
+    Comment,
+}
+
+impl Bundle {
+    /// Writes the finished bundle, which is the script, or with `--format self-extract`, a
+    /// stub followed by an archive holding it
+    pub fn write_to(&self, out: &mut impl Write) -> Result<()> {
+        let Some(self_extract) = &self.self_extract else {
+            return self.write_script_to(out);
+        };
+        let mut script = vec![];
+        self.write_script_to(&mut script)?;
+        return self_extract.write(&script, out);
+    }
+
+    /// Writes the finished script, without the archive a self-extracting bundle puts it in
+    pub fn write_script_to(&self, out: &mut impl Write) -> Result<()> {
+        return payload::write_content(&self.content, &self.payloads, out);
+    }
+
+    /// Returns the finished script, which holds every payload in memory at once. A
+    /// self-extracting bundle's archive isn't included.
+    pub fn into_string(self) -> Result<String> {
+        if self.payloads.is_empty() {
+            return Ok(self.content);
+        }
+        let mut out = vec![];
+        self.write_script_to(&mut out)?;
+        return Ok(String::from_utf8(out)?);
+    }
+
+    /// Every file that was inlined into the bundle or read by a `# build:` directive, not
+    /// including the entry script
+    pub fn sources(&self) -> &[PathBuf] {
+        return &self.sources;
+    }
+
+    /// Problems that didn't stop the bundle from being built
+    pub fn warnings(&self) -> &[String] {
+        return &self.warnings;
+    }
+
+    /// Decisions the bundler made that are worth knowing about
+    pub fn notes(&self) -> &[String] {
+        return &self.notes;
+    }
+
+    /// The external tools the bundle relies on at runtime, and why
+    pub fn required_tools(&self) -> &BTreeMap<String, String> {
+        return &self.required_tools;
+    }
+
+    /// Which file sourced which, relative to the root, with None for the entry script
+    pub fn includes(&self) -> &[(Option<String>, String)] {
+        return &self.includes;
+    }
+
+    /// The inlined files, relative to the root, in the order their contents appear in the
+    /// bundle, which decides which of two definitions of a function wins. Files skipped
+    /// because they were already included aren't listed again.
+    pub fn order(&self) -> &[String] {
+        return &self.order;
+    }
+
+    /// The directories the bundle's globs searched, and what they found there
+    pub fn listings(&self) -> &[Listing] {
+        return &self.listings;
+    }
+
+    /// Whether any `# build: inline` command was executed to produce the bundle
+    pub fn ran_commands(&self) -> bool {
+        return self.ran_commands;
+    }
+
+    /// The functions the bundle exports to child processes with `export -f`
+    pub fn exported_functions(&self) -> &[String] {
+        return &self.exported_functions;
+    }
+
+    /// Writes the standalone completion files that `# build: cli-spec` directives asked
+    /// for, which is left to the caller so checking or graphing a script writes nothing
+    pub fn write_completion_files(&self) -> Result<()> {
+        for file in &self.completion_files {
+            fs_util::write_atomic(&file.path, file.content.as_bytes())?;
+        }
+        return Ok(());
+    }
+}
+
+/// An inlined file along with everything it sourced
+struct Fragment {
+    /// The path of the file relative to the bundler's root
+    path: String,
+    /// The size of the contents in bytes
+    bytes: u64,
+    content: FragmentContent,
+}
+
+/// Where the contents of a fragment are kept
+enum FragmentContent {
+    Memory(String),
+    /// Written to disk because the bundler went over its memory cap
+    Spilled(TempPath),
+}
+
+impl Fragment {
+    fn new(path: String, content: String) -> Self {
+        return Fragment {
+            path,
+            bytes: content.len() as u64,
+            content: FragmentContent::Memory(content),
+        };
+    }
+
+    /// Creates a fragment whose contents are kept on disk
+    fn spilled(path: String, content: &str) -> Result<Self> {
+        return Ok(Fragment {
+            path,
+            bytes: content.len() as u64,
+            content: FragmentContent::Spilled(spill(content)?),
+        });
+    }
+
+    /// Moves the contents to disk, if they aren't there already
+    fn spill(&mut self) -> Result<()> {
+        if let FragmentContent::Memory(content) = &self.content {
+            self.content = FragmentContent::Spilled(spill(content)?);
+        }
+        return Ok(());
+    }
+
+    fn content(&self) -> Result<String> {
+        return match &self.content {
+            FragmentContent::Memory(content) => Ok(content.clone()),
+            FragmentContent::Spilled(path) => Ok(fs::read_to_string(path)?),
+        };
+    }
+
+    /// Puts the outputs of `# build: inline` commands in place of their placeholders
+    fn fill(&mut self, replacements: &[(String, String)]) -> Result<()> {
+        let content = fill(self.content()?, replacements);
+        self.bytes = content.len() as u64;
+        self.content = match self.content {
+            FragmentContent::Memory(_) => FragmentContent::Memory(content),
+            FragmentContent::Spilled(_) => FragmentContent::Spilled(spill(&content)?),
+        };
+        return Ok(());
+    }
+}
+
+/// Replaces each placeholder in `text` with what it stands for
+fn fill(mut text: String, replacements: &[(String, String)]) -> String {
+    for (placeholder, content) in replacements {
+        if text.contains(placeholder.as_str()) {
+            text = text.replace(placeholder.as_str(), content);
+        }
+    }
+    return text;
+}
+
+/// Writes `content` to a temporary file
+fn spill(content: &str) -> Result<TempPath> {
+    let mut file = NamedTempFile::new()?;
+    file.write_all(content.as_bytes())?;
+    return Ok(file.into_temp_path());
+}
+
+impl Bundler {
+    /// Starts configuring a bundler
+    pub fn builder() -> BundlerBuilder {
+        return BundlerBuilder::new();
+    }
+
+    /// A bundler with the default options, whose root is set by [`BundlerBuilder::build`]
+    fn unrooted() -> Self {
+        Bundler {
+            path_relative_to: PathBuf::new(),
+            include_paths: vec![],
+            shabang: Default::default(),
+            shabang_override: None,
+            include_options: IncludeOptions::default(),
+            trace: None,
+            source_cache: None,
+            command_dir: None,
+            command_env: None,
+            visiting: vec![],
+            visited: HashSet::new(),
+            once: HashSet::new(),
+            fragments: vec![],
+            warnings: vec![],
+            notes: vec![],
+            ran_commands: false,
+            stream_threshold: payload::DEFAULT_STREAM_THRESHOLD,
+            payloads: vec![],
+            memory_cap: None,
+            held: 0,
+            over_cap: false,
+            compression: Compression::default(),
+            compress_threshold: payload::DEFAULT_COMPRESS_THRESHOLD,
+            encoding: Encoding::default(),
+            target: None,
+            runtime: Runtime::default(),
+            defines: Defines::new(),
+            metadata: Metadata::new(),
+            data_files: BTreeSet::new(),
+            listings: vec![],
+            tree_shake: false,
+            keep: vec![],
+            why_kept: vec![],
+            portable: false,
+            with_logging: false,
+            rewrite_log_echoes: false,
+            version_stamp: false,
+            harden: vec![],
+            includes: vec![],
+            order: vec![],
+            commands: vec![],
+            libraries: vec![],
+            deny_version_conflicts: false,
+            secrets: SecretsPolicy::default(),
+            secret_findings: vec![],
+            format: Format::default(),
+            archive_dir: None,
+            embedded: vec![],
+            scoped_variables: 0,
+            completion_files: vec![],
+            deny_absolute_sources: false,
+            allow_large_include: false,
+            allow_circular: false,
+            exempt: vec![],
+            runtime_sources: vec![],
+            external: vec![],
+            source_aliases: vec![],
+            strip_source_aliases: false,
+            wrappers: BTreeMap::new(),
+            script_dir_variables: vec![],
+            entry_dir: PathBuf::new(),
+            markers: Markers::default(),
+            topological_includes: false,
+            hoisted: vec![],
+            prelude: vec![],
+            epilogue: vec![],
+            transforms: vec![],
+            wasm: vec![],
+            run_commands: true,
+            jobs: Jobs::new(jobs::default_limit()),
+            pending: vec![],
+            minify: false,
+            inject_trace: false,
+            entry_name: String::from("<stdin>"),
+            provenance: None,
+            progress: false,
+            chaos: Chaos::default(),
+            deadline: None,
+            retries: 0,
+            retry_backoff: jobs::DEFAULT_BACKOFF,
+        }
+    }
+
+    /// Bundles the script in `path`, resolving the files it sources relative to its directory
+    pub fn bundle_file(mut self, path: &Path) -> Result<Bundle> {
+        let source = fs_util::read_script(path)
+            .wrap_err_with(|| format!("can't bundle {}", path.display()))?;
+        let path = path.canonicalize()?;
+        self.entry_name = self.display_name(&path);
+        let cwd = path.parent().expect("file path should have parent");
+        return self.bundle(source, cwd);
+    }
+
+    // Must consume self since the data managed by Bundler must be reset after each bundle
+    /// Bundles the given source code, resolving the files it sources relative to `cwd`
+    pub fn bundle(mut self, source: String, cwd: &Path) -> Result<Bundle> {
+        let cwd = &cwd.canonicalize()?;
+        if !shell::is_identifier(&self.runtime.prefix) {
+            return Err(eyre!(
+                "the symbol prefix must be a valid variable name, found `{}`",
+                self.runtime.prefix
+            ));
+        }
+        self.jobs.deadline = self.deadline.as_ref().map(Deadline::at);
+        self.check_deadline("starting")?;
+        self.chaos.file()?;
+        if let Some(target) = self.target {
+            self.defines
+                .entry("OS".to_string())
+                .or_insert(target.name().to_string());
+        }
+        self.entry_dir = cwd.clone();
+        self.wasm = wasm::load(&self.transforms)?;
+        // The modules are inputs to the bundle like the files they rewrite
+        self.data_files.extend(self.transforms.iter().cloned());
+        let source = wasm::apply(&self.wasm, &self.entry_name, source)?;
+        if self.inject_trace && self.minify {
+            return Err(eyre!(
+                "the ERR trap finds lines by the markers that minifying removes, so --inject-trace can't be used with --minify"
+            ));
+        }
+        // Kept to trace lines of the bundle back to the entry script
+        let entry_source = source.clone();
+        self.hold(source.len() as u64)?;
+        let (prelude, epilogue) = (mem::take(&mut self.prelude), mem::take(&mut self.epilogue));
+        let mut prelude = self.inline_around(prelude)?;
+        let mut out = self._bundle_from_string(source, cwd, &IncludeOptions::default())?;
+        let epilogue = self.inline_around(epilogue)?;
+        if !epilogue.is_empty() {
+            out = format!("{}\n{}\n", out, epilogue);
+        }
+        if self.secrets != SecretsPolicy::Off {
+            // Scanned before command outputs are filled in, since they're scanned decoded
+            let mut text = prelude.clone();
+            for block in &self.hoisted {
+                text = format!("{}\n{}", text, block);
+            }
+            text = format!("{}\n{}", text, out);
+            self.scan_bundle(&text, &entry_source);
+        }
+        let archived = match &self.archive_dir {
+            Some(dir) => self.archive_files(dir.clone())?,
+            None => vec![],
+        };
+        let mut replacements = self.finish_commands()?;
+        replacements.append(&mut self.embedded);
+        if !replacements.is_empty() {
+            prelude = fill(prelude, &replacements);
+            out = fill(out, &replacements);
+            self.hoisted = self
+                .hoisted
+                .into_iter()
+                .map(|block| fill(block, &replacements))
+                .collect();
+            for fragment in &mut self.fragments {
+                fragment.fill(&replacements)?;
+            }
+        }
+        if !self.hoisted.is_empty() {
+            out = format!("{}\n{}", self.hoisted.join("\n"), out);
+        }
+        if !self.secret_findings.is_empty() {
+            if self.secrets == SecretsPolicy::Deny {
+                return Err(eyre!(
+                    "the bundle looks like it contains credentials:\n  {}\nRemove them, put `# shpack: allow-secret` above what's safe to ship, or pass --secrets warn.",
+                    self.secret_findings.join("\n  ")
+                ));
+            }
+            for finding in mem::take(&mut self.secret_findings) {
+                self.warnings.push(format!(
+                    "{}. Put `# shpack: allow-secret` above what's safe to ship.",
+                    finding
+                ));
+            }
+        }
+        // The prelude comes first, even before included libraries
+        if !prelude.is_empty() {
+            out = format!("{}\n{}", prelude, out);
+        }
+        let mut shaken = BTreeSet::new();
+        if self.tree_shake {
+            let decisions;
+            (out, decisions) = shake::shake(out, &self.keep)?;
+            self.notes.extend(decisions.indirect_notes());
+            for name in &self.why_kept {
+                self.notes.push(decisions.explain(name));
+            }
+            shaken = decisions.removed.into_keys().collect();
+        }
+        // Child processes can only call functions that are exported, which breaks quietly
+        let exports = Exports::find(&out)?;
+        for name in exports.missing() {
+            self.warnings.push(if shaken.contains(name) {
+                format!(
+                    "tree-shaking removed `{}`, which is exported with `export -f`. Keep it with `# shpack: keep` or --keep.",
+                    name
+                )
+            } else {
+                format!(
+                    "`{}` is exported with `export -f`, but the bundle doesn't define it",
+                    name
+                )
+            });
+        }
+        if self.portable {
+            out = portable::shim(out, &mut self.runtime)?;
+        } else if self.target == Some(Target::Macos) {
+            for usage in portable::find(&out)? {
+                self.warnings.push(format!(
+                    "`{}` is used, which macOS's version doesn't support the same way. Use --portable to replace it with a helper that works on both.",
+                    usage
+                ));
+            }
+        }
+        if self.version_stamp {
+            let stamp = Stamp::new(
+                &self.path_relative_to,
+                self.metadata.get("name").cloned(),
+                self.metadata.get("version").cloned(),
+            );
+            let p = self.runtime.prefix.clone();
+            self.runtime
+                .define(format!("{}version", p), || stamp.definition(&p));
+        }
+        if self.with_logging || self.rewrite_log_echoes {
+            let warnings;
+            (out, warnings) = logging::inject(out, &mut self.runtime, self.rewrite_log_echoes)?;
+            self.warnings.extend(warnings);
+        }
+        // A library that ends with `exec` cuts off everything after where it's sourced
+        let dead_execs = unreachable::execs(&out)?;
+        if !dead_execs.is_empty() {
+            let sources: Vec<PathBuf> = self.visited.iter().cloned().collect();
+            let (files, lines) = annotate::line_table(
+                &out,
+                (self.entry_name.clone(), entry_source.clone()),
+                &self.path_relative_to,
+                &sources,
+                &self.runtime.prefix,
+                &self.markers,
+            );
+            for row in dead_execs {
+                let location = match lines.get(row).copied().flatten() {
+                    Some((file, _)) if file > 0 && self.is_exempt(&files[file]) => continue,
+                    Some((file, line)) => format!("on line {} of {}", line, files[file]),
+                    None => format!("on line {} of the bundle", row + 1),
+                };
+                self.warnings.push(format!(
+                    "`{}` {} replaces the shell, so the code after it in the bundle never runs. Move it to the end, or put `# shpack: allow-exec` above it if that's intended.",
+                    out.lines().nth(row).unwrap_or_default().trim(),
+                    location
+                ));
+            }
+        }
+        let conflicts = library::conflicts(&self.libraries);
+        if self.deny_version_conflicts && !conflicts.is_empty() {
+            return Err(eyre!(conflicts.join("\n")));
+        }
+        self.warnings.extend(conflicts);
+        if self.target == Some(Target::Macos)
+            && let Some(min_bash) = library::min_bash(&self.libraries)
+            && library::compare_versions(min_bash, "3.2") == Ordering::Greater
+        {
+            self.warnings.push(format!(
+                "the bundled libraries need bash {} or newer, but macOS ships bash 3.2",
+                min_bash
+            ));
+        }
+        let mut required_tools: BTreeMap<String, String> = self
+            .runtime
+            .tools()
+            .iter()
+            .map(|(tool, reason)| (tool.to_string(), reason.to_string()))
+            .collect();
+        for library in &self.libraries {
+            for tool in &library.tools {
+                required_tools
+                    .entry(tool.clone())
+                    .or_insert_with(|| format!("used by library `{}`", library.label()));
+            }
+        }
+        let mut content = format!(
+            "{}{}{}",
+            harden::prologue(&self.harden, &self.runtime.prefix),
+            self.runtime.code(),
+            out
+        );
+        if self.minify {
+            content = minify(content)?;
+        }
+        let mut shabang = match self.shabang_override {
+            Some(shabang) => {
+                if !shabang.starts_with("#!") || shabang.contains('\n') {
+                    return Err(eyre!(
+                        "the bundle's shabang must be a single line starting with `#!`, not `{}`",
+                        shabang
+                    ));
+                }
+                shabang
+            }
+            None => self.shabang.ok_or(eyre!("Shabang is missing"))?,
+        };
+        if let Some(args) = &self.provenance {
+            shabang = format!("{}\n{}", shabang, provenance::header(args));
+        }
+        if let Some(target) = self.target {
+            target.check(self.runtime.tools(), &mut self.warnings)?;
+        }
+        let self_extract = match self.format {
+            Format::Script => None,
+            Format::SelfExtract => {
+                let tools: BTreeMap<&str, &str> = self_extract::TOOLS.iter().copied().collect();
+                if let Some(target) = self.target {
+                    target.check(&tools, &mut self.warnings)?;
+                }
+                for (tool, reason) in tools {
+                    required_tools
+                        .entry(tool.to_string())
+                        .or_insert(reason.to_string());
+                }
+                let name = match Path::new(&self.entry_name).file_name() {
+                    Some(name) if self.entry_name != "<stdin>" => {
+                        name.to_string_lossy().to_string()
+                    }
+                    _ => String::from("bundle.sh"),
+                };
+                Some(SelfExtract {
+                    name,
+                    interpreter: shabang.lines().next().unwrap_or_default()[2..]
+                        .trim()
+                        .to_string(),
+                    dir: self.archive_dir.clone(),
+                    tmpdir: self.runtime.tmpdir.clone(),
+                })
+            }
+        };
+        let mut sources: Vec<PathBuf> = self.visited.into_iter().collect();
+        sources.extend(self.data_files);
+        sources.extend(archived);
+        sources.sort();
+        let mut content = format!("{}\n\n{}", shabang, content);
+        let mut payloads = self.payloads;
+        if self.inject_trace {
+            if !shabang.lines().next().unwrap_or_default().contains("bash") {
+                return Err(eyre!(
+                    "--inject-trace needs bash, but the shabang is `{}`",
+                    shabang.lines().next().unwrap_or_default()
+                ));
+            }
+            // Payloads can span lines, so the table is made from the finished script
+            let mut full = vec![];
+            payload::write_content(&content, &payloads, &mut full)?;
+            let full = String::from_utf8(full)?;
+            let (files, lines) = annotate::line_table(
+                &full,
+                (self.entry_name, entry_source),
+                &self.path_relative_to,
+                &sources,
+                &self.runtime.prefix,
+                &self.markers,
+            );
+            let offset = shabang.lines().count();
+            let split = full
+                .match_indices('\n')
+                .nth(offset - 1)
+                .map(|(i, _)| i + 1)
+                .unwrap_or(full.len());
+            content = format!(
+                "{}{}{}",
+                &full[..split],
+                error_trap::handler(&files, &lines, offset, &self.runtime.prefix),
+                &full[split..]
+            );
+            payloads = vec![];
+        }
+        return Ok(Bundle {
+            content,
+            payloads,
+            sources,
+            fragments: self.fragments,
+            warnings: self.warnings,
+            notes: self.notes,
+            ran_commands: self.ran_commands,
+            required_tools,
+            includes: self.includes,
+            order: self.order,
+            commands: self.commands,
+            libraries: self.libraries,
+            exported_functions: exports
+                .exported
+                .intersection(&exports.defined)
+                .cloned()
+                .collect(),
+            self_extract,
+            completion_files: self.completion_files,
+            listings: self.listings,
+        });
+    }
+
+    /// Inlines each of `paths`, which `path_str` named, returning the blocks that take the
+    /// place of the command that sourced them. With `hoist`, the blocks are moved to the top
+    /// of the bundle instead.
+    fn inline_files(
+        &mut self,
+        paths: Vec<PathBuf>,
+        include_options: &IncludeOptions,
+        path_str: &str,
+        hoist: bool,
+    ) -> Result<Vec<String>> {
+        let mut parts = vec![];
+        for path in paths {
+            let include = (self.current_file(), self.display_name(&path));
+            if !self.includes.contains(&include) {
+                self.includes.push(include);
+            }
+
+            let once = self.once.contains(&path) || include_options.once != Some(false);
+            if self.visited.contains(&path) && once {
+                continue;
+            }
+            // The file's code is already around this point, so the cycle ends here
+            if self.allow_circular && self.visiting.contains(&path) {
+                self.notes.push(format!(
+                    "skipped sourcing {} again from {}, since it's still being inlined (--allow-circular)",
+                    self.display_name(&path),
+                    self.current_file().unwrap_or(String::from("the entry script"))
+                ));
+                continue;
+            }
+            let relative_path = self.relative_name(&path).ok_or_else(|| {
+                eyre!(
+                    "trying to access script outside of current working directory: {}",
+                    path_str
+                )
+            })?;
+            if !hoist {
+                self.order.push(relative_path.clone());
+            }
+            let mut body = self._bundle_from_path(&path, include_options)?;
+            if !body.ends_with('\n') {
+                body.push('\n');
+            }
+            if self.over_cap {
+                self.fragments
+                    .push(Fragment::spilled(relative_path.clone(), &body)?);
+            } else {
+                self.fragments
+                    .push(Fragment::new(relative_path.clone(), body.clone()));
+                self.hold(body.len() as u64)?;
+            }
+            if include_options.isolate {
+                if include_options.lazy == Some(true) {
+                    return Err(eyre!(
+                        "{} can't be both lazy and isolated, since nothing it defines outlives its subshell",
+                        relative_path
+                    ));
+                }
+                let lost = isolate::lost_definitions(&body)?;
+                if !lost.is_empty() {
+                    self.warnings.push(format!(
+                        "{} is isolated in a subshell, so what it defines isn't available after it: {}",
+                        relative_path,
+                        lost.iter()
+                            .map(|name| format!("`{}`", name))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ));
+                }
+                body = isolate::wrap(&body);
+            }
+            if self.once.contains(&path) {
+                body = once_guard(&relative_path, &body, &self.runtime.prefix);
+            }
+            let lazy = match (include_options.lazy, &self.trace) {
+                _ if include_options.isolate => false,
+                (Some(lazy), _) => lazy,
+                (None, Some(trace)) => trace.is_cold(&body)?,
+                (None, None) => false,
+            };
+            if !include_options.variables.is_empty() {
+                // Inside the lazy loader, so the variables are set when the file actually runs
+                let saved = format!("{}with{}", self.runtime.prefix, self.scoped_variables);
+                self.scoped_variables += 1;
+                body = include_options.scope_variables(&body, &saved);
+            }
+            if lazy {
+                body = lazy_loader(
+                    &relative_path,
+                    &body,
+                    &self.runtime.prefix,
+                    &mut self.warnings,
+                )?;
+            }
+            let depth = if hoist { 1 } else { self.visiting.len() + 1 };
+            let block = format!(
+                "{}\n\n{}\n\n{}",
+                self.markers.open(&relative_path, depth),
+                body,
+                self.markers.close(&relative_path, depth)
+            );
+            if hoist {
+                // The files this one sourced finished first, so they're already ahead of it
+                self.order.push(relative_path);
+                self.hoisted.push(block);
+            } else {
+                parts.push(block);
+            }
+        }
+        return Ok(parts);
+    }
+
+    /// Inlines the prelude or epilogue files, like files the entry script sources
+    fn inline_around(&mut self, paths: Vec<PathBuf>) -> Result<String> {
+        let mut parts = vec![];
+        for path in paths {
+            let path = path
+                .canonicalize()
+                .wrap_err_with(|| format!("{} doesn't exist", path.display()))?;
+            let path_str = path.to_string_lossy().to_string();
+            let options = self.include_options.clone();
+            parts.extend(self.inline_files(
+                vec![path],
+                &options,
+                &path_str,
+                self.topological_includes,
+            )?);
+        }
+        return Ok(parts.join("\n"));
+    }
+
+    /// Waits for the `# build: inline` commands that were started, and embeds their outputs.
+    /// Returns each placeholder along with what replaces it.
+    fn finish_commands(&mut self) -> Result<Vec<(String, String)>> {
+        let outputs = self.jobs.finish();
+        let pending = mem::take(&mut self.pending);
+        if let Some(deadline) = &self.deadline
+            && deadline.has_passed()
+        {
+            let stopped: Vec<String> = pending
+                .iter()
+                .zip(&outputs)
+                .filter(|(_, (output, _))| output.is_err())
+                .map(|(pending, _)| format!("\"{}\" on {}", pending.command, pending.location()))
+                .collect();
+            if !stopped.is_empty() {
+                return Err(deadline.error(&format!(
+                    "running `# build: inline` commands. These were killed or never started:\n  {}",
+                    stopped.join("\n  ")
+                )));
+            }
+        }
+        let mut replacements = vec![];
+        for (index, (pending, (output, attempts))) in pending.into_iter().zip(outputs).enumerate() {
+            let location = pending.location();
+            let command = pending.command;
+            // The runs that failed before the last one, if it was run again
+            let history = match attempts.is_empty() {
+                true => String::new(),
+                false => format!(
+                    " on attempt {}:{}",
+                    attempts.len() + 1,
+                    attempts
+                        .iter()
+                        .enumerate()
+                        .map(|(i, attempt)| format!("\n  attempt {}: {}", i + 1, attempt))
+                        .collect::<String>()
+                ),
+            };
+            let output =
+                output.wrap_err_with(|| format!("failed to run \"{}\"{}", command, history))?;
+            if !output.status.success() {
+                return Err(eyre!(
+                    "\"{}\" returned with exit code {}{}",
+                    command,
+                    output.status,
+                    history
+                ));
+            }
+            if !attempts.is_empty() {
+                self.notes.push(format!(
+                    "\"{}\" on {} succeeded{}",
+                    command, location, history
+                ));
+            }
+
+            self.ran_commands = true;
+
+            let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+            let mut directive_replacement = String::new();
+            if !stderr.is_empty() {
+                match pending.stderr_policy {
+                    StderrPolicy::Show => {
+                        eprintln!("From executed command substitution's stderr: {}", stderr);
+                    }
+                    StderrPolicy::Fail => {
+                        return Err(eyre!(
+                            "\"{}\" wrote to stderr, which `stderr=fail` doesn't allow:\n{}",
+                            command,
+                            stderr.trim_end()
+                        ));
+                    }
+                    StderrPolicy::Silence => {}
+                    StderrPolicy::Comment => {
+                        directive_replacement = stderr
+                            .lines()
+                            .map(|line| format!("# stderr: {}", line))
+                            .collect::<Vec<_>>()
+                            .join("\n");
+                    }
+                }
+            }
+
+            if self.secrets != SecretsPolicy::Off && !pending.allow_secrets {
+                let output = fs::read(pending.spool.path())?;
+                self.scan_payload(
+                    &output,
+                    &format!("the output of \"{}\" on {}", command, location),
+                );
+            }
+            let output_bytes = pending.spool.as_file().metadata()?.len();
+            let extract = pending.as_file.then(|| self.runtime.extract_file());
+            let (content, compression) = self.embed_payload(
+                pending.spool,
+                pending.compression,
+                pending.encoding,
+                extract,
+            )?;
+            self.commands.push(InlineCommand {
+                file: pending.file,
+                line: pending.line,
+                command,
+                output_bytes,
+                encoding: pending.encoding,
+                compression,
+                as_file: pending.as_file,
+                stderr,
+                attempts,
+            });
+            let prefix = &self.runtime.prefix;
+            replacements.push((PendingCommand::output_placeholder(prefix, index), content));
+            replacements.push((
+                PendingCommand::stderr_placeholder(prefix, index),
+                directive_replacement,
+            ));
+        }
+        return Ok(replacements);
+    }
+
+    /// Records what looks like credentials in `text`, the bundle before command outputs are
+    /// filled in, by where they came from
+    fn scan_bundle(&mut self, text: &str, entry_source: &str) {
+        let findings = secrets::scan(text, false);
+        if findings.is_empty() {
+            return;
+        }
+        let sources: Vec<PathBuf> = self.visited.iter().cloned().collect();
+        let (files, lines) = annotate::exact_line_table(
+            text,
+            (self.entry_name.clone(), entry_source.to_string()),
+            &self.path_relative_to,
+            &sources,
+            &self.runtime.prefix,
+            &self.markers,
+        );
+        for finding in findings {
+            let location = match lines.get(finding.row).copied().flatten() {
+                Some((file, _)) if file > 0 && self.is_exempt(&files[file]) => continue,
+                Some((file, line)) => format!("on line {} of {}", line, files[file]),
+                None => String::from("in code shpack generated or rewrote, like a -D value"),
+            };
+            self.secret_findings.push(format!(
+                "possible {} (`{}…`) {}",
+                finding.kind, finding.start, location
+            ));
+        }
+    }
+
+    /// Records what looks like credentials in a command's output or an embedded file,
+    /// described by `location`
+    fn scan_payload(&mut self, content: &[u8], location: &str) {
+        let text = String::from_utf8_lossy(content);
+        let binary = content.contains(&0) || str::from_utf8(content).is_err();
+        let findings = secrets::scan(&text, binary);
+        for finding in findings.iter().take(secrets::MAX_PER_PAYLOAD) {
+            self.secret_findings.push(format!(
+                "possible {} (`{}…`) on line {} of {}",
+                finding.kind,
+                finding.start,
+                finding.row + 1,
+                location
+            ));
+        }
+        if findings.len() > secrets::MAX_PER_PAYLOAD {
+            self.secret_findings.push(format!(
+                "{} more possible credentials in {}",
+                findings.len() - secrets::MAX_PER_PAYLOAD,
+                location
+            ));
+        }
+    }
+
+    /// Fails if the deadline has passed, saying what the build was `doing`, once the
+    /// `# build: inline` commands still running have been killed
+    fn check_deadline(&mut self, doing: &str) -> Result<()> {
+        let Some(deadline) = self.deadline.clone() else {
+            return Ok(());
+        };
+        if !deadline.has_passed() {
+            return Ok(());
+        }
+        self.jobs.finish();
+        return Err(deadline.error(doing));
+    }
+
+    /// The files in `dir`, which a self-extracting bundle's archive holds, after scanning
+    /// them for credentials
+    fn archive_files(&mut self, dir: PathBuf) -> Result<Vec<PathBuf>> {
+        if self.format != Format::SelfExtract {
+            return Err(eyre!(
+                "only self-extracting bundles have an archive to put {} in, use --format self-extract",
+                dir.display()
+            ));
+        }
+        if !dir.is_dir() {
+            return Err(eyre!("{} isn't a directory", dir.display()));
+        }
+        let mut files = vec![];
+        for entry in archive::entries(&dir)? {
+            let file = dir.join(&entry);
+            if file.is_dir() {
+                continue;
+            }
+            if self.secrets != SecretsPolicy::Off {
+                let content = fs::read(&file)
+                    .wrap_err_with(|| format!("failed to read {}", file.display()))?;
+                let location = format!(
+                    "{}, in the self-extracting archive",
+                    self.display_name(&file)
+                );
+                self.scan_payload(&content, &location);
+            }
+            files.push(file);
+        }
+        return Ok(files);
+    }
+
+    /// Returns a command substitution that reproduces the contents of `spool` at runtime, or
+    /// with `extract`, what that command prints when they're piped into it, along with the
+    /// compression that was applied, if it made the output any smaller
+    fn embed_payload(
+        &mut self,
+        spool: NamedTempFile,
+        compression: Compression,
+        encoding: Encoding,
+        extract: Option<String>,
+    ) -> Result<(String, Compression)> {
+        let mut spool = spool;
+        let mut filters = vec![];
+        if let Some(tool) = encoding.tool() {
+            self.runtime
+                .require(tool, "decodes `# build: inline` output");
+        }
+        if let Some(filter) = encoding.filter() {
+            filters.push(filter.to_string());
+        }
+
+        // Compressed data is binary, so it only goes in encodings that can carry it
+        let len = spool.as_file().metadata()?.len();
+        let mut applied = Compression::None;
+        if let Some(tool) = compression.tool()
+            && encoding.is_binary_safe()
+            && len >= self.compress_threshold
+        {
+            let compressed = compression.compress(spool.path())?;
+            if compressed.as_file().metadata()?.len() < len {
+                spool = compressed;
+                applied = compression;
+                filters.push(format!("{} -dc", tool));
+                self.runtime
+                    .require(tool, "decompresses `# build: inline` output");
+            }
+        }
+
+        let mut pipeline = String::new();
+        if !filters.is_empty() {
+            let filters: Vec<&str> = filters.iter().map(String::as_str).collect();
+            pipeline = format!(" | {}", self.runtime.decoder(&filters));
+        }
+
+        let delimiter = if encoding == Encoding::Heredoc {
+            payload::heredoc_delimiter(spool.path())?
+        } else {
+            String::new()
+        };
+        if let Some(extract) = extract {
+            pipeline.push_str(&format!(" | {}", extract));
+        }
+
+        // Heredocs always end in a newline, so only add one if the output didn't have its own
+        let add_newline =
+            encoding == Encoding::Heredoc && !payload::ends_with_newline(spool.path())?;
+
+        let mut encoded =
+            if spool.as_file().metadata()?.len() >= self.stream_threshold || self.over_cap {
+                self.payloads.push((spool.into_temp_path(), encoding));
+                payload::marker(self.payloads.len() - 1)
+            } else {
+                let mut encoded = vec![];
+                encoding.encode(&mut File::open(spool.path())?, &mut encoded)?;
+                let encoded = String::from_utf8(encoded)
+                    .wrap_err("the heredoc encoding only works for UTF-8 text, use base64")?;
+                self.hold(encoded.len() as u64)?;
+                encoded
+            };
+        if add_newline {
+            encoded.push('\n');
+        }
+        return Ok((encoding.decoder(&encoded, &delimiter, &pipeline), applied));
+    }
+
+    /// Counts `bytes` more as held in memory. Once that passes the memory cap, the fragments
+    /// kept so far are moved to disk, and later fragments and command outputs go straight
+    /// there, which is slower but keeps pathological inputs from exhausting memory.
+    fn hold(&mut self, bytes: u64) -> Result<()> {
+        self.held += bytes;
+        let Some(cap) = self.memory_cap else {
+            return Ok(());
+        };
+        if self.over_cap || self.held <= cap {
+            return Ok(());
+        }
+        self.over_cap = true;
+        self.notes.push(format!(
+            "about {} bytes were held in memory while bundling {}, over the --memory-cap of {}, so bundled files and `# build: inline` outputs were kept on disk from then on",
+            self.held,
+            self.current_file().unwrap_or(String::from("the entry script")),
+            cap
+        ));
+        for fragment in &mut self.fragments {
+            fragment.spill()?;
+            self.held = self.held.saturating_sub(fragment.bytes);
+        }
+        return Ok(());
+    }
+
+    /// The name of a bundled file: its path relative to the root, or for a file outside the
+    /// root, relative to the include path it was found in
+    fn relative_name(&self, path: &Path) -> Option<String> {
+        let include_paths = self
+            .include_paths
+            .iter()
+            .filter_map(|dir| dir.canonicalize().ok());
+        for dir in iter::once(self.path_relative_to.clone()).chain(include_paths) {
+            if let Ok(relative) = path.strip_prefix(&dir) {
+                return Some(relative.to_string_lossy().to_string());
+            }
+        }
+        return None;
+    }
+
+    /// The name of a file for messages, which is its full path if it doesn't have a
+    /// [`Bundler::relative_name`]
+    fn display_name(&self, path: &Path) -> String {
+        return self
+            .relative_name(path)
+            .unwrap_or_else(|| path.to_string_lossy().to_string());
+    }
+
+    /// How the file being bundled was reached, like `sourced by the entry script, then lib/a.sh`
+    fn include_chain(&self) -> String {
+        let mut chain = vec![String::from("the entry script")];
+        chain.extend(
+            self.visiting[..self.visiting.len() - 1]
+                .iter()
+                .map(|path| self.display_name(path)),
+        );
+        return format!("sourced by {}", chain.join(", then "));
+    }
+
+    /// Whether `file`, a sourced file's name relative to the root, matches an `--exempt`
+    /// glob. Third-party files can't be expected to follow the project's conventions.
+    fn is_exempt(&self, file: &str) -> bool {
+        return self.exempt.iter().any(|glob| shake::glob_match(glob, file));
+    }
+
+    /// The file being bundled relative to the root, or None for the entry script
+    fn current_file(&self) -> Option<String> {
+        return self.visiting.last().map(|path| self.display_name(path));
+    }
+
+    /// Resolves a sourced path relative to `cwd`, falling back to the include paths
+    fn resolve_source(&self, path_str: &str, cwd: &Path) -> Result<PathBuf> {
+        let relative = cwd.join(path_str);
+        if !relative.exists() && Path::new(path_str).is_relative() {
+            for dir in &self.include_paths {
+                let candidate = dir.join(path_str);
+                if candidate.exists() {
+                    return Ok(candidate.canonicalize()?);
+                }
+            }
+        }
+        return relative
+            .canonicalize()
+            .wrap_err_with(|| format!("failed to get full path for source: \"{}\"", path_str));
+    }
+
+    /// Whether a sourced file matches an --external glob, by its path relative to the root or
+    /// its absolute path
+    fn is_external(&self, path: &Path) -> bool {
+        let absolute = path.to_string_lossy();
+        let relative = self.relative_name(path);
+        return self.external.iter().any(|glob| {
+            return shake::glob_match(glob, &absolute)
+                || relative
+                    .as_ref()
+                    .is_some_and(|relative| shake::glob_match(glob, relative));
+        });
+    }
+
+    /// The files a glob in a `source` path matches, relative to `cwd` or else to the first
+    /// include path it matches anything in
+    fn expand_glob(&mut self, pattern: &str, cwd: &Path) -> Result<Vec<PathBuf>> {
+        let mut dirs = vec![cwd];
+        if Path::new(pattern).is_relative() {
+            dirs.extend(self.include_paths.iter().map(PathBuf::as_path));
+        }
+        for dir in dirs {
+            let files = fs_util::glob(dir, pattern)?;
+            self.listings.push(Listing::Glob {
+                dir: dir.to_path_buf(),
+                pattern: pattern.to_string(),
+                files: files.clone(),
+            });
+            if !files.is_empty() {
+                let mut matches = vec![];
+                for file in files {
+                    let file = file.canonicalize()?;
+                    // A file sourcing the directory it's in doesn't source itself again
+                    if !self.visiting.contains(&file) {
+                        matches.push(file);
+                    }
+                }
+                return Ok(matches);
+            }
+        }
+        return Err(eyre!("`{}` doesn't match any files", pattern));
+    }
+
+    fn _bundle_from_path(&mut self, path: &Path, options: &IncludeOptions) -> Result<String> {
+        if let Some(start) = self.visiting.iter().position(|visiting| visiting == path) {
+            let mut cycle: Vec<String> = self.visiting[start..]
+                .iter()
+                .map(|path| self.display_name(path))
+                .collect();
+            cycle.push(self.display_name(path));
+            return Err(eyre!(
+                "Circular dependencies are not supported! {} sources itself through {}. Pass --allow-circular to inline each file in the cycle once.",
+                cycle[0],
+                cycle.join(" -> ")
+            ));
+        } else {
+            self.visiting.push(path.to_owned());
+        }
+
+        self.check_deadline(&format!("bundling {}", self.display_name(path)))?;
+        self.chaos.file()?;
+        let size = fs::metadata(path)?.len();
+        if size >= LARGE_INCLUDE {
+            let message = format!(
+                "{} ({}) is {:.1} MiB ({} bytes), which is unusually large for a sourced file",
+                self.current_file().expect("a file is being visited"),
+                self.include_chain(),
+                size as f64 / (1024.0 * 1024.0),
+                size
+            );
+            if !self.allow_large_include {
+                return Err(eyre!(
+                    "{}. If it's meant to be bundled, pass --allow-large-include.",
+                    message
+                ));
+            }
+            self.warnings.push(message);
+        }
+        let source = match &self.source_cache {
+            Some(cache) => cache.read(path),
+            None => fs_util::read_script(path).and_then(|source| {
+                let tree = parse_file(&source)?;
+                return Ok((source, tree));
+            }),
+        };
+        let (source, tree) = source.wrap_err_with(|| {
+            format!(
+                "can't bundle {} ({})",
+                self.current_file().expect("a file is being visited"),
+                self.include_chain()
+            )
+        })?;
+        let (source, tree) = match self.wasm.is_empty() {
+            true => (source, tree),
+            false => {
+                let name = self.current_file().expect("a file is being visited");
+                let source = wasm::apply(&self.wasm, &name, source)?;
+                let tree = parse_file(&source)?;
+                (source, tree)
+            }
+        };
+        let source_bytes = source.len() as u64;
+        self.hold(source_bytes)?;
+        let relative_path = self.current_file().expect("a file is being visited");
+        // An isolated file's `cd` stays in its subshell
+        let directory_changes = match options.isolate || self.is_exempt(&relative_path) {
+            true => vec![],
+            false => cd::top_level(&tree, &source)?,
+        };
+        for (row, command) in directory_changes {
+            self.warnings.push(format!(
+                "`{}` on line {} of {} changes the working directory for everything bundled after it too. Move it into a function or a subshell, put `# shpack: isolate` above the `source` command to run the whole file in one, or put `# shpack: allow-cd` above it if that's intended.",
+                command,
+                row + 1,
+                relative_path
+            ));
+        }
+        if let Some(mut library) = Library::parse(&source, &relative_path)?
+            && self
+                .libraries
+                .iter()
+                .all(|other| other.path != library.path)
+        {
+            library.included_by = self.visiting[..self.visiting.len() - 1]
+                .iter()
+                .map(|path| self.display_name(path))
+                .collect();
+            self.libraries.push(library);
+        }
+        let cwd = path
+            .parent()
+            .ok_or(eyre!("Can't source the root directory"))?;
+        if self.progress {
+            progress::emit(&Event::FileStarted {
+                path: &relative_path,
+            });
+        }
+        let out = self._bundle_from_tree(source, tree, cwd, options)?;
+        if self.progress {
+            progress::emit(&Event::FileFinished {
+                path: &relative_path,
+                bytes: out.len() as u64,
+            });
+        }
+
+        self.held = self.held.saturating_sub(source_bytes);
+        self.visiting.pop();
+        self.visited.insert(path.to_owned());
+        return Ok(out);
+    }
+
+    fn _bundle_from_string(
+        &mut self,
+        source: String,
+        cwd: &Path,
+        options: &IncludeOptions,
+    ) -> Result<String> {
+        let tree = parse_file(&source)?;
+        return self._bundle_from_tree(source, tree, cwd, options);
+    }
+
+    fn _bundle_from_tree(
+        &mut self,
+        source: String,
+        tree: Tree,
+        cwd: &Path,
+        options: &IncludeOptions,
+    ) -> Result<String> {
+        let file = self
+            .current_file()
+            .unwrap_or(String::from("the entry script"));
+        let (source, tree) = match template::render(&source, &self.metadata)
+            .wrap_err_with(|| format!("failed to fill in placeholders in {}", file))?
+        {
+            Some(rendered) => {
+                let tree = parse_file(&rendered)?;
+                (rendered, tree)
+            }
+            None => (source, tree),
+        };
+        let (source, tree) = match conditional::select(&source, &self.defines)? {
+            Some(selected) => {
+                let tree = parse_file(&selected)?;
+                (selected, tree)
+            }
+            None => (source, tree),
+        };
+        if !self
+            .current_file()
+            .is_some_and(|file| self.is_exempt(&file))
+        {
+            self.runtime.check_reserved(&tree, &source)?;
+        }
+        let mut found_shabang = false;
+        let mut edits = vec![];
+
+        // Uses of aliases for `source` are bundled, so their definitions can go
+        let mut stripped = vec![];
+        for definition in alias::definitions(&tree, &source)? {
+            if self.strip_source_aliases
+                && definition.only_source
+                && !shares_line(definition.bytes.clone(), &source)
+            {
+                stripped.push(definition.bytes);
+            }
+            for name in definition.names {
+                if !self.source_aliases.contains(&name) {
+                    self.source_aliases.push(name);
+                }
+            }
+        }
+        let mut wrapper_sources = vec![];
+        for (name, wrapper, command) in alias::wrappers(&tree, &source, cwd)? {
+            self.wrappers.insert(name, wrapper);
+            wrapper_sources.push(command);
+        }
+        if self.strip_source_aliases {
+            visit_node(tree.root_node(), &mut |node| {
+                if node.kind() == "function_definition"
+                    && let Some(name) = node.child_by_field_name("name")
+                    && self
+                        .source_aliases
+                        .iter()
+                        .any(|alias| alias == name.text(&source))
+                {
+                    stripped.push(node.byte_range());
+                }
+                return Ok(());
+            })?;
+        }
+        for removed in &stripped {
+            edits.push(Edit {
+                start_byte: removed.start,
+                end_byte: removed.end + usize::from(source[removed.end..].starts_with('\n')),
+                new_content: String::new(),
+            });
+        }
+
+        // The pragma is build-time only, like directives
+        let pragma = once_pragma(&tree, &source)?;
+        if let Some(pragma) = pragma {
+            if let Some(path) = self.visiting.last() {
+                self.once.insert(path.clone());
+            }
+            edits.push(Edit {
+                start_byte: pragma.start_byte(),
+                end_byte: pragma
+                    .next_sibling()
+                    .map(|n| n.start_byte())
+                    .unwrap_or(pragma.end_byte()),
+                new_content: String::new(),
+            });
+        }
+
+        visit_node(tree.root_node(), &mut |node| {
+            if stripped
+                .iter()
+                .any(|removed| removed.start <= node.start_byte() && node.end_byte() <= removed.end)
+            {
+                return Ok(());
+            }
+            match node.kind() {
+                "comment" if node.text(&source).starts_with("#!") => {
+                    // Initial checks
+                    if found_shabang {
+                        return Err(eyre!("Only one shabang per file is allowed"));
+                    }
+                    if node.start_position().row != 0 {
+                        return Err(eyre!("The shabang must be at the top of the file"));
+                    }
+
+                    let t = node.text(&source);
+
+                    // Compare with saved shabang
+                    if self.shabang_override.is_some() {
+                        // The bundle's shabang is set, so the files' can differ
+                        self.shabang.get_or_insert_with(|| t.to_string());
+                    } else if let Some(shabang) = self.shabang.as_ref() {
+                        if shabang != t {
+                            return Err(eyre!(
+                                "Shabangs across all files must match. Found {} and {}. Give the bundle its own with --shebang to allow both.",
+                                shabang,
+                                t
+                            ));
+                        }
+                    } else {
+                        self.shabang = Some(t.to_string());
+                    }
+                    found_shabang = true;
+
+                    // Remove shabang
+                    edits.push(Edit {
+                        start_byte: node.start_byte(),
+                        end_byte: node
+                            .next_sibling()
+                            .map(|n| n.start_byte())
+                            .unwrap_or(node.end_byte()),
+                        new_content: String::new(),
+                    })
+                }
+                "command" => {
+                    let name_node = if let Some(c) = node.child(0) {
+                        c
+                    } else {
+                        return Ok(());
+                    };
+                    let command_name_text = name_node.text(&source);
+                    let wrapper = self.wrappers.get(command_name_text).cloned();
+                    if wrapper_sources.contains(&node.byte_range()) {
+                        // Calls to the wrapper are bundled instead
+                        return Ok(());
+                    }
+                    if command_name_text == "source"
+                        || command_name_text == "."
+                        || self
+                            .source_aliases
+                            .iter()
+                            .any(|alias| alias == command_name_text)
+                        || wrapper.is_some()
+                    {
+                        // Like `source /etc/profile # shpack: ignore`, which stays a runtime
+                        // lookup
+                        let ignored = trailing_directive(node, &source)?
+                            .into_iter()
+                            .chain(directives_above(node, &source)?.into_iter().map(|(_, d)| d))
+                            .any(|directive| directive.name == "ignore");
+                        if ignored {
+                            return Ok(());
+                        }
+                        let argument = node
+                            .child(1)
+                            .and_then(|n| match n.kind() {
+                                "word" => Some(n.text(&source).to_string()),
+                                "string" => {
+                                    let s = n.text(&source);
+                                    Some(s[1..s.len() - 1].to_string())
+                                }
+                                // Like `"$(dirname "$0")"/lib.sh`
+                                "concatenation" => Some(n.text(&source).replace('"', "")),
+                                _ => None,
+                            })
+                            .ok_or(eyre!("source command missing its argument"))?;
+                        let (path_str, cwd) = match &wrapper {
+                            Some(wrapper) => (
+                                wrapper.expand(&argument, &self.defines),
+                                wrapper.dir.as_path(),
+                            ),
+                            None => (argument, cwd),
+                        };
+
+                        let location = format!(
+                            "`{}` on line {} of {}",
+                            node.text(&source),
+                            node.start_position().row + 1,
+                            self.current_file()
+                                .unwrap_or(String::from("the entry script"))
+                        );
+                        let strict = !self.runtime_sources.is_empty();
+                        if self
+                            .runtime_sources
+                            .iter()
+                            .any(|glob| shake::glob_match(glob, &path_str))
+                        {
+                            return Ok(());
+                        }
+                        // `$(dirname "$0")/lib.sh` and `$SCRIPT_DIR/lib.sh` are the usual
+                        // ways to source a file next to the script
+                        let (path_str, cwd) =
+                            match script_dir::strip(&path_str, &self.script_dir_variables) {
+                                Some((Anchor::Entry, rest)) => (rest, self.entry_dir.clone()),
+                                Some((Anchor::File, rest)) => (rest, cwd.to_path_buf()),
+                                None => (path_str, cwd.to_path_buf()),
+                            };
+                        if path_str.starts_with("$")
+                            || (wrapper.is_some() && path_str.contains('$'))
+                        {
+                            if strict {
+                                return Err(eyre!(
+                                    "{} can't be bundled because its path is only known at runtime, and it doesn't match any --runtime-source glob",
+                                    location
+                                ));
+                            }
+                            return Ok(());
+                        }
+
+                        let mut include_options = self.include_options.clone();
+                        let mut allow_absolute = false;
+                        let mut directives = directives_above(node, &source)?;
+                        directives.retain(|(comment, _)| Some(*comment) != pragma);
+                        for (_, directive) in &directives {
+                            match directive.name.as_str() {
+                                "inline" => include_options.apply(directive)?,
+                                "with" => include_options.add_variables(directive)?,
+                                "isolate" => {
+                                    if !directive.args.is_empty() || !directive.options.is_empty() {
+                                        return Err(eyre!(
+                                            "`# shpack: isolate` doesn't take any options"
+                                        ));
+                                    }
+                                    include_options.isolate = true;
+                                }
+                                "allow-absolute" => {
+                                    if !directive.args.is_empty() || !directive.options.is_empty() {
+                                        return Err(eyre!(
+                                            "`# shpack: allow-absolute` doesn't take any options"
+                                        ));
+                                    }
+                                    allow_absolute = true;
+                                }
+                                _ => {
+                                    return Err(eyre!(
+                                        "unknown directive `# shpack: {}` above source command",
+                                        directive.name
+                                    ));
+                                }
+                            }
+                        }
+
+                        let paths = if fs_util::is_glob(&path_str) {
+                            // Like `source lib/*.sh`, which inlines every match
+                            self.expand_glob(&path_str, &cwd)
+                                .wrap_err_with(|| format!("can't bundle {}", location))?
+                        } else {
+                            let path = self.resolve_source(&path_str, &cwd);
+                            let external = match &path {
+                                Ok(path) => self.is_external(path),
+                                // Files on the machine the bundle runs on may be missing here
+                                Err(_) => {
+                                    Path::new(&path_str).is_absolute()
+                                        && self.is_external(Path::new(&path_str))
+                                }
+                            };
+                            if strict && !external {
+                                let reason = match &path {
+                                    Err(_) => Some("doesn't exist"),
+                                    Ok(path) if !path.starts_with(&self.path_relative_to) => {
+                                        Some("is outside the root")
+                                    }
+                                    Ok(_) => None,
+                                };
+                                if let Some(reason) = reason {
+                                    return Err(eyre!(
+                                        "{} can't be bundled because the file {}, and it doesn't match any --runtime-source glob",
+                                        location,
+                                        reason
+                                    ));
+                                }
+                            }
+                            match path {
+                                Err(_) if external => vec![PathBuf::from(&path_str)],
+                                path => vec![path?],
+                            }
+                        };
+
+                        let external = paths.iter().filter(|path| self.is_external(path)).count();
+                        if external > 0 {
+                            if external < paths.len() {
+                                return Err(eyre!(
+                                    "{} sources files that match --external globs and files that don't, so it can't be left as it is or bundled",
+                                    location
+                                ));
+                            }
+                            // Left as it is, but directives are still build-time only
+                            if let Some((first, _)) = directives.first() {
+                                edits.push(Edit {
+                                    start_byte: first.start_byte(),
+                                    end_byte: node.start_byte(),
+                                    new_content: String::new(),
+                                });
+                            }
+                            return Ok(());
+                        }
+                        if self.deny_absolute_sources
+                            && !allow_absolute
+                            && (path_str.starts_with('/') || path_str.starts_with('~'))
+                        {
+                            return Err(eyre!(
+                                "{} sources an absolute path, which --deny-absolute-sources forbids. Use a path relative to the file, or put `# shpack: allow-absolute` above it.",
+                                location
+                            ));
+                        }
+
+                        // Directives are build-time only
+                        if let Some((first, _)) = directives.first() {
+                            edits.push(Edit {
+                                start_byte: first.start_byte(),
+                                end_byte: node.start_byte(),
+                                new_content: String::new(),
+                            });
+                        }
+
+                        let parts = self.inline_files(
+                            paths,
+                            &include_options,
+                            &path_str,
+                            self.topological_includes,
+                        )?;
+                        // Splicing a file into a line that has other code on it, like
+                        // `source lib.sh; main`, would leave that code after the closing
+                        // marker, so the file is grouped with braces instead
+                        let grouped = shares_line(node.byte_range(), &source);
+                        let content = match (parts.is_empty(), grouped) {
+                            (true, true) => String::from(":"),
+                            (true, false) => String::new(),
+                            (false, true) => format!("{{\n{}\n}}", parts.join("\n")),
+                            (false, false) => parts.join("\n"),
+                        };
+
+                        // Write source contents
+                        edits.push(Edit {
+                            start_byte: node.start_byte(),
+                            end_byte: node.end_byte(),
+                            new_content: content,
+                        });
+                    }
+                }
+                "command_substitution" => {
+                    let sib = if let Some(sib) = node
+                        .next_named_sibling()
+                        .or(node.parent().and_then(|p| p.next_named_sibling()))
+                    {
+                        sib
+                    } else {
+                        return Ok(());
+                    };
+
+                    let directive = if sib.kind() == "comment" {
+                        Directive::parse_build(sib.text(&source))?
+                    } else {
+                        None
+                    };
+                    if let Some(directive) = directive
+                        && directive.name == "inline"
+                    {
+                        let mut compression = self.compression;
+                        let mut encoding = self.encoding;
+                        let mut as_file = false;
+                        let mut pure = false;
+                        let mut idempotent = false;
+                        let mut stderr_policy = StderrPolicy::Show;
+                        let mut id = None;
+                        let mut after = vec![];
+                        let flags = directive.args.iter().map(|arg| (arg, "true"));
+                        let options = directive.options.iter().map(|(k, v)| (k, v.as_str()));
+                        for (key, value) in flags.chain(options) {
+                            match key.as_str() {
+                                "pure" => pure = parse_bool(key, value)?,
+                                "idempotent" => idempotent = parse_bool(key, value)?,
+                                "id" => id = Some(value),
+                                "after" => after.extend(value.split(',').map(String::from)),
+                                "stderr" => {
+                                    stderr_policy = match value {
+                                        "show" => StderrPolicy::Show,
+                                        "fail" => StderrPolicy::Fail,
+                                        "silence" => StderrPolicy::Silence,
+                                        "comment" => StderrPolicy::Comment,
+                                        _ => {
+                                            return Err(eyre!(
+                                                "expected `stderr=show`, `fail`, `silence` or `comment`, found `stderr={}`",
+                                                value
+                                            ));
+                                        }
+                                    }
+                                }
+                                "compress" => compression = Compression::parse(value)?,
+                                "encoding" => encoding = Encoding::parse(value)?,
+                                "as" => {
+                                    as_file = match value {
+                                        "file" => true,
+                                        "text" => false,
+                                        _ => {
+                                            return Err(eyre!(
+                                                "expected `as=text` or `as=file`, found `as={}`",
+                                                value
+                                            ));
+                                        }
+                                    }
+                                }
+                                _ => {
+                                    return Err(eyre!(
+                                        "unknown option `{}` for `# build: inline`",
+                                        key
+                                    ));
+                                }
+                            }
+                        }
+
+                        if !self.run_commands {
+                            return Ok(());
+                        }
+                        let command_raw = node.text(&source);
+                        let command = &command_raw[2..command_raw.len() - 1];
+                        let mut bash = Command::new("bash");
+                        if let Some(dir) = &self.command_dir {
+                            bash.current_dir(dir);
+                        }
+                        if let Some(env) = &self.command_env {
+                            bash.env_clear().envs(env);
+                        }
+                        if pure {
+                            pure::scrub(&mut bash);
+                            for name in pure::impure_reads(command)? {
+                                self.warnings.push(format!(
+                                    "\"{}\" reads ${}, which isn't set for `# build: inline pure` commands",
+                                    command, name
+                                ));
+                            }
+                        }
+                        // Stdout goes to disk so large outputs never have to fit in memory
+                        let spool = tempfile::NamedTempFile::new()?;
+                        let mut retry = None;
+                        let stdout = if idempotent && self.retries > 0 {
+                            // Appended to, so emptying the file starts a retry's output at
+                            // its beginning
+                            retry = Some(Retry {
+                                retries: self.retries,
+                                backoff: self.retry_backoff,
+                                stdout: spool.reopen()?,
+                            });
+                            fs::OpenOptions::new().append(true).open(spool.path())?
+                        } else {
+                            spool.reopen()?
+                        };
+                        bash.arg("-c")
+                            .arg(command)
+                            .stdout(Stdio::from(stdout))
+                            .stderr(Stdio::piped());
+                        self.jobs.start(bash, id, &after, retry)?;
+
+                        let index = self.pending.len();
+                        self.pending.push(PendingCommand {
+                            file: self.current_file(),
+                            line: node.start_position().row + 1,
+                            command: command.to_string(),
+                            spool,
+                            compression,
+                            encoding,
+                            as_file,
+                            stderr_policy,
+                            allow_secrets: secrets::allowed(
+                                &source.lines().collect::<Vec<_>>(),
+                                node.start_position().row,
+                            ),
+                        });
+                        edits.push(Edit {
+                            start_byte: node.start_byte(),
+                            end_byte: node.end_byte(),
+                            new_content: PendingCommand::output_placeholder(
+                                &self.runtime.prefix,
+                                index,
+                            ),
+                        });
+                        edits.push(Edit {
+                            start_byte: sib.start_byte(),
+                            end_byte: sib.end_byte(),
+                            new_content: PendingCommand::stderr_placeholder(
+                                &self.runtime.prefix,
+                                index,
+                            ),
+                        });
+                    }
+                }
+                "comment" => {
+                    if let Some(directive) = Directive::parse(node.text(&source))?
+                        && directive.name == "include"
+                    {
+                        let Some((path_str, flags)) = directive.args.split_first() else {
+                            return Err(eyre!(
+                                "expected `# shpack: include <file> [options]`, found `{}`",
+                                node.text(&source)
+                            ));
+                        };
+                        let mut include_options = self.include_options.clone();
+                        include_options.apply(&Directive {
+                            args: flags.to_vec(),
+                            ..directive.clone()
+                        })?;
+                        let location = format!(
+                            "`{}` on line {} of {}",
+                            node.text(&source),
+                            node.start_position().row + 1,
+                            self.current_file()
+                                .unwrap_or(String::from("the entry script"))
+                        );
+                        let paths = if fs_util::is_glob(path_str) {
+                            self.expand_glob(path_str, cwd)
+                        } else {
+                            self.resolve_source(path_str, cwd).map(|path| vec![path])
+                        }
+                        .wrap_err_with(|| format!("can't bundle {}", location))?;
+                        // Included files always go at the top of the bundle, and the directive
+                        // leaves nothing behind
+                        self.inline_files(paths, &include_options, path_str, true)?;
+                        edits.push(Edit {
+                            start_byte: node.start_byte(),
+                            end_byte: node.end_byte(),
+                            new_content: String::new(),
+                        });
+                        return Ok(());
+                    }
+                    let Some(directive) = Directive::parse_build(node.text(&source))? else {
+                        return Ok(());
+                    };
+                    if directive.name == "messages" {
+                        let (dir, name) = match directive.args.as_slice() {
+                            [dir] => (dir, "msg"),
+                            [dir, as_, name] if as_ == "as" => (dir, name.as_str()),
+                            _ => {
+                                return Err(eyre!(
+                                    "expected `# build: messages <dir> [as <NAME>] [default=<LOCALE>]`, found `{}`",
+                                    node.text(&source)
+                                ));
+                            }
+                        };
+                        let mut default = None;
+                        for (key, value) in &directive.options {
+                            match key.as_str() {
+                                "default" => default = Some(value.as_str()),
+                                _ => {
+                                    return Err(eyre!(
+                                        "unknown option `{}` for `# build: messages`",
+                                        key
+                                    ));
+                                }
+                            }
+                        }
+
+                        let path = self.resolve_source(dir, cwd)?;
+                        let catalog = Catalog::load(&path)?;
+                        self.listings.push(Listing::Messages {
+                            dir: path,
+                            files: catalog.files.clone(),
+                        });
+                        let code = catalog
+                            .lookup_function(name, default, &self.runtime.prefix)
+                            .wrap_err_with(|| format!("failed to bundle messages in {}", dir))?;
+                        self.data_files.extend(catalog.files);
+                        edits.push(Edit {
+                            start_byte: node.start_byte(),
+                            end_byte: node.end_byte(),
+                            new_content: format!("# messages {}\n\n{}\n#########", dir, code),
+                        });
+                        return Ok(());
+                    }
+                    if directive.name == "cli-spec" {
+                        let [path_str] = directive.args.as_slice() else {
+                            return Err(eyre!(
+                                "expected `# build: cli-spec <file> [completion=<FILE>] [parse=true]`, found `{}`",
+                                node.text(&source)
+                            ));
+                        };
+                        let mut completion_file = None;
+                        let mut parse = false;
+                        for (key, value) in &directive.options {
+                            match key.as_str() {
+                                "completion" => completion_file = Some(value),
+                                "parse" => parse = parse_bool(key, value)?,
+                                _ => {
+                                    return Err(eyre!(
+                                        "unknown option `{}` for `# build: cli-spec`",
+                                        key
+                                    ));
+                                }
+                            }
+                        }
+
+                        let path = self.resolve_source(path_str, cwd)?;
+                        let spec = CliSpec::load(&path)?;
+                        let completion = spec.completion(&self.runtime.prefix);
+                        // Standalone completion files go where bash-completion looks for them,
+                        // so they're relative to the root rather than the bundle
+                        if let Some(file) = completion_file {
+                            self.completion_files.push(CompletionFile {
+                                path: self.path_relative_to.join(file),
+                                content: format!(
+                                    "# Bash completion for {}, generated by shpack
+
+{}",
+                                    spec.name, completion
+                                ),
+                            });
+                        }
+                        let mut code = completion;
+                        if parse {
+                            code.push_str(&spec.parser(&self.runtime.prefix, self.version_stamp));
+                        }
+                        self.data_files.insert(path);
+                        edits.push(Edit {
+                            start_byte: node.start_byte(),
+                            end_byte: node.end_byte(),
+                            new_content: format!(
+                                "# cli-spec {}
+
+{}
+#########",
+                                path_str, code
+                            ),
+                        });
+                        return Ok(());
+                    }
+                    if directive.name == "embed" || directive.name == "embed-dir" {
+                        let what = if directive.name == "embed" {
+                            "file"
+                        } else {
+                            "dir"
+                        };
+                        let [path_str, as_, name] = directive.args.as_slice() else {
+                            return Err(eyre!(
+                                "expected `# build: {} <{}> as <NAME> [compress=<ALGORITHM>] [encoding=<ENCODING>]`, found `{}`",
+                                directive.name,
+                                what,
+                                node.text(&source)
+                            ));
+                        };
+                        if as_ != "as" || !shell::is_identifier(name) {
+                            return Err(eyre!(
+                                "expected `# build: {} <{}> as <NAME>` with a variable name, found `{}`",
+                                directive.name,
+                                what,
+                                node.text(&source)
+                            ));
+                        }
+                        let mut compression = self.compression;
+                        let mut encoding = self.encoding;
+                        for (key, value) in &directive.options {
+                            match key.as_str() {
+                                "compress" => compression = Compression::parse(value)?,
+                                "encoding" => encoding = Encoding::parse(value)?,
+                                _ => {
+                                    return Err(eyre!(
+                                        "unknown option `{}` for `# build: {}`",
+                                        key,
+                                        directive.name
+                                    ));
+                                }
+                            }
+                        }
+
+                        let path = self.resolve_source(path_str, cwd)?;
+                        let files: Vec<PathBuf> = if directive.name == "embed" {
+                            vec![path.clone()]
+                        } else {
+                            if !path.is_dir() {
+                                return Err(eyre!(
+                                    "{} isn't a directory, embed it with `# build: embed`",
+                                    path_str
+                                ));
+                            }
+                            archive::entries(&path)?
+                                .into_iter()
+                                .map(|entry| path.join(entry))
+                                .filter(|file| !file.is_dir())
+                                .collect()
+                        };
+                        if self.secrets != SecretsPolicy::Off
+                            && !secrets::allowed(
+                                &source.lines().collect::<Vec<_>>(),
+                                node.start_position().row,
+                            )
+                        {
+                            let by = self
+                                .current_file()
+                                .unwrap_or(String::from("the entry script"));
+                            for file in &files {
+                                let location = match file.strip_prefix(&path) {
+                                    Ok(relative) if !relative.as_os_str().is_empty() => format!(
+                                        "{}/{}, embedded by {}",
+                                        path_str.trim_end_matches('/'),
+                                        relative.display(),
+                                        by
+                                    ),
+                                    _ => format!("{}, embedded by {}", path_str, by),
+                                };
+                                let content = fs::read(file).wrap_err_with(|| {
+                                    format!("failed to read {}", file.display())
+                                })?;
+                                self.scan_payload(&content, &location);
+                            }
+                        }
+                        let (spool, extract) = if directive.name == "embed" {
+                            let mut file = File::open(&path)
+                                .wrap_err_with(|| format!("failed to read {}", path.display()))?;
+                            let mut spool = tempfile::NamedTempFile::new()?;
+                            io::copy(&mut file, spool.as_file_mut())?;
+                            (spool, self.runtime.extract_file())
+                        } else {
+                            let entries = archive::entries(&path)?;
+                            let spool = archive::pack(&path, &entries)?;
+                            self.listings.push(Listing::Directory {
+                                dir: path.clone(),
+                                entries,
+                            });
+                            (spool, self.runtime.extract_dir())
+                        };
+                        let (extract, _) = self
+                            .embed_payload(spool, compression, encoding, Some(extract))
+                            .wrap_err_with(|| format!("failed to embed {}", path_str))?;
+                        // Filled in with the commands' outputs, after the bundle is scanned for
+                        // secrets, which the encoded file would look like
+                        let placeholder =
+                            format!("{}embed_{}_", self.runtime.prefix, self.embedded.len());
+                        self.embedded.push((placeholder.clone(), extract));
+                        let mut code = format!("{}={}\n", name, placeholder);
+                        // Extracted files are created without permissions to run them, while
+                        // tar keeps the permissions of the files in a directory
+                        if directive.name == "embed"
+                            && fs::metadata(&path)?.permissions().mode() & 0o111 != 0
+                        {
+                            self.runtime
+                                .require("chmod", "makes embedded programs executable");
+                            code.push_str(&format!("chmod +x \"${}\"\n", name));
+                        }
+                        self.data_files.extend(files);
+                        edits.push(Edit {
+                            start_byte: node.start_byte(),
+                            end_byte: node.end_byte(),
+                            new_content: format!(
+                                "# {} {}\n\n{}#########",
+                                directive.name, path_str, code
+                            ),
+                        });
+                        return Ok(());
+                    }
+                    if directive.name != "data" && directive.name != "json" {
+                        return Ok(());
+                    }
+                    let (path_str, name) = match directive.args.as_slice() {
+                        [path] if directive.name == "data" => (path, ""),
+                        [path, as_, name] if as_ == "as" => (path, name.as_str()),
+                        _ => {
+                            return Err(eyre!(
+                                "expected `# build: data <file> [as <PREFIX>]` or `# build: json <file> as <NAME>`, found `{}`",
+                                node.text(&source)
+                            ));
+                        }
+                    };
+
+                    let path = self.resolve_source(path_str, cwd)?;
+                    let data = data::load(&path)?;
+                    let code = if directive.name == "data" {
+                        data::variables(&data, name)
+                    } else {
+                        data::accessors(&data, name, &self.runtime.prefix)
+                    }
+                    .wrap_err_with(|| format!("failed to inject {}", path_str))?;
+                    self.data_files.insert(path);
+                    edits.push(Edit {
+                        start_byte: node.start_byte(),
+                        end_byte: node.end_byte(),
+                        new_content: format!(
+                            "# {} {}\n\n{}\n#########",
+                            directive.name, path_str, code
+                        ),
+                    });
+                }
+                "variable_assignment" => {
+                    let Some(sib) = node
+                        .next_named_sibling()
+                        .or(node.parent().and_then(|p| p.next_named_sibling()))
+                    else {
+                        return Ok(());
+                    };
+                    if sib.kind() != "comment"
+                        || sib.start_position().row != node.end_position().row
+                    {
+                        return Ok(());
+                    }
+                    let Some(directive) = Directive::parse_build(sib.text(&source))? else {
+                        return Ok(());
+                    };
+                    if directive.name != "env" {
+                        return Ok(());
+                    }
+
+                    let name = node
+                        .child_by_field_name("name")
+                        .ok_or(eyre!("assignment has no name"))?
+                        .text(&source);
+                    let mut define = name;
+                    for (key, value) in &directive.options {
+                        match key.as_str() {
+                            "name" => define = value,
+                            _ => {
+                                return Err(eyre!("unknown option `{}` for `# build: env`", key));
+                            }
+                        }
+                    }
+
+                    // Without a define, the assignment keeps its value as a default
+                    if let Some(value) = self.defines.get(define) {
+                        let (start_byte, end_byte) = match node.child_by_field_name("value") {
+                            Some(value) => (value.start_byte(), value.end_byte()),
+                            None => (node.end_byte(), node.end_byte()),
+                        };
+                        edits.push(Edit {
+                            start_byte,
+                            end_byte,
+                            new_content: shell::quote(value),
+                        });
+                    }
+                    edits.push(Edit {
+                        start_byte: sib.start_byte(),
+                        end_byte: sib.end_byte(),
+                        new_content: String::new(),
+                    });
+                }
+                _ => {}
+            }
+
+            return Ok(());
+        })?;
+
+        // Libraries are often written without one, since they're only ever sourced
+        if !found_shabang && self.current_file().is_none() {
+            return Err(eyre!("The entry script needs a shabang"));
+        }
+
+        // Transformations never touch code that was already replaced
+        for edit in transform_edits(&tree, &source, options)? {
+            if edits
+                .iter()
+                .all(|e| e.end_byte <= edit.start_byte || edit.end_byte <= e.start_byte)
+            {
+                edits.push(edit);
+            }
+        }
+
+        let out = apply_edits(source, edits)?;
+        return match &self.trace {
+            Some(trace) => trace.reorder(out),
+            None => Ok(out),
+        };
+    }
+}
+
+/// Whether there's code besides the command at `bytes` on its line, like in
+/// `source lib.sh; main` or `[ -f lib.sh ] && source lib.sh`. Indentation and a trailing
+/// comment don't count.
+fn shares_line(bytes: Range<usize>, source: &str) -> bool {
+    let line_start = source[..bytes.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[bytes.end..]
+        .find('\n')
+        .map_or(source.len(), |i| bytes.end + i);
+    let before = source[line_start..bytes.start].trim();
+    let after = source[bytes.end..line_end].trim();
+    return !before.is_empty() || !(after.is_empty() || after.starts_with('#'));
+}
+
+/// Finds a `# shpack: once` pragma among the comments at the top of a file, which a library
+/// uses to ask to be included only once, however it's sourced
+fn once_pragma<'tree>(tree: &'tree Tree, source: &str) -> Result<Option<Node<'tree>>> {
+    let root = tree.root_node();
+    let mut cursor = root.walk();
+    for node in root.named_children(&mut cursor) {
+        if node.kind() != "comment" {
+            break;
+        }
+        if let Some(directive) = Directive::parse(node.text(source))?
+            && directive.name == "once"
+        {
+            if !directive.args.is_empty() || !directive.options.is_empty() {
+                return Err(eyre!("`# shpack: once` doesn't take any options"));
+            }
+            return Ok(Some(node));
+        }
+    }
+    return Ok(None);
+}
+
+/// Finds the block of `# shpack:` directives on the lines directly above `node`, in source order
+fn directives_above<'tree>(
+    node: Node<'tree>,
+    source: &str,
+) -> Result<Vec<(Node<'tree>, Directive)>> {
+    let mut directives = vec![];
+    let mut below = node;
+    while let Some(comment) = below.prev_sibling() {
+        if comment.kind() != "comment"
+            || comment.end_position().row + 1 != below.start_position().row
+        {
+            break;
+        }
+        match Directive::parse(comment.text(source))? {
+            // An include stands on its own rather than applying to the command below it
+            Some(directive) if directive.name != "include" => directives.push((comment, directive)),
+            _ => break,
+        }
+        below = comment;
+    }
+    directives.reverse();
+    return Ok(directives);
+}
+
+/// The directive in a comment after `node` on the line it ends on, like the one in
+/// `source /etc/profile # shpack: ignore`
+fn trailing_directive(node: Node, source: &str) -> Result<Option<Directive>> {
+    let row = node.end_position().row;
+    let mut current = node;
+    loop {
+        if let Some(next) = current.next_sibling() {
+            if next.kind() == "comment" && next.start_position().row == row {
+                return Directive::parse(next.text(source));
+            }
+            if next.start_position().row != row
+                || !next.kind().chars().all(|c| !c.is_alphanumeric())
+            {
+                return Ok(None);
+            }
+            // Punctuation like `;` or `&&` between the command and the comment
+            current = next;
+            continue;
+        }
+        match current.parent() {
+            Some(parent) if parent.end_position().row == row => current = parent,
+            _ => return Ok(None),
+        }
+    }
+}
+
+struct Edit {
+    start_byte: usize,
+    end_byte: usize,
+    new_content: String,
+}
+
+/// Apply disjoint edits simultaneously
+fn apply_edits(mut source: String, mut edits: Vec<Edit>) -> Result<String> {
+    edits.sort_by_key(|e| e.start_byte);
+    if edits
+        .windows(2)
+        .any(|pair| pair[0].end_byte > pair[1].start_byte)
+    {
+        return Err(eyre!("edits are not disjoint"));
+    }
+
+    let mut edit_offset: isize = 0;
+    for edit in edits {
+        source.replace_range(
+            (edit.start_byte as isize + edit_offset) as usize
+                ..(edit.end_byte as isize + edit_offset) as usize,
+            &edit.new_content,
+        );
+        edit_offset +=
+            edit.new_content.len() as isize - (edit.end_byte as isize - edit.start_byte as isize);
+    }
+
+    return Ok(source);
+}
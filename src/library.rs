@@ -0,0 +1,173 @@
+use std::cmp::Ordering;
+
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::directive::split_words;
+
+/// What a library says about itself in `# shpack-lib:` comments at the top of the file, like
+/// `# shpack-lib: name=net version=1.2.0 min-bash=4.2 tools=curl,jq`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Library {
+    /// The file declaring it, relative to the root
+    pub path: String,
+    pub name: String,
+    pub version: Option<String>,
+    /// The oldest version of bash the library works with
+    pub min_bash: Option<String>,
+    /// External tools the library runs
+    pub tools: Vec<String>,
+    /// The files that sourced it, relative to the root, from the one the entry script sourced
+    /// down to the one that sourced the library itself
+    #[serde(default)]
+    pub included_by: Vec<String>,
+}
+
+impl Library {
+    /// Parses the `# shpack-lib:` comments among those at the top of `source`, returning None
+    /// if there aren't any. Several comments may split up the fields between them.
+    pub fn parse(source: &str, path: &str) -> Result<Option<Library>> {
+        let mut found = false;
+        let mut library = Library {
+            path: path.to_string(),
+            name: String::new(),
+            version: None,
+            min_bash: None,
+            tools: vec![],
+            included_by: vec![],
+        };
+        for line in source.lines() {
+            let line = line.trim();
+            if !line.is_empty() && !line.starts_with('#') {
+                break;
+            }
+            let Some(rest) = line.strip_prefix("# shpack-lib:") else {
+                continue;
+            };
+            found = true;
+            for word in split_words(rest)? {
+                let (key, value) = word.split_once('=').ok_or(eyre!(
+                    "expected key=value in `# shpack-lib:` of {}, found `{}`",
+                    path,
+                    word
+                ))?;
+                match key {
+                    "name" => library.name = value.to_string(),
+                    "version" => library.version = Some(value.to_string()),
+                    "min-bash" => {
+                        if !is_version(value) {
+                            return Err(eyre!(
+                                "expected a version like 4.2 for min-bash in {}, found `{}`",
+                                path,
+                                value
+                            ));
+                        }
+                        library.min_bash = Some(value.to_string());
+                    }
+                    "tools" => library.tools.extend(
+                        value
+                            .split(',')
+                            .filter(|tool| !tool.is_empty())
+                            .map(str::to_string),
+                    ),
+                    _ => {
+                        return Err(eyre!(
+                            "unknown field `{}` in `# shpack-lib:` of {}",
+                            key,
+                            path
+                        ));
+                    }
+                }
+            }
+        }
+        if !found {
+            return Ok(None);
+        }
+        if library.name.is_empty() {
+            return Err(eyre!("`# shpack-lib:` in {} is missing a name", path));
+        }
+        return Ok(Some(library));
+    }
+
+    /// How the library is shown to people, like `net 1.2.0`
+    pub fn label(&self) -> String {
+        return match &self.version {
+            Some(version) => format!("{} {}", self.name, version),
+            None => self.name.clone(),
+        };
+    }
+
+    /// The include chain that pulled the library in, like
+    /// `the entry script -> lib/http.sh -> vendor/net.sh`
+    fn chain(&self) -> String {
+        let mut chain = vec!["the entry script"];
+        chain.extend(self.included_by.iter().map(String::as_str));
+        chain.push(&self.path);
+        return chain.join(" -> ");
+    }
+}
+
+/// Whether `text` is a dotted version made of numbers, like `4.2`
+fn is_version(text: &str) -> bool {
+    return text
+        .split('.')
+        .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()));
+}
+
+/// Compares dotted versions part by part, so `4.10` is newer than `4.9`
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let parts = |v: &str| -> Vec<u64> { v.split('.').map(|p| p.parse().unwrap_or(0)).collect() };
+    return parts(a).cmp(&parts(b));
+}
+
+/// Describes every library bundled in more than one version, along with the include chain
+/// that pulled in each version, since one of their consumers was probably written against the
+/// other
+pub fn conflicts(libraries: &[Library]) -> Vec<String> {
+    let mut names: Vec<&str> = vec![];
+    for library in libraries {
+        if !names.contains(&library.name.as_str()) {
+            names.push(&library.name);
+        }
+    }
+
+    let mut conflicts = vec![];
+    for name in names {
+        let mut versions: Vec<&Library> = vec![];
+        for library in libraries.iter().filter(|library| library.name == name) {
+            if versions
+                .iter()
+                .all(|other| other.version != library.version)
+            {
+                versions.push(library);
+            }
+        }
+        if versions.len() < 2 {
+            continue;
+        }
+        let mut message = format!(
+            "{} versions of library `{}` are bundled:",
+            versions.len(),
+            name
+        );
+        for library in versions {
+            message.push_str(&format!(
+                "\n  {}: {}",
+                library.version.as_deref().unwrap_or("unversioned"),
+                library.chain()
+            ));
+        }
+        conflicts.push(message);
+    }
+    return conflicts;
+}
+
+/// The newest of the libraries' minimum bash versions, which is what the bundle needs
+pub fn min_bash(libraries: &[Library]) -> Option<&str> {
+    return libraries
+        .iter()
+        .filter_map(|library| library.min_bash.as_deref())
+        .max_by(|a, b| compare_versions(a, b));
+}
@@ -0,0 +1,45 @@
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::archive;
+use crate::fs_util;
+use crate::messages;
+
+/// Files a bundle found by listing a directory rather than by name. Adding or removing a file
+/// there can change the bundle even when none of the files it read have changed.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", tag = "kind")]
+pub enum Listing {
+    /// What a glob like `source lib/*.sh` matched in `dir`. Include paths searched before
+    /// the one that matched are listed too, with no files.
+    Glob {
+        dir: PathBuf,
+        pattern: String,
+        files: Vec<PathBuf>,
+    },
+    /// Everything under a directory embedded with `# build: embed-dir`, relative to it
+    Directory { dir: PathBuf, entries: Vec<PathBuf> },
+    /// The locale files of a `# build: messages` directory
+    Messages { dir: PathBuf, files: Vec<PathBuf> },
+}
+
+impl Listing {
+    /// Whether listing the directory again finds the same files
+    pub fn is_current(&self) -> bool {
+        return match self {
+            Listing::Glob {
+                dir,
+                pattern,
+                files,
+            } => fs_util::glob(dir, pattern).is_ok_and(|found| &found == files),
+            Listing::Directory { dir, entries } => {
+                archive::entries(dir).is_ok_and(|found| &found == entries)
+            }
+            Listing::Messages { dir, files } => {
+                messages::files(dir).is_ok_and(|found| &found == files)
+            }
+        };
+    }
+}
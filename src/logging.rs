@@ -0,0 +1,178 @@
+use color_eyre::Result;
+use tree_sitter::Node;
+
+use crate::runtime::Runtime;
+use crate::{Edit, GetText, apply_edits, parse_file, visit_node};
+
+/// The functions the logging library defines, and the `echo` prefixes that map to each
+const LEVELS: [(&str, &[&str]); 4] = [
+    ("log_debug", &["DEBUG:"]),
+    ("log_info", &["INFO:"]),
+    ("log_warn", &["WARN:", "WARNING:"]),
+    ("log_error", &["ERROR:"]),
+];
+
+/// An `echo "LEVEL: ..."` that can become a call to the logging library
+struct Echo {
+    function: &'static str,
+    edits: Vec<Edit>,
+}
+
+/// Adds the logging library to the runtime, then either rewrites `echo "DEBUG: ..."` and the
+/// like into calls to it, or returns warnings about them
+pub fn inject(
+    source: String,
+    runtime: &mut Runtime,
+    rewrite: bool,
+) -> Result<(String, Vec<String>)> {
+    let p = runtime.prefix.clone();
+    runtime.define(format!("{}log", p), || definition(&p));
+
+    let tree = parse_file(&source)?;
+    let mut warnings = vec![];
+    let mut edits = vec![];
+    let mut found: Vec<(&str, usize)> = vec![];
+    visit_node(tree.root_node(), &mut |node| {
+        if node.kind() == "function_definition"
+            && let Some(name) = node.child_by_field_name("name")
+            && let Some((function, _)) = LEVELS.iter().find(|(f, _)| name.text(&source) == *f)
+        {
+            warnings.push(format!(
+                "`{}` is defined in the bundle, replacing the one --with-logging adds",
+                function
+            ));
+        }
+        if node.kind() != "command" {
+            return Ok(());
+        }
+        let Some(echo) = log_echo(node, &source) else {
+            return Ok(());
+        };
+        if rewrite {
+            edits.extend(echo.edits);
+        } else {
+            match found
+                .iter_mut()
+                .find(|(function, _)| *function == echo.function)
+            {
+                Some((_, count)) => *count += 1,
+                None => found.push((echo.function, 1)),
+            }
+        }
+        return Ok(());
+    })?;
+    for (function, count) in found {
+        warnings.push(format!(
+            "{} `echo` line(s) could call `{}` instead. Use --rewrite-log-echoes to rewrite them.",
+            count, function
+        ));
+    }
+    if edits.is_empty() {
+        return Ok((source, warnings));
+    }
+    return Ok((apply_edits(source, edits)?, warnings));
+}
+
+/// Recognizes `echo "DEBUG: ..."`, optionally redirected to stderr, returning the edits that
+/// turn it into `log_debug "..."`. Echoes whose output is captured, piped or written to a file
+/// are left alone, since the logging functions write to stderr.
+fn log_echo(node: Node, source: &str) -> Option<Echo> {
+    let name = node.child_by_field_name("name")?;
+    if name.text(source) != "echo" {
+        return None;
+    }
+    let mut cursor = node.walk();
+    let arguments: Vec<Node> = node
+        .children_by_field_name("argument", &mut cursor)
+        .collect();
+    let first = *arguments.first()?;
+    let text = first.text(source);
+    let quote = if text.starts_with(['"', '\'']) { 1 } else { 0 };
+    let (function, prefix) = LEVELS.iter().find_map(|(function, prefixes)| {
+        let prefix = prefixes
+            .iter()
+            .find(|prefix| text[quote..].starts_with(**prefix))?;
+        return Some((*function, *prefix));
+    })?;
+
+    let mut edits = vec![Edit {
+        start_byte: name.start_byte(),
+        end_byte: name.end_byte(),
+        new_content: function.to_string(),
+    }];
+    let mut ancestor = node.parent();
+    while let Some(parent) = ancestor {
+        match parent.kind() {
+            "pipeline" | "command_substitution" | "process_substitution" => return None,
+            "redirected_statement" => {
+                let mut cursor = parent.walk();
+                let to_stderr = parent
+                    .children_by_field_name("redirect", &mut cursor)
+                    .all(|redirect| matches!(redirect.text(source), ">&2" | "1>&2"));
+                if Some(parent) != node.parent() || !to_stderr {
+                    return None;
+                }
+                edits.push(Edit {
+                    start_byte: node.end_byte(),
+                    end_byte: parent.end_byte(),
+                    new_content: String::new(),
+                });
+            }
+            _ => {}
+        }
+        ancestor = parent.parent();
+    }
+
+    // The prefix and the spaces after it go, along with the whole argument if that leaves it
+    // empty, like in `echo "DEBUG:" "$x"`
+    let start = first.start_byte() + quote;
+    let rest = &text[quote + prefix.len()..];
+    let end = start + prefix.len() + (rest.len() - rest.trim_start_matches(' ').len());
+    let remaining = &text[end - first.start_byte()..];
+    if remaining.is_empty() || (quote == 1 && remaining == &text[..1]) {
+        edits.push(Edit {
+            start_byte: name.end_byte(),
+            end_byte: arguments
+                .get(1)
+                .map_or(first.end_byte(), |next| next.start_byte() - 1),
+            new_content: String::new(),
+        });
+    } else {
+        edits.push(Edit {
+            start_byte: start,
+            end_byte: end,
+            new_content: String::new(),
+        });
+    }
+    return Some(Echo { function, edits });
+}
+
+/// The logging library. Messages go to stderr with a timestamp and their level, colored when
+/// stderr is a terminal and `NO_COLOR` isn't set, and those below `LOG_LEVEL` (info by
+/// default) are dropped. bash 4.2's `printf '%(...)T'` saves running `date` for each message.
+fn definition(p: &str) -> String {
+    return format!(
+        "{p}log() {{\n  \
+         local {p}level=$1 {p}color=$2 {p}rank=$3 {p}min {p}time\n  \
+         shift 3\n  \
+         case ${{LOG_LEVEL:-info}} in\n    \
+         [Dd][Ee][Bb][Uu][Gg]) {p}min=0 ;;\n    \
+         [Ww][Aa][Rr][Nn]*) {p}min=2 ;;\n    \
+         [Ee][Rr][Rr][Oo][Rr]) {p}min=3 ;;\n    \
+         [Oo][Ff][Ff]) {p}min=4 ;;\n    \
+         *) {p}min=1 ;;\n  \
+         esac\n  \
+         [ \"${p}rank\" -ge \"${p}min\" ] || return 0\n  \
+         printf -v {p}time '%(%Y-%m-%dT%H:%M:%S%z)T' -1 2>/dev/null || {p}time=$(date '+%Y-%m-%dT%H:%M:%S%z')\n  \
+         if [ -t 2 ] && [ -z \"${{NO_COLOR-}}\" ]; then\n    \
+         printf '%s \\033[%sm%-5s\\033[0m %s\\n' \"${p}time\" \"${p}color\" \"${p}level\" \"$*\" >&2\n  \
+         else\n    \
+         printf '%s %-5s %s\\n' \"${p}time\" \"${p}level\" \"$*\" >&2\n  \
+         fi\n\
+         }}\n\
+         log_debug() {{ {p}log DEBUG 2 0 \"$@\"; }}\n\
+         log_info() {{ {p}log INFO 32 1 \"$@\"; }}\n\
+         log_warn() {{ {p}log WARN 33 2 \"$@\"; }}\n\
+         log_error() {{ {p}log ERROR 31 3 \"$@\"; }}\n"
+    );
+}
@@ -0,0 +1,143 @@
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::SystemTime;
+
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+use serde::Deserialize;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use shpack::Bundle;
+use shpack::fs_util;
+use shpack::target::Target;
+
+use crate::sink::Sink;
+
+/// Describes a published bundle, so it can be downloaded and checked later
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Manifest {
+    /// The bundle's file name, which is next to the manifest
+    pub name: String,
+    pub sha256: String,
+    pub size: u64,
+    pub build: BuildInfo,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BuildInfo {
+    pub shpack_version: String,
+    /// Seconds since the Unix epoch
+    pub built_at: u64,
+    /// The entry script, relative to the root, or None if it was read from stdin
+    pub entry: Option<String>,
+    pub target: Option<Target>,
+    /// The inlined files in the order their contents appear in the bundle
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub order: Vec<String>,
+    /// The functions the bundle exports to child processes with `export -f`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exported_functions: Vec<String>,
+}
+
+/// Hashes a bundle as it will be written, returning its SHA-256 in hex and its size
+pub fn digest(bundle: &Bundle) -> Result<(String, u64)> {
+    struct Hasher {
+        sha256: Sha256,
+        size: u64,
+    }
+    impl Write for Hasher {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.sha256.update(buf);
+            self.size += buf.len() as u64;
+            return Ok(buf.len());
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            return Ok(());
+        }
+    }
+
+    let mut hasher = Hasher {
+        sha256: Sha256::new(),
+        size: 0,
+    };
+    bundle.write_to(&mut hasher)?;
+    return Ok((format!("{:x}", hasher.sha256.finalize()), hasher.size));
+}
+
+/// Where the manifest of the bundle at `artifact` goes
+pub fn location(artifact: &Sink) -> Sink {
+    return artifact.sibling(&format!("{}.manifest.json", artifact.file_name()));
+}
+
+/// Writes the manifest of a bundle that was written to `artifact`
+pub fn publish(
+    artifact: &Sink,
+    (sha256, size): (String, u64),
+    entry: Option<String>,
+    target: Option<Target>,
+    order: Vec<String>,
+    exported_functions: Vec<String>,
+) -> Result<()> {
+    let manifest = Manifest {
+        name: artifact.file_name(),
+        sha256,
+        size,
+        build: BuildInfo {
+            shpack_version: env!("CARGO_PKG_VERSION").to_string(),
+            built_at: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)?
+                .as_secs(),
+            entry,
+            target,
+            order,
+            exported_functions,
+        },
+    };
+    let content = serde_json::to_string_pretty(&manifest)? + "\n";
+    return location(artifact).put(content.as_bytes());
+}
+
+/// Downloads the bundle described by the manifest at `manifest`, checks that it's the one the
+/// manifest describes, and writes it to `out`, which defaults to its name in the current
+/// directory
+pub fn fetch(manifest: &Path, out: Option<&Path>) -> Result<()> {
+    let location = Sink::parse(Some(manifest))?;
+    let manifest: Manifest = serde_json::from_slice(&location.read()?)
+        .wrap_err_with(|| format!("{} isn't a shpack manifest", location))?;
+    if manifest.name.is_empty() || manifest.name.contains('/') {
+        return Err(eyre!(
+            "expected the manifest to name a file, found `{}`",
+            manifest.name
+        ));
+    }
+
+    let artifact = location.sibling(&manifest.name);
+    let content = artifact.read()?;
+    let sha256 = format!("{:x}", Sha256::digest(&content));
+    if content.len() as u64 != manifest.size || sha256 != manifest.sha256 {
+        return Err(eyre!(
+            "{} doesn't match its manifest: expected {} bytes with SHA-256 {}, found {} bytes with SHA-256 {}",
+            artifact,
+            manifest.size,
+            manifest.sha256,
+            content.len(),
+            sha256
+        ));
+    }
+
+    let out = out.unwrap_or(Path::new(&manifest.name));
+    fs_util::write_atomic(out, &content)?;
+    fs::set_permissions(out, fs::Permissions::from_mode(0o755))?;
+    eprintln!(
+        "Fetched {} ({} bytes, SHA-256 {}) to {}",
+        artifact,
+        content.len(),
+        sha256,
+        out.display()
+    );
+    return Ok(());
+}
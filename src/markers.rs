@@ -0,0 +1,91 @@
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+use serde::Serialize;
+
+/// The comments around each inlined file, as templates. `{path}` is replaced with the file's
+/// path, `{depth}` with how deeply it's nested (1 for files the entry script sources), and
+/// `{indent}` with two spaces per level below that. The first template is used for depth 1,
+/// the second for depth 2 and so on, with the last one used for any deeper files.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Markers {
+    #[serde(rename = "marker-open")]
+    open: Vec<String>,
+    #[serde(rename = "marker-close")]
+    close: Vec<String>,
+}
+
+pub const DEFAULT_OPEN: &str = "# source {path}";
+pub const DEFAULT_CLOSE: &str = "#########";
+
+impl Default for Markers {
+    fn default() -> Self {
+        return Markers {
+            open: vec![DEFAULT_OPEN.to_string()],
+            close: vec![DEFAULT_CLOSE.to_string()],
+        };
+    }
+}
+
+impl Markers {
+    /// Checks the templates, using the defaults for either list if it's empty
+    pub fn new(open: Vec<String>, close: Vec<String>) -> Result<Self> {
+        let mut markers = Markers::default();
+        if !open.is_empty() {
+            markers.open = open;
+        }
+        if !close.is_empty() {
+            markers.close = close;
+        }
+        for template in markers.open.iter().chain(&markers.close) {
+            check(template)?;
+        }
+        return Ok(markers);
+    }
+
+    /// The comment before a file sourced at `depth`
+    pub fn open(&self, path: &str, depth: usize) -> String {
+        return render(pick(&self.open, depth), path, depth);
+    }
+
+    /// The comment after a file sourced at `depth`
+    pub fn close(&self, path: &str, depth: usize) -> String {
+        return render(pick(&self.close, depth), path, depth);
+    }
+}
+
+fn pick(templates: &[String], depth: usize) -> &str {
+    return &templates[depth.saturating_sub(1).min(templates.len() - 1)];
+}
+
+fn render(template: &str, path: &str, depth: usize) -> String {
+    return template
+        .replace("{indent}", &"  ".repeat(depth.saturating_sub(1)))
+        .replace("{depth}", &depth.to_string())
+        .replace("{path}", path);
+}
+
+/// Markers have to stay comments, or they'd change what the bundle does
+fn check(template: &str) -> Result<()> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        let placeholder = &rest[start + 1..start + end];
+        if !matches!(placeholder, "path" | "depth" | "indent") {
+            return Err(eyre!(
+                "unknown placeholder `{{{}}}` in the marker `{}`, expected {{path}}, {{depth}} or {{indent}}",
+                placeholder,
+                template
+            ));
+        }
+        rest = &rest[start + end + 1..];
+    }
+    if template.contains('\n') || !template.trim_start().starts_with('#') {
+        return Err(eyre!(
+            "the marker `{}` must be a single comment line, starting with #",
+            template
+        ));
+    }
+    return Ok(());
+}
@@ -0,0 +1,115 @@
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+
+use crate::data;
+use crate::shell;
+
+/// The `.toml` and `.json` files in `dir`, sorted
+pub fn files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .wrap_err_with(|| format!("failed to read {}", dir.display()))?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<std::io::Result<_>>()?;
+    files.retain(|path| {
+        path.extension()
+            .is_some_and(|ext| ext == "toml" || ext == "json")
+    });
+    files.sort();
+    return Ok(files);
+}
+
+/// A catalog of user-facing messages, one TOML or JSON file per locale
+pub struct Catalog {
+    /// The files the catalog was read from
+    pub files: Vec<PathBuf>,
+    /// Messages keyed by `locale:key`, where nested keys are joined with `.`
+    messages: Vec<(String, String)>,
+    locales: Vec<String>,
+}
+
+impl Catalog {
+    /// Reads every `.toml` and `.json` file in `dir`, named after its locale (`en.toml`,
+    /// `pt_BR.json`)
+    pub fn load(dir: &Path) -> Result<Catalog> {
+        let files = files(dir)?;
+        let mut messages = vec![];
+        let mut locales = vec![];
+        for path in &files {
+            let locale = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .ok_or(eyre!("invalid locale file name: {}", path.display()))?
+                .replace('-', "_");
+            let mut values = vec![];
+            data::index(&data::load(path)?, ".", &mut values, &mut vec![]);
+            for (key, message) in values {
+                messages.push((format!("{}:{}", locale, key), message));
+            }
+            locales.push(locale);
+        }
+        if locales.is_empty() {
+            return Err(eyre!(
+                "no .toml or .json message files in {}",
+                dir.display()
+            ));
+        }
+
+        return Ok(Catalog {
+            files,
+            messages,
+            locales,
+        });
+    }
+
+    /// Generates a `name KEY [ARGS...]` function that prints the message for `KEY` in the
+    /// user's locale, taken from `LC_ALL`, `LC_MESSAGES` or `LANG`, using it as a printf format
+    /// for `ARGS`. Falls back to the locale's language (`pt` for `pt_BR`), then `default`, then
+    /// the key itself.
+    pub fn lookup_function(
+        &self,
+        name: &str,
+        default: Option<&str>,
+        prefix: &str,
+    ) -> Result<String> {
+        if !shell::is_identifier(name) {
+            return Err(eyre!("`{}` isn't a valid function name", name));
+        }
+        let default = match default {
+            Some(locale) if self.locales.iter().any(|l| l == locale) => locale,
+            Some(locale) => return Err(eyre!("there are no messages for locale {}", locale)),
+            None if self.locales.iter().any(|l| l == "en") => "en",
+            None => &self.locales[0],
+        };
+
+        let array = format!("{}messages_{}", prefix, name);
+        let entries: String = self
+            .messages
+            .iter()
+            .map(|(key, message)| format!("  [{}]={}\n", shell::quote(key), shell::quote(message)))
+            .collect();
+        return Ok(format!(
+            "declare -gA {array}=(\n{entries})\n\
+             {name}() {{\n  \
+             local {p}locale {p}candidate\n  \
+             {p}locale=${{LC_ALL:-${{LC_MESSAGES:-${{LANG:-}}}}}}\n  \
+             {p}locale=${{{p}locale%%[.@]*}}\n  \
+             for {p}candidate in \"${p}locale\" \"${{{p}locale%%_*}}\" {default}; do\n    \
+             if [[ ${{{array}[${p}candidate:$1]+set}} ]]; then\n      \
+             printf -- \"${{{array}[${p}candidate:$1]}}\\n\" \"${{@:2}}\"\n      \
+             return\n    \
+             fi\n  \
+             done\n  \
+             printf '%s\\n' \"$1\"\n\
+             }}\n",
+            array = array,
+            entries = entries,
+            name = name,
+            p = prefix,
+            default = shell::quote(default),
+        ));
+    }
+}
@@ -0,0 +1,197 @@
+use std::path::PathBuf;
+
+use color_eyre::Result;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::builder::BundlerBuilder;
+use crate::deadline;
+use crate::defines::Defines;
+use crate::harden::Hardening;
+use crate::markers::Markers;
+use crate::payload::{Compression, Encoding};
+use crate::secrets::SecretsPolicy;
+use crate::self_extract::Format;
+use crate::target::Target;
+use crate::template::Metadata;
+use crate::transform::Strip;
+
+/// Every option that changes what a bundle contains, as the command line, `shpack build` and
+/// batch requests all pass them on. Batch requests take them as their `options`, and a
+/// package's build cache is keyed on them.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BundleOptions {
+    #[serde(default)]
+    pub include_paths: Vec<PathBuf>,
+    pub order_by_trace: Option<PathBuf>,
+    pub stream_threshold: Option<u64>,
+    pub memory_cap: Option<u64>,
+    pub jobs: Option<usize>,
+    pub retries: Option<u32>,
+    /// A duration like `2s`
+    pub retry_backoff: Option<String>,
+    #[serde(default)]
+    pub prelude: Vec<PathBuf>,
+    #[serde(default)]
+    pub epilogue: Vec<PathBuf>,
+    #[serde(default)]
+    pub transforms: Vec<PathBuf>,
+    pub compress: Option<Compression>,
+    pub compress_threshold: Option<u64>,
+    pub encoding: Option<Encoding>,
+    pub target: Option<Target>,
+    pub runtime_tmpdir: Option<String>,
+    pub symbol_prefix: Option<String>,
+    pub shebang: Option<String>,
+    #[serde(default)]
+    pub defines: Defines,
+    #[serde(default)]
+    pub metadata: Metadata,
+    #[serde(default)]
+    pub tree_shake: bool,
+    #[serde(default)]
+    pub keep: Vec<String>,
+    /// Only asked for on the command line, so it's left out when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub why_kept: Vec<String>,
+    #[serde(default)]
+    pub portable: bool,
+    #[serde(default)]
+    pub with_logging: bool,
+    #[serde(default)]
+    pub rewrite_log_echoes: bool,
+    #[serde(default)]
+    pub version_stamp: bool,
+    #[serde(default)]
+    pub harden: Vec<Hardening>,
+    #[serde(default)]
+    pub exempt: Vec<String>,
+    #[serde(default)]
+    pub runtime_sources: Vec<String>,
+    #[serde(default)]
+    pub external: Vec<String>,
+    #[serde(default)]
+    pub source_aliases: Vec<String>,
+    #[serde(default)]
+    pub strip_source_aliases: bool,
+    #[serde(default)]
+    pub script_dir_variables: Vec<String>,
+    #[serde(default)]
+    pub deny_version_conflicts: bool,
+    #[serde(default)]
+    pub secrets: SecretsPolicy,
+    #[serde(default)]
+    pub format: Format,
+    pub archive_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub deny_absolute_sources: bool,
+    #[serde(default)]
+    pub allow_large_include: bool,
+    #[serde(default)]
+    pub allow_circular: bool,
+    #[serde(default)]
+    pub topological_includes: bool,
+    #[serde(default)]
+    pub strip: Strip,
+    #[serde(default)]
+    pub minify: bool,
+    #[serde(default)]
+    pub inject_trace: bool,
+    /// The arguments that rebuild the bundle, recorded in it by `--provenance`
+    #[serde(default)]
+    pub provenance: Option<Vec<String>>,
+    #[serde(default)]
+    pub marker_open: Vec<String>,
+    #[serde(default)]
+    pub marker_close: Vec<String>,
+}
+
+impl BundleOptions {
+    /// Sets every option on `builder`, leaving those that aren't given at its defaults
+    pub fn apply(&self, builder: BundlerBuilder) -> Result<BundlerBuilder> {
+        let mut builder = builder
+            .include_paths(self.include_paths.clone())
+            .prelude(self.prelude.clone())
+            .epilogue(self.epilogue.clone())
+            .transforms(self.transforms.clone())
+            .defines(self.defines.clone())
+            .metadata(self.metadata.clone())
+            .tree_shake(self.tree_shake)
+            .keep(self.keep.clone())
+            .why_kept(self.why_kept.clone())
+            .portable(self.portable)
+            .with_logging(self.with_logging)
+            .rewrite_log_echoes(self.rewrite_log_echoes)
+            .version_stamp(self.version_stamp)
+            .harden(self.harden.clone())
+            .exempt(self.exempt.clone())
+            .runtime_sources(self.runtime_sources.clone())
+            .external(self.external.clone())
+            .source_aliases(self.source_aliases.clone())
+            .strip_source_aliases(self.strip_source_aliases)
+            .script_dir_variables(self.script_dir_variables.clone())
+            .deny_version_conflicts(self.deny_version_conflicts)
+            .secrets(self.secrets)
+            .format(self.format)
+            .deny_absolute_sources(self.deny_absolute_sources)
+            .allow_large_include(self.allow_large_include)
+            .allow_circular(self.allow_circular)
+            .topological_includes(self.topological_includes)
+            .strip(self.strip)
+            .minify(self.minify)
+            .inject_trace(self.inject_trace)
+            .markers(self.markers()?);
+        if let Some(trace) = &self.order_by_trace {
+            builder = builder.order_by_trace(trace);
+        }
+        if let Some(threshold) = self.stream_threshold {
+            builder = builder.stream_threshold(threshold);
+        }
+        if let Some(cap) = self.memory_cap {
+            builder = builder.memory_cap(cap);
+        }
+        if let Some(jobs) = self.jobs {
+            builder = builder.jobs(jobs);
+        }
+        if let Some(retries) = self.retries {
+            builder = builder.retries(retries);
+        }
+        if let Some(backoff) = &self.retry_backoff {
+            builder = builder.retry_backoff(deadline::parse_option("retry-backoff", backoff)?);
+        }
+        if let Some(compression) = self.compress {
+            builder = builder.compression(compression);
+        }
+        if let Some(threshold) = self.compress_threshold {
+            builder = builder.compress_threshold(threshold);
+        }
+        if let Some(encoding) = self.encoding {
+            builder = builder.encoding(encoding);
+        }
+        if let Some(target) = self.target {
+            builder = builder.target(target);
+        }
+        if let Some(dir) = &self.runtime_tmpdir {
+            builder = builder.runtime_tmpdir(dir);
+        }
+        if let Some(shabang) = &self.shebang {
+            builder = builder.shabang(shabang);
+        }
+        if let Some(prefix) = &self.symbol_prefix {
+            builder = builder.symbol_prefix(prefix);
+        }
+        if let Some(dir) = &self.archive_dir {
+            builder = builder.archive_dir(dir);
+        }
+        if let Some(args) = &self.provenance {
+            builder = builder.provenance(args.clone());
+        }
+        return Ok(builder);
+    }
+
+    /// The `marker_open` and `marker_close` templates, checked and with their defaults
+    pub fn markers(&self) -> Result<Markers> {
+        return Markers::new(self.marker_open.clone(), self.marker_close.clone());
+    }
+}
@@ -0,0 +1,271 @@
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+
+use base64::prelude::BASE64_STANDARD;
+use base64::write::EncoderWriter;
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+use data_encoding::BASE32;
+use flate2::write::GzEncoder;
+use serde::Deserialize;
+use serde::Serialize;
+use tempfile::NamedTempFile;
+use tempfile::TempPath;
+
+/// Payloads at least this large are spooled to disk and streamed into the output
+pub const DEFAULT_STREAM_THRESHOLD: u64 = 1024 * 1024;
+
+/// Payloads at least this large are compressed, if that makes them smaller
+pub const DEFAULT_COMPRESS_THRESHOLD: u64 = 16 * 1024;
+
+/// How payloads are compressed before being encoded into the bundle
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum, schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum Compression {
+    None,
+    #[default]
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn parse(value: &str) -> Result<Compression> {
+        return match value {
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(eyre!(
+                "unknown compression \"{}\", expected none, gzip or zstd",
+                value
+            )),
+        };
+    }
+
+    /// The tool needed to decompress the payload at runtime
+    pub fn tool(self) -> Option<&'static str> {
+        return match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gzip"),
+            Compression::Zstd => Some("zstd"),
+        };
+    }
+
+    /// Compresses the file at `path` into a new temporary file
+    pub fn compress(self, path: &Path) -> Result<NamedTempFile> {
+        let mut out = NamedTempFile::new()?;
+        let mut input = File::open(path)?;
+        match self {
+            Compression::None => {
+                io::copy(&mut input, &mut out)?;
+            }
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(out.as_file_mut(), flate2::Compression::best());
+                io::copy(&mut input, &mut encoder)?;
+                encoder.finish()?;
+            }
+            Compression::Zstd => {
+                zstd::stream::copy_encode(&mut input, out.as_file_mut(), 19)?;
+            }
+        }
+        return Ok(out);
+    }
+}
+
+/// Marks where the encoding of a spooled payload goes in a bundle's content. Shell
+/// scripts can't contain NUL bytes, so markers can't collide with real code.
+pub fn marker(index: usize) -> String {
+    return format!("\0{}\0", index);
+}
+
+/// Writes bundle content to `out`, streaming the encoding of each spooled payload in place of
+/// its marker so the encoded payload never has to be held in memory
+pub fn write_content(
+    content: &str,
+    payloads: &[(TempPath, Encoding)],
+    out: &mut impl Write,
+) -> Result<()> {
+    for (i, part) in content.split('\0').enumerate() {
+        if i % 2 == 0 {
+            out.write_all(part.as_bytes())?;
+            continue;
+        }
+
+        let (path, encoding) = part
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| payloads.get(index))
+            .ok_or(eyre!("bundle contains an invalid payload marker"))?;
+        encoding.encode(&mut File::open(path)?, out)?;
+    }
+    return Ok(());
+}
+
+/// How payloads are written into the bundle as shell code
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum, schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum Encoding {
+    /// Decoded by `base64 -d`
+    #[default]
+    Base64,
+    /// Decoded by `base32 -d`, for systems without base64
+    Base32,
+    /// A quoted heredoc read by `cat`, which only works for text
+    Heredoc,
+    /// Octal escapes decoded by the `printf` builtin, which needs no external tools
+    Octal,
+}
+
+impl Encoding {
+    pub fn parse(value: &str) -> Result<Encoding> {
+        return match value {
+            "base64" => Ok(Encoding::Base64),
+            "base32" => Ok(Encoding::Base32),
+            "heredoc" => Ok(Encoding::Heredoc),
+            "octal" => Ok(Encoding::Octal),
+            _ => Err(eyre!(
+                "unknown encoding \"{}\", expected base64, base32, heredoc or octal",
+                value
+            )),
+        };
+    }
+
+    /// The external tool that decodes the payload at runtime, if any
+    pub fn tool(self) -> Option<&'static str> {
+        return match self {
+            Encoding::Base64 => Some("base64"),
+            Encoding::Base32 => Some("base32"),
+            Encoding::Heredoc => Some("cat"),
+            Encoding::Octal => None,
+        };
+    }
+
+    /// Whether the encoding can carry arbitrary bytes rather than just text
+    pub fn is_binary_safe(self) -> bool {
+        return self != Encoding::Heredoc;
+    }
+
+    /// Writes the encoding of `input` to `out`
+    pub fn encode(self, input: &mut impl Read, out: &mut impl Write) -> Result<()> {
+        match self {
+            Encoding::Base64 => {
+                let mut encoder = EncoderWriter::new(out, &BASE64_STANDARD);
+                io::copy(input, &mut encoder)?;
+                encoder.finish()?;
+            }
+            Encoding::Base32 => {
+                // Chunks must be a multiple of 5 bytes so they encode without padding
+                let mut chunk = vec![0; 5 * 4096];
+                loop {
+                    let len = read_full(input, &mut chunk)?;
+                    out.write_all(BASE32.encode(&chunk[..len]).as_bytes())?;
+                    if len < chunk.len() {
+                        break;
+                    }
+                }
+            }
+            Encoding::Heredoc => {
+                io::copy(input, out)?;
+            }
+            Encoding::Octal => {
+                let mut encoded = vec![];
+                for byte in BufReader::new(input).bytes() {
+                    let byte = byte?;
+                    match byte {
+                        b'\\' | b'%' | b'\'' => write!(encoded, "\\{:03o}", byte)?,
+                        0x20..=0x7e => encoded.push(byte),
+                        _ => write!(encoded, "\\{:03o}", byte)?,
+                    }
+                    if encoded.len() >= 64 * 1024 {
+                        out.write_all(&encoded)?;
+                        encoded.clear();
+                    }
+                }
+                out.write_all(&encoded)?;
+            }
+        }
+        return Ok(());
+    }
+
+    /// The command that turns the encoded text back into the payload, if it isn't already
+    /// decoded by the time it's written out
+    pub fn filter(self) -> Option<&'static str> {
+        return match self {
+            Encoding::Base64 => Some("base64 -d"),
+            Encoding::Base32 => Some("base32 -d"),
+            Encoding::Heredoc | Encoding::Octal => None,
+        };
+    }
+
+    /// Generates a command substitution that writes out `encoded` and pipes it through
+    /// `pipeline` (e.g. ` | __shpack_decode_base64`), which must include the [`Encoding::filter`].
+    /// `delimiter` ends heredocs and must not appear as a line of the payload, which must end
+    /// in a newline.
+    pub fn decoder(self, encoded: &str, delimiter: &str, pipeline: &str) -> String {
+        return match self {
+            Encoding::Base64 | Encoding::Base32 => format!("$(echo '{}'{})", encoded, pipeline),
+            Encoding::Heredoc => format!(
+                "$(cat <<'{}'{}\n{}{}\n)",
+                delimiter, pipeline, encoded, delimiter
+            ),
+            Encoding::Octal => format!("$(printf '{}'{})", encoded, pipeline),
+        };
+    }
+}
+
+/// Picks a heredoc delimiter that doesn't appear as a line of the file at `path`. Fails if the
+/// file isn't text, since heredocs can't carry NUL bytes.
+pub fn heredoc_delimiter(path: &Path) -> Result<String> {
+    let mut lines = vec![];
+    for line in BufReader::new(File::open(path)?).split(b'\n') {
+        let line = line?;
+        if line.contains(&0) {
+            return Err(eyre!(
+                "the heredoc encoding only works for text, use base64 for binary output"
+            ));
+        }
+        if line.starts_with(b"__SHPACK_EOF") {
+            lines.push(line);
+        }
+    }
+
+    let mut delimiter = String::from("__SHPACK_EOF__");
+    let mut i = 0;
+    while lines.iter().any(|line| line == delimiter.as_bytes()) {
+        i += 1;
+        delimiter = format!("__SHPACK_EOF_{}__", i);
+    }
+    return Ok(delimiter);
+}
+
+/// Whether the file at `path` ends in a newline
+pub fn ends_with_newline(path: &Path) -> Result<bool> {
+    let mut file = File::open(path)?;
+    if file.metadata()?.len() == 0 {
+        return Ok(false);
+    }
+    file.seek(SeekFrom::End(-1))?;
+    let mut last = [0];
+    file.read_exact(&mut last)?;
+    return Ok(last[0] == b'\n');
+}
+
+/// Reads until `buf` is full or the input ends, returning how much was read
+fn read_full(input: &mut impl Read, buf: &mut [u8]) -> Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match input.read(&mut buf[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    return Ok(len);
+}
@@ -0,0 +1,168 @@
+use color_eyre::Result;
+use tree_sitter::Node;
+
+use crate::runtime::Runtime;
+use crate::{Edit, GetText, apply_edits, parse_file, visit_node};
+
+/// Tools whose GNU and BSD versions disagree about a common option, along with the option
+const DIVERGENCES: [(&str, &str); 4] = [
+    ("readlink", "-f"),
+    ("sed", "-i"),
+    ("date", "-d"),
+    ("stat", "-c"),
+];
+
+/// Returns the `tool option` usage a command relies on that GNU and BSD systems disagree about,
+/// like `sed -i`
+fn divergence(node: Node, source: &str) -> Option<(&'static str, &'static str)> {
+    let name = node.child_by_field_name("name")?;
+    if name.named_child(0).is_none_or(|word| word.kind() != "word") {
+        return None;
+    }
+    let (tool, option) = DIVERGENCES
+        .into_iter()
+        .find(|(tool, _)| name.text(source) == *tool)?;
+    let mut cursor = node.walk();
+    let mut arguments = node.children_by_field_name("argument", &mut cursor);
+    // readlink's option takes no value, while the others' values may be attached to them
+    let uses = arguments.any(|arg| {
+        let text = arg.text(source);
+        return if tool == "readlink" {
+            text == option
+        } else {
+            text.starts_with(option)
+        };
+    });
+    return uses.then_some((tool, option));
+}
+
+/// Lists the distinct GNU-only usages in `source`, like `sed -i`
+pub fn find(source: &str) -> Result<Vec<String>> {
+    let tree = parse_file(source)?;
+    let mut found = vec![];
+    visit_node(tree.root_node(), &mut |node| {
+        if node.kind() == "command"
+            && let Some((tool, option)) = divergence(node, source)
+        {
+            let usage = format!("{} {}", tool, option);
+            if !found.contains(&usage) {
+                found.push(usage);
+            }
+        }
+        return Ok(());
+    })?;
+    return Ok(found);
+}
+
+/// Replaces commands that rely on `readlink -f`, `sed -i`, `date -d` or `stat -c` with calls to
+/// runtime helpers that behave like GNU's version on BSD systems like macOS too
+pub fn shim(source: String, runtime: &mut Runtime) -> Result<String> {
+    let tree = parse_file(&source)?;
+    let mut edits = vec![];
+    visit_node(tree.root_node(), &mut |node| {
+        if node.kind() != "command" {
+            return Ok(());
+        }
+        let Some((tool, _)) = divergence(node, &source) else {
+            return Ok(());
+        };
+        let name = node.child_by_field_name("name").expect("checked above");
+        let helper = format!("{}{}", runtime.prefix, tool);
+        let prefix = runtime.prefix.clone();
+        runtime.define(helper.clone(), || definition(tool, &prefix));
+        edits.push(Edit {
+            start_byte: name.start_byte(),
+            end_byte: name.end_byte(),
+            new_content: helper,
+        });
+        return Ok(());
+    })?;
+    if edits.is_empty() {
+        return Ok(source);
+    }
+    return apply_edits(source, edits);
+}
+
+/// The helper standing in for `tool`. Each one defers to the tool itself where it's GNU's
+/// version, which answers `--version`. Arrays are expanded with `${a[@]+"${a[@]}"}` since
+/// macOS's bash 3.2 treats empty arrays as unset under `set -u`.
+fn definition(tool: &str, p: &str) -> String {
+    return match tool {
+        "readlink" => format!(
+            "{p}readlink() {{\n  \
+             if [ \"$1\" != -f ] || command readlink -f / >/dev/null 2>&1; then command readlink \"$@\"; return; fi\n  \
+             shift; [ \"${{1-}}\" = -- ] && shift\n  \
+             local {p}path=$1 {p}dir\n  \
+             while [ -L \"${p}path\" ]; do\n    \
+             {p}dir=$(cd -P \"$(dirname -- \"${p}path\")\" && pwd) || return 1\n    \
+             {p}path=$(command readlink \"${p}path\")\n    \
+             case ${p}path in /*) ;; *) {p}path=${p}dir/${p}path ;; esac\n  \
+             done\n  \
+             {p}dir=$(cd -P \"$(dirname -- \"${p}path\")\" 2>/dev/null && pwd) || return 1\n  \
+             printf '%s\\n' \"${{{p}dir%/}}/$(basename -- \"${p}path\")\"\n\
+             }}\n"
+        ),
+        // GNU sed takes a backup suffix only when it's attached to -i, while BSD sed always
+        // takes one, so `sed -i ''` is how BSD scripts ask for no backup
+        "sed" => format!(
+            "{p}sed() {{\n  \
+             local {p}gnu= {p}args=()\n  \
+             command sed --version >/dev/null 2>&1 && {p}gnu=1\n  \
+             while [ $# -gt 0 ]; do\n    \
+             case $1 in\n      \
+             -i) if [ -n \"${p}gnu\" ]; then {p}args+=(-i); else {p}args+=(-i ''); fi\n        \
+             [ \"${{2-x}}\" = '' ] && shift ;;\n      \
+             -i*) if [ -n \"${p}gnu\" ]; then {p}args+=(\"$1\"); else {p}args+=(-i \"${{1#-i}}\"); fi ;;\n      \
+             *) {p}args+=(\"$1\") ;;\n    \
+             esac\n    \
+             shift\n  \
+             done\n  \
+             command sed ${{{p}args[@]+\"${{{p}args[@]}}\"}}\n\
+             }}\n"
+        ),
+        // BSD date parses dates with an explicit format, so only the common ones are handled
+        "date" => format!(
+            "{p}date() {{\n  \
+             if command date --version >/dev/null 2>&1; then command date \"$@\"; return; fi\n  \
+             local {p}when= {p}args=()\n  \
+             while [ $# -gt 0 ]; do\n    \
+             case $1 in\n      \
+             -d) {p}when=$2; shift ;;\n      \
+             -d*) {p}when=${{1#-d}} ;;\n      \
+             *) {p}args+=(\"$1\") ;;\n    \
+             esac\n    \
+             shift\n  \
+             done\n  \
+             case ${p}when in\n    \
+             '') command date ${{{p}args[@]+\"${{{p}args[@]}}\"}} ;;\n    \
+             @*) command date -r \"${{{p}when#@}}\" ${{{p}args[@]+\"${{{p}args[@]}}\"}} ;;\n    \
+             ????-??-??) command date -j -f '%Y-%m-%d %H:%M:%S' \"${p}when 00:00:00\" ${{{p}args[@]+\"${{{p}args[@]}}\"}} ;;\n    \
+             ????-??-??[T\\ ]??:??:??) command date -j -f \"%Y-%m-%d${{{p}when:10:1}}%H:%M:%S\" \"${p}when\" ${{{p}args[@]+\"${{{p}args[@]}}\"}} ;;\n    \
+             *) echo \"$0: date -d '${p}when' isn't supported on this system\" >&2; return 1 ;;\n  \
+             esac\n\
+             }}\n"
+        ),
+        // The BSD equivalents of the common GNU format sequences. None of the replacements
+        // are themselves replaced later.
+        "stat" => format!(
+            "{p}stat() {{\n  \
+             if command stat --version >/dev/null 2>&1; then command stat \"$@\"; return; fi\n  \
+             local {p}format {p}args=()\n  \
+             while [ $# -gt 0 ]; do\n    \
+             case $1 in\n      \
+             -c) {p}format=$2; shift ;;\n      \
+             -c*) {p}format=${{1#-c}} ;;\n      \
+             *) {p}args+=(\"$1\"); shift; continue ;;\n    \
+             esac\n    \
+             shift\n    \
+             {p}format=${{{p}format//\\%s/%z}}; {p}format=${{{p}format//\\%Y/%m}}; {p}format=${{{p}format//\\%n/%N}}\n    \
+             {p}format=${{{p}format//\\%a/%Lp}}; {p}format=${{{p}format//\\%U/%Su}}; {p}format=${{{p}format//\\%G/%Sg}}\n    \
+             {p}format=${{{p}format//\\%h/%l}}; {p}format=${{{p}format//\\%F/%HT}}\n    \
+             {p}args+=(-f \"${p}format\")\n  \
+             done\n  \
+             command stat ${{{p}args[@]+\"${{{p}args[@]}}\"}}\n\
+             }}\n"
+        ),
+        _ => unreachable!("no helper for {}", tool),
+    };
+}
@@ -0,0 +1,55 @@
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::target::Target;
+
+/// Something that happened during a build, for UIs that show progress. With `--progress json`,
+/// events are written to stderr as JSON lines, in place of the messages meant for people.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event<'a> {
+    BundleStarted {
+        /// The entry script, relative to the root, or None if it's read from stdin
+        entry: Option<&'a str>,
+        target: Option<Target>,
+    },
+    /// A sourced file, relative to the root, is being inlined
+    FileStarted {
+        path: &'a str,
+    },
+    FileFinished {
+        path: &'a str,
+        /// The size of the file's contents in the bundle, including the files it sourced
+        bytes: u64,
+    },
+    Warning {
+        message: &'a str,
+    },
+    Note {
+        message: &'a str,
+    },
+    BundleFinished {
+        target: Option<Target>,
+        /// Where the bundle was written
+        out: String,
+        bytes: u64,
+    },
+    PackageStarted {
+        name: &'a str,
+    },
+    /// The package was skipped because it's up to date
+    PackageFresh {
+        name: &'a str,
+    },
+    PackageFinished {
+        name: &'a str,
+        bytes: u64,
+    },
+}
+
+/// Writes `event` to stderr as a line of JSON
+pub fn emit(event: &Event) {
+    let line = serde_json::to_string(event).expect("events serialize to JSON");
+    let _ = writeln!(std::io::stderr().lock(), "{}", line);
+}
@@ -0,0 +1,80 @@
+use crate::shell;
+
+const PREFIX: &str = "# generated by shpack ";
+
+/// The comment after the shabang that records how a bundle was built, given the arguments
+/// shpack was run with
+pub fn header(args: &[String]) -> String {
+    let args: Vec<String> = args.iter().map(|arg| quote(arg)).collect();
+    return format!(
+        "{}{} with: {}",
+        PREFIX,
+        env!("CARGO_PKG_VERSION"),
+        args.join(" ")
+    );
+}
+
+/// Quotes an argument only if the shell needs it to be, keeping it on one line
+pub fn quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,@+%".contains(c))
+    {
+        return arg.to_string();
+    }
+    if arg.contains(['\n', '\r']) {
+        return format!(
+            "$'{}'",
+            arg.replace('\\', "\\\\")
+                .replace('\'', "\\'")
+                .replace('\n', "\\n")
+                .replace('\r', "\\r")
+        );
+    }
+    return shell::quote(arg);
+}
+
+/// The shpack version and arguments recorded in a bundle's provenance comment, if it has one
+pub fn parse(bundle: &str) -> Option<(String, Vec<String>)> {
+    let line = bundle.lines().nth(1)?;
+    let (version, args) = line.strip_prefix(PREFIX)?.split_once(" with: ")?;
+    return Some((version.to_string(), split(args)?));
+}
+
+/// Splits arguments quoted by [`quote`] back apart, or returns None if the quotes don't match
+fn split(args: &str) -> Option<Vec<String>> {
+    let mut words = vec![];
+    let mut chars = args.chars().peekable();
+    loop {
+        while chars.next_if(|c| *c == ' ').is_some() {}
+        if chars.peek().is_none() {
+            return Some(words);
+        }
+        let mut word = String::new();
+        while let Some(c) = chars.next_if(|c| *c != ' ') {
+            match c {
+                '\'' => loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                },
+                '$' if chars.next_if_eq(&'\'').is_some() => loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        '\\' => match chars.next()? {
+                            'n' => word.push('\n'),
+                            'r' => word.push('\r'),
+                            c => word.push(c),
+                        },
+                        c => word.push(c),
+                    }
+                },
+                '\\' => word.push(chars.next()?),
+                c => word.push(c),
+            }
+        }
+        words.push(word);
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::BTreeSet;
+use std::process::Command;
+
+use color_eyre::Result;
+
+use crate::{GetText, parse_file, visit_node};
+
+/// The `PATH` of `# build: inline pure` commands
+pub const PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+/// Variables bash sets itself, so reading them doesn't depend on the environment
+const SET_BY_BASH: &[&str] = &[
+    "PATH",
+    "LC_ALL",
+    "PWD",
+    "OLDPWD",
+    "SHLVL",
+    "IFS",
+    "PPID",
+    "UID",
+    "EUID",
+    "GROUPS",
+    "RANDOM",
+    "SRANDOM",
+    "SECONDS",
+    "LINENO",
+    "REPLY",
+    "OPTIND",
+    "OPTARG",
+    "OPTERR",
+    "PIPESTATUS",
+    "FUNCNAME",
+    "HOSTNAME",
+    "HOSTTYPE",
+    "OSTYPE",
+    "MACHTYPE",
+    "SHELLOPTS",
+    "BASHOPTS",
+    "PS4",
+];
+
+/// Runs `command` with only a fixed `PATH` and `LC_ALL=C` in its environment, so its output
+/// doesn't depend on the machine it's built on
+pub fn scrub(command: &mut Command) {
+    command.env_clear().env("PATH", PATH).env("LC_ALL", "C");
+}
+
+/// The variables `command` reads that a pure command's environment doesn't have, and that the
+/// command doesn't set itself. Variables read by the programs it runs can't be seen.
+pub fn impure_reads(command: &str) -> Result<Vec<String>> {
+    let tree = parse_file(command)?;
+    let mut assigned = BTreeSet::new();
+    let mut read = BTreeSet::new();
+    visit_node(tree.root_node(), &mut |node| {
+        match node.kind() {
+            "variable_assignment" => {
+                if let Some(name) = node.child_by_field_name("name") {
+                    assigned.insert(name.text(command).to_string());
+                }
+            }
+            "for_statement" => {
+                if let Some(variable) = node.child_by_field_name("variable") {
+                    assigned.insert(variable.text(command).to_string());
+                }
+            }
+            "simple_expansion" | "expansion" => {
+                let mut cursor = node.walk();
+                if let Some(name) = node
+                    .named_children(&mut cursor)
+                    .find(|child| child.kind() == "variable_name")
+                {
+                    read.insert(name.text(command).to_string());
+                }
+            }
+            _ => {}
+        }
+        return Ok(());
+    })?;
+    return Ok(read
+        .into_iter()
+        .filter(|name| {
+            !assigned.contains(name)
+                && !SET_BY_BASH.contains(&name.as_str())
+                && !name.starts_with("BASH")
+        })
+        .collect());
+}
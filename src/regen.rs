@@ -0,0 +1,68 @@
+use std::env;
+use std::path::Path;
+use std::process::Command;
+use std::process::Stdio;
+
+use color_eyre::Result;
+use color_eyre::eyre::WrapErr;
+use color_eyre::eyre::eyre;
+use shpack::fs_util;
+use shpack::fs_util::read_script;
+use shpack::provenance;
+
+/// Rebuilds the bundle at `path` with the arguments recorded in its provenance comment, from
+/// the current directory, and reports whether the result matches. Builds that print the
+/// bundle have it written back to `path`, and the rest write it wherever they did before.
+pub fn run(path: &Path) -> Result<()> {
+    let previous = read_script(path).wrap_err_with(|| format!("can't read {}", path.display()))?;
+    let Some((version, args)) = provenance::parse(&previous) else {
+        return Err(eyre!(
+            "{} doesn't say how it was built. Bundles built with --provenance, or `provenance = true` in shpack.toml, do.",
+            path.display()
+        ));
+    };
+    if version != env!("CARGO_PKG_VERSION") {
+        eprintln!(
+            "warning: {} was built by shpack {}, but this is shpack {}, so it may differ",
+            path.display(),
+            version,
+            env!("CARGO_PKG_VERSION")
+        );
+    }
+
+    let command: Vec<String> = args.iter().map(|arg| provenance::quote(arg)).collect();
+    eprintln!(
+        "  Rebuilding {} with: shpack {}",
+        path.display(),
+        command.join(" ")
+    );
+    let output = Command::new(env::current_exe()?)
+        .args(&args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .wrap_err("couldn't run shpack")?;
+    if !output.status.success() {
+        return Err(eyre!("rebuilding {} failed", path.display()));
+    }
+
+    let current = if output.stdout.is_empty() {
+        read_script(path).wrap_err_with(|| format!("can't read {}", path.display()))?
+    } else {
+        let current =
+            String::from_utf8(output.stdout).wrap_err("the rebuilt bundle isn't valid UTF-8")?;
+        if current != previous {
+            fs_util::write_atomic(path, current.as_bytes())?;
+        }
+        current
+    };
+    if current == previous {
+        eprintln!("   Unchanged {}", path.display());
+    } else {
+        eprintln!(
+            "     Changed {}, it differs from the previous build",
+            path.display()
+        );
+    }
+    return Ok(());
+}
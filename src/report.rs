@@ -0,0 +1,303 @@
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+use serde::Serialize;
+
+use crate::annotate::escape;
+use crate::library;
+use crate::library::Library;
+use crate::{Bundle, InlineCommand};
+
+/// What went into a bundle, for reviewers who won't run shpack themselves
+#[derive(Debug, Serialize)]
+pub struct Report<'a> {
+    /// The entry script, relative to the root
+    entry: String,
+    bundle_bytes: u64,
+    files: Vec<FileSize>,
+    /// Which file sourced which, with None for the entry script
+    includes: &'a [(Option<String>, String)],
+    /// The inlined files in the order their contents appear in the bundle
+    order: &'a [String],
+    warnings: &'a [String],
+    notes: &'a [String],
+    commands: &'a [InlineCommand],
+    required_tools: &'a BTreeMap<String, String>,
+    libraries: &'a [Library],
+    /// The newest bash version any of the libraries asks for
+    min_bash: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileSize {
+    /// Relative to the root
+    pub path: String,
+    pub source_bytes: u64,
+    /// The size of the file's contents in the bundle, including the files it sourced. None
+    /// for files read by `# build:` directives, which aren't inlined as they are.
+    pub bundled_bytes: Option<u64>,
+}
+
+impl<'a> Report<'a> {
+    pub fn new(
+        bundle: &'a Bundle,
+        entry: String,
+        entry_bytes: u64,
+        root: &Path,
+        bundle_bytes: u64,
+    ) -> Result<Self> {
+        let mut files = vec![FileSize {
+            path: entry.clone(),
+            source_bytes: entry_bytes,
+            bundled_bytes: Some(bundle_bytes),
+        }];
+        for source in &bundle.sources {
+            let path = source
+                .strip_prefix(root)
+                .unwrap_or(source)
+                .to_string_lossy()
+                .to_string();
+            let bundled_bytes = bundle
+                .fragments
+                .iter()
+                .find(|fragment| fragment.path == path)
+                .map(|fragment| fragment.bytes);
+            files.push(FileSize {
+                source_bytes: fs::metadata(source)?.len(),
+                path,
+                bundled_bytes,
+            });
+        }
+        return Ok(Report {
+            entry,
+            bundle_bytes,
+            files,
+            includes: &bundle.includes,
+            order: &bundle.order,
+            warnings: &bundle.warnings,
+            notes: &bundle.notes,
+            commands: &bundle.commands,
+            required_tools: &bundle.required_tools,
+            libraries: &bundle.libraries,
+            min_bash: library::min_bash(&bundle.libraries),
+        });
+    }
+
+    /// Writes the report to `path`, as a single HTML page if it ends in `.html` and as JSON if
+    /// it ends in `.json`
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let content = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("html") => self.html(),
+            Some("json") => serde_json::to_string_pretty(self)? + "\n",
+            _ => {
+                return Err(eyre!(
+                    "expected the report to be a .html or .json file, found {}",
+                    path.display()
+                ));
+            }
+        };
+        return fs::write(path, content)
+            .wrap_err_with(|| format!("failed to write report {}", path.display()));
+    }
+
+    fn html(&self) -> String {
+        let mut body = String::new();
+        let _ = write!(
+            body,
+            "<h1>{}</h1>\n<p>{} bytes from {} file(s), running {} inline command(s).</p>\n",
+            escape(&self.entry),
+            self.bundle_bytes,
+            self.files.len(),
+            self.commands.len()
+        );
+
+        body.push_str("<h2>Include graph</h2>\n<ul>\n");
+        let mut seen = HashSet::new();
+        self.include_tree(None, &self.entry, &mut seen, &mut body);
+        body.push_str("</ul>\n");
+
+        body.push_str("<h2>Flatten order</h2>\n");
+        if self.order.is_empty() {
+            body.push_str("<p>No files were inlined.</p>\n");
+        } else {
+            body.push_str("<ol>\n");
+            for path in self.order {
+                let _ = writeln!(body, "<li><code>{}</code></li>", escape(path));
+            }
+            body.push_str("</ol>\n");
+        }
+
+        body.push_str(
+            "<h2>Files</h2>\n<table>\n<tr><th>File</th><th>Source bytes</th>\
+             <th>Bundled bytes, with includes</th><th></th></tr>\n",
+        );
+        for file in &self.files {
+            let bundled = file.bundled_bytes.unwrap_or(0);
+            let share = if self.bundle_bytes == 0 {
+                0.0
+            } else {
+                bundled as f64 * 100.0 / self.bundle_bytes as f64
+            };
+            let _ = writeln!(
+                body,
+                "<tr><td><code>{}</code></td><td>{}</td><td>{}</td>\
+                 <td><div class=\"bar\" style=\"width: {:.1}%\"></div></td></tr>",
+                escape(&file.path),
+                file.source_bytes,
+                file.bundled_bytes
+                    .map(|bytes| bytes.to_string())
+                    .unwrap_or(String::from("read by a directive")),
+                share
+            );
+        }
+        body.push_str("</table>\n");
+
+        body.push_str("<h2>Warnings</h2>\n");
+        if self.warnings.is_empty() && self.notes.is_empty() {
+            body.push_str("<p>None.</p>\n");
+        } else {
+            body.push_str("<ul>\n");
+            for warning in self.warnings {
+                let _ = writeln!(body, "<li class=\"warning\">{}</li>", escape(warning));
+            }
+            for note in self.notes {
+                let _ = writeln!(body, "<li><pre>{}</pre></li>", escape(note));
+            }
+            body.push_str("</ul>\n");
+        }
+
+        body.push_str("<h2>Inline commands</h2>\n");
+        if self.commands.is_empty() {
+            body.push_str("<p>The bundle doesn't embed the output of any commands.</p>\n");
+        } else {
+            body.push_str(
+                "<p>These commands were run at build time, and their output is embedded in the \
+                 bundle.</p>\n<table>\n<tr><th>Where</th><th>Command</th><th>Output bytes</th>\
+                 <th>Embedded as</th></tr>\n",
+            );
+            for command in self.commands {
+                let _ = writeln!(
+                    body,
+                    "<tr><td><code>{}:{}</code></td><td><code>{}</code></td><td>{}</td>\
+                     <td>{}, {}{}</td></tr>",
+                    escape(command.file.as_deref().unwrap_or(&self.entry)),
+                    command.line,
+                    escape(&command.command),
+                    command.output_bytes,
+                    format!("{:?}", command.encoding).to_lowercase(),
+                    match command.compression.tool() {
+                        Some(tool) => format!("compressed with {}", tool),
+                        None => String::from("uncompressed"),
+                    },
+                    if command.as_file { ", as a file" } else { "" }
+                );
+                if !command.stderr.is_empty() {
+                    let _ = writeln!(
+                        body,
+                        "<tr><td></td><td colspan=\"3\">stderr:<pre>{}</pre></td></tr>",
+                        escape(&command.stderr)
+                    );
+                }
+            }
+            body.push_str("</table>\n");
+        }
+
+        body.push_str("<h2>Libraries</h2>\n");
+        if self.libraries.is_empty() {
+            body.push_str("<p>None of the bundled files describe themselves as libraries.</p>\n");
+        } else {
+            if let Some(min_bash) = self.min_bash {
+                let _ = writeln!(
+                    body,
+                    "<p>The bundle needs bash {} or newer.</p>",
+                    escape(min_bash)
+                );
+            }
+            body.push_str(
+                "<table>\n<tr><th>Library</th><th>Version</th><th>File</th>\
+                 <th>Minimum bash</th><th>Tools</th></tr>\n",
+            );
+            for library in self.libraries {
+                let _ = writeln!(
+                    body,
+                    "<tr><td>{}</td><td>{}</td><td><code>{}</code></td><td>{}</td><td>{}</td></tr>",
+                    escape(&library.name),
+                    escape(library.version.as_deref().unwrap_or("")),
+                    escape(&library.path),
+                    escape(library.min_bash.as_deref().unwrap_or("")),
+                    escape(&library.tools.join(", "))
+                );
+            }
+            body.push_str("</table>\n");
+        }
+
+        body.push_str("<h2>Runtime tools</h2>\n");
+        if self.required_tools.is_empty() {
+            body.push_str("<p>The bundle doesn't need any external tools at runtime.</p>\n");
+        } else {
+            body.push_str("<table>\n");
+            for (tool, reason) in self.required_tools {
+                let _ = writeln!(
+                    body,
+                    "<tr><td><code>{}</code></td><td>{}</td></tr>",
+                    escape(tool),
+                    escape(reason)
+                );
+            }
+            body.push_str("</table>\n");
+        }
+
+        return format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>{title}: shpack report</title>\n<style>\n\
+             body {{ font-family: sans-serif; margin: 2em; max-width: 60em; }}\n\
+             table {{ border-collapse: collapse; }}\n\
+             td, th {{ border-bottom: 1px solid #ddd; padding: 0.3em 0.8em; text-align: left; }}\n\
+             .bar {{ background: #6a9fd8; height: 0.8em; min-width: 1px; }}\n\
+             td:last-child {{ width: 12em; }}\n\
+             .warning {{ color: #a15c00; }}\n\
+             </style>\n</head>\n<body>\n{body}</body>\n</html>\n",
+            title = escape(&self.entry),
+            body = body
+        );
+    }
+
+    /// Adds the files `file` sourced to the include graph as a nested list. Files included
+    /// more than once are only expanded the first time.
+    fn include_tree(
+        &self,
+        file: Option<&str>,
+        name: &str,
+        seen: &mut HashSet<String>,
+        out: &mut String,
+    ) {
+        let first = seen.insert(name.to_string());
+        let _ = write!(out, "<li><code>{}</code>", escape(name));
+        if let Some(library) = self.libraries.iter().find(|library| library.path == name) {
+            let _ = write!(out, " ({})", escape(&library.label()));
+        }
+        if !first {
+            out.push_str(" (already included)</li>\n");
+            return;
+        }
+        let children: Vec<&String> = self
+            .includes
+            .iter()
+            .filter(|(from, _)| from.as_deref() == file)
+            .map(|(_, to)| to)
+            .collect();
+        if !children.is_empty() {
+            out.push_str("\n<ul>\n");
+            for child in children {
+                self.include_tree(Some(child), child, seen, out);
+            }
+            out.push_str("</ul>\n");
+        }
+        out.push_str("</li>\n");
+    }
+}
@@ -0,0 +1,34 @@
+use std::ffi::OsString;
+use std::fs::File;
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+
+use color_eyre::Result;
+use color_eyre::eyre::WrapErr;
+use shpack::Bundle;
+
+/// Writes the bundle to a temporary file named like the entry script, so `$0` looks the same,
+/// and runs it with bash and `args`. It shares shpack's stdin, stdout, stderr and working
+/// directory.
+pub fn bundle(bundle: &Bundle, entry: &Path, args: &[OsString]) -> Result<ExitStatus> {
+    let dir = tempfile::tempdir()?;
+    let path = dir
+        .path()
+        .join(entry.file_name().unwrap_or("bundle.sh".as_ref()));
+    bundle.write_to(&mut File::create(&path)?)?;
+    return Command::new("bash")
+        .arg(&path)
+        .args(args)
+        .status()
+        .wrap_err("failed to run bash");
+}
+
+/// The exit code a shell would report for `status`, which is 128 plus the signal for a
+/// killed process
+pub fn exit_code(status: ExitStatus) -> i32 {
+    return status
+        .code()
+        .or(status.signal().map(|signal| 128 + signal))
+        .unwrap_or(1);
+}
@@ -0,0 +1,163 @@
+use std::collections::BTreeMap;
+
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+use tree_sitter::Tree;
+
+use crate::shell;
+use crate::{GetText, visit_node};
+
+/// What every function and variable in generated code is named with
+pub const DEFAULT_PREFIX: &str = "__shpack_";
+
+/// Helpers that generated code calls, defined once in a `__shpack_runtime` block near the top
+/// of a bundle. Only the helpers something asked for are emitted.
+#[derive(Debug)]
+pub struct Runtime {
+    /// Starts the name of everything generated, and is reserved in user code
+    pub prefix: String,
+    /// Where extracted files go at runtime, instead of `$TMPDIR` or /tmp
+    pub tmpdir: Option<String>,
+    /// External tools the generated code needs, and why. The bundle checks for them up front.
+    tools: BTreeMap<&'static str, &'static str>,
+    /// Decode helpers by name, each a pipeline of filters
+    decoders: BTreeMap<String, String>,
+    /// How many files have been extracted, which also names the next one
+    extracted: usize,
+    /// Other helper functions by name, with their definitions
+    functions: BTreeMap<String, String>,
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Runtime {
+            prefix: DEFAULT_PREFIX.to_string(),
+            tmpdir: None,
+            tools: BTreeMap::new(),
+            decoders: BTreeMap::new(),
+            extracted: 0,
+            functions: BTreeMap::new(),
+        }
+    }
+}
+
+impl Runtime {
+    /// Records that the bundle needs `tool` at runtime
+    pub fn require(&mut self, tool: &'static str, reason: &'static str) {
+        self.tools.entry(tool).or_insert(reason);
+    }
+
+    pub fn tools(&self) -> &BTreeMap<&'static str, &'static str> {
+        return &self.tools;
+    }
+
+    /// Returns the name of a helper that runs its stdin through `filters` in order, like
+    /// `base64 -d` then `gzip -dc`
+    pub fn decoder(&mut self, filters: &[&str]) -> String {
+        let name = filters
+            .iter()
+            .map(|filter| filter.split_whitespace().next().unwrap_or_default())
+            .collect::<Vec<_>>()
+            .join("_");
+        let name = format!("{}decode_{}", self.prefix, name);
+        self.decoders
+            .entry(name.clone())
+            .or_insert_with(|| filters.join(" | "));
+        return name;
+    }
+
+    /// Returns a command that saves its stdin to a new file in the bundle's temporary
+    /// directory and prints the file's path. Files are numbered in the order they're requested
+    /// at build time, so the same bundle always uses the same names.
+    pub fn extract_file(&mut self) -> String {
+        self.require("mktemp", "creates the directory files are extracted to");
+        self.extracted += 1;
+        return format!("{}extract {}", self.prefix, self.extracted);
+    }
+
+    /// Returns a command that unpacks the tar archive on its stdin into a new directory in
+    /// the bundle's temporary directory and prints the directory's path
+    pub fn extract_dir(&mut self) -> String {
+        self.require("mktemp", "creates the directory files are extracted to");
+        self.require("tar", "unpacks embedded directories");
+        self.extracted += 1;
+        let p = self.prefix.clone();
+        self.define(format!("{p}extract_dir"), || {
+            return format!(
+                "{p}extract_dir() {{ mkdir \"${p}tmp/$1\" && tar -xf - -C \"${p}tmp/$1\" && printf '%s' \"${p}tmp/$1\"; }}\n"
+            );
+        });
+        return format!("{}extract_dir {}", p, self.extracted);
+    }
+
+    /// Adds a helper function, unless one with the same name was added already
+    pub fn define(&mut self, name: String, definition: impl FnOnce() -> String) {
+        self.functions.entry(name).or_insert_with(definition);
+    }
+
+    /// The `__shpack_runtime` block, or nothing if no generated code needs it
+    pub fn code(&self) -> String {
+        let p = &self.prefix;
+        let mut code = String::new();
+        if !self.tools.is_empty() {
+            code.push_str(&format!(
+                "{p}require() {{\n  \
+                 local {p}tool\n  \
+                 for {p}tool; do\n    \
+                 command -v \"${p}tool\" >/dev/null 2>&1 || {{ echo \"$0: ${p}tool is required to run this script\" >&2; exit 1; }}\n  \
+                 done\n\
+                 }}\n"
+            ));
+            let tools: Vec<&str> = self.tools.keys().copied().collect();
+            code.push_str(&format!("{}require {}\n", p, tools.join(" ")));
+        }
+        for (name, pipeline) in &self.decoders {
+            code.push_str(&format!("{}() {{ {}; }}\n", name, pipeline));
+        }
+        for definition in self.functions.values() {
+            code.push_str(definition);
+        }
+        if self.extracted > 0 {
+            // Created up front rather than on first use, since uses are in command
+            // substitutions whose variables and traps don't outlive them
+            let base = match &self.tmpdir {
+                Some(dir) => shell::quote(dir),
+                None => String::from("\"${TMPDIR:-/tmp}\""),
+            };
+            code.push_str(&format!(
+                "{p}tmp=$(mktemp -d {base}/shpack.XXXXXX) || exit 1\n\
+                 trap 'rm -rf \"${p}tmp\"' EXIT\n\
+                 {p}extract() {{ cat >\"${p}tmp/$1\" && printf '%s' \"${p}tmp/$1\"; }}\n"
+            ));
+        }
+
+        if code.is_empty() {
+            return code;
+        }
+        return format!("# {}runtime\n\n{}\n#########\n\n", p, code);
+    }
+
+    /// Fails if a file defines a function or variable whose name starts with the prefix, since
+    /// generated code could silently clobber it
+    pub fn check_reserved(&self, tree: &Tree, source: &str) -> Result<()> {
+        return visit_node(tree.root_node(), &mut |node| {
+            let name = match node.kind() {
+                "function_definition" | "variable_assignment" | "for_statement" => node
+                    .child_by_field_name("name")
+                    .or(node.child_by_field_name("variable")),
+                _ => None,
+            };
+            if let Some(name) = name
+                && name.text(source).starts_with(&self.prefix)
+            {
+                return Err(eyre!(
+                    "`{}` on line {} starts with `{}`, which is reserved for code generated by shpack. Rename it, or reserve another prefix with --symbol-prefix.",
+                    name.text(source),
+                    name.start_position().row + 1,
+                    self.prefix
+                ));
+            }
+            return Ok(());
+        });
+    }
+}
@@ -0,0 +1,61 @@
+/// The directory a `source` path's prefix stands for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anchor {
+    /// The entry script's directory, from `$0`
+    Entry,
+    /// The directory of the file the command is in, from `$BASH_SOURCE`
+    File,
+}
+
+/// The variable scripts most often store their directory in
+pub const DEFAULT_VARIABLE: &str = "SCRIPT_DIR";
+
+const ENTRY_PREFIXES: &[&str] = &[
+    "$(dirname $0)",
+    "`dirname $0`",
+    "$(cd $(dirname $0) && pwd)",
+    "$(cd -- $(dirname $0) && pwd)",
+    "${0%/*}",
+];
+
+const FILE_PREFIXES: &[&str] = &[
+    "$(dirname $BASH_SOURCE)",
+    "$(dirname ${BASH_SOURCE[0]})",
+    "`dirname ${BASH_SOURCE[0]}`",
+    "$(cd $(dirname $BASH_SOURCE) && pwd)",
+    "$(cd $(dirname ${BASH_SOURCE[0]}) && pwd)",
+    "$(cd -- $(dirname ${BASH_SOURCE[0]}) && pwd)",
+    "${BASH_SOURCE%/*}",
+    "${BASH_SOURCE[0]%/*}",
+];
+
+/// Splits a `source` path like `$(dirname "$0")/lib.sh` or `$SCRIPT_DIR/lib.sh` into the
+/// directory its prefix stands for and the path relative to it. `variables` are the names,
+/// besides [`DEFAULT_VARIABLE`], that hold the entry script's directory.
+pub fn strip(path: &str, variables: &[String]) -> Option<(Anchor, String)> {
+    // Quotes don't change what the prefix means, and source paths don't contain any
+    let path = path.replace('"', "");
+    let variables = variables
+        .iter()
+        .map(String::as_str)
+        .chain([DEFAULT_VARIABLE])
+        .flat_map(|name| [format!("${}", name), format!("${{{}}}", name)]);
+    let prefixes = ENTRY_PREFIXES
+        .iter()
+        .map(|prefix| (Anchor::Entry, prefix.to_string()))
+        .chain(variables.map(|prefix| (Anchor::Entry, prefix)))
+        .chain(
+            FILE_PREFIXES
+                .iter()
+                .map(|prefix| (Anchor::File, prefix.to_string())),
+        );
+    for (anchor, prefix) in prefixes {
+        if let Some(rest) = path
+            .strip_prefix(prefix.as_str())
+            .and_then(|rest| rest.strip_prefix('/'))
+        {
+            return Some((anchor, rest.to_string()));
+        }
+    }
+    return None;
+}
@@ -0,0 +1,214 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+/// What happens when a bundle looks like it contains a credential
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum, schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum SecretsPolicy {
+    /// Don't scan the bundle
+    Off,
+    /// Report each one as a warning
+    #[default]
+    Warn,
+    /// Fail the build
+    Deny,
+}
+
+/// Something that looks like a credential
+#[derive(Debug)]
+pub struct Finding {
+    /// What it looks like, such as "GitHub token"
+    pub kind: &'static str,
+    /// The 0-based line it's on
+    pub row: usize,
+    /// Its first few characters, which identify it without giving it away
+    pub start: String,
+}
+
+/// A token format with a recognizable prefix
+struct Pattern {
+    kind: &'static str,
+    prefixes: &'static [&'static str],
+    /// Which characters follow the prefix
+    body: fn(char) -> bool,
+    /// How many characters follow the prefix
+    len: (usize, usize),
+}
+
+fn alphanumeric(c: char) -> bool {
+    return c.is_ascii_alphanumeric();
+}
+
+fn upper_alphanumeric(c: char) -> bool {
+    return c.is_ascii_uppercase() || c.is_ascii_digit();
+}
+
+fn word(c: char) -> bool {
+    return c.is_ascii_alphanumeric() || c == '_' || c == '-';
+}
+
+const PATTERNS: &[Pattern] = &[
+    Pattern {
+        kind: "AWS access key ID",
+        prefixes: &["AKIA", "ASIA"],
+        body: upper_alphanumeric,
+        len: (16, 16),
+    },
+    Pattern {
+        kind: "GitHub token",
+        prefixes: &["ghp_", "gho_", "ghu_", "ghs_", "ghr_"],
+        body: alphanumeric,
+        len: (36, 255),
+    },
+    Pattern {
+        kind: "GitHub token",
+        prefixes: &["github_pat_"],
+        body: word,
+        len: (22, 255),
+    },
+    Pattern {
+        kind: "GitLab token",
+        prefixes: &["glpat-"],
+        body: word,
+        len: (20, 255),
+    },
+    Pattern {
+        kind: "Slack token",
+        prefixes: &["xoxb-", "xoxp-", "xoxa-", "xoxr-", "xoxs-"],
+        body: word,
+        len: (10, 255),
+    },
+    Pattern {
+        kind: "Stripe secret key",
+        prefixes: &["sk_live_", "rk_live_"],
+        body: alphanumeric,
+        len: (16, 255),
+    },
+    Pattern {
+        kind: "Google API key",
+        prefixes: &["AIza"],
+        body: word,
+        len: (35, 35),
+    },
+    Pattern {
+        kind: "npm token",
+        prefixes: &["npm_"],
+        body: alphanumeric,
+        len: (36, 36),
+    },
+];
+
+/// How many findings are reported for a single command output or embedded file
+pub const MAX_PER_PAYLOAD: usize = 3;
+/// Tokens at least this long are checked for randomness
+const MIN_RANDOM_LEN: usize = 32;
+/// Bits of entropy per character above which a token looks random. Hex strings, like
+/// checksums and commit IDs, stay below it, since they can't carry more than 4.
+const RANDOM_ENTROPY: f64 = 4.5;
+
+/// Finds known credential formats and random-looking strings in `text`, skipping lines marked
+/// with a `# shpack: allow-secret` directive. Random-looking strings are only looked for in
+/// text, since binary data is random-looking throughout.
+pub fn scan(text: &str, binary: bool) -> Vec<Finding> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut findings = vec![];
+    // Certificates and public keys are random-looking, but not secret
+    let mut in_pem = false;
+    for (row, line) in lines.iter().enumerate() {
+        if line.starts_with("-----END ") {
+            in_pem = false;
+        }
+        if in_pem || allowed(&lines, row) {
+            continue;
+        }
+        if line.starts_with("-----BEGIN ") && !line.contains("PRIVATE KEY-----") {
+            in_pem = true;
+        }
+        let before = findings.len();
+        for pattern in PATTERNS {
+            for prefix in pattern.prefixes {
+                for (start, _) in line.match_indices(prefix) {
+                    if line[..start].ends_with(|c: char| c.is_ascii_alphanumeric()) {
+                        continue;
+                    }
+                    let body = line[start + prefix.len()..]
+                        .chars()
+                        .take_while(|c| (pattern.body)(*c))
+                        .count();
+                    if body >= pattern.len.0 && body <= pattern.len.1 {
+                        findings.push(Finding {
+                            kind: pattern.kind,
+                            row,
+                            start: prefix.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+        if line.contains("-----BEGIN ") && line.contains("PRIVATE KEY-----") {
+            findings.push(Finding {
+                kind: "private key",
+                row,
+                start: String::from("-----BEGIN"),
+            });
+        }
+        // A line with a known format doesn't need a vaguer finding too
+        if binary || findings.len() > before {
+            continue;
+        }
+        // Paths are long and varied too, so slashes end tokens
+        for token in line.split(|c: char| !(c.is_ascii_alphanumeric() || "+=_-".contains(c))) {
+            if token.len() >= MIN_RANDOM_LEN
+                && token.contains(|c: char| c.is_ascii_digit())
+                && token.contains(|c: char| c.is_ascii_alphabetic())
+                && entropy(token) >= RANDOM_ENTROPY
+                && !all_different(token)
+            {
+                findings.push(Finding {
+                    kind: "random-looking string",
+                    row,
+                    start: token[..4].to_string(),
+                });
+            }
+        }
+    }
+    return findings;
+}
+
+/// Whether a `# shpack: allow-secret` directive ends line `row` or is on the line above it
+pub fn allowed(lines: &[&str], row: usize) -> bool {
+    let is_directive = |line: &str| line.trim_end().ends_with("# shpack: allow-secret");
+    return lines.get(row).is_some_and(|line| is_directive(line))
+        || (row > 0 && lines[row - 1].trim() == "# shpack: allow-secret");
+}
+
+/// Whether no character of `token` repeats, which makes it a list of characters, like an
+/// alphabet or a regular expression's character class, rather than a random string
+fn all_different(token: &str) -> bool {
+    let mut seen = [false; 128];
+    for byte in token.bytes() {
+        if seen[byte as usize & 127] {
+            return false;
+        }
+        seen[byte as usize & 127] = true;
+    }
+    return true;
+}
+
+/// The Shannon entropy of `token`, in bits per character
+fn entropy(token: &str) -> f64 {
+    let mut counts = [0usize; 128];
+    for byte in token.bytes() {
+        counts[byte as usize & 127] += 1;
+    }
+    let len = token.len() as f64;
+    return counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / len;
+            return -p * p.log2();
+        })
+        .sum();
+}
@@ -0,0 +1,117 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+use flate2::write::GzEncoder;
+use serde::Deserialize;
+use serde::Serialize;
+use tar::Builder;
+use tar::Header;
+use tar::HeaderMode;
+
+use crate::archive;
+use crate::shell;
+
+/// How a bundle is written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum, schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum Format {
+    /// A shell script
+    #[default]
+    Script,
+    /// A stub script followed by a gzipped tar archive holding the bundle, which the stub
+    /// extracts to a temporary directory and runs
+    SelfExtract,
+}
+
+/// The tools the stub of a self-extracting bundle runs, and why
+pub const TOOLS: &[(&str, &str)] = &[
+    ("gzip", "decompresses self-extracting bundles"),
+    (
+        "mktemp",
+        "creates the directory self-extracting bundles are extracted to",
+    ),
+    ("tail", "finds the archive in self-extracting bundles"),
+    ("tar", "unpacks self-extracting bundles"),
+];
+
+/// What a self-extracting bundle's archive holds, and how its stub runs the bundle
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SelfExtract {
+    /// The bundle's file name in the archive
+    pub name: String,
+    /// The command from the bundle's shabang, like `/usr/bin/env bash`
+    pub interpreter: String,
+    /// A directory whose files are extracted next to the bundle
+    pub dir: Option<PathBuf>,
+    /// Where the archive is extracted, like `--runtime-tmpdir`
+    pub tmpdir: Option<String>,
+}
+
+impl SelfExtract {
+    /// Writes the stub, then the archive holding `script` and the files of `dir`
+    pub fn write(&self, script: &[u8], out: &mut impl Write) -> Result<()> {
+        let entries = match &self.dir {
+            Some(dir) => archive::entries(dir)?,
+            None => vec![],
+        };
+        if entries
+            .iter()
+            .any(|entry| entry.as_os_str() == self.name.as_str())
+        {
+            return Err(eyre!(
+                "{} has a file named {}, which is what the bundle is called in the archive",
+                self.dir.as_ref().expect("entries come from dir").display(),
+                self.name
+            ));
+        }
+        out.write_all(self.stub().as_bytes())?;
+
+        // The timestamp in gzip's header is left out, like the ones in tar's
+        let mut builder = Builder::new(GzEncoder::new(out, flate2::Compression::best()));
+        builder.mode(HeaderMode::Deterministic);
+        let mut header = Header::new_gnu();
+        header.set_size(script.len() as u64);
+        header.set_mode(0o755);
+        header.set_mtime(0);
+        builder.append_data(&mut header, &self.name, script)?;
+        if let Some(dir) = &self.dir {
+            archive::append(&mut builder, dir, &entries)?;
+        }
+        builder.into_inner()?.finish()?;
+        return Ok(());
+    }
+
+    /// The script before the archive. The shell stops reading at its `exit`, so it never
+    /// reaches the binary data after `__ARCHIVE__`.
+    fn stub(&self) -> String {
+        let base = match &self.tmpdir {
+            Some(dir) => shell::quote(dir),
+            None => String::from("\"${TMPDIR:-/tmp}\""),
+        };
+        let body = format!(
+            "#!/bin/sh\n\
+             # A self-extracting bundle made by shpack. The archive after __ARCHIVE__ holds {name},\n\
+             # which is extracted to a temporary directory and run with this script's arguments.\n\
+             dir=$(mktemp -d {base}/shpack.XXXXXX) || exit 1\n\
+             trap 'rm -rf \"$dir\"' EXIT\n\
+             trap 'exit 129' HUP\n\
+             trap 'exit 130' INT\n\
+             trap 'exit 143' TERM\n",
+            name = self.name,
+        );
+        // The archive starts on the line after the marker, which is counted here
+        let line = body.lines().count() + 5;
+        return format!(
+            "{body}\
+             tail -n +{line} \"$0\" | gzip -dc | tar -xf - -C \"$dir\" || {{ echo \"$0: the archive is damaged\" >&2; exit 1; }}\n\
+             {interpreter} \"$dir\"/{name} \"$@\"\n\
+             exit $?\n\
+             __ARCHIVE__\n",
+            interpreter = self.interpreter,
+            name = shell::quote(&self.name),
+        );
+    }
+}
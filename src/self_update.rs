@@ -0,0 +1,105 @@
+use std::env;
+use std::env::consts::{ARCH, OS};
+
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+use minisign_verify::{PublicKey, Signature};
+use serde::Deserialize;
+use shpack::fs_util::write_atomic;
+
+const LATEST_RELEASE_URL: &str =
+    "https://api.github.com/repos/jsimonrichard/shpack/releases/latest";
+
+/// The minisign key release binaries are signed with, baked in when the release is built
+const PUBLIC_KEY: Option<&str> = option_env!("SHPACK_RELEASE_PUBLIC_KEY");
+
+/// Release binaries are well under this, so anything bigger isn't one
+const MAX_BINARY_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl Release {
+    fn asset(&self, name: &str) -> Result<&Asset> {
+        return self
+            .assets
+            .iter()
+            .find(|asset| asset.name == name)
+            .ok_or(eyre!("release {} has no {} asset", self.tag_name, name));
+    }
+}
+
+/// Replaces the running binary with the latest release, if it's newer. The new binary is only
+/// installed once its signature checks out, and replaces the old one in a single step. With
+/// `check_only`, just reports whether an update is available.
+pub fn run(check_only: bool) -> Result<()> {
+    let release: Release = ureq::get(LATEST_RELEASE_URL)
+        .header("User-Agent", concat!("shpack/", env!("CARGO_PKG_VERSION")))
+        .call()
+        .wrap_err("failed to check for the latest release")?
+        .body_mut()
+        .read_json()?;
+
+    let current = env!("CARGO_PKG_VERSION");
+    let latest = release.tag_name.trim_start_matches('v');
+    if !is_newer(latest, current) {
+        eprintln!("shpack {} is up to date", current);
+        return Ok(());
+    }
+    if check_only {
+        eprintln!("shpack {} is available (you have {})", latest, current);
+        return Ok(());
+    }
+
+    let public_key = PUBLIC_KEY.ok_or(eyre!(
+        "this binary was built without SHPACK_RELEASE_PUBLIC_KEY, so it can't verify updates"
+    ))?;
+    let public_key = PublicKey::from_base64(public_key)?;
+
+    let name = format!("shpack-{}-{}", ARCH, OS);
+    let binary = download(&release.asset(&name)?.browser_download_url)?;
+    let signature = download(
+        &release
+            .asset(&format!("{}.minisig", name))?
+            .browser_download_url,
+    )?;
+    let signature = Signature::decode(&String::from_utf8(signature)?)?;
+    public_key
+        .verify(&binary, &signature, false)
+        .wrap_err_with(|| format!("the signature of {} doesn't match, not installing it", name))?;
+
+    let exe = env::current_exe()?.canonicalize()?;
+    eprintln!("    Updating {} to {}", exe.display(), latest);
+    write_atomic(&exe, &binary)?;
+    return Ok(());
+}
+
+fn download(url: &str) -> Result<Vec<u8>> {
+    return Ok(ureq::get(url)
+        .call()
+        .wrap_err_with(|| format!("failed to download {}", url))?
+        .body_mut()
+        .with_config()
+        .limit(MAX_BINARY_SIZE)
+        .read_to_vec()?);
+}
+
+/// Whether dotted version `a` is newer than `b`
+fn is_newer(a: &str, b: &str) -> bool {
+    let parts = |v: &str| -> Vec<u64> {
+        return v
+            .split(['.', '-'])
+            .map_while(|part| part.parse().ok())
+            .collect();
+    };
+    return parts(a) > parts(b);
+}
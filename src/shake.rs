@@ -0,0 +1,516 @@
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+use color_eyre::Result;
+use tree_sitter::Node;
+
+use crate::directive::Directive;
+use crate::exports;
+use crate::{Edit, GetText, apply_edits, parse_file};
+
+/// How code refers to a function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Use {
+    /// Runs it as a command
+    Call,
+    /// Names it in a `trap` handler
+    Trap,
+    /// Registers it with `complete -F`
+    Completion,
+    /// Lists it in an array, like a table of handlers
+    Array,
+    /// Runs a command whose name is built at runtime, like `"cmd_$1"`, and starts with the same
+    /// text as the function
+    Dynamic,
+    /// Lists every defined function with `declare -F` or `compgen -A function`
+    ListsFunctions,
+    /// Exports it to child processes with `export -f`
+    Export,
+    /// Mentions its name some other way, like as an argument
+    Mention,
+}
+
+impl Use {
+    fn describe(self) -> &'static str {
+        return match self {
+            Use::Call => "called",
+            Use::Trap => "named in `trap`",
+            Use::Completion => "registered with `complete -F`",
+            Use::Array => "listed in an array",
+            Use::Dynamic => "possibly called through a dynamic command name",
+            Use::ListsFunctions => "possibly found through `declare -F` or `compgen -A function`",
+            Use::Export => "exported with `export -f`",
+            Use::Mention => "mentioned",
+        };
+    }
+}
+
+/// Why tree-shaking kept a function
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reason {
+    /// Used by the named function, or by top-level code if None
+    Used { by: Option<String>, how: Use },
+    /// Annotated with `# shpack: keep`
+    Annotated,
+    /// Matched a `keep` glob
+    Glob(String),
+}
+
+/// What tree-shaking decided about each top-level function
+#[derive(Debug, Default)]
+pub struct Decisions {
+    pub kept: HashMap<String, Reason>,
+    /// Removed functions, along with the removed functions that use them
+    pub removed: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl Decisions {
+    /// Describes each function that was kept only because of an indirect use
+    pub fn indirect_notes(&self) -> Vec<String> {
+        let mut notes: Vec<String> = self
+            .kept
+            .iter()
+            .filter_map(|(name, reason)| match reason {
+                Reason::Used { by, how } if *how != Use::Call => Some(format!(
+                    "tree-shaking kept `{}`, which is {} by {}",
+                    name,
+                    how.describe(),
+                    by.as_ref()
+                        .map(|by| format!("`{}`", by))
+                        .unwrap_or("top-level code".to_string())
+                )),
+                _ => None,
+            })
+            .collect();
+        notes.sort();
+        return notes;
+    }
+
+    /// Explains why `name` was kept, following the chain of uses back to top-level code or a
+    /// `keep`, or why it was removed
+    pub fn explain(&self, name: &str) -> String {
+        if let Some(users) = self.removed.get(name) {
+            if users.is_empty() {
+                return format!("`{}` was removed because nothing uses it", name);
+            }
+            let users: Vec<String> = users.iter().map(|user| format!("`{}`", user)).collect();
+            return format!(
+                "`{}` was removed because it's only used by {}, which {} removed too",
+                name,
+                users.join(", "),
+                if users.len() == 1 { "was" } else { "were" }
+            );
+        }
+        if !self.kept.contains_key(name) {
+            return format!(
+                "`{}` isn't a top-level function in the bundle, so tree-shaking didn't touch it",
+                name
+            );
+        }
+
+        let mut lines = vec![format!("`{}` was kept because:", name)];
+        let mut current = name;
+        // Every function was kept after the one that uses it, so the chain ends, but don't
+        // trust that blindly
+        for _ in 0..self.kept.len() {
+            match &self.kept[current] {
+                Reason::Used { by, how } => {
+                    let by_text = by
+                        .as_ref()
+                        .map(|by| format!("`{}`", by))
+                        .unwrap_or("top-level code".to_string());
+                    lines.push(format!(
+                        "  `{}` is {} by {}",
+                        current,
+                        how.describe(),
+                        by_text
+                    ));
+                    match by {
+                        Some(by) => current = by,
+                        None => break,
+                    }
+                }
+                Reason::Annotated => {
+                    lines.push(format!(
+                        "  `{}` is annotated with `# shpack: keep`",
+                        current
+                    ));
+                    break;
+                }
+                Reason::Glob(glob) => {
+                    lines.push(format!("  `{}` matches the keep glob `{}`", current, glob));
+                    break;
+                }
+            }
+        }
+        return lines.join("\n");
+    }
+}
+
+/// A top-level function definition, along with the comments directly above it
+struct Function<'a> {
+    name: &'a str,
+    start_byte: usize,
+    end_byte: usize,
+    /// Annotated with `# shpack: keep`
+    keep: bool,
+    /// Defined in a block, which can't be left empty
+    nested: bool,
+    references: References<'a>,
+}
+
+/// The ways a piece of code refers to functions
+#[derive(Default)]
+struct References<'a> {
+    names: Vec<(&'a str, Use)>,
+    /// The literal beginnings of command names built at runtime
+    dynamic_prefixes: Vec<String>,
+    lists_functions: bool,
+}
+
+/// Removes top-level function definitions that nothing in the bundle can call. A function is
+/// used if top-level code, or a used function, mentions its name anywhere, including in strings
+/// like `trap 'cleanup' EXIT`. Commands whose names are built at runtime, like `"cmd_$1"`, use
+/// every function starting with their literal text, and `declare -F` uses every function.
+/// Anything else must be annotated with `# shpack: keep` or match one of the `keep` globs.
+/// Functions defined in top-level blocks, like the guard around a `# shpack: once` library,
+/// count as top-level too.
+pub fn shake(source: String, keep: &[String]) -> Result<(String, Decisions)> {
+    let tree = parse_file(&source)?;
+
+    let mut functions = vec![];
+    let mut top_level = References::default();
+    scan(
+        tree.root_node(),
+        &source,
+        false,
+        &mut functions,
+        &mut top_level,
+    )?;
+
+    // A name may be defined more than once, and every definition is kept if it's used
+    let mut by_name: BTreeMap<&str, Vec<&Function>> = BTreeMap::new();
+    for function in &functions {
+        by_name.entry(function.name).or_default().push(function);
+    }
+    let uses = |references: &References<'_>| -> Vec<(String, Use)> {
+        let mut uses: Vec<(String, Use)> = references
+            .names
+            .iter()
+            .map(|(name, how)| (name.to_string(), *how))
+            .collect();
+        for prefix in &references.dynamic_prefixes {
+            uses.extend(
+                by_name
+                    .keys()
+                    .filter(|name| name.starts_with(prefix.as_str()))
+                    .map(|name| (name.to_string(), Use::Dynamic)),
+            );
+        }
+        if references.lists_functions {
+            uses.extend(
+                by_name
+                    .keys()
+                    .map(|name| (name.to_string(), Use::ListsFunctions)),
+            );
+        }
+        return uses;
+    };
+
+    let mut kept: HashMap<String, Reason> = HashMap::new();
+    for function in &functions {
+        if function.keep {
+            kept.insert(function.name.to_string(), Reason::Annotated);
+        } else if let Some(glob) = keep.iter().find(|glob| glob_match(glob, function.name)) {
+            kept.insert(function.name.to_string(), Reason::Glob(glob.clone()));
+        }
+    }
+
+    // Direct calls are explored first, so functions that are both called and used indirectly
+    // are explained by the call
+    let mut queue: VecDeque<(String, Reason)> = VecDeque::new();
+    let enqueue = |queue: &mut VecDeque<(String, Reason)>, by: Option<&str>, uses| {
+        for (name, how) in uses {
+            let reason = Reason::Used {
+                by: by.map(str::to_string),
+                how,
+            };
+            if how == Use::Call {
+                queue.push_front((name, reason));
+            } else {
+                queue.push_back((name, reason));
+            }
+        }
+    };
+    enqueue(&mut queue, None, uses(&top_level));
+    for function in &functions {
+        if kept.contains_key(function.name) {
+            enqueue(&mut queue, Some(function.name), uses(&function.references));
+        }
+    }
+    while let Some((name, reason)) = queue.pop_front() {
+        let Some(definitions) = by_name.get(name.as_str()) else {
+            continue;
+        };
+        if kept.contains_key(&name) {
+            continue;
+        }
+        kept.insert(name.clone(), reason);
+        for function in definitions {
+            enqueue(&mut queue, Some(&name), uses(&function.references));
+        }
+    }
+
+    let mut removed: BTreeMap<String, BTreeSet<String>> = functions
+        .iter()
+        .filter(|function| !kept.contains_key(function.name))
+        .map(|function| (function.name.to_string(), BTreeSet::new()))
+        .collect();
+    for function in &functions {
+        for (name, _) in uses(&function.references) {
+            if name != function.name
+                && let Some(users) = removed.get_mut(&name)
+            {
+                users.insert(function.name.to_string());
+            }
+        }
+    }
+
+    let mut edits = vec![];
+    for function in &functions {
+        if kept.contains_key(function.name) {
+            continue;
+        }
+        if function.nested {
+            edits.push(Edit {
+                start_byte: function.start_byte,
+                end_byte: function.end_byte,
+                new_content: String::from(":"),
+            });
+            continue;
+        }
+        edits.push(Edit {
+            start_byte: function.start_byte,
+            // Take the line break after the function with it
+            end_byte: if source[function.end_byte..].starts_with('\n') {
+                function.end_byte + 1
+            } else {
+                function.end_byte
+            },
+            new_content: String::new(),
+        });
+    }
+    let decisions = Decisions { kept, removed };
+    if edits.is_empty() {
+        return Ok((source, decisions));
+    }
+    return Ok((apply_edits(source, edits)?, decisions));
+}
+
+/// Collects the function definitions among the statements in `block`, and what the rest of
+/// them refers to. Blocks that run at the top level, like `if` statements and `{ ... }` groups,
+/// are searched too, and their functions are `nested`.
+fn scan<'a>(
+    block: Node,
+    source: &'a str,
+    nested: bool,
+    functions: &mut Vec<Function<'a>>,
+    top_level: &mut References<'a>,
+) -> Result<()> {
+    // The start byte, last row and `keep` annotation of the comments directly above the
+    // current node
+    let mut comments: Option<(usize, usize, bool)> = None;
+    let mut previous_end_row = None;
+    let mut cursor = block.walk();
+    for child in block.named_children(&mut cursor) {
+        let row = child.start_position().row;
+        match child.kind() {
+            // Trailing comments belong to the code before them
+            "comment" if previous_end_row == Some(row) => comments = None,
+            "comment" => {
+                let keep = is_keep(child.text(source))?;
+                comments = match comments {
+                    Some((start, last_row, kept)) if last_row + 1 == row => {
+                        Some((start, child.end_position().row, kept || keep))
+                    }
+                    _ => Some((child.start_byte(), child.end_position().row, keep)),
+                };
+            }
+            "function_definition" => {
+                let (start_byte, keep) = match comments.take() {
+                    Some((start, last_row, keep)) if last_row + 1 == row => (start, keep),
+                    _ => (child.start_byte(), false),
+                };
+                let mut references = References::default();
+                if let Some(body) = child.child_by_field_name("body") {
+                    collect_references(body, source, Use::Mention, &mut references);
+                }
+                functions.push(Function {
+                    name: child
+                        .child_by_field_name("name")
+                        .map(|name| name.text(source))
+                        .unwrap_or_default(),
+                    start_byte,
+                    end_byte: child.end_byte(),
+                    keep,
+                    nested,
+                    references,
+                });
+            }
+            // An `if`'s conditions are among its statements, so they're collected like any
+            // other top-level code
+            "compound_statement" | "if_statement" | "elif_clause" | "else_clause" => {
+                scan(child, source, true, functions, top_level)?;
+                comments = None;
+            }
+            _ => {
+                collect_references(child, source, Use::Mention, top_level);
+                comments = None;
+            }
+        }
+        previous_end_row = Some(child.end_position().row);
+    }
+    return Ok(());
+}
+
+fn is_keep(comment: &str) -> Result<bool> {
+    return Ok(Directive::parse(comment)?.is_some_and(|directive| directive.name == "keep"));
+}
+
+/// Adds the ways the code in `node` refers to functions to `references`. Words that aren't
+/// part of a more specific pattern are taken to be used as `how`.
+fn collect_references<'a>(node: Node, source: &'a str, how: Use, references: &mut References<'a>) {
+    match node.kind() {
+        "word" | "raw_string" | "string_content" | "heredoc_content" | "heredoc_body" => {
+            references.names.extend(
+                node.text(source)
+                    .split(|c: char| c.is_whitespace() || "'\"`;|&()<>{}$=".contains(c))
+                    .filter(|word| !word.is_empty())
+                    .map(|word| (word, how)),
+            );
+            return;
+        }
+        "array" => {
+            let mut cursor = node.walk();
+            for child in node.children(&mut cursor) {
+                collect_references(child, source, Use::Array, references);
+            }
+            return;
+        }
+        "command" => {
+            collect_command(node, source, references);
+            return;
+        }
+        // The names aren't words, so they'd be missed otherwise
+        "declaration_command" if !exports::exported_names(node, source).is_empty() => {
+            references.names.extend(
+                exports::exported_names(node, source)
+                    .into_iter()
+                    .map(|name| (name, Use::Export)),
+            );
+            return;
+        }
+        "declaration_command"
+            if node
+                .child(0)
+                .is_some_and(|keyword| matches!(keyword.text(source), "declare" | "typeset")) =>
+        {
+            let mut cursor = node.walk();
+            references.lists_functions |= node.named_children(&mut cursor).any(|child| {
+                let text = child.text(source);
+                return child.kind() == "word"
+                    && text.starts_with('-')
+                    && !text.starts_with("--")
+                    && text.contains('F');
+            });
+        }
+        _ => {}
+    }
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        collect_references(child, source, how, references);
+    }
+}
+
+fn collect_command<'a>(node: Node, source: &'a str, references: &mut References<'a>) {
+    let Some(name) = node.child_by_field_name("name") else {
+        return;
+    };
+    let command = name.text(source);
+    let is_literal = name
+        .named_child(0)
+        .is_some_and(|child| child.kind() == "word");
+    if is_literal {
+        references.names.push((command, Use::Call));
+    } else {
+        // Only the literal text before the first expansion says anything about the name
+        let prefix: String = command
+            .split(['$', '`'])
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .filter(|c| !matches!(c, '"' | '\''))
+            .collect();
+        if !prefix.is_empty() {
+            references.dynamic_prefixes.push(prefix);
+        }
+        collect_references(name, source, Use::Mention, references);
+    }
+
+    let mut cursor = node.walk();
+    let arguments: Vec<Node> = node
+        .children_by_field_name("argument", &mut cursor)
+        .collect();
+    let texts: Vec<&str> = arguments.iter().map(|arg| arg.text(source)).collect();
+    if command == "compgen" && texts.windows(2).any(|w| w == ["-A", "function"]) {
+        references.lists_functions = true;
+    }
+
+    for (i, argument) in arguments.iter().enumerate() {
+        let how = match command {
+            "trap" => Use::Trap,
+            "complete" | "compgen" if i > 0 && texts[i - 1] == "-F" => Use::Completion,
+            _ => Use::Mention,
+        };
+        collect_references(*argument, source, how, references);
+    }
+    // Redirections and the like can run code too
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        if child.id() != name.id() && !arguments.iter().any(|arg| arg.id() == child.id()) {
+            collect_references(child, source, Use::Mention, references);
+        }
+    }
+}
+
+/// Matches `name` against a glob where `*` matches any run of characters and `?` matches one
+pub fn glob_match(glob: &str, name: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // Where to resume after the last `*`, as (glob index, name index)
+    let mut backtrack = None;
+    let (mut g, mut n) = (0, 0);
+    while n < name.len() {
+        match glob.get(g) {
+            Some('*') => {
+                backtrack = Some((g, n));
+                g += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                g += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    backtrack = Some((star, matched + 1));
+                    g = star + 1;
+                    n = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    return glob[g..].iter().all(|&c| c == '*');
+}
@@ -0,0 +1,13 @@
+/// Quotes a string so bash reads it back as a single literal word
+pub fn quote(s: &str) -> String {
+    return format!("'{}'", s.replace('\'', r"'\''"));
+}
+
+/// Whether `name` can be used as a shell variable name
+pub fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    return chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+}
@@ -0,0 +1,363 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+use shpack::Bundle;
+use shpack::fs_util;
+
+use crate::usage::CountingWriter;
+
+/// Where a bundle is written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Sink {
+    Stdout,
+    File(PathBuf),
+    /// Uploaded with an HTTP PUT to the URL
+    Http(String),
+    /// Uploaded to an S3 bucket, or any storage with the same API
+    S3 {
+        bucket: String,
+        key: String,
+    },
+}
+
+impl Sink {
+    /// Parses the value of `-o`: an `s3://bucket/key` or `http(s)://` URL, or a path to write
+    /// to. None writes to stdout.
+    pub fn parse(out: Option<&Path>) -> Result<Sink> {
+        let Some(out) = out else {
+            return Ok(Sink::Stdout);
+        };
+        let text = out.to_string_lossy();
+        let sink = if let Some(location) = text.strip_prefix("s3://") {
+            let (bucket, key) = location
+                .split_once('/')
+                .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+                .ok_or_else(|| eyre!("expected s3://BUCKET/KEY, found {}", text))?;
+            Sink::S3 {
+                bucket: bucket.to_string(),
+                key: key.to_string(),
+            }
+        } else if text.starts_with("http://") || text.starts_with("https://") {
+            Sink::Http(text.to_string())
+        } else {
+            return Ok(Sink::File(out.to_path_buf()));
+        };
+        if !cfg!(feature = "upload") {
+            return Err(eyre!(
+                "reaching {} needs shpack to be built with the `upload` feature",
+                text
+            ));
+        }
+        return Ok(sink);
+    }
+
+    /// Whether the sink is somewhere other than this machine
+    pub fn is_remote(&self) -> bool {
+        return matches!(self, Sink::Http(_) | Sink::S3 { .. });
+    }
+
+    /// The last part of the path or URL, like `tool.sh`
+    pub fn file_name(&self) -> String {
+        return match self {
+            Sink::Stdout => String::from("<stdout>"),
+            Sink::File(path) => path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+            Sink::Http(url) => url.rsplit('/').next().unwrap_or_default().to_string(),
+            Sink::S3 { key, .. } => key.rsplit('/').next().unwrap_or_default().to_string(),
+        };
+    }
+
+    /// The location named `name` in the same directory, bucket prefix or URL path
+    pub fn sibling(&self, name: &str) -> Sink {
+        let replace = |text: &str| match text.rsplit_once('/') {
+            Some((dir, _)) => format!("{}/{}", dir, name),
+            None => name.to_string(),
+        };
+        return match self {
+            Sink::Stdout => Sink::Stdout,
+            Sink::File(path) => Sink::File(path.with_file_name(name)),
+            Sink::Http(url) => Sink::Http(replace(url)),
+            Sink::S3 { bucket, key } => Sink::S3 {
+                bucket: bucket.clone(),
+                key: replace(key),
+            },
+        };
+    }
+
+    /// Writes the bundle, returning its size in bytes. Files are replaced in one step, and
+    /// uploads only start once the whole bundle is ready.
+    pub fn write(&self, bundle: &Bundle) -> Result<u64> {
+        return match self {
+            Sink::Stdout => {
+                let mut stdout = CountingWriter {
+                    inner: io::stdout().lock(),
+                    count: 0,
+                };
+                bundle.write_to(&mut stdout)?;
+                io::Write::write_all(&mut stdout, b"\n")?;
+                Ok(stdout.count)
+            }
+            Sink::File(path) => {
+                fs_util::write_atomic_with(path, |file| bundle.write_to(file))?;
+                Ok(fs::metadata(path)?.len())
+            }
+            Sink::Http(_) | Sink::S3 { .. } => {
+                let mut content = vec![];
+                bundle.write_to(&mut content)?;
+                self.put(&content)?;
+                Ok(content.len() as u64)
+            }
+        };
+    }
+
+    /// Writes `content` to a file or remote location
+    pub fn put(&self, content: &[u8]) -> Result<()> {
+        return match self {
+            Sink::Stdout => Ok(io::Write::write_all(&mut io::stdout(), content)?),
+            Sink::File(path) => fs_util::write_atomic(path, content),
+            #[cfg(feature = "upload")]
+            Sink::Http(_) | Sink::S3 { .. } => upload::put(self, content),
+            #[cfg(not(feature = "upload"))]
+            Sink::Http(_) | Sink::S3 { .. } => unreachable!("rejected by Sink::parse"),
+        };
+    }
+
+    /// Reads the contents of a file or remote location
+    pub fn read(&self) -> Result<Vec<u8>> {
+        return match self {
+            Sink::Stdout => Err(eyre!("can't read from stdout")),
+            Sink::File(path) => {
+                fs::read(path).wrap_err_with(|| format!("failed to read {}", path.display()))
+            }
+            #[cfg(feature = "upload")]
+            Sink::Http(_) | Sink::S3 { .. } => upload::get(self),
+            #[cfg(not(feature = "upload"))]
+            Sink::Http(_) | Sink::S3 { .. } => unreachable!("rejected by Sink::parse"),
+        };
+    }
+}
+
+impl fmt::Display for Sink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Sink::Stdout => write!(f, "stdout"),
+            Sink::File(path) => write!(f, "{}", path.display()),
+            Sink::Http(url) => write!(f, "{}", url),
+            Sink::S3 { bucket, key } => write!(f, "s3://{}/{}", bucket, key),
+        };
+    }
+}
+
+#[cfg(feature = "upload")]
+mod upload {
+    use std::env;
+    use std::time::SystemTime;
+
+    use color_eyre::Result;
+    use color_eyre::eyre::WrapErr;
+    use data_encoding::HEXLOWER;
+    use sha2::{Digest, Sha256};
+
+    use super::Sink;
+
+    /// Uploads `content` to a remote sink
+    pub fn put(sink: &Sink, content: &[u8]) -> Result<()> {
+        let (url, headers) = request("PUT", sink, content)?;
+        let mut request = ureq::put(&url);
+        for (name, value) in &headers {
+            request = request.header(*name, value);
+        }
+        let content_type = if url.ends_with(".json") {
+            "application/json"
+        } else {
+            "text/x-shellscript"
+        };
+        request
+            .header("Content-Type", content_type)
+            .send(content)
+            .wrap_err_with(|| format!("failed to upload to {}", url))?;
+        eprintln!("Uploaded {} bytes to {}", content.len(), url);
+        return Ok(());
+    }
+
+    /// Downloads the contents of a remote sink
+    pub fn get(sink: &Sink) -> Result<Vec<u8>> {
+        let (url, headers) = request("GET", sink, &[])?;
+        let mut request = ureq::get(&url);
+        for (name, value) in &headers {
+            request = request.header(*name, value);
+        }
+        return Ok(request
+            .call()
+            .wrap_err_with(|| format!("failed to download {}", url))?
+            .body_mut()
+            .with_config()
+            .limit(u64::MAX)
+            .read_to_vec()?);
+    }
+
+    /// The URL and headers of a request to a remote sink
+    fn request(
+        method: &str,
+        sink: &Sink,
+        content: &[u8],
+    ) -> Result<(String, Vec<(&'static str, String)>)> {
+        return match sink {
+            Sink::Http(url) => {
+                let mut headers = vec![];
+                // Artifact stores usually take a bearer token
+                if let Ok(token) = env::var("SHPACK_UPLOAD_TOKEN") {
+                    headers.push(("Authorization", format!("Bearer {}", token)));
+                }
+                Ok((url.clone(), headers))
+            }
+            Sink::S3 { bucket, key } => s3_request(method, bucket, key, content),
+            Sink::Stdout | Sink::File(_) => unreachable!("not a remote sink"),
+        };
+    }
+
+    /// The URL and headers of a request for an S3 object, signed with AWS Signature Version 4
+    /// using the standard `AWS_*` environment variables. `AWS_ENDPOINT_URL_S3` or
+    /// `AWS_ENDPOINT_URL` point it at other S3-compatible storage.
+    fn s3_request(
+        method: &str,
+        bucket: &str,
+        key: &str,
+        content: &[u8],
+    ) -> Result<(String, Vec<(&'static str, String)>)> {
+        let var = |name: &str| {
+            env::var(name).wrap_err_with(|| format!("uploading to S3 needs {} to be set", name))
+        };
+        let access_key = var("AWS_ACCESS_KEY_ID")?;
+        let secret_key = var("AWS_SECRET_ACCESS_KEY")?;
+        let region = var("AWS_REGION")
+            .or_else(|_| var("AWS_DEFAULT_REGION"))
+            .unwrap_or(String::from("us-east-1"));
+        let session_token = env::var("AWS_SESSION_TOKEN").ok();
+
+        let key_path: String = key.split('/').map(uri_encode).collect::<Vec<_>>().join("/");
+        let (url, host, path) =
+            match env::var("AWS_ENDPOINT_URL_S3").or_else(|_| env::var("AWS_ENDPOINT_URL")) {
+                // Custom endpoints are addressed by path, since they rarely have DNS for buckets
+                Ok(endpoint) => {
+                    let endpoint = endpoint.trim_end_matches('/');
+                    let host = endpoint
+                        .split_once("://")
+                        .map(|(_, rest)| rest)
+                        .unwrap_or(endpoint)
+                        .to_string();
+                    let path = format!("/{}/{}", uri_encode(bucket), key_path);
+                    (format!("{}{}", endpoint, path), host, path)
+                }
+                Err(_) => {
+                    let host = format!("{}.s3.{}.amazonaws.com", bucket, region);
+                    let path = format!("/{}", key_path);
+                    (format!("https://{}{}", host, path), host, path)
+                }
+            };
+
+        let (date, time) = utc_now();
+        let timestamp = format!("{}T{}Z", date, time);
+        let content_hash = format!("{:x}", Sha256::digest(content));
+        let mut headers = vec![
+            ("host", host),
+            ("x-amz-content-sha256", content_hash.clone()),
+            ("x-amz-date", timestamp.clone()),
+        ];
+        if let Some(token) = &session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+            .collect();
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_request = format!(
+            "{}\n{}\n\n{}\n{}\n{}",
+            method, path, canonical_headers, signed_headers, content_hash
+        );
+
+        let scope = format!("{}/{}/s3/aws4_request", date, region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{:x}",
+            timestamp,
+            scope,
+            Sha256::digest(canonical_request.as_bytes())
+        );
+        let mut signing_key = hmac(format!("AWS4{}", secret_key).as_bytes(), date.as_bytes());
+        for part in [region.as_str(), "s3", "aws4_request"] {
+            signing_key = hmac(&signing_key, part.as_bytes());
+        }
+        let signature = HEXLOWER.encode(&hmac(&signing_key, string_to_sign.as_bytes()));
+
+        let mut headers = vec![
+            (
+                "Authorization",
+                format!(
+                    "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                    access_key, scope, signed_headers, signature
+                ),
+            ),
+            ("x-amz-content-sha256", content_hash),
+            ("x-amz-date", timestamp),
+        ];
+        if let Some(token) = session_token {
+            headers.push(("x-amz-security-token", token));
+        }
+        return Ok((url, headers));
+    }
+
+    /// Percent-encodes everything but the characters S3 leaves alone in paths
+    fn uri_encode(text: &str) -> String {
+        let mut out = String::new();
+        for byte in text.bytes() {
+            if byte.is_ascii_alphanumeric() || b"-_.~".contains(&byte) {
+                out.push(byte as char);
+            } else {
+                out.push_str(&format!("%{:02X}", byte));
+            }
+        }
+        return out;
+    }
+
+    fn hmac(key: &[u8], data: &[u8]) -> [u8; 32] {
+        let mut block = [0u8; 64];
+        if key.len() > block.len() {
+            block[..32].copy_from_slice(&Sha256::digest(key));
+        } else {
+            block[..key.len()].copy_from_slice(key);
+        }
+        let mut inner = Sha256::new();
+        inner.update(block.map(|b| b ^ 0x36));
+        inner.update(data);
+        let mut outer = Sha256::new();
+        outer.update(block.map(|b| b ^ 0x5c));
+        outer.update(inner.finalize());
+        return outer.finalize().into();
+    }
+
+    /// The current UTC date and time, as `YYYYMMDD` and `HHMMSS`
+    fn utc_now() -> (String, String) {
+        let seconds = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("the clock is after 1970")
+            .as_secs();
+        let ((year, month, day), time) = shpack::utc(seconds);
+        return (
+            format!("{:04}{:02}{:02}", year, month, day),
+            format!("{:02}{:02}{:02}", time / 3600, time % 3600 / 60, time % 60),
+        );
+    }
+}
@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use color_eyre::Result;
+use tree_sitter::Tree;
+
+use crate::fs_util;
+use crate::parse_file;
+
+/// Keeps the contents and parse trees of source files in memory between bundles, so long-running
+/// processes only re-read and re-parse the files that changed
+#[derive(Default)]
+pub struct SourceCache {
+    entries: Mutex<HashMap<PathBuf, Entry>>,
+}
+
+struct Entry {
+    modified: SystemTime,
+    len: u64,
+    source: String,
+    tree: Tree,
+}
+
+impl SourceCache {
+    /// Returns the file's contents and parse tree, from memory if the file hasn't been modified
+    pub(crate) fn read(&self, path: &Path) -> Result<(String, Tree)> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata.modified()?;
+
+        let mut entries = self.entries.lock().expect("source cache lock poisoned");
+        if let Some(entry) = entries.get(path)
+            && entry.modified == modified
+            && entry.len == metadata.len()
+        {
+            return Ok((entry.source.clone(), entry.tree.clone()));
+        }
+
+        let source = fs_util::read_script(path)?;
+        let tree = parse_file(&source)?;
+        entries.insert(
+            path.to_owned(),
+            Entry {
+                modified,
+                len: metadata.len(),
+                source: source.clone(),
+                tree: tree.clone(),
+            },
+        );
+        return Ok((source, tree));
+    }
+}
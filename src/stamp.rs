@@ -0,0 +1,106 @@
+use std::env;
+use std::path::Path;
+use std::process::Command;
+use std::time::SystemTime;
+
+use crate::shell;
+
+/// Identifies a build, for the `--version` output of the bundle
+pub struct Stamp {
+    /// The package's name, or None to use the name the bundle is run as
+    pub name: Option<String>,
+    pub version: Option<String>,
+    /// The short hash of the commit checked out in the root, ending in `-dirty` if there are
+    /// uncommitted changes, or None outside of a git repository
+    pub commit: Option<String>,
+    pub built_at: String,
+}
+
+impl Stamp {
+    /// Stamps a build of the sources in `root`. The build date comes from `SOURCE_DATE_EPOCH`
+    /// if it's set, so reproducible builds stay reproducible.
+    pub fn new(root: &Path, name: Option<String>, version: Option<String>) -> Self {
+        let seconds = env::var("SOURCE_DATE_EPOCH")
+            .ok()
+            .and_then(|epoch| epoch.parse().ok())
+            .unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .expect("the clock is after 1970")
+                    .as_secs()
+            });
+        let ((year, month, day), time) = utc(seconds);
+        return Stamp {
+            name,
+            version,
+            commit: commit(root),
+            built_at: format!(
+                "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+                year,
+                month,
+                day,
+                time / 3600,
+                time % 3600 / 60,
+                time % 60
+            ),
+        };
+    }
+
+    /// The `version` helper, which prints the stamp
+    pub fn definition(&self, p: &str) -> String {
+        let version = self
+            .version
+            .as_ref()
+            .map(|version| format!(" {}", version))
+            .unwrap_or_default();
+        let mut lines = vec![match &self.name {
+            Some(name) => shell::quote(&format!("{}{}", name, version)),
+            None if version.is_empty() => String::from("\"${0##*/}\""),
+            None => format!("\"${{0##*/}}\"{}", shell::quote(&version)),
+        }];
+        if let Some(commit) = &self.commit {
+            lines.push(shell::quote(&format!("commit {}", commit)));
+        }
+        lines.push(shell::quote(&format!("built {}", self.built_at)));
+        lines.push(shell::quote(&format!(
+            "shpack {}",
+            env!("CARGO_PKG_VERSION")
+        )));
+        return format!("{}version() {{ printf '%s\\n' {}; }}\n", p, lines.join(" "));
+    }
+}
+
+/// The commit checked out in `root`, as described in [`Stamp::commit`]
+pub fn commit(root: &Path) -> Option<String> {
+    let git = |args: &[&str]| -> Option<String> {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(root)
+            .args(args)
+            .output()
+            .ok()?;
+        return output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string());
+    };
+    let commit = git(&["rev-parse", "--short=12", "HEAD"])?;
+    let dirty = git(&["status", "--porcelain"]).is_some_and(|status| !status.is_empty());
+    return Some(if dirty { commit + "-dirty" } else { commit });
+}
+
+/// Splits seconds since the Unix epoch into a UTC date and the seconds since midnight
+pub fn utc(seconds: u64) -> ((i64, i64, i64), u64) {
+    let (days, time) = (seconds / 86400, seconds % 86400);
+    // Howard Hinnant's days-to-civil algorithm
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    return ((year, month, day), time);
+}
@@ -0,0 +1,116 @@
+use std::cmp::Reverse;
+use std::io::Write;
+use std::process::Command;
+use std::process::Stdio;
+use std::time::Duration;
+use std::time::Instant;
+
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+
+use crate::Bundle;
+use crate::GetText;
+use crate::parse_file;
+
+/// How many times each file is sourced, to smooth out noise
+const RUNS: usize = 5;
+
+/// Prints an estimate of how long `shell` spends sourcing the bundle and each file inlined into
+/// it, reading the code and defining functions.
+///
+/// Files are sourced with their top-level commands skipped (see `stub`), so the numbers don't
+/// include whatever the scripts do at the top level. Each time is the median over several runs
+/// with the cost of starting the shell subtracted.
+pub fn report(bundle: &Bundle, shell: &str) -> Result<()> {
+    let baseline = source_time(shell, "")?;
+
+    let mut rows = vec![];
+    for fragment in &bundle.fragments {
+        let time = source_time(shell, &fragment.content()?)?.saturating_sub(baseline);
+        rows.push((time, fragment.bytes, fragment.path.as_str()));
+    }
+    rows.sort_by_key(|row| Reverse(row.0));
+    let total = source_time(shell, &bundle.content)?.saturating_sub(baseline);
+
+    eprintln!(
+        "Startup report ({}, top-level commands skipped, median of {} runs, includes nested sources)",
+        shell, RUNS
+    );
+    eprintln!("{:>10} {:>10}  file", "ms", "bytes");
+    for (time, bytes, path) in rows {
+        eprintln!("{:>10.2} {:>10}  {}", millis(time), bytes, path);
+    }
+    eprintln!(
+        "{:>10.2} {:>10}  (whole bundle)",
+        millis(total),
+        bundle.content.len()
+    );
+
+    return Ok(());
+}
+
+/// `script` with everything at its top level but function definitions and `shopt` commands
+/// wrapped in `if false`, so the shell still reads it but doesn't run it. `shopt` commands
+/// still run since they can change how the rest is parsed, like `shopt -s extglob`.
+fn stub(script: &str) -> Result<String> {
+    let tree = parse_file(script)?;
+    let mut stubbed = String::new();
+    let mut copied = 0;
+    let mut skipping = false;
+    let mut cursor = tree.root_node().walk();
+    for node in tree.root_node().named_children(&mut cursor) {
+        let runs = node.kind() == "function_definition"
+            || (node.kind() == "command"
+                && node
+                    .child_by_field_name("name")
+                    .is_some_and(|name| name.text(script) == "shopt"));
+        if runs == skipping {
+            stubbed.push_str(&script[copied..node.start_byte()]);
+            copied = node.start_byte();
+            // Heredoc bodies can come after the node they belong to, so the block is only
+            // closed right before the next node that runs
+            stubbed.push_str(if runs {
+                "\nfi\n"
+            } else {
+                "\nif false; then :\n"
+            });
+            skipping = !runs;
+        }
+    }
+    stubbed.push_str(&script[copied..]);
+    if skipping {
+        stubbed.push_str("\nfi\n");
+    }
+    return Ok(stubbed);
+}
+
+fn source_time(shell: &str, script: &str) -> Result<Duration> {
+    let mut file = tempfile::NamedTempFile::new()?;
+    file.write_all(stub(script)?.as_bytes())?;
+
+    let mut times = vec![];
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        let status = Command::new(shell)
+            .arg("-c")
+            .arg("source \"$1\"")
+            .arg(shell)
+            .arg(file.path())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .wrap_err_with(|| format!("failed to run {}", shell))?;
+        times.push(start.elapsed());
+
+        if !status.success() {
+            return Err(eyre!("{} failed to source the bundle", shell));
+        }
+    }
+
+    times.sort();
+    return Ok(times[RUNS / 2]);
+}
+
+fn millis(duration: Duration) -> f64 {
+    return duration.as_secs_f64() * 1000.0;
+}
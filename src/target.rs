@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::path::PathBuf;
+
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// A kind of system bundles are built to run on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum, schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum Target {
+    /// A typical Linux distribution with GNU coreutils
+    Linux,
+    Macos,
+    /// A minimal image where busybox provides every tool
+    Busybox,
+}
+
+impl Target {
+    /// The name used on the command line, and for the `OS` define
+    pub fn name(self) -> &'static str {
+        return match self {
+            Target::Linux => "linux",
+            Target::Macos => "macos",
+            Target::Busybox => "busybox",
+        };
+    }
+
+    /// The external tools the generated code may rely on that the target is known to provide
+    pub fn tools(self) -> &'static [&'static str] {
+        return match self {
+            Target::Linux => &["base32", "base64", "cat", "gzip", "mktemp", "tail", "tar"],
+            Target::Macos => &["base64", "cat", "gzip", "mktemp", "tail", "tar"],
+            Target::Busybox => &["base32", "base64", "cat", "gzip", "mktemp", "tail", "tar"],
+        };
+    }
+
+    /// Whether needing a tool the target lacks fails the build rather than warning. Minimal
+    /// images usually can't install missing tools, so bundles for them must do without.
+    pub fn is_strict(self) -> bool {
+        return self == Target::Busybox;
+    }
+
+    /// Checks that the target provides every tool in `tools`, which maps each tool to why the
+    /// bundle needs it
+    pub fn check(self, tools: &BTreeMap<&str, &str>, warnings: &mut Vec<String>) -> Result<()> {
+        for (tool, reason) in tools {
+            if self.tools().contains(tool) {
+                continue;
+            }
+            let message = format!(
+                "the bundle needs `{}` at runtime ({}), which {} targets don't provide",
+                tool,
+                reason,
+                self.name()
+            );
+            if self.is_strict() {
+                return Err(eyre!(message));
+            }
+            warnings.push(message);
+        }
+        return Ok(());
+    }
+}
+
+/// Names the bundle for `target` when building for several. `{target}` in `out` is replaced
+/// with the target's name, and otherwise the name is added before the extension, so
+/// `dist/tool.sh` becomes `dist/tool-macos.sh`.
+pub fn variant_path(out: &Path, target: Target) -> PathBuf {
+    let text = out.to_string_lossy();
+    if text.contains("{target}") {
+        return PathBuf::from(text.replace("{target}", target.name()));
+    }
+    let stem = out.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match out.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, target.name(), ext.to_string_lossy()),
+        None => format!("{}-{}", stem, target.name()),
+    };
+    return out.with_file_name(name);
+}
+
+/// Prints the external tools a bundle relies on at runtime, and why, to stderr
+pub fn report(tools: &BTreeMap<String, String>) {
+    if tools.is_empty() {
+        eprintln!("The bundle doesn't need any external tools at runtime");
+        return;
+    }
+    eprintln!("Runtime tools needed by the bundle:");
+    let width = tools.keys().map(|tool| tool.len()).max().unwrap_or(0);
+    for (tool, reason) in tools {
+        eprintln!("  {:<width$}  {}", tool, reason, width = width);
+    }
+}
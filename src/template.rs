@@ -0,0 +1,37 @@
+use std::collections::BTreeMap;
+
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+
+/// A package's `name`, `version` and `description` from `shpack.toml`, which `{{name}}` and the
+/// like are replaced with
+pub type Metadata = BTreeMap<String, String>;
+
+/// The metadata placeholders can name
+const KEYS: [&str; 3] = ["name", "version", "description"];
+
+/// Replaces `{{name}}`, `{{version}}` and `{{description}}` in `source` with the package's
+/// metadata, returning None if there are none. Other `{{...}}` are left alone, since scripts
+/// may use them for templates of their own.
+pub fn render(source: &str, metadata: &Metadata) -> Result<Option<String>> {
+    if metadata.is_empty() || !source.contains("{{") {
+        return Ok(None);
+    }
+    let mut out = source.to_string();
+    for key in KEYS {
+        let placeholder = format!("{{{{{}}}}}", key);
+        if !out.contains(&placeholder) {
+            continue;
+        }
+        let value = metadata.get(key).ok_or(eyre!(
+            "`{}` is used, but the package doesn't set `{}` in shpack.toml",
+            placeholder,
+            key
+        ))?;
+        out = out.replace(&placeholder, value);
+    }
+    if out == source {
+        return Ok(None);
+    }
+    return Ok(Some(out));
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use color_eyre::Result;
+use color_eyre::eyre::WrapErr;
+use tree_sitter::Node;
+
+use crate::{Edit, GetText, apply_edits, parse_file};
+
+/// The order functions were first called in during a recorded run, used to lay out the bundle
+/// so that code needed early comes first and code that's never needed is loaded lazily.
+///
+/// A trace has one entry per line, and the first word of each line (ignoring leading `+`s) is
+/// taken as the name of a called function, so both a plain list of names and the xtrace output
+/// of `bash -x bundle.sh 2> trace.txt` work.
+pub struct Trace {
+    first_call: HashMap<String, usize>,
+}
+
+impl Trace {
+    pub fn load(path: &Path) -> Result<Trace> {
+        let text = fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read trace {}", path.display()))?;
+
+        let mut first_call = HashMap::new();
+        for line in text.lines() {
+            let line = line.trim_start_matches('+').trim_start();
+            if let Some(name) = line.split_whitespace().next() {
+                let next = first_call.len();
+                first_call.entry(name.to_string()).or_insert(next);
+            }
+        }
+
+        return Ok(Trace { first_call });
+    }
+
+    /// Whether a bundled file only defines functions, none of which were called in the trace
+    pub fn is_cold(&self, body: &str) -> Result<bool> {
+        let tree = parse_file(body)?;
+        let root = tree.root_node();
+        let mut cursor = root.walk();
+        let mut defines_functions = false;
+        for child in root.named_children(&mut cursor) {
+            match child.kind() {
+                "comment" => {}
+                "function_definition" => {
+                    defines_functions = true;
+                    if self.first_call.contains_key(function_name(child, body)) {
+                        return Ok(false);
+                    }
+                }
+                _ => return Ok(false),
+            }
+        }
+        return Ok(defines_functions);
+    }
+
+    /// Sorts each run of consecutive top-level function definitions by when the functions were
+    /// first called, keeping uncalled functions in their original order at the end of the run.
+    /// Comments directly above a function move with it.
+    pub fn reorder(&self, source: String) -> Result<String> {
+        let tree = parse_file(&source)?;
+        let root = tree.root_node();
+        // Each run is a list of (start byte, end byte, function name)
+        let mut runs: Vec<Vec<(usize, usize, &str)>> = vec![vec![]];
+        // The start byte and last row of the comments directly above the current node
+        let mut comments: Option<(usize, usize)> = None;
+        let mut previous_end_row = None;
+        let mut cursor = root.walk();
+        for child in root.named_children(&mut cursor) {
+            let row = child.start_position().row;
+            match child.kind() {
+                "comment" if previous_end_row == Some(row) => {
+                    // Trailing comments belong to the code before them
+                    runs.push(vec![]);
+                    comments = None;
+                }
+                "comment" => {
+                    comments = match comments {
+                        Some((start, last_row)) if last_row + 1 == row => {
+                            Some((start, child.end_position().row))
+                        }
+                        Some(_) => {
+                            runs.push(vec![]);
+                            Some((child.start_byte(), child.end_position().row))
+                        }
+                        None => Some((child.start_byte(), child.end_position().row)),
+                    };
+                }
+                "function_definition" => {
+                    let start = match comments.take() {
+                        Some((start, last_row)) if last_row + 1 == row => start,
+                        Some(_) => {
+                            runs.push(vec![]);
+                            child.start_byte()
+                        }
+                        None => child.start_byte(),
+                    };
+                    runs.last_mut().expect("runs is never empty").push((
+                        start,
+                        child.end_byte(),
+                        function_name(child, &source),
+                    ));
+                }
+                _ => {
+                    runs.push(vec![]);
+                    comments = None;
+                }
+            }
+            previous_end_row = Some(child.end_position().row);
+        }
+
+        let mut edits = vec![];
+        for run in runs {
+            if run.len() < 2 {
+                continue;
+            }
+            let mut names: Vec<&str> = run.iter().map(|f| f.2).collect();
+            names.sort();
+            names.dedup();
+            if names.len() != run.len() {
+                // Redefinitions make the order significant
+                continue;
+            }
+
+            let mut sorted = run.clone();
+            sorted.sort_by_key(|f| self.first_call.get(f.2).copied().unwrap_or(usize::MAX));
+            edits.push(Edit {
+                start_byte: run[0].0,
+                end_byte: run[run.len() - 1].1,
+                new_content: sorted
+                    .iter()
+                    .map(|f| &source[f.0..f.1])
+                    .collect::<Vec<_>>()
+                    .join("\n\n"),
+            });
+        }
+
+        if edits.is_empty() {
+            return Ok(source);
+        }
+        return apply_edits(source, edits);
+    }
+}
+
+fn function_name<'a>(function: Node, source: &'a str) -> &'a str {
+    return function
+        .child_by_field_name("name")
+        .map(|name| name.text(source))
+        .unwrap_or_default();
+}
@@ -0,0 +1,374 @@
+use std::collections::HashSet;
+
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+use serde::Deserialize;
+use serde::Serialize;
+use tree_sitter::Node;
+use tree_sitter::Tree;
+
+use crate::directive::{Directive, parse_bool};
+use crate::shell;
+use crate::{Edit, GetText, apply_edits, parse_file, visit_node};
+
+/// What's stripped from an included file. The entry script is never stripped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum, schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum Strip {
+    #[default]
+    None,
+    /// Comments, except shabangs and directives
+    Comments,
+    /// Blank lines outside strings and heredocs
+    Blank,
+    /// Both comments and blank lines
+    All,
+}
+
+impl Strip {
+    pub fn parse(value: &str) -> Result<Strip> {
+        return match value {
+            "none" => Ok(Strip::None),
+            "comments" => Ok(Strip::Comments),
+            "blank" => Ok(Strip::Blank),
+            "all" => Ok(Strip::All),
+            _ => Err(eyre!(
+                "unknown strip option \"{}\", expected none, comments, blank or all",
+                value
+            )),
+        };
+    }
+
+    fn comments(self) -> bool {
+        return matches!(self, Strip::Comments | Strip::All);
+    }
+
+    fn blank(self) -> bool {
+        return matches!(self, Strip::Blank | Strip::All);
+    }
+}
+
+/// Controls how an included file is transformed before being inlined
+#[derive(Debug, Clone, Default)]
+pub struct IncludeOptions {
+    /// Strip comments, blank lines or both from the file
+    pub strip: Strip,
+    /// Prefix every function defined by the file with `<namespace>::`
+    pub namespace: Option<String>,
+    /// Variables assigned right before the file's contents
+    pub variables: Vec<(String, String)>,
+    /// Only run the file the first time one of its functions is called. When unset, the
+    /// bundler decides based on the trace it was given, if any.
+    pub lazy: Option<bool>,
+    /// Inline the file only where it's first sourced, which is the default. Files with a
+    /// `# shpack: once` pragma are always inlined once, whatever this says.
+    pub once: Option<bool>,
+    /// Run the file in a subshell, so its changes to the environment don't leak out
+    pub isolate: bool,
+}
+
+impl IncludeOptions {
+    /// Overrides these options with the `key=value` pairs of a directive. Bare words are
+    /// shorthand for `word=true`.
+    pub fn apply(&mut self, directive: &Directive) -> Result<()> {
+        let flags = directive.args.iter().map(|arg| (arg, "true"));
+        let options = directive.options.iter().map(|(k, v)| (k, v.as_str()));
+        for (key, value) in flags.chain(options) {
+            match key.as_str() {
+                "minify" => {
+                    self.strip = if parse_bool(key, value)? {
+                        Strip::All
+                    } else {
+                        Strip::None
+                    }
+                }
+                "strip" => self.strip = Strip::parse(value)?,
+                "lazy" => self.lazy = Some(parse_bool(key, value)?),
+                "once" => self.once = Some(parse_bool(key, value)?),
+                "isolate" => self.isolate = parse_bool(key, value)?,
+                "namespace" => {
+                    self.namespace = if value.is_empty() {
+                        None
+                    } else {
+                        Some(value.to_string())
+                    }
+                }
+                _ => {
+                    return Err(eyre!(
+                        "unknown option `{}` for `# shpack: {}`",
+                        key,
+                        directive.name
+                    ));
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    /// Adds the `NAME=value` pairs of a `# shpack: with` directive to the variables
+    pub fn add_variables(&mut self, directive: &Directive) -> Result<()> {
+        if let Some(arg) = directive.args.first() {
+            return Err(eyre!(
+                "expected NAME=value in `# shpack: with`, found `{}`",
+                arg
+            ));
+        }
+        for (name, value) in &directive.options {
+            if !shell::is_identifier(name) {
+                return Err(eyre!("invalid variable name in `# shpack: with`: {}", name));
+            }
+            self.variables.push((name.clone(), value.clone()));
+        }
+        return Ok(());
+    }
+
+    /// Sets the variables before `body` and puts back what they were after it, so they don't
+    /// leak into the rest of the bundle. Their old values are saved in variables named after
+    /// `saved`, which has to be unique to the file.
+    pub fn scope_variables(&self, body: &str, saved: &str) -> String {
+        if self.variables.is_empty() {
+            return body.to_string();
+        }
+        let mut before = String::new();
+        let mut after = String::new();
+        for (name, value) in &self.variables {
+            before.push_str(&format!(
+                "{saved}_set_{name}=${{{name}+1}}\n{saved}_{name}=${{{name}-}}\n{name}={value}\n",
+                saved = saved,
+                name = name,
+                value = shell::quote(value)
+            ));
+            // Put back in reverse, in case a name is given twice
+            after.insert_str(0, &format!(
+                "if [ -n \"${saved}_set_{name}\" ]; then {name}=${saved}_{name}; else unset {name}; fi\nunset {saved}_set_{name} {saved}_{name}\n",
+                saved = saved,
+                name = name
+            ));
+        }
+        return format!("{}{}\n{}", before, body, after);
+    }
+}
+
+/// Returns the edits needed to apply `options` to a parsed file
+pub fn transform_edits(tree: &Tree, source: &str, options: &IncludeOptions) -> Result<Vec<Edit>> {
+    let mut edits = vec![];
+    if options.strip != Strip::None {
+        edits.extend(strip_edits(tree, source, options.strip)?);
+    }
+    if let Some(namespace) = &options.namespace {
+        edits.extend(namespace_edits(tree, source, namespace)?);
+    }
+    return Ok(edits);
+}
+
+/// Strips comments and blank lines from a whole bundle, for `--minify`
+pub fn minify(source: String) -> Result<String> {
+    let tree = parse_file(&source)?;
+    let edits = strip_edits(&tree, &source, Strip::All)?;
+    if edits.is_empty() {
+        return Ok(source);
+    }
+    return apply_edits(source, edits);
+}
+
+/// Removes comments, blank lines or both, leaving shebangs, directives, and the contents of
+/// strings and heredocs alone
+fn strip_edits(tree: &Tree, source: &str, strip: Strip) -> Result<Vec<Edit>> {
+    let mut edits = vec![];
+
+    visit_node(tree.root_node(), &mut |node| {
+        if !strip.comments() || node.kind() != "comment" {
+            return Ok(());
+        }
+        let text = node.text(source);
+        if text.starts_with("#!") || text.starts_with("# shpack:") || text.starts_with("# build:") {
+            return Ok(());
+        }
+
+        let line_start = source[..node.start_byte()]
+            .rfind('\n')
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let before = &source[line_start..node.start_byte()];
+        if before.trim().is_empty() {
+            // The comment is alone on its line, so remove the line entirely
+            let line_end = source[node.end_byte()..]
+                .find('\n')
+                .map(|i| node.end_byte() + i + 1)
+                .unwrap_or(source.len());
+            edits.push(Edit {
+                start_byte: line_start,
+                end_byte: line_end,
+                new_content: String::new(),
+            });
+        } else {
+            edits.push(Edit {
+                start_byte: line_start + before.trim_end().len(),
+                end_byte: node.end_byte(),
+                new_content: String::new(),
+            });
+        }
+        return Ok(());
+    })?;
+
+    if !strip.blank() {
+        return Ok(edits);
+    }
+    let mut line_start = 0;
+    for line in source.split_inclusive('\n') {
+        let line_end = line_start + line.len();
+        if line.trim().is_empty() && !in_literal(tree, line_start) {
+            edits.push(Edit {
+                start_byte: line_start,
+                end_byte: line_end,
+                new_content: String::new(),
+            });
+        }
+        line_start = line_end;
+    }
+
+    return Ok(edits);
+}
+
+/// Whether the byte at `offset` is inside a string or heredoc, where whitespace is significant
+fn in_literal(tree: &Tree, offset: usize) -> bool {
+    let mut node = tree.root_node().descendant_for_byte_range(offset, offset);
+    while let Some(n) = node {
+        if matches!(
+            n.kind(),
+            "string" | "raw_string" | "ansi_c_string" | "heredoc_body" | "heredoc_content"
+        ) {
+            return true;
+        }
+        node = n.parent();
+    }
+    return false;
+}
+
+/// Renames the functions defined in a file, and every call to them within that file, into a
+/// namespace
+fn namespace_edits(tree: &Tree, source: &str, namespace: &str) -> Result<Vec<Edit>> {
+    let mut defined = HashSet::new();
+    let mut edits = vec![];
+
+    visit_node(tree.root_node(), &mut |node| {
+        if node.kind() == "function_definition"
+            && let Some(name) = node.child_by_field_name("name")
+        {
+            defined.insert(name.text(source).to_string());
+            edits.push(rename(name, source, namespace));
+        }
+        return Ok(());
+    })?;
+
+    visit_node(tree.root_node(), &mut |node| {
+        if node.kind() == "command_name" && defined.contains(node.text(source)) {
+            edits.push(rename(node, source, namespace));
+        }
+        return Ok(());
+    })?;
+
+    return Ok(edits);
+}
+
+fn rename(node: Node, source: &str, namespace: &str) -> Edit {
+    return Edit {
+        start_byte: node.start_byte(),
+        end_byte: node.end_byte(),
+        new_content: format!("{}::{}", namespace, node.text(source)),
+    };
+}
+
+/// Wraps a bundled file in a guard, named with `prefix`, so it only runs the first time the
+/// bundle reaches it, like a library sourced from a function that's called repeatedly
+pub fn once_guard(path: &str, body: &str, prefix: &str) -> String {
+    let guard = format!(
+        "{}once_{}",
+        prefix,
+        path.replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+    );
+    return format!(
+        "if [ -z \"${{{guard}-}}\" ]; then\n{guard}=1\n{body}\nfi",
+        guard = guard,
+        body = body
+    );
+}
+
+/// Wraps a bundled file in a loader function, named with `prefix`, that runs it the first time
+/// any of the functions it defines is called. Returns the body unchanged if it doesn't define
+/// any functions.
+pub fn lazy_loader(
+    path: &str,
+    body: &str,
+    prefix: &str,
+    warnings: &mut Vec<String>,
+) -> Result<String> {
+    let tree = parse_file(body)?;
+    let mut functions = vec![];
+    visit_node(tree.root_node(), &mut |node| {
+        if node.kind() == "function_definition"
+            && !has_ancestor(node, "function_definition")
+            && let Some(name) = node.child_by_field_name("name")
+        {
+            functions.push(name.text(body).to_string());
+        }
+        if node.kind() == "declaration_command"
+            && !has_ancestor(node, "function_definition")
+            && matches!(
+                node.child(0).map(|n| n.text(body)),
+                Some("declare" | "typeset")
+            )
+            && !node
+                .text(body)
+                .split_whitespace()
+                .any(|w| w.starts_with('-') && !w.starts_with("--") && w.contains('g'))
+        {
+            warnings.push(format!(
+                "`{}` in lazily loaded {} will declare a local variable, use `declare -g` instead",
+                node.text(body).lines().next().unwrap_or_default(),
+                path
+            ));
+        }
+        return Ok(());
+    })?;
+
+    if functions.is_empty() {
+        warnings.push(format!(
+            "{} doesn't define any functions, so it can't be loaded lazily",
+            path
+        ));
+        return Ok(body.to_string());
+    }
+
+    let loader = format!(
+        "{}load_{}",
+        prefix,
+        path.replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+    );
+    let mut out = format!(
+        "{}() {{\n  unset -f {} {}\n{}\n}}\n",
+        loader,
+        loader,
+        functions.join(" "),
+        body
+    );
+    for function in &functions {
+        out.push_str(&format!(
+            "{}() {{ {}; {} \"$@\"; }}\n",
+            function, loader, function
+        ));
+    }
+    return Ok(out);
+}
+
+fn has_ancestor(node: Node, kind: &str) -> bool {
+    let mut parent = node.parent();
+    while let Some(p) = parent {
+        if p.kind() == kind {
+            return true;
+        }
+        parent = p.parent();
+    }
+    return false;
+}
@@ -0,0 +1,43 @@
+use color_eyre::Result;
+use tree_sitter::Node;
+
+use crate::directive;
+use crate::{GetText, parse_file};
+
+/// Finds top-level `exec` commands with code after them, which never runs since `exec`
+/// replaces the shell. Returns the 0-based line of each. An `exec` with only redirections,
+/// like `exec >log`, keeps the shell running, and one below a `# shpack: allow-exec`
+/// directive is intended.
+pub fn execs(source: &str) -> Result<Vec<usize>> {
+    let tree = parse_file(source)?;
+    let root = tree.root_node();
+    let mut cursor = root.walk();
+    let statements: Vec<Node> = root.named_children(&mut cursor).collect();
+    let mut rows = vec![];
+    for (i, statement) in statements.iter().enumerate() {
+        if replaces_shell(*statement, source)
+            && !directive::is_above(*statement, source, "allow-exec")?
+            && statements[i + 1..]
+                .iter()
+                .any(|after| after.kind() != "comment")
+        {
+            rows.push(statement.start_position().row);
+        }
+    }
+    return Ok(rows);
+}
+
+/// Whether `statement` is an `exec` that runs a command
+fn replaces_shell(statement: Node, source: &str) -> bool {
+    let command = match statement.kind() {
+        "redirected_statement" => statement.child_by_field_name("body"),
+        _ => Some(statement),
+    };
+    return command.is_some_and(|command| {
+        return command.kind() == "command"
+            && command
+                .child_by_field_name("name")
+                .is_some_and(|name| name.text(source) == "exec")
+            && command.child_by_field_name("argument").is_some();
+    });
+}
@@ -0,0 +1,95 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use color_eyre::Result;
+use color_eyre::eyre::WrapErr;
+use serde::Serialize;
+
+/// Metrics about one run of shpack, appended to a file of the user's choosing so they can be
+/// aggregated across builds. Nothing is ever sent anywhere.
+#[derive(Debug, Default, Serialize)]
+pub struct Usage {
+    /// Seconds since the Unix epoch when the run finished
+    pub timestamp: u64,
+    /// `bundle` for single scripts, `build` for workspaces
+    pub command: &'static str,
+    pub duration_ms: u128,
+    /// Packages that were up to date and skipped, and packages that were bundled
+    pub fresh: usize,
+    pub bundled: usize,
+    /// The share of packages that were up to date, for workspace builds
+    pub cache_hit_rate: Option<f64>,
+    /// Files read to produce the bundles, including entry scripts
+    pub files: usize,
+    /// The total size of the bundles written
+    pub bundle_bytes: u64,
+}
+
+impl Usage {
+    /// Stamps the record with the current time and how long ago `started` was
+    pub fn finish(&mut self, started: Instant) {
+        self.timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        self.duration_ms = started.elapsed().as_millis();
+    }
+
+    /// Appends this record to `path`, as CSV if it ends in `.csv` and as a line of JSON
+    /// otherwise. Each record is a single write, so concurrent builds can share a file.
+    pub fn append(&self, path: &Path) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .wrap_err_with(|| format!("failed to open usage report {}", path.display()))?;
+
+        let mut record = String::new();
+        if path.extension().is_some_and(|ext| ext == "csv") {
+            if file.metadata()?.len() == 0 {
+                record.push_str(
+                    "timestamp,command,duration_ms,fresh,bundled,cache_hit_rate,files,bundle_bytes\n",
+                );
+            }
+            record.push_str(&format!(
+                "{},{},{},{},{},{},{},{}\n",
+                self.timestamp,
+                self.command,
+                self.duration_ms,
+                self.fresh,
+                self.bundled,
+                self.cache_hit_rate
+                    .map(|rate| rate.to_string())
+                    .unwrap_or_default(),
+                self.files,
+                self.bundle_bytes
+            ));
+        } else {
+            record = serde_json::to_string(self)?;
+            record.push('\n');
+        }
+        file.write_all(record.as_bytes())?;
+        return Ok(());
+    }
+}
+
+/// Counts the bytes written through it
+pub struct CountingWriter<W> {
+    pub inner: W,
+    pub count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        return Ok(n);
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        return self.inner.flush();
+    }
+}
@@ -0,0 +1,217 @@
+use std::path::PathBuf;
+
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+
+/// Compiles the transform modules in `paths`, in order
+pub fn load(paths: &[PathBuf]) -> Result<Vec<Transform>> {
+    if let Some(path) = paths.first()
+        && !cfg!(feature = "wasm-transforms")
+    {
+        return Err(eyre!(
+            "running the transform {} needs shpack to be built with the `wasm-transforms` feature",
+            path.display()
+        ));
+    }
+    return paths.iter().map(|path| Transform::load(path)).collect();
+}
+
+/// Runs each of `transforms` on `source`, the contents of the file `name`, in order
+pub fn apply(transforms: &[Transform], name: &str, mut source: String) -> Result<String> {
+    for transform in transforms {
+        source = transform.apply(name, &source)?;
+    }
+    return Ok(source);
+}
+
+#[cfg(not(feature = "wasm-transforms"))]
+pub use disabled::Transform;
+
+#[cfg(not(feature = "wasm-transforms"))]
+mod disabled {
+    use std::path::Path;
+
+    use color_eyre::Result;
+
+    /// Transforms can't be loaded without the `wasm-transforms` feature
+    pub enum Transform {}
+
+    impl Transform {
+        pub(super) fn load(_path: &Path) -> Result<Transform> {
+            unreachable!("rejected by wasm::load");
+        }
+
+        pub(super) fn apply(&self, _name: &str, _source: &str) -> Result<String> {
+            match *self {}
+        }
+    }
+}
+
+#[cfg(feature = "wasm-transforms")]
+pub use sandbox::Transform;
+
+#[cfg(feature = "wasm-transforms")]
+mod sandbox {
+    use std::fs;
+    use std::path::Path;
+    use std::path::PathBuf;
+
+    use color_eyre::Result;
+    use color_eyre::eyre::{WrapErr, eyre};
+    use wasmi::{
+        Config, Engine, Instance, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder,
+        TypedFunc, WasmParams, WasmResults,
+    };
+
+    /// The version of the interface between shpack and transform modules, which a module
+    /// returns from its `shpack_abi_version` export
+    const ABI_VERSION: i32 = 1;
+    /// About how many instructions a transform may run on one file, so a module stuck in a
+    /// loop fails the build instead of hanging it
+    const FUEL: u64 = 1_000_000_000;
+    /// How large a transform's memory may grow
+    const MEMORY_LIMIT: usize = 256 * 1024 * 1024;
+
+    /// A compiled WebAssembly module that rewrites files before they're bundled. Modules can't
+    /// import anything, so they can't reach files, the network or the clock, and each file
+    /// gets a fresh instance.
+    pub struct Transform {
+        path: PathBuf,
+        engine: Engine,
+        module: Module,
+    }
+
+    impl Transform {
+        pub(super) fn load(path: &Path) -> Result<Transform> {
+            let wasm =
+                fs::read(path).wrap_err_with(|| format!("failed to read {}", path.display()))?;
+            let mut config = Config::default();
+            config.consume_fuel(true);
+            let engine = Engine::new(&config);
+            let module = Module::new(&engine, &wasm)
+                .map_err(|e| eyre!("{} isn't a valid WebAssembly module: {}", path.display(), e))?;
+            if let Some(import) = module.imports().next() {
+                return Err(eyre!(
+                    "{} imports `{}::{}`, but transforms can't use anything from outside their sandbox",
+                    path.display(),
+                    import.module(),
+                    import.name()
+                ));
+            }
+            let transform = Transform {
+                path: path.to_owned(),
+                engine,
+                module,
+            };
+            let (mut store, instance) = transform.instantiate()?;
+            let version = transform
+                .function::<(), i32>(&store, &instance, "shpack_abi_version")?
+                .call(&mut store, ())
+                .map_err(|e| eyre!("{} failed to start: {}", path.display(), e))?;
+            if version != ABI_VERSION {
+                return Err(eyre!(
+                    "{} was built for version {} of the transform interface, but this shpack supports version {}",
+                    path.display(),
+                    version,
+                    ABI_VERSION
+                ));
+            }
+            return Ok(transform);
+        }
+
+        /// Runs the transform on `source`, the contents of the file `name`
+        pub(super) fn apply(&self, name: &str, source: &str) -> Result<String> {
+            let (mut store, instance) = self.instantiate()?;
+            let memory = instance
+                .get_memory(&store, "memory")
+                .ok_or_else(|| eyre!("{} doesn't export its `memory`", self.path.display()))?;
+            let alloc = self.function::<i32, i32>(&store, &instance, "shpack_alloc")?;
+            let transform =
+                self.function::<(i32, i32, i32, i32), i64>(&store, &instance, "shpack_transform")?;
+            let (name_ptr, name_len) = self.write(&mut store, memory, &alloc, name)?;
+            let (source_ptr, source_len) = self.write(&mut store, memory, &alloc, source)?;
+            let result = transform
+                .call(&mut store, (name_ptr, name_len, source_ptr, source_len))
+                .map_err(|e| eyre!("{} failed on {}: {}", self.path.display(), name, e))?;
+            if result < 0 {
+                let reason = match instance.get_typed_func::<(), i64>(&store, "shpack_error") {
+                    Ok(error) => {
+                        let packed = error.call(&mut store, ()).map_err(|e| {
+                            eyre!("{} failed on {}: {}", self.path.display(), name, e)
+                        })?;
+                        self.read(&store, memory, packed)?
+                    }
+                    Err(_) => String::from("it gave no reason"),
+                };
+                return Err(eyre!(
+                    "{} rejected {}: {}",
+                    self.path.display(),
+                    name,
+                    reason
+                ));
+            }
+            return self.read(&store, memory, result);
+        }
+
+        fn instantiate(&self) -> Result<(Store<StoreLimits>, Instance)> {
+            let limits = StoreLimitsBuilder::new().memory_size(MEMORY_LIMIT).build();
+            let mut store = Store::new(&self.engine, limits);
+            store.limiter(|limits| limits);
+            store.set_fuel(FUEL).expect("fuel is enabled");
+            let instance = Linker::new(&self.engine)
+                .instantiate(&mut store, &self.module)
+                .and_then(|instance| instance.start(&mut store))
+                .map_err(|e| eyre!("{} failed to start: {}", self.path.display(), e))?;
+            return Ok((store, instance));
+        }
+
+        fn function<Params: WasmParams, Results: WasmResults>(
+            &self,
+            store: &Store<StoreLimits>,
+            instance: &Instance,
+            name: &str,
+        ) -> Result<TypedFunc<Params, Results>> {
+            return instance.get_typed_func(store, name).map_err(|e| {
+                eyre!(
+                    "{} doesn't export `{}` the way transforms have to: {}",
+                    self.path.display(),
+                    name,
+                    e
+                )
+            });
+        }
+
+        /// Copies `text` into memory the module allocates for it, returning its address and
+        /// length
+        fn write(
+            &self,
+            store: &mut Store<StoreLimits>,
+            memory: Memory,
+            alloc: &TypedFunc<i32, i32>,
+            text: &str,
+        ) -> Result<(i32, i32)> {
+            let len = i32::try_from(text.len())
+                .map_err(|_| eyre!("{} bytes is too much for a transform", text.len()))?;
+            let ptr = alloc
+                .call(&mut *store, len)
+                .map_err(|e| eyre!("{} failed to allocate: {}", self.path.display(), e))?;
+            memory
+                .write(&mut *store, ptr as u32 as usize, text.as_bytes())
+                .map_err(|e| eyre!("{} allocated bad memory: {}", self.path.display(), e))?;
+            return Ok((ptr, len));
+        }
+
+        /// Reads the text at the address in the high 32 bits of `packed`, as long as the low
+        /// 32 bits say
+        fn read(&self, store: &Store<StoreLimits>, memory: Memory, packed: i64) -> Result<String> {
+            let ptr = (packed >> 32) as u32 as usize;
+            let len = packed as u32 as usize;
+            let mut bytes = vec![0; len];
+            memory
+                .read(store, ptr, &mut bytes)
+                .map_err(|e| eyre!("{} returned bad memory: {}", self.path.display(), e))?;
+            return String::from_utf8(bytes)
+                .map_err(|_| eyre!("{} returned text that isn't UTF-8", self.path.display()));
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use color_eyre::Result;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+
+/// How long files have to stop changing before a rebuild, so that saving several files at
+/// once, or an editor writing a file in steps, only rebuilds once
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Runs `build`, then runs it again whenever one of the files it returned changes, until the
+/// process is interrupted. A failed build is reported and waits for the next change, watching
+/// the files of the last build that succeeded along with `entry`.
+pub fn run(entry: PathBuf, mut build: impl FnMut() -> Result<BTreeSet<PathBuf>>) -> Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    let mut inputs = BTreeSet::from([entry.clone()]);
+    // Directories are watched rather than the files themselves, since editors often save by
+    // replacing a file, which would end a watch on the file
+    let mut watched: BTreeSet<PathBuf> = BTreeSet::new();
+    loop {
+        match build() {
+            Ok(files) => {
+                inputs = files;
+                inputs.insert(entry.clone());
+            }
+            Err(error) => eprintln!("error: {:#}", error),
+        }
+        let dirs: BTreeSet<PathBuf> = inputs
+            .iter()
+            .filter_map(|path| path.parent().map(Path::to_path_buf))
+            .collect();
+        for dir in dirs.difference(&watched) {
+            watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        }
+        for dir in watched.difference(&dirs) {
+            let _ = watcher.unwatch(dir);
+        }
+        watched = dirs;
+        eprintln!("Watching {} file(s) for changes", inputs.len());
+
+        let mut changed = BTreeSet::new();
+        while changed.is_empty() {
+            changed.extend(changes(receiver.recv()?, &inputs)?);
+        }
+        while let Ok(event) = receiver.recv_timeout(DEBOUNCE) {
+            changed.extend(changes(event, &inputs)?);
+        }
+        let changed: Vec<String> = changed
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        eprintln!("{} changed, rebuilding", changed.join(", "));
+    }
+}
+
+/// The inputs an event changed, ignoring events that only read them
+fn changes(event: notify::Result<Event>, inputs: &BTreeSet<PathBuf>) -> Result<Vec<PathBuf>> {
+    let event = event?;
+    if matches!(event.kind, EventKind::Access(_)) {
+        return Ok(vec![]);
+    }
+    return Ok(event
+        .paths
+        .into_iter()
+        .filter(|path| inputs.contains(path))
+        .collect());
+}
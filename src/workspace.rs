@@ -0,0 +1,472 @@
+use std::fs;
+use std::iter;
+use std::path::Path;
+use std::path::PathBuf;
+
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+use serde::Serialize;
+use shpack::BundleOptions;
+use shpack::Bundler;
+use shpack::Chaos;
+use shpack::Markers;
+use shpack::Metadata;
+use shpack::deadline;
+use shpack::deadline::Deadline;
+use shpack::defines::{Defines, load_env_file};
+use shpack::fs_util::{read_script, shpack_path, write_atomic_with};
+use shpack::progress;
+use shpack::progress::Event;
+
+use crate::cache::Cache;
+use crate::config::{CONFIG_FILE_NAME, Config, HardenConfig, PackageConfig};
+use crate::usage::Usage;
+
+/// A buildable script package, with all of its paths made absolute
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Package {
+    pub name: String,
+    /// The entry script, or for a generated one, a file named after the package in its
+    /// directory that's never read
+    pub entry: PathBuf,
+    /// Files a generated entry script sources before running `main`, as written in its
+    /// `source` commands
+    pub includes: Vec<PathBuf>,
+    pub main: Option<String>,
+    pub out: PathBuf,
+    /// Whether the bundle records the command that rebuilds it
+    pub provenance: bool,
+    /// The include paths are the package's own followed by the workspace's shared ones, and
+    /// the defines are its env file's followed by its own
+    #[serde(flatten)]
+    pub options: BundleOptions,
+}
+
+/// A set of packages built together, sharing include paths and a build cache
+pub struct Workspace {
+    pub root: PathBuf,
+    pub cache: Cache,
+    pub packages: Vec<Package>,
+    /// Write progress events instead of messages
+    pub progress: bool,
+    pub chaos: Chaos,
+    /// When the build fails if it isn't done
+    pub deadline: Option<Deadline>,
+}
+
+impl Workspace {
+    /// Finds the workspace that `start` belongs to. Like cargo, the outermost `shpack.toml`
+    /// with a `[workspace]` table wins; otherwise the nearest `shpack.toml` is used on its own.
+    pub fn discover(start: &Path) -> Result<Workspace> {
+        let start = start.canonicalize()?;
+        let configs: Vec<PathBuf> = start
+            .ancestors()
+            .map(|dir| dir.join(CONFIG_FILE_NAME))
+            .filter(|path| path.is_file())
+            .collect();
+
+        let nearest = configs.first().ok_or(eyre!(
+            "could not find {} in {} or any parent directory",
+            CONFIG_FILE_NAME,
+            start.display()
+        ))?;
+
+        for path in configs.iter().rev() {
+            let config = Config::load(path)?;
+            if config.workspace.is_some() {
+                return Workspace::load(path, config);
+            }
+        }
+
+        return Workspace::load(nearest, Config::load(nearest)?);
+    }
+
+    fn load(config_path: &Path, config: Config) -> Result<Workspace> {
+        let root = config_path
+            .parent()
+            .expect("config path should have parent")
+            .to_owned();
+        let workspace = config.workspace.unwrap_or_default();
+        let shared_include_paths: Vec<PathBuf> = workspace
+            .include_paths
+            .iter()
+            .map(|p| root.join(p))
+            .collect();
+
+        let mut packages = vec![];
+        if let Some(package) = config.package {
+            packages.push(Package::new(&root, package, &shared_include_paths)?);
+        }
+        for member in &workspace.members {
+            let member_root = root
+                .join(member)
+                .canonicalize()
+                .wrap_err_with(|| format!("workspace member not found: {}", member.display()))?;
+            let member_config = Config::load(&member_root.join(CONFIG_FILE_NAME))?;
+            let package = member_config.package.ok_or(eyre!(
+                "workspace member {} has no [package] table",
+                member.display()
+            ))?;
+            packages.push(Package::new(&member_root, package, &shared_include_paths)?);
+        }
+
+        for (i, package) in packages.iter().enumerate() {
+            if packages[..i].iter().any(|p| p.name == package.name) {
+                return Err(eyre!("duplicate package name: {}", package.name));
+            }
+        }
+
+        let cache_dir = root.join(
+            workspace
+                .cache_dir
+                .unwrap_or_else(|| PathBuf::from(".shpack-cache")),
+        );
+
+        return Ok(Workspace {
+            root,
+            cache: Cache::new(cache_dir),
+            packages,
+            progress: false,
+            chaos: Chaos::default(),
+            deadline: None,
+        });
+    }
+
+    /// Builds the packages named in `selected`, or every package if it is empty. `defines`
+    /// override those of each package.
+    pub fn build(&self, selected: &[String], defines: &Defines) -> Result<Usage> {
+        let packages = self.select(selected)?;
+        let mut usage = Usage {
+            command: "build",
+            ..Default::default()
+        };
+        for package in packages {
+            self.build_package(package, defines, &mut usage)
+                .wrap_err_with(|| format!("failed to build package `{}`", package.name))?;
+        }
+        let packages = usage.fresh + usage.bundled;
+        if packages > 0 {
+            usage.cache_hit_rate = Some(usage.fresh as f64 / packages as f64);
+        }
+
+        return Ok(usage);
+    }
+
+    /// The packages named in `selected`, or every package if it is empty
+    fn select(&self, selected: &[String]) -> Result<Vec<&Package>> {
+        for name in selected {
+            if !self.packages.iter().any(|p| &p.name == name) {
+                return Err(eyre!("package `{}` not found in workspace", name));
+            }
+        }
+        return Ok(self
+            .packages
+            .iter()
+            .filter(|package| selected.is_empty() || selected.contains(&package.name))
+            .collect());
+    }
+
+    /// The configuration the packages named in `selected`, or every package, are built with
+    /// after merging the workspace's settings and `defines` into their own, as TOML
+    pub fn show_config(&self, selected: &[String], defines: &Defines) -> Result<String> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "kebab-case")]
+        struct Shown<'a> {
+            workspace: ShownWorkspace<'a>,
+            package: Vec<toml::Table>,
+        }
+        #[derive(Serialize)]
+        #[serde(rename_all = "kebab-case")]
+        struct ShownWorkspace<'a> {
+            root: &'a Path,
+            cache_dir: &'a Path,
+        }
+
+        let mut packages = vec![];
+        for package in self.select(selected)? {
+            let mut package = package.clone();
+            package.options.defines.extend(defines.clone());
+            // The options are named like a batch request's, rather than like shpack.toml's keys
+            let table: toml::Table = toml::Table::try_from(&package)?
+                .into_iter()
+                .map(|(key, value)| (key.replace('_', "-"), value))
+                .collect();
+            packages.push(table);
+        }
+        return Ok(toml::to_string(&Shown {
+            workspace: ShownWorkspace {
+                root: &self.root,
+                cache_dir: self.cache.dir(),
+            },
+            package: packages,
+        })?);
+    }
+
+    fn build_package(&self, package: &Package, defines: &Defines, usage: &mut Usage) -> Result<()> {
+        let _lock = self.cache.lock(&package.name)?;
+        // The command that rebuilds the package, with the defines given on the command line
+        let provenance = package.provenance.then(|| {
+            let mut args = vec![
+                String::from("build"),
+                String::from("--package"),
+                package.name.clone(),
+            ];
+            for (name, value) in defines {
+                args.push(String::from("-D"));
+                args.push(format!("{}={}", name, value));
+            }
+            return args;
+        });
+        let mut options = BundleOptions {
+            provenance,
+            ..package.options.clone()
+        };
+        options.defines.extend(defines.clone());
+        let mut key = package.fingerprint_key(&options)?;
+        // The stamp names the commit, which can change without the sources changing
+        if options.version_stamp {
+            key.push_str(&format!(" {:?}", shpack::commit(&self.root)));
+        }
+        let name = package.name.as_str();
+        // Simulated failures and delays happen while reading files, so nothing is skipped
+        if !self.chaos.is_active() && self.cache.is_fresh(&package.name, &key, &package.out)? {
+            if self.progress {
+                progress::emit(&Event::PackageFresh { name });
+            } else {
+                eprintln!("       Fresh {}", package.name);
+            }
+            usage.fresh += 1;
+            return Ok(());
+        }
+        if self.progress {
+            progress::emit(&Event::PackageStarted { name });
+        } else {
+            eprintln!("    Bundling {}", package.name);
+        }
+
+        let cwd = package
+            .entry
+            .parent()
+            .expect("entry path should have parent")
+            .to_owned();
+        let source = match package.generated_entry() {
+            Some(source) => source,
+            None => read_script(&package.entry)
+                .wrap_err_with(|| format!("can't bundle {}", package.entry.display()))?,
+        };
+
+        let entry = match package.main {
+            Some(_) => cwd.canonicalize()?.join(&package.name),
+            None => package.entry.canonicalize()?,
+        };
+        let root = self.root.canonicalize()?;
+        let entry_name = entry.strip_prefix(&root).unwrap_or(&entry);
+        let mut builder = options
+            .apply(Bundler::builder().root(root.clone()))?
+            .entry_name(entry_name.to_string_lossy())
+            .progress(self.progress)
+            .chaos(self.chaos.clone());
+        if let Some(deadline) = &self.deadline {
+            builder = builder.deadline(deadline.clone());
+        }
+        let bundle = builder.build()?.bundle(source, &cwd)?;
+        for warning in bundle.warnings() {
+            if self.progress {
+                progress::emit(&Event::Warning { message: warning });
+            } else {
+                eprintln!("warning: {}", warning);
+            }
+        }
+        for note in bundle.notes() {
+            if self.progress {
+                progress::emit(&Event::Note { message: note });
+            } else {
+                eprintln!("note: {}", note);
+            }
+        }
+
+        write_atomic_with(&package.out, |file| bundle.write_to(file))?;
+        bundle.write_completion_files()?;
+        let bytes = fs::metadata(&package.out)?.len();
+        if self.progress {
+            progress::emit(&Event::PackageFinished { name, bytes });
+        }
+        usage.bundled += 1;
+        usage.files += 1 + bundle.sources().len();
+        usage.bundle_bytes += bytes;
+
+        // Inline commands can produce different output on every run, so their results
+        // are never considered fresh
+        if bundle.ran_commands() {
+            self.cache.forget(&package.name)?;
+        } else {
+            let mut inputs = vec![];
+            if package.main.is_none() {
+                inputs.push(package.entry.clone());
+            }
+            inputs.extend(bundle.sources().iter().cloned());
+            self.cache
+                .record(&package.name, &key, &inputs, bundle.listings())?;
+        }
+
+        return Ok(());
+    }
+}
+
+impl Package {
+    fn new(root: &Path, config: PackageConfig, shared_include_paths: &[PathBuf]) -> Result<Self> {
+        let mut include_paths: Vec<PathBuf> =
+            config.include_paths.iter().map(|p| root.join(p)).collect();
+        include_paths.extend_from_slice(shared_include_paths);
+        include_paths.extend(shpack_path());
+
+        let mut defines = Defines::new();
+        if let Some(env_file) = &config.env_file {
+            defines.extend(load_env_file(&root.join(env_file))?);
+        }
+        defines.extend(config.defines);
+
+        let mut metadata = Metadata::new();
+        metadata.insert(String::from("name"), config.name.clone());
+        if let Some(version) = config.version {
+            metadata.insert(String::from("version"), version);
+        }
+        if let Some(description) = config.description {
+            metadata.insert(String::from("description"), description);
+        }
+
+        // Bad markers and durations fail when the workspace is loaded rather than when the
+        // package is built
+        Markers::new(config.marker_open.clone(), config.marker_close.clone())
+            .wrap_err_with(|| format!("invalid markers for package {}", config.name))?;
+        if let Some(backoff) = &config.retry_backoff {
+            deadline::parse_option("retry-backoff", backoff)?;
+        }
+
+        let entry = match (config.entry, &config.main) {
+            (Some(entry), None) if config.includes.is_empty() => root.join(entry),
+            (Some(_), _) => {
+                return Err(eyre!(
+                    "package {} has an `entry` script, so it can't also generate one from `includes` and `main`",
+                    config.name
+                ));
+            }
+            (None, Some(_)) => root.join(&config.name),
+            (None, None) => {
+                return Err(eyre!(
+                    "package {} needs an `entry` script, or a `main` command to generate one",
+                    config.name
+                ));
+            }
+        };
+
+        return Ok(Package {
+            name: config.name,
+            entry,
+            includes: config.includes,
+            main: config.main,
+            out: root.join(config.out),
+            provenance: config.provenance,
+            options: BundleOptions {
+                include_paths,
+                order_by_trace: None,
+                stream_threshold: config.stream_threshold,
+                memory_cap: config.memory_cap,
+                jobs: config.jobs,
+                retries: config.retries,
+                retry_backoff: config.retry_backoff,
+                prelude: config.prelude.iter().map(|p| root.join(p)).collect(),
+                epilogue: config.epilogue.iter().map(|p| root.join(p)).collect(),
+                transforms: config.transforms.iter().map(|p| root.join(p)).collect(),
+                compress: config.compress,
+                compress_threshold: config.compress_threshold,
+                encoding: config.encoding,
+                target: config.target,
+                runtime_tmpdir: config.runtime_tmpdir,
+                symbol_prefix: config.symbol_prefix,
+                shebang: config.shebang,
+                defines,
+                metadata,
+                tree_shake: config.tree_shake,
+                keep: config.keep,
+                why_kept: vec![],
+                portable: config.portable,
+                with_logging: config.with_logging,
+                rewrite_log_echoes: config.rewrite_log_echoes,
+                version_stamp: config.version_stamp,
+                harden: config
+                    .harden
+                    .as_ref()
+                    .map(HardenConfig::measures)
+                    .unwrap_or_default(),
+                exempt: config.exempt,
+                runtime_sources: config.runtime_sources,
+                external: config.external,
+                source_aliases: config.source_aliases,
+                strip_source_aliases: config.strip_source_aliases,
+                script_dir_variables: config.script_dir_variables,
+                deny_version_conflicts: config.deny_version_conflicts,
+                secrets: config.secrets,
+                format: config.format,
+                archive_dir: config.archive_dir.map(|dir| root.join(dir)),
+                deny_absolute_sources: config.deny_absolute_sources,
+                allow_large_include: config.allow_large_include,
+                allow_circular: config.allow_circular,
+                topological_includes: config.topological_includes,
+                strip: config.strip,
+                minify: config.minify,
+                inject_trace: config.inject_trace,
+                provenance: None,
+                marker_open: config.marker_open,
+                marker_close: config.marker_close,
+            },
+        });
+    }
+
+    /// Where a `source` command in the package's directory would find `include`: there, or
+    /// else in the first include path that has it
+    pub fn find_include(&self, include: &Path) -> Option<PathBuf> {
+        let dir = self.entry.parent().expect("entry path should have parent");
+        return iter::once(dir)
+            .chain(self.options.include_paths.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(include))
+            .find(|path| path.is_file());
+    }
+
+    /// The source of a generated entry script, if the package has one: the first include's
+    /// shabang, a `source` line for each include and then the `main` command. A missing
+    /// include is reported when its `source` line is bundled.
+    fn generated_entry(&self) -> Option<String> {
+        let main = self.main.as_ref()?;
+        let shabang = self
+            .includes
+            .first()
+            .and_then(|first| self.find_include(first))
+            .and_then(|first| read_script(&first).ok())
+            .and_then(|text| text.lines().next().map(str::to_string))
+            .filter(|line| line.starts_with("#!"))
+            .unwrap_or(String::from("#!/usr/bin/env bash"));
+        let mut source = format!("{}\n", shabang);
+        for include in &self.includes {
+            source.push_str(&format!("source \"{}\"\n", include.display()));
+        }
+        source.push_str(main);
+        source.push('\n');
+        return Some(source);
+    }
+
+    /// Identifies everything about a package's build besides the contents of its sources,
+    /// given the `options` it's built with
+    fn fingerprint_key(&self, options: &BundleOptions) -> Result<String> {
+        return Ok(format!(
+            "{} {} {:?} {:?} {} {}",
+            env!("CARGO_PKG_VERSION"),
+            self.entry.display(),
+            self.includes,
+            self.main,
+            self.out.display(),
+            serde_json::to_string(options)?,
+        ));
+    }
+}
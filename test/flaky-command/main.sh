@@ -0,0 +1,7 @@
+#!/bin/bash
+
+# Fails the first time it's run in a directory, like a download that times out
+value=$(
+  if [ -e tried ]; then echo "fetched"; else touch tried; exit 1; fi
+) # build: inline idempotent
+echo "$value"
@@ -0,0 +1,7 @@
+#!/bin/bash
+
+# Whoever runs shpack sets this, and the bundle keeps their value
+value=$(
+  echo "$SHPACK_TEST_VALUE"
+) # build: inline
+echo "$value"
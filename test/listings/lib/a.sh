@@ -0,0 +1,3 @@
+#!/bin/bash
+
+echo "lib/a.sh"
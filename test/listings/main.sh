@@ -0,0 +1,9 @@
+#!/bin/bash
+
+source lib/*.sh
+
+# build: embed-dir conf as CONF
+ls "$CONF"
+
+# build: messages locales/ default=en
+LANG=pt msg greeting
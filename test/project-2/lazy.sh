@@ -0,0 +1,8 @@
+#!/bin/bash
+
+# shpack: inline lazy
+source "report.sh"
+
+echo "started"
+report_line status
+report_line again
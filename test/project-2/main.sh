@@ -0,0 +1,7 @@
+#!/bin/bash
+
+# shpack: inline minify=true namespace=net
+# shpack: with USER_AGENT="shpack test" RETRIES=3
+source "net.sh"
+
+net::fetch "https://example.com"
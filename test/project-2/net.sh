@@ -0,0 +1,20 @@
+#!/bin/bash
+
+# Builds the curl command line
+
+# Configured by the variables set when it's sourced
+user_agent=$USER_AGENT
+retries=$RETRIES
+
+curl_args() {
+  # Fail on HTTP errors
+  echo "-fsSL"
+}
+
+fetch() {
+  cat <<END
+# not a comment
+
+fetching with $(curl_args) -A "$user_agent" --retry $retries $1
+END
+}
@@ -0,0 +1,12 @@
+#!/bin/bash
+
+declare -Ag REPORT_COLORS=([ok]=green)
+
+report_header() {
+  echo "== report =="
+}
+
+report_line() {
+  report_header
+  echo "$1: ${REPORT_COLORS[ok]}"
+}
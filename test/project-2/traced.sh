@@ -0,0 +1,7 @@
+#!/bin/bash
+
+source "util.sh"
+source "report.sh"
+
+whisper world
+greet world
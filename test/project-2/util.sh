@@ -0,0 +1,15 @@
+#!/bin/bash
+
+# Prints a greeting
+greet() {
+  echo "hello $1"
+}
+
+# Rarely used
+shout() {
+  echo "HELLO $1"
+}
+
+whisper() {
+  echo "hello $1..."
+}
@@ -0,0 +1,5 @@
+#!/bin/bash
+
+greet() {
+  echo "hello, $1"
+} # no newline after this comment
\ No newline at end of file
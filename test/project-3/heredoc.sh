@@ -0,0 +1,5 @@
+#!/bin/bash
+
+cat <<EOF
+from a heredoc
+EOF
\ No newline at end of file
@@ -0,0 +1,6 @@
+#!/bin/bash
+
+source "greet.sh"; greet world
+if true; then source "heredoc.sh"; fi; echo "after the heredoc"
+source "quiet.sh" >/dev/null && echo "quiet.sh was silenced"
+source "greet.sh"; greet again
@@ -0,0 +1,3 @@
+#!/bin/bash
+
+echo "this goes to /dev/null"
\ No newline at end of file
@@ -0,0 +1,5 @@
+# shpack: it's sourced by main.sh
+# build:
+greet() {
+    echo "hello, $1"
+}
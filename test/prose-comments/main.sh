@@ -0,0 +1,7 @@
+#!/bin/bash
+# build: don't forget to run make first
+# build:
+# shpack: this file is bundled, so don't edit dist/ by hand
+
+source "lib.sh"
+greet "$(echo world)" # build: it's fine to run this at build time too
@@ -0,0 +1,6 @@
+#!/bin/bash
+
+value=$(
+  sleep 30
+) # build: inline
+echo "$value"
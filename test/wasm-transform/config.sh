@@ -0,0 +1,2 @@
+DB_USER=admin
+DB_PASSWORD=hunter2
@@ -0,0 +1,5 @@
+#!/usr/bin/env bash
+
+source ./config.sh
+
+echo "connecting as $DB_USER"
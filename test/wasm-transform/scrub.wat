@@ -0,0 +1,63 @@
+;; A transform that replaces every "hunter2" with asterisks. Compile it with
+;; `wasm-tools parse scrub.wat -o scrub.wasm` or `wat2wasm scrub.wat`.
+(module
+  (memory (export "memory") 1)
+  (data (i32.const 0) "hunter2")
+  (global $heap (mut i32) (i32.const 1024))
+
+  (func (export "shpack_abi_version") (result i32)
+    (i32.const 1))
+
+  ;; A bump allocator, growing memory as needed
+  (func (export "shpack_alloc") (param $len i32) (result i32)
+    (local $ptr i32)
+    (local $end i32)
+    (local.set $ptr (global.get $heap))
+    (local.set $end (i32.add (local.get $ptr) (local.get $len)))
+    (if (i32.gt_u (local.get $end) (i32.mul (memory.size) (i32.const 65536)))
+      (then
+        (drop (memory.grow
+          (i32.add
+            (i32.shr_u
+              (i32.sub (local.get $end) (i32.mul (memory.size) (i32.const 65536)))
+              (i32.const 16))
+            (i32.const 1))))))
+    (global.set $heap (local.get $end))
+    (local.get $ptr))
+
+  ;; Whether the 7 bytes at $at are "hunter2"
+  (func $matches (param $at i32) (result i32)
+    (local $i i32)
+    (block $no
+      (loop $next
+        (br_if $no
+          (i32.ne
+            (i32.load8_u (i32.add (local.get $at) (local.get $i)))
+            (i32.load8_u (local.get $i))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br_if $next (i32.lt_u (local.get $i) (i32.const 7))))
+      (return (i32.const 1)))
+    (i32.const 0))
+
+  ;; Rewrites the source in place and returns it
+  (func (export "shpack_transform")
+    (param $name i32) (param $name_len i32) (param $src i32) (param $len i32) (result i64)
+    (local $i i32)
+    (local $j i32)
+    (block $done
+      (loop $scan
+        (br_if $done (i32.gt_u (i32.add (local.get $i) (i32.const 7)) (local.get $len)))
+        (if (call $matches (i32.add (local.get $src) (local.get $i)))
+          (then
+            (local.set $j (i32.const 0))
+            (loop $star
+              (i32.store8
+                (i32.add (i32.add (local.get $src) (local.get $i)) (local.get $j))
+                (i32.const 42))
+              (local.set $j (i32.add (local.get $j) (i32.const 1)))
+              (br_if $star (i32.lt_u (local.get $j) (i32.const 7))))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $scan)))
+    (i64.or
+      (i64.shl (i64.extend_i32_u (local.get $src)) (i64.const 32))
+      (i64.extend_i32_u (local.get $len)))))
@@ -0,0 +1,8 @@
+#!/bin/bash
+
+# Loaded the first time describe_lazily is called
+lazy_agent=$USER_AGENT
+
+describe_lazily() {
+  echo "lazy library: $lazy_agent"
+}
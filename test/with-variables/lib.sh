@@ -0,0 +1,9 @@
+#!/bin/bash
+
+# Configured by the variables set when it's sourced
+agent=$USER_AGENT
+retries=$RETRIES
+
+describe() {
+  echo "library: $agent, retries $retries"
+}
@@ -0,0 +1,14 @@
+#!/bin/bash
+
+USER_AGENT="caller"
+
+# shpack: with USER_AGENT="library" RETRIES=3
+source "lib.sh"
+
+# shpack: inline lazy=true
+# shpack: with USER_AGENT="lazy library"
+source "lazy.sh"
+
+describe
+describe_lazily
+echo "caller: $USER_AGENT, retries ${RETRIES-unset}"
@@ -0,0 +1,5 @@
+#!/bin/bash
+
+log() {
+  echo "[$(date +%T)] $*" >&2
+}
@@ -0,0 +1,5 @@
+#!/bin/bash
+
+source "log.sh"
+
+log "running backup"
@@ -0,0 +1,66 @@
+//! `# build: inline` commands can hang or fail now and then. `--deadline` stops the ones that
+//! hang, and `--retries` runs the ones marked `idempotent` again.
+#![allow(clippy::needless_return)]
+
+mod common;
+
+use std::path::Path;
+use std::time::Duration;
+use std::time::Instant;
+
+use color_eyre::Result;
+use common::fixture;
+use common::run;
+use shpack::Bundle;
+use shpack::Bundler;
+use shpack::deadline::Deadline;
+
+/// Bundles the fixture `name` with at most `retries` retries, running its commands in a
+/// fresh directory
+fn bundle_with_retries(name: &str, retries: u32) -> Result<Bundle> {
+    let dir = fixture(name);
+    let commands = tempfile::tempdir()?;
+    return Bundler::builder()
+        .root(&dir)
+        .command_dir(commands.path())
+        .retries(retries)
+        .retry_backoff(Duration::ZERO)
+        .build()?
+        .bundle_file(&dir.join("main.sh"));
+}
+
+#[test]
+fn hung_commands_are_killed_at_the_deadline() {
+    let dir = fixture("slow-command");
+    let started = Instant::now();
+    let deadline = Deadline::new("500ms", started).expect("the deadline should parse");
+    let error = Bundler::builder()
+        .root(&dir)
+        .deadline(deadline)
+        .build()
+        .and_then(|bundler| bundler.bundle_file(&dir.join("main.sh")))
+        .err()
+        .expect("the build should fail");
+
+    assert!(started.elapsed() < Duration::from_secs(20));
+    let error = format!("{:#}", error);
+    assert!(error.contains("500ms deadline"), "{}", error);
+    assert!(error.contains("sleep 30"), "{}", error);
+}
+
+#[test]
+fn idempotent_commands_are_retried() {
+    let bundle = bundle_with_retries("flaky-command", 1).expect("the retry should succeed");
+    let script = bundle.into_string().expect("the bundle should be text");
+
+    assert_eq!(run(&script, Path::new(".")), "fetched\n");
+}
+
+#[test]
+fn commands_are_not_retried_by_default() {
+    let error = bundle_with_retries("flaky-command", 0)
+        .err()
+        .expect("the build should fail");
+
+    assert!(format!("{:#}", error).contains("returned with exit code"));
+}
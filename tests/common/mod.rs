@@ -0,0 +1,74 @@
+//! Helpers shared by the integration tests, which bundle the fixtures in test/
+// Each test crate uses only some of them
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+use color_eyre::Result;
+use shpack::Bundle;
+use shpack::Bundler;
+use shpack::BundlerBuilder;
+use tempfile::TempDir;
+
+pub fn fixture(name: &str) -> PathBuf {
+    return Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("test")
+        .join(name);
+}
+
+/// Copies the fixture `name` to a temporary directory, for tests that change its files
+pub fn copy_fixture(name: &str) -> TempDir {
+    let dir = tempfile::tempdir().expect("a temporary directory should be created");
+    copy_dir(&fixture(name), dir.path());
+    return dir;
+}
+
+fn copy_dir(from: &Path, to: &Path) {
+    for entry in fs::read_dir(from).expect("the fixture should be readable") {
+        let entry = entry.expect("the fixture should be readable");
+        let target = to.join(entry.file_name());
+        if entry.path().is_dir() {
+            fs::create_dir(&target).expect("the copy should be writable");
+            copy_dir(&entry.path(), &target);
+        } else {
+            fs::copy(entry.path(), &target).expect("the copy should be writable");
+        }
+    }
+}
+
+/// Bundles `main.sh` in the fixture `dir` with the options `configure` sets
+pub fn try_bundle(dir: &Path, configure: fn(BundlerBuilder) -> BundlerBuilder) -> Result<Bundle> {
+    return configure(Bundler::builder().root(dir))
+        .build()?
+        .bundle_file(&dir.join("main.sh"));
+}
+
+/// Bundles `main.sh` in the fixture `dir` with the options `configure` sets, and returns
+/// the script
+pub fn bundle(dir: &Path, configure: fn(BundlerBuilder) -> BundlerBuilder) -> String {
+    let bundle = try_bundle(dir, configure).expect("the fixture should bundle");
+    let mut script = vec![];
+    bundle
+        .write_to(&mut script)
+        .expect("the bundle should write");
+    return String::from_utf8(script).expect("the bundle should be UTF-8");
+}
+
+/// Runs `script` with bash in `dir`, returning its stdout
+pub fn run(script: &str, dir: &Path) -> String {
+    let output = Command::new("bash")
+        .arg("-c")
+        .arg(script)
+        .current_dir(dir)
+        .output()
+        .expect("bash should run");
+    assert!(
+        output.status.success(),
+        "the script failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    return String::from_utf8(output.stdout).expect("the output should be UTF-8");
+}
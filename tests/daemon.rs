@@ -0,0 +1,145 @@
+//! `shpack daemon` builds what `shpack FILE` hands it, so its `# build: inline` commands have
+//! to run the way they would have in the client
+#![cfg(feature = "cli")]
+#![allow(clippy::needless_return)]
+
+mod common;
+
+use std::io::BufReader;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Child;
+use std::process::Command;
+use std::process::Stdio;
+use std::thread;
+use std::time::Duration;
+
+use common::fixture;
+use common::run;
+use shpack::batch;
+use shpack::batch::Response;
+use shpack::batch::read_message;
+use shpack::batch::write_message;
+use tempfile::TempDir;
+
+/// A daemon listening in a directory of its own, killed when it's dropped
+struct Daemon {
+    process: Child,
+    socket: PathBuf,
+    _dir: TempDir,
+}
+
+impl Daemon {
+    fn start() -> Daemon {
+        let dir = tempfile::tempdir().expect("a temporary directory should be created");
+        let socket = dir.path().join("daemon.sock");
+        let mut process = Command::new(env!("CARGO_BIN_EXE_shpack"))
+            .arg("daemon")
+            .env("SHPACK_DAEMON_SOCKET", &socket)
+            .env("SHPACK_TEST_VALUE", "from the daemon")
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("the daemon should start");
+        for _ in 0..100 {
+            if UnixStream::connect(&socket).is_ok() {
+                return Daemon {
+                    process,
+                    socket,
+                    _dir: dir,
+                };
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        let _ = process.kill();
+        let _ = process.wait();
+        panic!("the daemon didn't start listening");
+    }
+
+    fn send(&self, request: serde_json::Value) -> Response {
+        let stream = UnixStream::connect(&self.socket).expect("the daemon should accept");
+        let mut writer = stream.try_clone().expect("the socket should clone");
+        write_message(&mut writer, request.to_string().as_bytes())
+            .expect("the request should send");
+        let message = read_message(&mut BufReader::new(stream))
+            .expect("the response should be readable")
+            .expect("the daemon should respond");
+        return serde_json::from_slice(&message).expect("the response should be JSON");
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+fn entry(dir: &Path) -> String {
+    return dir.join("main.sh").to_string_lossy().to_string();
+}
+
+#[test]
+fn requests_choose_the_environment_of_commands() {
+    let daemon = Daemon::start();
+    let dir = fixture("inline-env");
+    let response = daemon.send(serde_json::json!({
+        "entry": entry(&dir),
+        "env": {"SHPACK_TEST_VALUE": "from the client"},
+        "version": batch::VERSION,
+    }));
+
+    assert!(response.ok, "the build failed: {:?}", response.error);
+    assert_eq!(response.version, batch::VERSION);
+    let script = response
+        .bundle
+        .expect("the response should have the bundle");
+    assert_eq!(run(&script, &dir), "from the client\n");
+}
+
+#[test]
+fn requests_without_an_environment_use_the_daemons() {
+    let daemon = Daemon::start();
+    let dir = fixture("inline-env");
+    let response = daemon.send(serde_json::json!({"entry": entry(&dir)}));
+
+    let script = response
+        .bundle
+        .expect("the response should have the bundle");
+    assert_eq!(run(&script, &dir), "from the daemon\n");
+}
+
+#[test]
+fn requests_for_another_version_are_refused() {
+    let daemon = Daemon::start();
+    let dir = fixture("inline-env");
+    let response = daemon.send(serde_json::json!({
+        "entry": entry(&dir),
+        "version": "0.0.0",
+    }));
+
+    assert!(!response.ok);
+    assert!(response.bundle.is_none());
+    assert!(response.error.is_some_and(|error| error.contains("0.0.0")));
+}
+
+#[test]
+fn delegated_builds_use_the_clients_environment() {
+    let daemon = Daemon::start();
+    let dir = fixture("inline-env");
+    let output = Command::new(env!("CARGO_BIN_EXE_shpack"))
+        .arg(dir.join("main.sh"))
+        .current_dir(&dir)
+        .env("SHPACK_DAEMON_SOCKET", &daemon.socket)
+        .env("SHPACK_TEST_VALUE", "from the client")
+        .output()
+        .expect("shpack should run");
+
+    assert!(
+        output.status.success(),
+        "the build failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let script = String::from_utf8(output.stdout).expect("the bundle should be UTF-8");
+    assert_eq!(run(&script, &dir), "from the client\n");
+}
@@ -0,0 +1,52 @@
+//! Comments that start with `# build:` or `# shpack:` but don't name a directive, like
+//! `# build: don't forget to run make first`, are ordinary comments and mustn't break the build
+#![allow(clippy::needless_return)]
+
+mod common;
+
+use std::path::Path;
+
+use common::bundle;
+use common::fixture;
+use common::run;
+use shpack::Bundler;
+
+#[test]
+fn prose_comments_still_bundle() {
+    let dir = fixture("prose-comments");
+    let script = bundle(&dir, |builder| builder);
+    assert_eq!(run(&script, &dir), "hello, world\n");
+}
+
+#[test]
+fn prose_comments_are_kept() {
+    let dir = fixture("prose-comments");
+    let script = bundle(&dir, |builder| builder);
+    for comment in [
+        "# build: don't forget to run make first",
+        "# shpack: this file is bundled, so don't edit dist/ by hand",
+        "# shpack: it's sourced by main.sh",
+    ] {
+        assert!(
+            script.lines().any(|line| line == comment),
+            "`{}` is missing from the bundle:\n{}",
+            comment,
+            script
+        );
+    }
+}
+
+#[test]
+fn directives_with_unterminated_quotes_fail() {
+    let source = "#!/bin/bash\nconfig=$(echo hi) # build: inline id=\"config\n";
+    let result = Bundler::builder()
+        .build()
+        .expect("the bundler should build")
+        .bundle(source.to_string(), Path::new("."));
+    let error = result.err().expect("the bundle should fail");
+    assert!(
+        error.to_string().contains("unterminated quote"),
+        "unexpected error: {}",
+        error
+    );
+}
@@ -0,0 +1,64 @@
+//! Bundles the fixtures in test/ whose files end without a newline or source each other in
+//! the middle of a line, and runs the results, since a marker or caller code glued onto the
+//! last line of an inlined file changes what the bundle does.
+#![allow(clippy::needless_return)]
+
+mod common;
+
+use common::bundle;
+use common::fixture;
+use common::run;
+use shpack::Strip;
+
+const PROJECT_3_OUTPUT: &str = "hello, world\n\
+                                 from a heredoc\n\
+                                 after the heredoc\n\
+                                 quiet.sh was silenced\n\
+                                 hello, again\n";
+
+#[test]
+fn files_without_trailing_newlines_stay_separate() {
+    let dir = fixture("project-3");
+    let script = bundle(&dir, |builder| builder);
+    assert_eq!(run(&script, &dir), PROJECT_3_OUTPUT);
+}
+
+#[test]
+fn bundle_behaves_like_its_sources() {
+    let dir = fixture("project-3");
+    let script = bundle(&dir, |builder| builder);
+    let sources = std::fs::read_to_string(dir.join("main.sh")).expect("main.sh should exist");
+    assert_eq!(run(&script, &dir), run(&sources, &dir));
+}
+
+#[test]
+fn stripped_files_stay_separate() {
+    let dir = fixture("project-3");
+    let script = bundle(&dir, |builder| builder.strip(Strip::All));
+    assert_eq!(run(&script, &dir), PROJECT_3_OUTPUT);
+}
+
+#[test]
+fn minified_files_stay_separate() {
+    let dir = fixture("project-3");
+    let script = bundle(&dir, |builder| builder.minify(true));
+    assert_eq!(run(&script, &dir), PROJECT_3_OUTPUT);
+}
+
+#[test]
+fn every_inlined_file_ends_its_line() {
+    let dir = fixture("project-3");
+    let script = bundle(&dir, |builder| builder);
+    for (text, what) in [
+        ("} # no newline after this comment", "greet.sh's last line"),
+        ("EOF", "heredoc.sh's terminator"),
+        ("echo \"this goes to /dev/null\"", "quiet.sh's last line"),
+    ] {
+        assert!(
+            script.lines().any(|line| line.trim() == text),
+            "{} isn't on a line of its own:\n{}",
+            what,
+            script
+        );
+    }
+}
@@ -0,0 +1,43 @@
+//! Self-extracting bundles carry the script in an archive after a stub, and have to behave
+//! like the plain script once the stub has unpacked it
+#![allow(clippy::needless_return)]
+
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use common::fixture;
+use common::try_bundle;
+use shpack::Format;
+
+#[test]
+fn self_extracting_bundles_run_like_scripts() {
+    let dir = fixture("project-3");
+    let bundle = try_bundle(&dir, |builder| builder.format(Format::SelfExtract))
+        .expect("the fixture should bundle");
+    let out = tempfile::tempdir().expect("a temporary directory should be created");
+    let path = out.path().join("bundle.sh");
+    let mut file = fs::File::create(&path).expect("the bundle should be writable");
+    bundle.write_to(&mut file).expect("the bundle should write");
+    drop(file);
+
+    let output = Command::new("bash")
+        .arg(&path)
+        .current_dir(&dir)
+        .output()
+        .expect("bash should run");
+    assert!(
+        output.status.success(),
+        "the bundle failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "hello, world\n\
+         from a heredoc\n\
+         after the heredoc\n\
+         quiet.sh was silenced\n\
+         hello, again\n"
+    );
+}
@@ -0,0 +1,27 @@
+//! `# shpack: with` sets variables only while the file it's on runs, so the caller's own
+//! values of them are back afterwards, like after a function with `local`s
+#![allow(clippy::needless_return)]
+
+mod common;
+
+use common::bundle;
+use common::fixture;
+use common::run;
+
+const OUTPUT: &str = "library: library, retries 3\n\
+                      lazy library: lazy library\n\
+                      caller: caller, retries unset\n";
+
+#[test]
+fn variables_dont_leak_into_the_caller() {
+    let dir = fixture("with-variables");
+    let script = bundle(&dir, |builder| builder);
+    assert_eq!(run(&script, &dir), OUTPUT);
+}
+
+#[test]
+fn variables_dont_leak_from_minified_bundles() {
+    let dir = fixture("with-variables");
+    let script = bundle(&dir, |builder| builder.minify(true));
+    assert_eq!(run(&script, &dir), OUTPUT);
+}
@@ -0,0 +1,82 @@
+//! `shpack build` skips packages that are fresh, so anything a bundle found by listing a
+//! directory, rather than by name, has to be checked again before a package is skipped
+#![cfg(feature = "cli")]
+#![allow(clippy::needless_return)]
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use common::copy_fixture;
+use common::run;
+
+/// Builds the workspace in `dir` and returns what it printed to stderr
+fn build(dir: &Path) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_shpack"))
+        .arg("build")
+        .current_dir(dir)
+        .env("SHPACK_NO_DAEMON", "1")
+        .output()
+        .expect("shpack should run");
+    let stderr = String::from_utf8(output.stderr).expect("the output should be UTF-8");
+    assert!(output.status.success(), "the build failed: {}", stderr);
+    return stderr;
+}
+
+fn built(dir: &Path) -> String {
+    let script =
+        fs::read_to_string(dir.join("dist/listings.sh")).expect("the bundle should be written");
+    return run(&script, dir);
+}
+
+#[test]
+fn unchanged_packages_are_fresh() {
+    let dir = copy_fixture("listings");
+    assert!(build(dir.path()).contains("Bundling listings"));
+    assert!(build(dir.path()).contains("Fresh listings"));
+}
+
+#[test]
+fn a_glob_matching_a_new_file_rebuilds() {
+    let dir = copy_fixture("listings");
+    build(dir.path());
+    fs::write(dir.path().join("lib/b.sh"), "echo \"lib/b.sh\"\n").expect("lib/ should be writable");
+
+    assert!(build(dir.path()).contains("Bundling listings"));
+    assert!(built(dir.path()).contains("lib/b.sh"));
+}
+
+#[test]
+fn a_glob_matching_fewer_files_rebuilds() {
+    let dir = copy_fixture("listings");
+    fs::write(dir.path().join("lib/b.sh"), "echo \"lib/b.sh\"\n").expect("lib/ should be writable");
+    build(dir.path());
+    fs::remove_file(dir.path().join("lib/b.sh")).expect("lib/b.sh should be removable");
+
+    assert!(build(dir.path()).contains("Bundling listings"));
+    assert!(!built(dir.path()).contains("lib/b.sh"));
+}
+
+#[test]
+fn an_embedded_directory_with_a_new_file_rebuilds() {
+    let dir = copy_fixture("listings");
+    build(dir.path());
+    fs::write(dir.path().join("conf/b"), "b\n").expect("conf/ should be writable");
+
+    assert!(build(dir.path()).contains("Bundling listings"));
+    assert!(built(dir.path()).lines().any(|line| line == "b"));
+}
+
+#[test]
+fn a_messages_directory_with_a_new_locale_rebuilds() {
+    let dir = copy_fixture("listings");
+    build(dir.path());
+    assert!(built(dir.path()).ends_with("hello\n"));
+    fs::write(dir.path().join("locales/pt.toml"), "greeting = \"olá\"\n")
+        .expect("locales/ should be writable");
+
+    assert!(build(dir.path()).contains("Bundling listings"));
+    assert!(built(dir.path()).ends_with("olá\n"));
+}